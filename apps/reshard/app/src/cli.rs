@@ -1,15 +1,27 @@
 //! CLI for reshard app.
 
+use std::io::Read;
+
+use base64::Engine;
+use logging::LogFormat;
 use qos_core::{
     cli::{EPHEMERAL_FILE_OPT, MANIFEST_FILE_OPT, QUORUM_FILE_OPT, USOCK},
-    handles::Handles,
     io::SocketAddress,
     parser::{GetParserForOptions, OptionsParser, Parser, Token},
-    protocol::services::boot::{QuorumMember, ShareSet},
-    server::SocketServer,
+    protocol::{
+        services::boot::{ManifestEnvelope, QuorumMember, ShareSet},
+        QosHash,
+    },
     EPHEMERAL_KEY_FILE, MANIFEST_FILE, QUORUM_FILE, SEC_APP_SOCK,
 };
 use qos_hex::FromHex;
+use qos_p256::P256Public;
+use zeroize::Zeroizing;
+
+use crate::{
+    keys::InMemoryKeys, service::alias_is_file_name, socket_address::socket_address_from_args,
+    ReshardAppConfig,
+};
 
 /// CLI options for starting up the app server.
 #[derive(Default, Clone, Debug, PartialEq)]
@@ -19,7 +31,15 @@ struct ReshardOpts {
 
 const MOCK_NSM: &str = "mock-nsm";
 const THRESHOLD: &str = "threshold";
+const THRESHOLD_FILE: &str = "threshold-file";
 const MEMBERS: &str = "members"; // semicolon-separated hex pubkeys
+const MEMBERS_FILE: &str = "members-file"; // newline- or semicolon-separated hex pubkeys
+const MEMBER_ALIASES: &str = "member-aliases"; // semicolon-separated aliases, one per member
+const ALLOW_UNSAFE_THRESHOLD: &str = "allow-unsafe-threshold";
+const LOG_FORMAT: &str = "log-format";
+const MANIFEST_STDIN: &str = "manifest-stdin";
+const MANIFEST_BASE64: &str = "manifest-base64";
+const KEYS_STDIN: &str = "keys-stdin";
 
 impl ReshardOpts {
     fn new(args: &mut Vec<String>) -> Self {
@@ -29,8 +49,14 @@ impl ReshardOpts {
         Self { parsed }
     }
 
+    /// The unix socket address to listen on, see [`socket_address_from_args`].
     fn addr(&self) -> SocketAddress {
-        SocketAddress::new_unix(self.parsed.single(USOCK).expect("unix socket is required"))
+        socket_address_from_args(None, None, Some(self.usock()), false)
+            .unwrap_or_else(|e| panic!("invalid socket options: {e}"))
+    }
+
+    fn usock(&self) -> &String {
+        self.parsed.single(USOCK).expect("unix socket is required")
     }
 
     /// Defaults to [`QUORUM_FILE`] if not explicitly specified
@@ -57,47 +83,235 @@ impl ReshardOpts {
             .clone()
     }
 
+    /// The manifest envelope piped in on stdin or given inline, `None` if it is to be read from
+    /// the manifest file.
+    fn manifest_envelope(&self) -> Option<ManifestEnvelope> {
+        let envelope = if self.parsed.flag(MANIFEST_STDIN).unwrap_or(false) {
+            read_manifest_envelope(std::io::stdin().lock())
+        } else {
+            decode_manifest_envelope_base64(self.parsed.single(MANIFEST_BASE64)?)
+        };
+        Some(envelope.unwrap_or_else(|e| panic!("{e}")))
+    }
+
+    /// The quorum and ephemeral keys piped in on stdin, `None` if they are to be read from the
+    /// quorum and ephemeral files.
+    fn keys(&self) -> Option<InMemoryKeys> {
+        if !self.parsed.flag(KEYS_STDIN).unwrap_or(false) {
+            return None;
+        }
+        Some(read_in_memory_keys(std::io::stdin().lock()).unwrap_or_else(|e| panic!("{e}")))
+    }
+
     fn mock_nsm(&self) -> bool {
         self.parsed.flag(MOCK_NSM).unwrap_or(false)
     }
 
+    fn allow_unsafe_threshold(&self) -> bool {
+        self.parsed.flag(ALLOW_UNSAFE_THRESHOLD).unwrap_or(false)
+    }
+
+    /// Defaults to [`LogFormat::Pretty`] if not explicitly specified
+    fn log_format(&self) -> LogFormat {
+        self.parsed
+            .single(LOG_FORMAT)
+            .expect("has a default value.")
+            .parse()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
     // Return a parsed ShareSet
     fn share_set(&self) -> ShareSet {
-        let threshold: usize = self
-            .parsed
-            .single(THRESHOLD)
-            .expect("--threshold is required")
-            .parse()
-            .expect("--threshold must be an integer");
+        let threshold = match (
+            self.parsed.single(THRESHOLD),
+            self.parsed.single(THRESHOLD_FILE),
+        ) {
+            (Some(threshold), None) => threshold.clone(),
+            (None, Some(path)) => read_threshold_file(path).unwrap_or_else(|e| panic!("{e}")),
+            _ => panic!("exactly one of --threshold or --threshold-file is required"),
+        };
 
-        let members = self
-            .parsed
-            .single(MEMBERS)
-            .expect("--members is required (semicolon-separated hex pubkeys)");
+        let members = match (
+            self.parsed.single(MEMBERS),
+            self.parsed.single(MEMBERS_FILE),
+        ) {
+            (Some(members), None) => members.clone(),
+            (None, Some(path)) => read_members_file(path).unwrap_or_else(|e| panic!("{e}")),
+            _ => panic!("exactly one of --members or --members-file is required"),
+        };
 
-        let pub_keys: Vec<Vec<u8>> = members
-            .split(";")
-            .map(|s| Vec::from_hex(s).expect("invalide hex in --members"))
-            .collect();
+        parse_share_set(
+            &threshold,
+            &members,
+            self.parsed.single(MEMBER_ALIASES).map(String::as_str),
+            self.allow_unsafe_threshold(),
+        )
+        .unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+/// Read the newline- or semicolon-separated hex pub keys in the `--members-file` at `path`,
+/// returning them in the semicolon-joined `--members` form accepted by [`parse_share_set`].
+/// Blank lines and surrounding whitespace are ignored.
+pub fn read_members_file(path: &str) -> Result<String, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("unable to read --members-file: {e}"))?;
+
+    let members: Vec<&str> = contents
+        .split(['\n', ';'])
+        .map(str::trim)
+        .filter(|hex| !hex.is_empty())
+        .collect();
+
+    Ok(members.join(";"))
+}
+
+/// Read the `--threshold` value from the `--threshold-file` at `path`, e.g. a share set's
+/// `quorum_threshold` file. Surrounding whitespace is ignored.
+pub fn read_threshold_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path)
+        .map(|threshold| threshold.trim().to_string())
+        .map_err(|e| format!("unable to read --threshold-file: {e}"))
+}
+
+/// Read a borsh encoded [`ManifestEnvelope`] from `reader` until it is closed, as piped to
+/// `--manifest-stdin`.
+pub fn read_manifest_envelope(mut reader: impl Read) -> Result<ManifestEnvelope, String> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("unable to read manifest envelope from stdin: {e}"))?;
+    decode_manifest_envelope(&bytes)
+}
+
+/// Read the hex encoded quorum and ephemeral secrets from `reader` until it is closed, as piped
+/// to `--keys-stdin`: the quorum secret on the first line, the ephemeral secret on the second,
+/// in the format of the secret files.
+pub fn read_in_memory_keys(mut reader: impl Read) -> Result<InMemoryKeys, String> {
+    let mut secrets = Zeroizing::new(String::new());
+    reader
+        .read_to_string(&mut secrets)
+        .map_err(|e| format!("unable to read keys from stdin: {e}"))?;
+
+    let lines: Vec<&str> = secrets.lines().filter(|l| !l.trim().is_empty()).collect();
+    let [quorum_secret, ephemeral_secret] = lines[..] else {
+        return Err(format!(
+            "expected the quorum and ephemeral secrets on 2 lines of stdin, got {}",
+            lines.len()
+        ));
+    };
+    InMemoryKeys::from_hex(quorum_secret, ephemeral_secret)
+}
+
+/// Decode the standard base64 of a borsh encoded [`ManifestEnvelope`], as given to
+/// `--manifest-base64`. Surrounding whitespace is ignored.
+pub fn decode_manifest_envelope_base64(b64: &str) -> Result<ManifestEnvelope, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(b64.trim())
+        .map_err(|e| format!("invalid base64 in --manifest-base64: {e}"))?;
+    decode_manifest_envelope(&bytes)
+}
+
+fn decode_manifest_envelope(bytes: &[u8]) -> Result<ManifestEnvelope, String> {
+    borsh::from_slice(bytes).map_err(|e| format!("invalid manifest envelope: {e}"))
+}
+
+/// Summary of `share_set` logged on startup, so operators can confirm the share set before the
+/// reshard is served. The fingerprint is the hex encoded qos hash of the share set.
+pub fn share_set_summary(share_set: &ShareSet) -> String {
+    format!(
+        "---- Share set ----\nmembers: {}\nthreshold: {}\nfingerprint: {}",
+        share_set.members.len(),
+        share_set.threshold,
+        qos_hex::encode(&share_set.qos_hash())
+    )
+}
 
-        if threshold < 2 || threshold > pub_keys.len() {
-            panic!("--threshold must be in 2..=len(--members)")
+/// Parse and validate a [`ShareSet`] from `--threshold` and semicolon-separated hex `--members`.
+///
+/// Members are aliased with the semicolon-separated `--member-aliases` in the order given, or
+/// `reshard-1`, `reshard-2`, ... if there are none. Errors if any pub key is not a valid P256
+/// public key or appears more than once, or if the aliases do not name each member once, so a bad
+/// share set fails before any resharding happens. A threshold of 1 is only accepted with
+/// `allow_unsafe_threshold`.
+pub fn parse_share_set(
+    threshold: &str,
+    members: &str,
+    aliases: Option<&str>,
+    allow_unsafe_threshold: bool,
+) -> Result<ShareSet, String> {
+    let threshold: usize = threshold
+        .parse()
+        .map_err(|_| "--threshold must be an integer".to_string())?;
+
+    let mut pub_keys: Vec<Vec<u8>> = Vec::new();
+    for (i, hex) in members.split(";").enumerate() {
+        let pub_key =
+            Vec::from_hex(hex).map_err(|e| format!("invalid hex in --members[{i}]: {e:?}"))?;
+        P256Public::from_bytes(&pub_key)
+            .map_err(|e| format!("invalid P256 public key in --members[{i}]: {e:?}"))?;
+        if pub_keys.contains(&pub_key) {
+            return Err(format!("duplicate public key in --members[{i}]"));
         }
+        pub_keys.push(pub_key);
+    }
+
+    let min_threshold = if allow_unsafe_threshold { 1 } else { 2 };
+    if threshold < min_threshold || threshold > pub_keys.len() {
+        return Err(format!(
+            "--threshold must be in {min_threshold}..=len(--members)"
+        ));
+    }
+    if threshold == 1 {
+        tracing::warn!("with --threshold 1 any single member can reconstruct the quorum key");
+    }
 
-        let members: Vec<QuorumMember> = pub_keys
-            .into_iter()
-            .enumerate()
-            .map(|(i, pub_key_bytes)| QuorumMember {
-                alias: format!("reshard-{}", i + 1),
-                pub_key: pub_key_bytes,
-            })
-            .collect();
-
-        ShareSet {
-            threshold: threshold as u32,
-            members,
+    let aliases = match aliases {
+        Some(aliases) => parse_member_aliases(aliases, pub_keys.len())?,
+        None => (1..=pub_keys.len())
+            .map(|i| format!("reshard-{i}"))
+            .collect(),
+    };
+
+    let members: Vec<QuorumMember> = aliases
+        .into_iter()
+        .zip(pub_keys)
+        .map(|(alias, pub_key)| QuorumMember { alias, pub_key })
+        .collect();
+
+    Ok(ShareSet {
+        threshold: threshold as u32,
+        members,
+    })
+}
+
+/// Split semicolon-separated `--member-aliases`, checking there is one distinct alias for each
+/// of the `num_members` members, usable as a file name as the secrets of members are named after
+/// their alias.
+fn parse_member_aliases(aliases: &str, num_members: usize) -> Result<Vec<String>, String> {
+    let aliases: Vec<String> = aliases.split(";").map(|a| a.trim().to_string()).collect();
+    if aliases.len() != num_members {
+        return Err(format!(
+            "--member-aliases has {} aliases, but --members has {num_members} members",
+            aliases.len()
+        ));
+    }
+
+    for (i, alias) in aliases.iter().enumerate() {
+        if alias.is_empty() {
+            return Err(format!("empty alias in --member-aliases[{i}]"));
+        }
+        if !alias_is_file_name(alias) {
+            return Err(format!(
+                "alias {alias:?} in --member-aliases[{i}] can not be used as a file name"
+            ));
+        }
+        if aliases[..i].contains(alias) {
+            return Err(format!("duplicate alias in --member-aliases[{i}]"));
         }
     }
+
+    Ok(aliases)
 }
 
 struct ReshardParser;
@@ -125,18 +339,56 @@ impl GetParserForOptions for ReshardParser {
 					.takes_value(true)
 					.default_value(MANIFEST_FILE)
 			)
+            .token(
+                Token::new(MANIFEST_STDIN, "read the borsh encoded manifest envelope from stdin and store it at --manifest-file")
+                    .forbids(vec![MANIFEST_BASE64, KEYS_STDIN])
+            )
+            .token(
+                Token::new(MANIFEST_BASE64, "base64 of the borsh encoded manifest envelope, stored at --manifest-file")
+                    .takes_value(true)
+                    .forbids(vec![MANIFEST_STDIN])
+            )
+            .token(
+                Token::new(KEYS_STDIN, "read the hex quorum and ephemeral secrets, one per line, from stdin instead of --quorum-file and --ephemeral-file, so they are never on disk")
+                    .forbids(vec![MANIFEST_STDIN])
+            )
             .token(
                 Token::new(THRESHOLD, "quorum threshold")
                 .takes_value(true)
+                .forbids(vec![THRESHOLD_FILE])
+            )
+            .token(
+                Token::new(THRESHOLD_FILE, "path to a file containing the quorum threshold")
+                    .takes_value(true)
+                    .forbids(vec![THRESHOLD])
             )
             .token(
                 Token::new(MEMBERS, "semicolon-separated hex pubkeys (e.g. 04ab..;04cd..)")
                     .takes_value(true)
+                    .forbids(vec![MEMBERS_FILE])
+            )
+            .token(
+                Token::new(MEMBERS_FILE, "path to a file of newline- or semicolon-separated hex pubkeys")
+                    .takes_value(true)
+                    .forbids(vec![MEMBERS])
+            )
+            .token(
+                Token::new(MEMBER_ALIASES, "semicolon-separated aliases of the members, in the same order (e.g. alice;bob). Defaults to reshard-1;reshard-2;..")
+                    .takes_value(true)
             )
             .token(Token::new(
                 MOCK_NSM,
                 "use the MockNsm. Should never be used in production",
             ))
+            .token(Token::new(
+                ALLOW_UNSAFE_THRESHOLD,
+                "allow a --threshold of 1. Should never be used in production",
+            ))
+            .token(
+                Token::new(LOG_FORMAT, "format of log lines, `json` or `pretty`")
+                    .takes_value(true)
+                    .default_value("pretty")
+            )
     }
 }
 
@@ -147,7 +399,7 @@ impl Cli {
     ///
     /// # Panics
     ///
-    /// Panics if the socket server errors.
+    /// Panics if the app fails to start, see [`crate::run`].
     pub fn execute() {
         let mut args: Vec<String> = std::env::args().collect();
 
@@ -158,7 +410,9 @@ impl Cli {
         } else if opts.parsed.help() {
             println!("{}", opts.parsed.info());
         } else {
-            let nsm: Box<dyn qos_nsm::NsmProvider> = if opts.mock_nsm() {
+            logging::init(opts.log_format());
+
+            let nsm: Box<dyn qos_nsm::NsmProvider + Send> = if opts.mock_nsm() {
                 #[cfg(feature = "vsock")]
                 panic!("cannot use mock nsm when \"vsock\" feature is enabled");
                 #[cfg(all(not(feature = "vsock"), feature = "mock"))]
@@ -171,21 +425,25 @@ impl Cli {
                 Box::new(qos_nsm::Nsm)
             };
 
-            // Build processor; panic on error so the app fails to come up if anything is wrong
-            let processor = crate::service::ReshardProcessor::new(
-                &Handles::new(
-                    opts.ephemeral_file(),
-                    opts.quorum_file(),
-                    opts.manifest_file(),
-                    "pivot not used".to_string(),
-                ),
-                &opts.share_set(),
-                nsm.as_ref(),
-            )
-            .unwrap_or_else(|e| panic!("reshard precompute failed: {e}"));
+            let share_set = opts.share_set();
+            tracing::info!("{}", share_set_summary(&share_set));
 
-            println!("---- Starting Reshard server -----");
-            SocketServer::listen(opts.addr(), processor).expect("unable to start Reshard server");
+            let addr = opts.addr();
+            crate::ensure_socket_unused(opts.usock().as_ref())
+                .unwrap_or_else(|e| panic!("unable to start Reshard server: {e}"));
+            crate::remove_socket_on_sigterm(opts.usock().into());
+            crate::run(ReshardAppConfig {
+                addr,
+                quorum_file: opts.quorum_file(),
+                ephemeral_file: opts.ephemeral_file(),
+                keys: opts
+                    .keys()
+                    .map(|keys| Box::new(keys) as Box<dyn crate::keys::KeySource>),
+                manifest_file: opts.manifest_file(),
+                manifest_envelope: opts.manifest_envelope(),
+                share_set,
+                nsm,
+            });
         }
     }
 }
@@ -0,0 +1,70 @@
+//! Serving requests while the reshard bundle is precomputed, so the app's socket is up
+//! immediately and health checks can tell an initializing app from one that is down.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{self, Receiver},
+};
+
+use qos_core::server::RequestProcessor;
+
+use crate::service::ReshardResponse;
+
+/// Answers every request with [`ReshardResponse::Initializing`] until the inner processor is
+/// built on a background thread, then forwards requests to it.
+pub struct InitializingProcessor<P> {
+    inner: Option<P>,
+    built: Receiver<P>,
+}
+
+impl<P: Send + 'static> InitializingProcessor<P> {
+    /// Build the inner processor with `build` on a background thread.
+    ///
+    /// If `build` fails or panics, the error is logged and the process exits, so the app still
+    /// fails to come up if anything is wrong rather than initializing forever.
+    pub fn spawn<F>(build: F) -> Self
+    where
+        F: FnOnce() -> Result<P, String> + Send + 'static,
+    {
+        let (built_tx, built) = mpsc::sync_channel(1);
+        std::thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(build))
+                .unwrap_or_else(|_| Err("precompute panicked".to_string()));
+            match result {
+                Ok(inner) => {
+                    // The processor is only dropped along with the server, never before
+                    let _ = built_tx.send(inner);
+                }
+                Err(e) => {
+                    tracing::error!("reshard precompute failed: {e}");
+                    std::process::exit(1);
+                }
+            }
+        });
+
+        Self { inner: None, built }
+    }
+
+    /// Whether the inner processor is built and serves requests.
+    pub fn is_ready(&mut self) -> bool {
+        if self.inner.is_none() {
+            self.inner = self.built.try_recv().ok();
+            if self.inner.is_some() {
+                tracing::info!("reshard precompute finished, serving requests");
+            }
+        }
+        self.inner.is_some()
+    }
+}
+
+impl<P: RequestProcessor + Send + 'static> RequestProcessor for InitializingProcessor<P> {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        if !self.is_ready() {
+            return borsh::to_vec(&ReshardResponse::Initializing).expect("should be valid borsh");
+        }
+        self.inner
+            .as_mut()
+            .expect("inner processor is built once ready")
+            .process(request)
+    }
+}
@@ -0,0 +1,99 @@
+//! Where the reshard app gets its quorum and ephemeral keys, see [`KeySource`].
+
+use qos_core::handles::Handles;
+use qos_p256::P256Pair;
+use zeroize::Zeroizing;
+
+/// Length of a P256 master seed, the secret both key pairs are derived from.
+const MASTER_SEED_LEN: usize = 32;
+
+/// Source of the quorum key the app reshards and the ephemeral key it signs with. Keys are
+/// loaded on every use, e.g. on recompute, rather than kept by the processor.
+pub trait KeySource: Send {
+    /// The Quorum Key pair.
+    fn quorum_key(&self) -> Result<P256Pair, String>;
+    /// The Ephemeral Key pair.
+    fn ephemeral_key(&self) -> Result<P256Pair, String>;
+}
+
+/// Keys read from the quorum and ephemeral files of the handles, as QOS writes them on boot.
+impl KeySource for Handles {
+    fn quorum_key(&self) -> Result<P256Pair, String> {
+        self.get_quorum_key()
+            .map_err(|e| format!("unable to get quorum key: {e:?}"))
+    }
+
+    fn ephemeral_key(&self) -> Result<P256Pair, String> {
+        self.get_ephemeral_key()
+            .map_err(|e| format!("unable to get ephemeral key: {e:?}"))
+    }
+}
+
+/// Keys held in memory, e.g. passed by a parent process over a pipe or fetched from a KMS, so
+/// the secrets never have to be written to disk. The seeds are zeroized on drop.
+pub struct InMemoryKeys {
+    quorum_seed: Zeroizing<[u8; MASTER_SEED_LEN]>,
+    ephemeral_seed: Zeroizing<[u8; MASTER_SEED_LEN]>,
+}
+
+impl InMemoryKeys {
+    /// Keys from the master seeds of `quorum_key` and `ephemeral_key`.
+    pub fn new(quorum_key: &P256Pair, ephemeral_key: &P256Pair) -> Self {
+        Self {
+            quorum_seed: Zeroizing::new(*quorum_key.to_master_seed()),
+            ephemeral_seed: Zeroizing::new(*ephemeral_key.to_master_seed()),
+        }
+    }
+
+    /// Keys from hex encoded master seeds, in the format of the quorum and ephemeral secret
+    /// files.
+    ///
+    /// Errors if either secret is not a valid master seed.
+    pub fn from_hex(quorum_secret: &str, ephemeral_secret: &str) -> Result<Self, String> {
+        let keys = Self {
+            quorum_seed: master_seed_from_hex("quorum", quorum_secret)?,
+            ephemeral_seed: master_seed_from_hex("ephemeral", ephemeral_secret)?,
+        };
+        keys.quorum_key()?;
+        keys.ephemeral_key()?;
+        Ok(keys)
+    }
+}
+
+impl KeySource for InMemoryKeys {
+    fn quorum_key(&self) -> Result<P256Pair, String> {
+        P256Pair::from_master_seed(&self.quorum_seed)
+            .map_err(|e| format!("invalid quorum key: {e:?}"))
+    }
+
+    fn ephemeral_key(&self) -> Result<P256Pair, String> {
+        P256Pair::from_master_seed(&self.ephemeral_seed)
+            .map_err(|e| format!("invalid ephemeral key: {e:?}"))
+    }
+}
+
+impl std::fmt::Debug for InMemoryKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InMemoryKeys { .. }")
+    }
+}
+
+fn master_seed_from_hex(
+    kind: &str,
+    secret: &str,
+) -> Result<Zeroizing<[u8; MASTER_SEED_LEN]>, String> {
+    let decoded = Zeroizing::new(
+        qos_hex::decode(secret.trim())
+            .map_err(|e| format!("{kind} secret is not valid hex: {e:?}"))?,
+    );
+    if decoded.len() != MASTER_SEED_LEN {
+        return Err(format!(
+            "{kind} secret is {} bytes, expected a {MASTER_SEED_LEN} byte master seed",
+            decoded.len()
+        ));
+    }
+
+    let mut seed = Zeroizing::new([0; MASTER_SEED_LEN]);
+    seed.copy_from_slice(&decoded);
+    Ok(seed)
+}
@@ -1,2 +1,155 @@
 pub mod cli;
+pub mod initializing;
+pub mod keys;
+pub mod rate_limit;
 pub mod service;
+pub mod socket_address;
+
+use std::{
+    fs, io,
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    path::{Path, PathBuf},
+};
+
+use qos_core::{
+    handles::Handles,
+    io::SocketAddress,
+    protocol::services::boot::{ManifestEnvelope, ShareSet},
+    server::SocketServer,
+};
+use qos_nsm::NsmProvider;
+
+use crate::{
+    initializing::InitializingProcessor,
+    keys::KeySource,
+    rate_limit::{RateLimitedProcessor, ReshardRateLimits},
+    service::ReshardProcessor,
+};
+
+/// Configuration for running the reshard app.
+pub struct ReshardAppConfig {
+    /// Address the app server listens on.
+    pub addr: SocketAddress,
+    /// Path to the Quorum Key secret.
+    pub quorum_file: String,
+    /// Path to the Ephemeral Key secret.
+    pub ephemeral_file: String,
+    /// Keys to use instead of those in `quorum_file` and `ephemeral_file`, e.g.
+    /// [`InMemoryKeys`](crate::keys::InMemoryKeys) so the secrets are never on disk. `None` to
+    /// read the files.
+    pub keys: Option<Box<dyn KeySource>>,
+    /// Path to the Manifest Envelope.
+    pub manifest_file: String,
+    /// Manifest Envelope to store at `manifest_file` before building the processor, e.g. one
+    /// piped in on stdin. `None` to use the one already there.
+    pub manifest_envelope: Option<ManifestEnvelope>,
+    /// Share set to reshard the Quorum Key to.
+    pub share_set: ShareSet,
+    /// NSM used to attest to the bundle.
+    pub nsm: Box<dyn NsmProvider + Send>,
+}
+
+impl ReshardAppConfig {
+    /// Build the processor, precomputing the reshard bundle.
+    pub fn processor(self) -> Result<ReshardProcessor, String> {
+        let handles = Handles::new(
+            self.ephemeral_file.clone(),
+            self.quorum_file.clone(),
+            self.manifest_file.clone(),
+            "pivot not used".to_string(),
+        );
+        if let Some(manifest_envelope) = &self.manifest_envelope {
+            handles
+                .put_manifest_envelope(manifest_envelope)
+                .map_err(|e| format!("unable to store manifest envelope: {e:?}"))?;
+        }
+
+        match self.keys {
+            Some(keys) => ReshardProcessor::with_keys(&handles, keys, &self.share_set, self.nsm),
+            None => ReshardProcessor::new(&handles, &self.share_set, self.nsm),
+        }
+    }
+}
+
+/// Make sure no other process serves the unix socket at `socket_file`, before the app listens
+/// on it. [`SocketServer::listen`] replaces an existing socket file, so a second app started on
+/// the same socket would otherwise silently take the traffic of the first.
+///
+/// A socket file nothing listens on, e.g. left behind by an app that crashed, is removed.
+/// Errors if something listens on the socket, or the file is not a socket.
+pub fn ensure_socket_unused(socket_file: &Path) -> Result<(), String> {
+    let metadata = match fs::symlink_metadata(socket_file) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("unable to inspect {}: {e}", socket_file.display())),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(format!(
+            "{} exists and is not a socket",
+            socket_file.display()
+        ));
+    }
+
+    match UnixStream::connect(socket_file) {
+        Ok(_) => Err(format!(
+            "{} is already in use, is another reshard app running?",
+            socket_file.display()
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            tracing::warn!("removing stale socket {}", socket_file.display());
+            fs::remove_file(socket_file)
+                .map_err(|e| format!("unable to remove {}: {e}", socket_file.display()))
+        }
+        Err(e) => Err(format!(
+            "unable to check whether {} is in use: {e}",
+            socket_file.display()
+        )),
+    }
+}
+
+/// Spawn a thread that waits for SIGTERM, then removes `socket_file` and exits the process.
+/// [`SocketServer::listen`] never returns, so this is how the app shuts down cleanly.
+///
+/// Must be called before any other thread is spawned: SIGTERM is blocked in the calling thread,
+/// and threads spawned afterwards inherit that, so only the waiting thread receives it.
+pub fn remove_socket_on_sigterm(socket_file: PathBuf) {
+    // SAFETY: `set` is initialized by `sigemptyset` before it is used
+    let set = unsafe {
+        let mut set = std::mem::zeroed::<libc::sigset_t>();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        set
+    };
+
+    std::thread::spawn(move || {
+        let mut signal = 0;
+        // SAFETY: `set` and `signal` are valid for the duration of the call
+        unsafe { libc::sigwait(&set, &mut signal) };
+        tracing::info!("SIGTERM received, shutting down Reshard server");
+
+        if let Err(e) = std::fs::remove_file(&socket_file) {
+            tracing::warn!("failed to remove {}: {e}", socket_file.display());
+        }
+        std::process::exit(0);
+    });
+}
+
+/// Serve the reshard processor on the configured address, limiting expensive requests with the
+/// default [`ReshardRateLimits`].
+///
+/// The server starts right away and answers [`crate::service::ReshardResponse::Initializing`]
+/// while the processor precomputes the bundle in the background, see [`InitializingProcessor`].
+///
+/// # Panics
+///
+/// Panics if the socket server errors. The process exits if building the processor fails, so
+/// the app fails to come up if anything is wrong.
+pub fn run(config: ReshardAppConfig) {
+    let addr = config.addr.clone();
+    let processor = InitializingProcessor::spawn(move || config.processor());
+
+    tracing::info!("---- Starting Reshard server -----");
+    let processor = RateLimitedProcessor::new(processor, ReshardRateLimits::default());
+    SocketServer::listen(addr, processor).expect("unable to start Reshard server");
+}
@@ -0,0 +1,175 @@
+//! Per request type rate limits applied in the enclave, before the app processes a request.
+//!
+//! The app wraps its processor in a [`RateLimitedProcessor`] with a [`RateLimitPolicy`] that
+//! knows its request types, [`ReshardRateLimits`] for the reshard app, so a flood of expensive
+//! requests is turned away cheaply instead of starving every other request of enclave CPU.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use borsh::from_slice;
+use qos_core::server::RequestProcessor;
+
+use crate::service::{ReshardRequest, ReshardResponse};
+
+/// Token bucket limit: up to `burst` requests at once, refilled at `per_second` requests per
+/// second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Requests allowed back to back after a quiet period.
+    pub burst: u32,
+    /// Sustained requests per second.
+    pub per_second: f64,
+}
+
+/// The request types of an app and their limits, see [`RateLimitedProcessor`].
+pub trait RateLimitPolicy {
+    /// Type of a request, each type is limited separately.
+    type Kind: Eq + Hash + Clone;
+
+    /// Type and limit of the encoded `request`, `None` if it is not limited. Requests the app
+    /// can not decode are best left unlimited, so the app rejects them as usual.
+    fn limit(&self, request: &[u8]) -> Option<(Self::Kind, RateLimit)>;
+
+    /// Encoded response to a request of `kind` rejected for exceeding its limit.
+    fn rejected(&self, kind: &Self::Kind) -> Vec<u8>;
+}
+
+/// Rejects requests over the limits of `policy` and passes the rest to the inner processor.
+///
+/// Wrap the app's processor before any batching processor, so each request of a batch counts
+/// against its limit.
+pub struct RateLimitedProcessor<P, L: RateLimitPolicy> {
+    inner: P,
+    policy: L,
+    buckets: HashMap<L::Kind, TokenBucket>,
+}
+
+impl<P, L: RateLimitPolicy> RateLimitedProcessor<P, L> {
+    /// Limit the requests processed by `inner` according to `policy`.
+    pub fn new(inner: P, policy: L) -> Self {
+        Self {
+            inner,
+            policy,
+            buckets: HashMap::new(),
+        }
+    }
+}
+
+impl<P: RequestProcessor, L: RateLimitPolicy> RequestProcessor for RateLimitedProcessor<P, L> {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        if let Some((kind, limit)) = self.policy.limit(&request) {
+            let now = Instant::now();
+            let bucket = self
+                .buckets
+                .entry(kind.clone())
+                .or_insert_with(|| TokenBucket::full(limit, now));
+            if !bucket.try_take(limit, now) {
+                tracing::warn!("rejected request over its rate limit of {limit:?}");
+                return self.policy.rejected(&kind);
+            }
+        }
+
+        self.inner.process(request)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            refilled_at: now,
+        }
+    }
+
+    /// Refill for the time passed since the last call, then take a token if there is one.
+    fn try_take(&mut self, limit: RateLimit, now: Instant) -> bool {
+        let elapsed = now
+            .checked_duration_since(self.refilled_at)
+            .unwrap_or(Duration::ZERO);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * limit.per_second).min(f64::from(limit.burst));
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Limits of the requests that are expensive to answer, see [`RateLimitedProcessor`]. Health and
+/// bundle id requests are never limited, so health checks keep working while the app turns a
+/// flood away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReshardRateLimits {
+    /// [`ReshardRequest::RetrieveBundle`], each answered with a copy of the whole bundle.
+    pub retrieve_bundle: RateLimit,
+    /// [`ReshardRequest::RetrieveAttestation`].
+    pub retrieve_attestation: RateLimit,
+    /// [`ReshardRequest::AttestWithNonce`], each a round trip to the NSM.
+    pub attest_with_nonce: RateLimit,
+    /// [`ReshardRequest::Recompute`] and [`ReshardRequest::ReplaceMember`], which sign and
+    /// encrypt anew.
+    pub recompute: RateLimit,
+}
+
+impl Default for ReshardRateLimits {
+    fn default() -> Self {
+        Self {
+            retrieve_bundle: RateLimit {
+                burst: 20,
+                per_second: 10.0,
+            },
+            retrieve_attestation: RateLimit {
+                burst: 50,
+                per_second: 25.0,
+            },
+            attest_with_nonce: RateLimit {
+                burst: 20,
+                per_second: 10.0,
+            },
+            recompute: RateLimit {
+                burst: 5,
+                per_second: 1.0,
+            },
+        }
+    }
+}
+
+impl RateLimitPolicy for ReshardRateLimits {
+    /// Name of the request type.
+    type Kind = &'static str;
+
+    fn limit(&self, request: &[u8]) -> Option<(Self::Kind, RateLimit)> {
+        match from_slice(request).ok()? {
+            ReshardRequest::RetrieveBundle => Some(("RetrieveBundle", self.retrieve_bundle)),
+            ReshardRequest::RetrieveAttestation => {
+                Some(("RetrieveAttestation", self.retrieve_attestation))
+            }
+            ReshardRequest::AttestWithNonce(_) => Some(("AttestWithNonce", self.attest_with_nonce)),
+            ReshardRequest::Recompute | ReshardRequest::ReplaceMember { .. } => {
+                Some(("Recompute", self.recompute))
+            }
+            ReshardRequest::HealthRequest | ReshardRequest::RetrieveBundleId => None,
+        }
+    }
+
+    fn rejected(&self, kind: &Self::Kind) -> Vec<u8> {
+        borsh::to_vec(&ReshardResponse::RateLimited(format!(
+            "{kind} requests are over their rate limit"
+        )))
+        .expect("should be valid borsh")
+    }
+}
@@ -1,16 +1,19 @@
 use qos_core::handles;
 use qos_core::protocol::services::{
-    boot::{ManifestEnvelope, ShareSet},
+    boot::{ManifestEnvelope, QuorumMember, ShareSet},
     genesis::GenesisMemberOutput,
 };
 use qos_core::protocol::QosHash;
 use qos_core::server::RequestProcessor;
-use qos_crypto::sha_512;
+use qos_crypto::{sha_256, sha_512};
 use qos_nsm::types::{NsmRequest, NsmResponse};
 use qos_nsm::NsmProvider;
-use qos_p256::P256Public;
+use qos_p256::{P256Pair, P256Public};
+
+use crate::keys::KeySource;
 
 use borsh::{from_slice, BorshDeserialize, BorshSerialize};
+use std::collections::{BTreeMap, HashSet};
 
 /// Signed, attested, and audit-friendly output of a resharding run.
 ///
@@ -67,50 +70,427 @@ pub struct ReshardBundle {
     /// Ephemeral-key signature binding outputs to this **attested run**.
     ///
     /// The ephemeral public key is carried in `attestation_doc`. The signature
-    /// is computed over `sha512(borsh(member_outputs))`. Verifiers should:
+    /// is computed over `hash(borsh(member_outputs))`, see [`Self::member_outputs_digest`].
+    /// Verifiers should:
     /// 1) parse & verify the attestation (incl. ephemeral pubkey),
     /// 2) recompute the digest from `member_outputs`,
     /// 3) verify this signature with the ephemeral pubkey.
     #[serde(with = "qos_hex::serde")]
     pub signature: Vec<u8>,
+
+    /// Hash of each member output's `share_hash` and of the signed digest.
+    ///
+    /// JSON bundles from before the field existed are read as [`HashAlgorithm::Sha512`]. The
+    /// borsh encoding has no such default: borsh bundles from before the field existed fail to
+    /// decode, and the [`Self::id`] of every bundle differs from the one it had without the field.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// Hash algorithms a bundle can be produced with.
+///
+/// Every algorithm produces 64 byte digests, the size of a member output's `share_hash`.
+/// Bundles naming an algorithm this build does not know fail to decode instead of being
+/// verified with the wrong one.
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Digest of `data`.
+    pub fn digest(self, data: &[u8]) -> [u8; 64] {
+        match self {
+            Self::Sha512 => sha_512(data),
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sha512 => f.write_str("sha512"),
+        }
+    }
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha512" => Ok(Self::Sha512),
+            _ => Err(format!(
+                "unknown hash algorithm '{s}', expected one of: sha512"
+            )),
+        }
+    }
+}
+
+impl ReshardBundle {
+    /// Identifier of this bundle: the sha256 of its borsh encoding.
+    ///
+    /// Changes whenever any field of the bundle does, so clients holding a bundle can compare
+    /// ids to detect that it was recomputed without retrieving the new bundle.
+    pub fn id(&self) -> Vec<u8> {
+        sha_256(&borsh::to_vec(self).expect("should be valid borsh")).to_vec()
+    }
+
+    /// Digest the ephemeral key signs: `hash(borsh(member_outputs))` with the bundle's
+    /// [`HashAlgorithm`].
+    pub fn member_outputs_digest(&self) -> [u8; 64] {
+        member_outputs_digest(self.hash_algorithm, &self.member_outputs)
+    }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
 pub enum ReshardRequest {
     RetrieveBundle,
     HealthRequest,
+    /// Only the attestation doc of the bundle, for a cheap check before retrieving the bundle.
+    RetrieveAttestation,
+    /// Recompute the bundle from the manifest envelope currently on disk, e.g. after new
+    /// approvals were delivered, without restarting the app.
+    Recompute,
+    /// Only the [`ReshardBundle::id`] of the bundle, to poll for changes without retrieving it.
+    RetrieveBundleId,
+    /// A fresh attestation doc bound to the caller's nonce, proving the enclave is running the
+    /// bundle's manifest now. At most [`MAX_NONCE_LEN`] bytes.
+    AttestWithNonce(Vec<u8>),
+    /// Re-encrypt the share of a member who lost their key to a replacement member, see
+    /// [`ReshardProcessor::replace_member`].
+    ReplaceMember {
+        replacement: MemberReplacement,
+        approvals: Vec<ReplacementApproval>,
+    },
 }
 
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+/// Replacement of the new share set member `alias` by `replacement`, who receives the same
+/// share. Approved by members of the share set, see [`ReplacementApproval`].
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+pub struct MemberReplacement {
+    /// [`ReshardBundle::id`] of the bundle the replacement applies to, so approvals can't be
+    /// replayed against a later bundle.
+    pub bundle_id: Vec<u8>,
+    /// Alias of the member being replaced.
+    pub alias: String,
+    pub replacement: QuorumMember,
+}
+
+impl MemberReplacement {
+    /// Digest members sign to approve the replacement: `sha512(borsh(self))`.
+    pub fn digest(&self) -> [u8; 64] {
+        sha_512(&borsh::to_vec(self).expect("should be valid borsh"))
+    }
+}
+
+/// Signature of a new share set member over a [`MemberReplacement::digest`].
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+pub struct ReplacementApproval {
+    /// Pub key of the approving member, as in the share set.
+    pub member_pub_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
 pub enum ReshardResponse {
     Bundle(Box<ReshardBundle>),
     Error,
     Health,
+    /// Raw attestation doc bytes. Identical to [`ReshardBundle::attestation_doc`] for
+    /// [`ReshardRequest::RetrieveAttestation`], fresh for [`ReshardRequest::AttestWithNonce`].
+    Attestation(Vec<u8>),
+    /// The bundle was recomputed and is served from here on.
+    Recomputed,
+    /// The bundle could not be recomputed. The previous bundle is still served.
+    RecomputeFailed(String),
+    /// [`ReshardBundle::id`] of the bundle currently served.
+    BundleId(Vec<u8>),
+    /// A fresh attestation doc could not be generated.
+    AttestationFailed(String),
+    /// The member was replaced and the updated bundle is served from here on.
+    MemberReplaced,
+    /// The member could not be replaced. The previous bundle is still served.
+    ReplaceMemberFailed(String),
+    /// The request was turned away for exceeding the rate limit of its type, see
+    /// [`crate::rate_limit::ReshardRateLimits`].
+    RateLimited(String),
+    /// `response` with out of band information for the client, e.g. a warning. The host
+    /// answers with `response` and passes `metadata` on in the gRPC response metadata, see
+    /// [`ReshardResponse::with_metadata`].
+    WithMetadata {
+        metadata: BTreeMap<String, String>,
+        response: Box<ReshardResponse>,
+    },
+    /// The app is still precomputing its bundle and serves no other response yet, see
+    /// [`crate::initializing::InitializingProcessor`].
+    Initializing,
 }
 
+/// Maximum size in bytes of the nonce of an attestation, as accepted by the NSM.
+pub const MAX_NONCE_LEN: usize = 512;
+
+/// Smallest attestation document in bytes embedded in a bundle. A Nitro attestation document is
+/// a COSE Sign1 structure whose payload alone holds 16 SHA-384 PCRs (768 bytes) and the DER
+/// certificate of the enclave, signed with a 96 byte P-384 signature. Real documents are
+/// several KiB, so one below 1 KiB can not be valid and means the NSM misbehaved.
+pub const MIN_ATTESTATION_DOC_LEN: usize = 1024;
+
+/// Largest attestation document in bytes embedded in a bundle, well above the largest document
+/// the NSM produces with the user data and public key of a bundle.
+pub const MAX_ATTESTATION_DOC_LEN: usize = 64 * 1024;
+
 impl ReshardResponse {
     fn error() -> Vec<u8> {
         borsh::to_vec(&Self::Error).expect("serializing should work")
     }
+
+    /// The response with `value` attached under `key`, replacing any previous value of `key`.
+    /// Keys should be lowercase ASCII, as gRPC metadata keys are.
+    pub fn with_metadata(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (response, mut metadata) = self.into_parts();
+        metadata.insert(key.into(), value.into());
+        Self::WithMetadata {
+            metadata,
+            response: Box::new(response),
+        }
+    }
+
+    /// The response without its metadata, along with the metadata, empty if it has none.
+    /// Nested metadata is merged, the outer value of a key winning.
+    pub fn into_parts(self) -> (Self, BTreeMap<String, String>) {
+        match self {
+            Self::WithMetadata { metadata, response } => {
+                let (response, mut merged) = response.into_parts();
+                merged.extend(metadata);
+                (response, merged)
+            }
+            response => (response, BTreeMap::new()),
+        }
+    }
 }
 
 pub struct ReshardProcessor {
-    cached_reshard_bundle: ReshardBundle,
+    /// The current bundle. Replaced as a whole on recompute, so the bundle and attestation
+    /// served always belong to the same run.
+    served: ServedBundle,
+    /// Inputs of the bundle, kept to recompute it.
+    handles: handles::Handles,
+    keys: Box<dyn KeySource>,
+    new_share_set: ShareSet,
+    nsm: Box<dyn NsmProvider + Send>,
+}
+
+/// A bundle with the shares it encrypts, kept to re-encrypt a share when a member is replaced.
+struct ServedBundle {
+    bundle: ReshardBundle,
+    /// Plaintext share of each member, in the order of `bundle.member_outputs`.
+    shares: Vec<Vec<u8>>,
+    responses: EncodedResponses,
+}
+
+/// Borsh encoded responses for one bundle.
+struct EncodedResponses {
+    /// Borsh encoded `ReshardResponse::Bundle`.
+    ///
+    /// The bundle only changes on recompute, so `RetrieveBundle` copies these bytes rather than
+    /// deep cloning the bundle (one allocation per attestation doc, manifest field, member
+    /// output, ...) and serializing it into a growing buffer on every request. A retrieval makes
+    /// a single allocation of exactly the response size, independent of the share set.
+    bundle: Vec<u8>,
+    /// Borsh encoded `ReshardResponse::Attestation` for the same bundle.
+    attestation: Vec<u8>,
+    /// Borsh encoded `ReshardResponse::BundleId` for the same bundle.
+    bundle_id: Vec<u8>,
+    /// Qos hash of the bundle's manifest envelope, the `user_data` of its attestations.
+    manifest_hash: Vec<u8>,
 }
 
 impl ReshardProcessor {
+    /// Compute the reshard bundle for `new_share_set` from the keys and manifest envelope in
+    /// `handles`, attested by `nsm`.
     pub fn new(
         handles: &handles::Handles,
         new_share_set: &ShareSet,
+        nsm: Box<dyn NsmProvider + Send>,
+    ) -> Result<Self, String> {
+        Self::with_keys(handles, Box::new(handles.clone()), new_share_set, nsm)
+    }
+
+    /// Like [`Self::new`], with the quorum and ephemeral keys from `keys` rather than the files
+    /// of `handles`, e.g. [`crate::keys::InMemoryKeys`]. Only the manifest envelope is read from
+    /// `handles`.
+    pub fn with_keys(
+        handles: &handles::Handles,
+        keys: Box<dyn KeySource>,
+        new_share_set: &ShareSet,
+        nsm: Box<dyn NsmProvider + Send>,
+    ) -> Result<Self, String> {
+        let served = ServedBundle::compute(handles, keys.as_ref(), new_share_set, nsm.as_ref())?;
+
+        Ok(Self {
+            served,
+            handles: handles.clone(),
+            keys,
+            new_share_set: new_share_set.clone(),
+            nsm,
+        })
+    }
+
+    /// Recompute the bundle from the manifest envelope currently in the handles. The previous
+    /// bundle keeps being served if this fails.
+    pub fn recompute(&mut self) -> Result<(), String> {
+        self.served = ServedBundle::compute(
+            &self.handles,
+            self.keys.as_ref(),
+            &self.new_share_set,
+            self.nsm.as_ref(),
+        )?;
+        Ok(())
+    }
+
+    /// Re-encrypt the share of the member `replacement.alias` to `replacement.replacement` and
+    /// sign the updated member outputs, without generating new shares. Replacements are kept
+    /// on recompute.
+    ///
+    /// Requires `approvals` from at least threshold distinct members of the share set, who
+    /// could reconstruct the quorum key together anyway, so the host can't hand shares to keys
+    /// of its choosing. The previous bundle keeps being served if this fails.
+    pub fn replace_member(
+        &mut self,
+        replacement: &MemberReplacement,
+        approvals: &[ReplacementApproval],
+    ) -> Result<(), String> {
+        let served = &self.served;
+        let outputs = &served.bundle.member_outputs;
+        if replacement.bundle_id != served.bundle.id() {
+            return Err("replacement is not for the bundle currently served".to_string());
+        }
+        let index = outputs
+            .iter()
+            .position(|output| output.share_set_member.alias == replacement.alias)
+            .ok_or_else(|| format!("no member '{}' in the share set", replacement.alias))?;
+
+        let new_member = &replacement.replacement;
+        // Verifiers write member files named after the alias, see `alias_is_file_name`
+        if !alias_is_file_name(&new_member.alias) {
+            return Err(format!(
+                "replacement alias {:?} can not be used as a file name",
+                new_member.alias
+            ));
+        }
+        let new_pub = P256Public::from_bytes(&new_member.pub_key)
+            .map_err(|e| format!("bad replacement pub key: {e:?}"))?;
+        for (i, output) in outputs.iter().enumerate() {
+            if output.share_set_member.pub_key == new_member.pub_key {
+                return Err(format!(
+                    "replacement pub key is already used by member '{}'",
+                    output.share_set_member.alias
+                ));
+            }
+            if i != index && output.share_set_member.alias == new_member.alias {
+                return Err(format!(
+                    "replacement alias '{}' is already used",
+                    new_member.alias
+                ));
+            }
+        }
+
+        let digest = replacement.digest();
+        let mut approvers = HashSet::with_capacity(approvals.len());
+        for approval in approvals {
+            if !outputs
+                .iter()
+                .any(|output| output.share_set_member.pub_key == approval.member_pub_key)
+            {
+                return Err("approval from a key that is not in the share set".to_string());
+            }
+            P256Public::from_bytes(&approval.member_pub_key)
+                .and_then(|member_pub| member_pub.verify(&digest, &approval.signature))
+                .map_err(|e| format!("invalid approval signature: {e:?}"))?;
+            approvers.insert(&approval.member_pub_key);
+        }
+        let threshold = self.new_share_set.threshold as usize;
+        if approvers.len() < threshold {
+            return Err(format!(
+                "replacement approved by {} members, but the threshold is {threshold}",
+                approvers.len()
+            ));
+        }
+
+        let share = &served.shares[index];
+        let encrypted = new_pub.encrypt(share).map_err(|e| {
+            format!(
+                "encryption of share to pub key of replacement member {index} '{}' failed: {e:?}",
+                new_member.alias
+            )
+        })?;
+        let mut bundle = served.bundle.clone();
+        bundle.member_outputs[index] = GenesisMemberOutput {
+            share_set_member: new_member.clone(),
+            encrypted_quorum_key_share: encrypted,
+            share_hash: outputs[index].share_hash,
+        };
+
+        let eph_pair = self.keys.ephemeral_key()?;
+        bundle.signature =
+            sign_member_outputs(&eph_pair, bundle.hash_algorithm, &bundle.member_outputs)?;
+
+        let responses = EncodedResponses::encode(&bundle, served.responses.manifest_hash.clone())?;
+        self.served = ServedBundle {
+            bundle,
+            shares: served.shares.clone(),
+            responses,
+        };
+        self.new_share_set.members[index] = new_member.clone();
+        Ok(())
+    }
+
+    /// Generate a fresh attestation doc binding the manifest envelope of the bundle currently
+    /// served and the ephemeral key, like the bundle's own, and `nonce`.
+    pub fn attest_with_nonce(&self, nonce: Vec<u8>) -> Result<Vec<u8>, String> {
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+            return Err(format!(
+                "nonce must be 1 to {MAX_NONCE_LEN} bytes, got {}",
+                nonce.len()
+            ));
+        }
+        let eph_pair = self.keys.ephemeral_key()?;
+
+        match self.nsm.nsm_process_request(NsmRequest::Attestation {
+            user_data: Some(self.served.responses.manifest_hash.clone()),
+            nonce: Some(nonce),
+            public_key: Some(eph_pair.public_key().to_bytes()),
+        }) {
+            NsmResponse::Attestation { document } => Ok(document),
+            other => Err(format!("unexpected NSM response: {other:?}")),
+        }
+    }
+}
+
+impl ServedBundle {
+    fn compute(
+        handles: &handles::Handles,
+        keys: &dyn KeySource,
+        new_share_set: &ShareSet,
         nsm: &dyn NsmProvider,
     ) -> Result<Self, String> {
         // load keys
-        let quorum_pair = handles
-            .get_quorum_key()
-            .map_err(|e| format!("unable to get quorum key: {e:?}"))?;
-        let eph_pair = handles
-            .get_ephemeral_key()
-            .map_err(|e| format!("unable to get ephemeral key: {e:?}"))?;
+        let quorum_pair = keys.quorum_key()?;
+        let eph_pair = keys.ephemeral_key()?;
 
         let quorum_pub = quorum_pair.public_key().to_bytes();
         let master_seed = quorum_pair.to_master_seed();
@@ -120,17 +500,44 @@ impl ReshardProcessor {
             .get_manifest_envelope()
             .map_err(|_| "get_manifest_envelope failed")?;
 
+        // A misconfigured enclave must not reshard a key other than the one approved
+        let manifest_quorum_key = &manifest_envelope.manifest.namespace.quorum_key;
+        if *manifest_quorum_key != quorum_pub {
+            return Err(format!(
+                "quorum key {} does not match the manifest's quorum key {}",
+                qos_hex::encode(&quorum_pub),
+                qos_hex::encode(manifest_quorum_key)
+            ));
+        }
+
         // Get attestation doc, which ties the running of this specific instance with:
         // 1. the creation of eph key
         // 2. the manifest and approvals
+        let manifest_hash = manifest_envelope.qos_hash().to_vec();
         let attestation_doc = match nsm.nsm_process_request(NsmRequest::Attestation {
-            user_data: Some(manifest_envelope.qos_hash().to_vec()),
+            user_data: Some(manifest_hash.clone()),
             nonce: None,
             public_key: Some(eph_pair.public_key().to_bytes()),
         }) {
             NsmResponse::Attestation { document } => document,
             other => return Err(format!("unexpected NSM response: {other:?}")),
         };
+        // Fail boot rather than ship a bundle nobody can verify
+        if !(MIN_ATTESTATION_DOC_LEN..=MAX_ATTESTATION_DOC_LEN).contains(&attestation_doc.len()) {
+            return Err(format!(
+                "NSM returned a {} byte attestation document, expected {MIN_ATTESTATION_DOC_LEN} \
+                 to {MAX_ATTESTATION_DOC_LEN} bytes",
+                attestation_doc.len()
+            ));
+        }
+
+        // Duplicate member keys would hand multiple shares to a single key holder
+        let mut seen_pub_keys = HashSet::with_capacity(new_share_set.members.len());
+        for member in &new_share_set.members {
+            if !seen_pub_keys.insert(&member.pub_key) {
+                return Err(format!("duplicate pub key for member '{}'", member.alias));
+            }
+        }
 
         // Split the master seed
         let n = new_share_set.members.len();
@@ -138,31 +545,55 @@ impl ReshardProcessor {
         let shares = qos_crypto::shamir::shares_generate(&master_seed[..], n, k)
             .map_err(|e| format!("shares_generate failed: {e:?}"))?;
 
-        // Encrypt per member of the new share set
+        // `zip` would silently drop members if fewer shares came back
+        if shares.len() != n {
+            return Err(format!(
+                "shares_generate returned {} shares for {n} members",
+                shares.len()
+            ));
+        }
+
+        // Encrypt per member of the new share set. Share `i` goes to member `i`, so
+        // `member_outputs` is in the same order as `new_share_set.members`.
+        let hash_algorithm = HashAlgorithm::default();
         let mut member_outputs = Vec::with_capacity(n);
-        for (share, member) in shares.into_iter().zip(new_share_set.members.clone()) {
-            let personal_pub = P256Public::from_bytes(&member.pub_key)
-                .map_err(|e| format!("bad member pubkey for '{}': {e:?}", member.alias))?;
-            let encrypted = personal_pub
-                .encrypt(&share)
-                .map_err(|e| format!("encryption of share to pub key failed: {e:?}"))?;
-            let hash = qos_crypto::sha_512(&share);
+        for (index, member) in new_share_set.members.iter().enumerate() {
+            let share = &shares[index];
+            let personal_pub = P256Public::from_bytes(&member.pub_key).map_err(|e| {
+                format!(
+                    "bad member pubkey for member {index} '{}': {e:?}",
+                    member.alias
+                )
+            })?;
+            // Encrypting to a key that parsed only fails on an internal error of the cipher, so
+            // no member key can trigger this. It is attributed all the same.
+            let encrypted = personal_pub.encrypt(share).map_err(|e| {
+                format!(
+                    "encryption of share to pub key of member {index} '{}' failed: {e:?}",
+                    member.alias
+                )
+            })?;
+            let hash = hash_algorithm.digest(share);
 
             member_outputs.push(GenesisMemberOutput {
-                share_set_member: member,
+                share_set_member: member.clone(),
                 encrypted_quorum_key_share: encrypted,
                 share_hash: hash,
             });
         }
 
-        // borsh serialize the member outputs vector, and sign it with the ephemeral key to tie the running of this specific instance
-        // with the creation of these new encrypted shares
-        let mo_bytes =
-            borsh::to_vec(&member_outputs).map_err(|e| format!("borsh member_outputs: {e}"))?;
-        let digest = sha_512(&mo_bytes);
-        let signature = eph_pair
-            .sign(&digest)
-            .map_err(|e| format!("ephemeral sign failed: {e:?}"))?;
+        // Identical shares indicate a serious bug in share generation
+        let mut seen_share_hashes = HashSet::with_capacity(n);
+        for output in &member_outputs {
+            if !seen_share_hashes.insert(output.share_hash) {
+                return Err(format!(
+                    "duplicate share hash for member '{}'",
+                    output.share_set_member.alias
+                ));
+            }
+        }
+
+        let signature = sign_member_outputs(&eph_pair, hash_algorithm, &member_outputs)?;
 
         // assemble all outputs together
         let reshard_bundle = ReshardBundle {
@@ -171,14 +602,82 @@ impl ReshardProcessor {
             manifest_envelope,
             member_outputs,
             signature,
+            hash_algorithm,
         };
 
+        let responses = EncodedResponses::encode(&reshard_bundle, manifest_hash)?;
         Ok(Self {
-            cached_reshard_bundle: reshard_bundle,
+            bundle: reshard_bundle,
+            shares,
+            responses,
         })
     }
 }
 
+impl EncodedResponses {
+    fn encode(reshard_bundle: &ReshardBundle, manifest_hash: Vec<u8>) -> Result<Self, String> {
+        let attestation = borsh::to_vec(&ReshardResponse::Attestation(
+            reshard_bundle.attestation_doc.clone(),
+        ))
+        .map_err(|e| format!("borsh attestation doc: {e}"))?;
+        let bundle_id = borsh::to_vec(&ReshardResponse::BundleId(reshard_bundle.id()))
+            .map_err(|e| format!("borsh bundle id: {e}"))?;
+        let bundle = borsh::to_vec(&ReshardResponse::Bundle(Box::new(reshard_bundle.clone())))
+            .map_err(|e| format!("borsh reshard bundle: {e}"))?;
+
+        Ok(Self {
+            bundle,
+            attestation,
+            bundle_id,
+            manifest_hash,
+        })
+    }
+}
+
+/// Borsh serialize `member_outputs` and sign them with the ephemeral key, to tie the running of
+/// this specific instance with the creation of these encrypted shares.
+fn sign_member_outputs(
+    eph_pair: &P256Pair,
+    hash_algorithm: HashAlgorithm,
+    member_outputs: &[GenesisMemberOutput],
+) -> Result<Vec<u8>, String> {
+    let digest = member_outputs_digest(hash_algorithm, member_outputs);
+    sign_checked(eph_pair, &eph_pair.public_key(), &digest)
+}
+
+fn member_outputs_digest(
+    hash_algorithm: HashAlgorithm,
+    member_outputs: &[GenesisMemberOutput],
+) -> [u8; 64] {
+    hash_algorithm.digest(&borsh::to_vec(member_outputs).expect("should be valid borsh"))
+}
+
+/// Whether a member `alias` can be used as is as a file name, e.g. `{alias}.secret`: it is not
+/// empty, hidden or a path, so it stays inside the directory it is joined to.
+pub fn alias_is_file_name(alias: &str) -> bool {
+    !alias.is_empty()
+        && !alias.starts_with('.')
+        && !alias.contains(['/', '\\'])
+        && !alias.contains(char::is_control)
+}
+
+/// Sign `digest` with `signer` and verify the signature against `public` before returning it, so
+/// a key handling bug fails the reshard instead of shipping a bundle that fails verification.
+pub fn sign_checked(
+    signer: &P256Pair,
+    public: &P256Public,
+    digest: &[u8],
+) -> Result<Vec<u8>, String> {
+    let signature = signer
+        .sign(digest)
+        .map_err(|e| format!("ephemeral sign failed: {e:?}"))?;
+    public
+        .verify(digest, &signature)
+        .map_err(|e| format!("ephemeral signature self-check failed: {e:?}"))?;
+
+    Ok(signature)
+}
+
 impl RequestProcessor for ReshardProcessor {
     fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
         let req: ReshardRequest = match from_slice(&request) {
@@ -186,14 +685,43 @@ impl RequestProcessor for ReshardProcessor {
             Err(_) => return ReshardResponse::error(),
         };
 
-        let output = match req {
-            ReshardRequest::HealthRequest => ReshardResponse::Health,
+        match req {
+            ReshardRequest::HealthRequest => {
+                borsh::to_vec(&ReshardResponse::Health).expect("should be valid borsh")
+            }
+
+            ReshardRequest::RetrieveBundle => self.served.responses.bundle.clone(),
+
+            ReshardRequest::RetrieveAttestation => self.served.responses.attestation.clone(),
 
-            ReshardRequest::RetrieveBundle => {
-                ReshardResponse::Bundle(Box::new(self.cached_reshard_bundle.clone()))
+            ReshardRequest::RetrieveBundleId => self.served.responses.bundle_id.clone(),
+
+            ReshardRequest::AttestWithNonce(nonce) => {
+                let response = match self.attest_with_nonce(nonce) {
+                    Ok(document) => ReshardResponse::Attestation(document),
+                    Err(e) => ReshardResponse::AttestationFailed(e),
+                };
+                borsh::to_vec(&response).expect("should be valid borsh")
             }
-        };
 
-        borsh::to_vec(&output).expect("should be valid borsh")
+            ReshardRequest::Recompute => {
+                let response = match self.recompute() {
+                    Ok(()) => ReshardResponse::Recomputed,
+                    Err(e) => ReshardResponse::RecomputeFailed(e),
+                };
+                borsh::to_vec(&response).expect("should be valid borsh")
+            }
+
+            ReshardRequest::ReplaceMember {
+                replacement,
+                approvals,
+            } => {
+                let response = match self.replace_member(&replacement, &approvals) {
+                    Ok(()) => ReshardResponse::MemberReplaced,
+                    Err(e) => ReshardResponse::ReplaceMemberFailed(e),
+                };
+                borsh::to_vec(&response).expect("should be valid borsh")
+            }
+        }
     }
 }
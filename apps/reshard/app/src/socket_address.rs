@@ -0,0 +1,61 @@
+//! Enclave socket addresses from command line options, validated the same way by the reshard
+//! app and host CLIs.
+
+use qos_core::io::SocketAddress;
+
+/// Longest unix socket path, leaving room for the nul terminator in `sockaddr_un.sun_path`.
+pub const MAX_UNIX_SOCKET_PATH_LEN: usize = 107;
+
+/// The socket address given by the `--cid`, `--port`, `--usock` and `--vsock-to-host` options
+/// of a CLI.
+///
+/// Exactly one of a unix socket `usock`, or a vsock `cid` and `port` pair must be given.
+/// `vsock_to_host` sets `VMADDR_FLAG_TO_HOST` on a vsock address, for a host in a nitro
+/// enclave reaching its parent. Vsock addresses require the `vsock` feature.
+pub fn socket_address_from_args(
+    cid: Option<u32>,
+    port: Option<u32>,
+    usock: Option<&str>,
+    vsock_to_host: bool,
+) -> Result<SocketAddress, String> {
+    match (cid, port, usock) {
+        (None, None, Some(usock)) => {
+            if vsock_to_host {
+                return Err("--vsock-to-host only applies to vsock addresses".to_string());
+            }
+            if usock.is_empty() {
+                return Err("--usock must not be empty".to_string());
+            }
+            if usock.len() > MAX_UNIX_SOCKET_PATH_LEN {
+                return Err(format!(
+                    "--usock {usock} is {} bytes, longer than the limit of \
+                     {MAX_UNIX_SOCKET_PATH_LEN} bytes",
+                    usock.len()
+                ));
+            }
+            Ok(SocketAddress::new_unix(usock))
+        }
+        (Some(cid), Some(port), None) => vsock_address(cid, port, vsock_to_host),
+        (Some(_), None, None) => Err("--cid requires --port".to_string()),
+        (None, Some(_), None) => Err("--port requires --cid".to_string()),
+        (None, None, None) => Err("either --usock or --cid and --port are required".to_string()),
+        (_, _, Some(_)) => Err("--usock can not be combined with --cid or --port".to_string()),
+    }
+}
+
+#[cfg(feature = "vsock")]
+fn vsock_address(cid: u32, port: u32, vsock_to_host: bool) -> Result<SocketAddress, String> {
+    let flags = if vsock_to_host {
+        tracing::info!("Configuring vsock with VMADDR_FLAG_TO_HOST.");
+        qos_core::io::VMADDR_FLAG_TO_HOST
+    } else {
+        tracing::info!("Configuring vsock with VMADDR_NO_FLAGS.");
+        qos_core::io::VMADDR_NO_FLAGS
+    };
+    Ok(SocketAddress::new_vsock(cid, port, flags))
+}
+
+#[cfg(not(feature = "vsock"))]
+fn vsock_address(_cid: u32, _port: u32, _vsock_to_host: bool) -> Result<SocketAddress, String> {
+    Err("vsock addresses require the vsock feature".to_string())
+}
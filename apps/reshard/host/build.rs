@@ -0,0 +1,14 @@
+//! Fails the build if the host links crates production images must not contain. The offline
+//! tools tell their dependents they are linked through their `links` metadata.
+
+fn main() {
+    println!("cargo::rerun-if-changed=build.rs");
+    let linked = |tool: &str| std::env::var_os(format!("DEP_{tool}_LINKED")).is_some();
+
+    if linked("RESHARD_VERIFY") && std::env::var_os("CARGO_FEATURE_VERIFY").is_none() {
+        panic!("reshard_verify is linked without the verify feature");
+    }
+    if linked("RESHARD_PROVISION") {
+        panic!("reshard_provision must never be linked into the host");
+    }
+}
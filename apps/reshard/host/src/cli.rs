@@ -3,10 +3,15 @@ use crate::ReshardHostConfig;
 
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
     str::FromStr,
+    time::Duration,
 };
 
+use host_primitives::{CircuitBreaker, Keepalive, ENCLAVE_QUEUE_CAPACITY, GRPC_MAX_RECV_MSG_SIZE};
+use logging::LogFormat;
 use qos_core::io::SocketAddress;
+use reshard_app::socket_address::socket_address_from_args;
 
 use clap::Parser;
 
@@ -30,6 +35,87 @@ struct Args {
 
     #[arg(long)]
     vsock_to_host: bool,
+
+    /// Maximum size in bytes of gRPC messages the host will decode or encode, and of the app
+    /// requests and responses it exchanges with the enclave.
+    #[arg(long, default_value_t = GRPC_MAX_RECV_MSG_SIZE)]
+    max_message_size: usize,
+
+    /// Do not serve the gRPC reflection service. Recommended for production.
+    #[arg(long)]
+    disable_reflection: bool,
+
+    /// Do not gzip compress responses, even for clients that accept it.
+    #[arg(long)]
+    disable_compression: bool,
+
+    /// Save a JSON copy of the bundle to this path the first time it is retrieved, and again
+    /// whenever the bundle served changes, e.g. after a recompute.
+    #[arg(long)]
+    bundle_archive_path: Option<PathBuf>,
+
+    /// Seconds between HTTP/2 keepalive pings on idle connections. 0 disables pings.
+    #[arg(long, default_value_t = default_keepalive_secs(Keepalive::default().http2_interval))]
+    http2_keepalive_interval_secs: u64,
+
+    /// Seconds to wait for a HTTP/2 keepalive ping to be acknowledged.
+    #[arg(long, default_value_t = default_keepalive_secs(Keepalive::default().http2_timeout))]
+    http2_keepalive_timeout_secs: u64,
+
+    /// TCP keepalive time in seconds for client connections. 0 disables TCP keepalive.
+    #[arg(long, default_value_t = default_keepalive_secs(Keepalive::default().tcp))]
+    tcp_keepalive_secs: u64,
+
+    /// Number of requests that can wait for the enclave. Once full, further requests wait for
+    /// room before being queued.
+    #[arg(long, default_value_t = ENCLAVE_QUEUE_CAPACITY, value_parser = parse_at_least_one)]
+    enclave_queue_capacity: usize,
+
+    /// Requests in progress to the enclave at once. Further requests wait before being queued.
+    /// Unlimited by default.
+    #[arg(long, value_parser = parse_at_least_one)]
+    concurrency_limit: Option<usize>,
+
+    /// Seconds a call waits on the enclave at most, even if the client set no deadline. Calls
+    /// always stop waiting shortly before the client's deadline. Unlimited by default.
+    #[arg(long, value_parser = parse_at_least_one)]
+    enclave_timeout_secs: Option<usize>,
+
+    /// Consecutive enclave failures after which calls fail fast with `unavailable` for
+    /// `--circuit-breaker-cooldown-secs`, rather than each waiting out the socket timeout.
+    /// Calls always wait on the enclave by default.
+    #[arg(long, value_parser = parse_at_least_one)]
+    circuit_breaker_failures: Option<usize>,
+
+    /// Seconds calls fail fast once the circuit breaker trips, before a call probes the
+    /// enclave again.
+    #[arg(long, default_value_t = 30, requires = "circuit_breaker_failures")]
+    circuit_breaker_cooldown_secs: u64,
+
+    /// JSON file of settings to apply on SIGHUP without a restart, any of `logLevel`,
+    /// `probeIntervalSecs`, `concurrencyLimit` and `outOfRotation`.
+    #[arg(long)]
+    runtime_config: Option<PathBuf>,
+
+    /// Number of worker threads of the async runtime. Defaults to one per CPU core.
+    #[arg(long, value_parser = parse_at_least_one)]
+    worker_threads: Option<usize>,
+
+    /// Format of log lines, `json` or `pretty`.
+    #[arg(long, default_value_t = LogFormat::default())]
+    log_format: LogFormat,
+}
+
+fn parse_at_least_one(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn default_keepalive_secs(duration: Option<Duration>) -> u64 {
+    duration.map_or(0, |d| d.as_secs())
 }
 
 impl Args {
@@ -43,40 +129,78 @@ impl Args {
     ///
     /// # Panics
     ///
-    /// Panics if the options are not valid for exactly one of unix or vsock.
+    /// Panics if the options are not valid for exactly one of unix or vsock, see
+    /// [`socket_address_from_args`].
     fn enclave_addr(&self) -> SocketAddress {
-        match (self.cid, self.port, &self.usock) {
-            #[cfg(feature = "vsock")]
-            (Some(c), Some(p), None) => SocketAddress::new_vsock(c, p, self.vsock_to_host_flag()),
-            (None, None, Some(u)) => SocketAddress::new_unix(u),
-            _ => panic!("Invalid socket options"),
-        }
+        socket_address_from_args(
+            self.cid,
+            self.port,
+            self.usock.as_deref(),
+            self.vsock_to_host,
+        )
+        .unwrap_or_else(|e| panic!("invalid enclave socket options: {e}"))
     }
 
-    #[cfg(feature = "vsock")]
-    fn vsock_to_host_flag(&self) -> u8 {
-        if self.vsock_to_host {
-            println!("Configuring vsock with VMADDR_FLAG_TO_HOST.");
-            qos_core::io::VMADDR_FLAG_TO_HOST
-        } else {
-            println!("Configuring vsock with VMADDR_NO_FLAGS.");
-            qos_core::io::VMADDR_NO_FLAGS
+    /// Circuit breaker for the enclave, if enabled with `--circuit-breaker-failures`.
+    fn circuit_breaker(&self) -> Option<CircuitBreaker> {
+        self.circuit_breaker_failures.map(|failures| {
+            CircuitBreaker::new(
+                u32::try_from(failures).unwrap_or(u32::MAX),
+                Duration::from_secs(self.circuit_breaker_cooldown_secs),
+            )
+        })
+    }
+
+    /// Keepalive settings, treating 0 seconds as disabled.
+    fn keepalive(&self) -> Keepalive {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+        Keepalive {
+            http2_interval: secs(self.http2_keepalive_interval_secs),
+            http2_timeout: secs(self.http2_keepalive_timeout_secs),
+            tcp: secs(self.tcp_keepalive_secs),
         }
     }
 }
 
+/// Multi-threaded runtime the host is served on, with `worker_threads` workers or one per CPU
+/// core if `None`.
+pub fn runtime(worker_threads: Option<usize>) -> std::io::Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    builder.enable_all().build()
+}
+
 /// Host server command line interface.
 pub struct CLI;
 impl CLI {
     /// Execute the command line interface.
-    pub async fn execute() {
+    pub fn execute() {
         let args = Args::parse();
+        let log_level = logging::init(args.log_format);
 
-        run(ReshardHostConfig {
-            listen_addr: args.host_addr(),
-            enclave_addr: args.enclave_addr(),
-        })
-        .await
-        .unwrap();
+        let circuit_breaker = args.circuit_breaker();
+
+        let runtime = runtime(args.worker_threads).expect("failed to build the async runtime");
+        runtime
+            .block_on(run(ReshardHostConfig {
+                listen_addr: args.host_addr(),
+                enclave_addr: args.enclave_addr(),
+                max_message_size: args.max_message_size,
+                enable_reflection: !args.disable_reflection,
+                keepalive: args.keepalive(),
+                enclave_queue_capacity: args.enclave_queue_capacity,
+                compression: !args.disable_compression,
+                bundle_archive_path: args.bundle_archive_path,
+                concurrency_limit: args.concurrency_limit,
+                runtime_config_path: args.runtime_config,
+                log_level: Some(log_level),
+                enclave_timeout: args
+                    .enclave_timeout_secs
+                    .map(|secs| Duration::from_secs(secs as u64)),
+                circuit_breaker,
+            }))
+            .unwrap();
     }
 }
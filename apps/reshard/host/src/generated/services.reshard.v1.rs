@@ -1,10 +1,180 @@
 // This file is @generated by prost-build.
 #[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
-pub struct RetrieveReshardRequest {}
+pub struct RetrieveReshardRequest {
+    #[prost(enumeration = "BundleFormat", tag = "1")]
+    pub format: i32,
+}
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct RetrieveReshardResponse {
+    /// Set for `BUNDLE_FORMAT_JSON`
     #[prost(string, tag = "1")]
     pub reshard_bundle: ::prost::alloc::string::String,
+    /// Set for `BUNDLE_FORMAT_BORSH`
+    #[prost(bytes = "vec", tag = "2")]
+    pub reshard_bundle_borsh: ::prost::alloc::vec::Vec<u8>,
+    /// sha256 of the borsh encoded bundle, regardless of the format requested
+    #[prost(bytes = "vec", tag = "3")]
+    pub bundle_id: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct RetrieveReshardStreamRequest {
+    /// Byte offset into the borsh encoded bundle to start from, 0 for the whole bundle
+    #[prost(uint64, tag = "1")]
+    pub offset: u64,
+    /// Id of the bundle being resumed. If set and the bundle has been recomputed since, the stream
+    /// fails with `ABORTED` instead of mixing the bytes of two bundles
+    #[prost(bytes = "vec", tag = "2")]
+    pub bundle_id: ::prost::alloc::vec::Vec<u8>,
+    /// Most bytes per chunk, 0 for the default of 64 KiB. Larger values are capped to the default
+    #[prost(uint32, tag = "3")]
+    pub max_chunk_size: u32,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct RetrieveReshardChunk {
+    /// Bytes of the borsh encoded bundle starting at `offset`
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub offset: u64,
+    /// Length of the borsh encoded bundle
+    #[prost(uint64, tag = "3")]
+    pub total_size: u64,
+    /// Same as `bundle_id` of a `RetrieveReshardResponse`
+    #[prost(bytes = "vec", tag = "4")]
+    pub bundle_id: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct RetrieveAttestationRequest {
+    /// If set, a fresh attestation doc bound to this nonce (at most 512 bytes) is generated
+    /// instead of returning the bundle's, so verifiers can check it was made recently
+    #[prost(bytes = "vec", tag = "1")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct RetrieveAttestationResponse {
+    /// Raw AWS Nitro attestation document. Identical to the bundle's `attestationDoc` unless a
+    /// nonce was requested
+    #[prost(bytes = "vec", tag = "1")]
+    pub attestation_doc: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetBundleIdRequest {}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetBundleIdResponse {
+    /// Same as `bundle_id` of a `RetrieveReshardResponse` for the bundle currently served
+    #[prost(bytes = "vec", tag = "1")]
+    pub bundle_id: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetLiveAttestationRequest {}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetLiveAttestationResponse {
+    /// Raw AWS Nitro attestation document generated by QOS for this request
+    #[prost(bytes = "vec", tag = "1")]
+    pub attestation_doc: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct VerifyBundleRequest {
+    /// Raw encoded P256 public key the bundle must be signed with. If unset, the key in the
+    /// bundle's attestation doc is used once the doc's certificate chain verifies and it attests
+    /// to the bundle's manifest, which adds the `attestation_doc` and `manifest_hash` checks
+    #[prost(bytes = "vec", tag = "1")]
+    pub ephemeral_public_key: ::prost::alloc::vec::Vec<u8>,
+    /// Raw encoded P256 public key expected as the bundle's quorum key. Not checked if unset
+    #[prost(bytes = "vec", tag = "2")]
+    pub quorum_public_key: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VerifyBundleResponse {
+    /// True when every check passed
+    #[prost(bool, tag = "1")]
+    pub passed: bool,
+    #[prost(message, repeated, tag = "2")]
+    pub checks: ::prost::alloc::vec::Vec<BundleCheck>,
+    /// Id of the bundle that was checked, see `GetBundleIdResponse`
+    #[prost(bytes = "vec", tag = "3")]
+    pub bundle_id: ::prost::alloc::vec::Vec<u8>,
+}
+/// Outcome of a single check, as reported by the offline verify tool. `expected` and
+/// `computed` are hex where they are digests or keys
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct BundleCheck {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(bool, tag = "2")]
+    pub passed: bool,
+    #[prost(string, tag = "3")]
+    pub expected: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub computed: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct RawRequestRequest {
+    /// Borsh encoded `ReshardRequest`
+    #[prost(bytes = "vec", tag = "1")]
+    pub request: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct RawRequestResponse {
+    /// Borsh encoded `ReshardResponse`, whatever the variant
+    #[prost(bytes = "vec", tag = "1")]
+    pub response: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetQueueUtilizationRequest {}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetQueueUtilizationResponse {
+    /// Every enclave queue of the host, in the order they were created
+    #[prost(message, repeated, tag = "1")]
+    pub queues: ::prost::alloc::vec::Vec<QueueUtilization>,
+    /// Requests given up on by their caller before they were sent to the enclave, since the host
+    /// started
+    #[prost(uint64, tag = "2")]
+    pub abandoned_requests: u64,
+    /// Enclave responses dropped because their caller was gone, since the host started
+    #[prost(uint64, tag = "3")]
+    pub dropped_responses: u64,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct QueueUtilization {
+    /// Name of the queue, e.g. `enclave` for app requests and `health` for health probes
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// Requests waiting to be sent to the enclave
+    #[prost(uint64, tag = "2")]
+    pub queued: u64,
+    /// Requests the queue holds before callers wait for room
+    #[prost(uint64, tag = "3")]
+    pub capacity: u64,
+}
+/// Encoding of the reshard bundle in a `RetrieveReshardResponse`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum BundleFormat {
+    /// JSON in `reshard_bundle`
+    Json = 0,
+    /// Borsh in `reshard_bundle_borsh`
+    Borsh = 1,
+}
+impl BundleFormat {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Json => "BUNDLE_FORMAT_JSON",
+            Self::Borsh => "BUNDLE_FORMAT_BORSH",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "BUNDLE_FORMAT_JSON" => Some(Self::Json),
+            "BUNDLE_FORMAT_BORSH" => Some(Self::Borsh),
+            _ => None,
+        }
+    }
 }
 /// Generated client implementations.
 pub mod reshard_service_client {
@@ -128,6 +298,210 @@ pub mod reshard_service_client {
                 );
             self.inner.unary(req, path, codec).await
         }
+        /// Stream the borsh encoded reshard bundle in chunks, starting from a byte offset so an
+        /// interrupted download can be resumed
+        pub async fn retrieve_reshard_stream(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RetrieveReshardStreamRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::RetrieveReshardChunk>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/services.reshard.v1.ReshardService/RetrieveReshardStream",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "services.reshard.v1.ReshardService",
+                        "RetrieveReshardStream",
+                    ),
+                );
+            self.inner.server_streaming(req, path, codec).await
+        }
+        /// Retrieve only the attestation doc of the reshard bundle, or a fresh one bound to a nonce
+        pub async fn retrieve_attestation(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RetrieveAttestationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RetrieveAttestationResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/services.reshard.v1.ReshardService/RetrieveAttestation",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "services.reshard.v1.ReshardService",
+                        "RetrieveAttestation",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// Retrieve only the id of the reshard bundle, to poll for a recomputed bundle
+        pub async fn get_bundle_id(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetBundleIdRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetBundleIdResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/services.reshard.v1.ReshardService/GetBundleId",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("services.reshard.v1.ReshardService", "GetBundleId"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// Retrieve a fresh attestation doc from the enclave, rather than the one in the bundle
+        pub async fn get_live_attestation(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetLiveAttestationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetLiveAttestationResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/services.reshard.v1.ReshardService/GetLiveAttestation",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "services.reshard.v1.ReshardService",
+                        "GetLiveAttestation",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// Run the checks of the offline verify tool against the bundle currently served. `UNIMPLEMENTED`
+        /// on hosts built without the `verify` feature
+        pub async fn verify_bundle(
+            &mut self,
+            request: impl tonic::IntoRequest<super::VerifyBundleRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::VerifyBundleResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/services.reshard.v1.ReshardService/VerifyBundle",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("services.reshard.v1.ReshardService", "VerifyBundle"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// Forward a borsh encoded app request to the enclave and return the borsh encoded app
+        /// response, for debugging. `UNIMPLEMENTED` on hosts built without the `dev-rpc` feature
+        pub async fn raw_request(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RawRequestRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RawRequestResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/services.reshard.v1.ReshardService/RawRequest",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("services.reshard.v1.ReshardService", "RawRequest"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// Report how full the host's enclave queues are, and the requests they dropped, for monitoring
+        pub async fn get_queue_utilization(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetQueueUtilizationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetQueueUtilizationResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/services.reshard.v1.ReshardService/GetQueueUtilization",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "services.reshard.v1.ReshardService",
+                        "GetQueueUtilization",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -151,6 +525,71 @@ pub mod reshard_service_server {
             tonic::Response<super::RetrieveReshardResponse>,
             tonic::Status,
         >;
+        /// Server streaming response type for the RetrieveReshardStream method.
+        type RetrieveReshardStreamStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::RetrieveReshardChunk, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        /// Stream the borsh encoded reshard bundle in chunks, starting from a byte offset so an
+        /// interrupted download can be resumed
+        async fn retrieve_reshard_stream(
+            &self,
+            request: tonic::Request<super::RetrieveReshardStreamRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::RetrieveReshardStreamStream>,
+            tonic::Status,
+        >;
+        /// Retrieve only the attestation doc of the reshard bundle, or a fresh one bound to a nonce
+        async fn retrieve_attestation(
+            &self,
+            request: tonic::Request<super::RetrieveAttestationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RetrieveAttestationResponse>,
+            tonic::Status,
+        >;
+        /// Retrieve only the id of the reshard bundle, to poll for a recomputed bundle
+        async fn get_bundle_id(
+            &self,
+            request: tonic::Request<super::GetBundleIdRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetBundleIdResponse>,
+            tonic::Status,
+        >;
+        /// Retrieve a fresh attestation doc from the enclave, rather than the one in the bundle
+        async fn get_live_attestation(
+            &self,
+            request: tonic::Request<super::GetLiveAttestationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetLiveAttestationResponse>,
+            tonic::Status,
+        >;
+        /// Run the checks of the offline verify tool against the bundle currently served. `UNIMPLEMENTED`
+        /// on hosts built without the `verify` feature
+        async fn verify_bundle(
+            &self,
+            request: tonic::Request<super::VerifyBundleRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::VerifyBundleResponse>,
+            tonic::Status,
+        >;
+        /// Forward a borsh encoded app request to the enclave and return the borsh encoded app
+        /// response, for debugging. `UNIMPLEMENTED` on hosts built without the `dev-rpc` feature
+        async fn raw_request(
+            &self,
+            request: tonic::Request<super::RawRequestRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::RawRequestResponse>,
+            tonic::Status,
+        >;
+        /// Report how full the host's enclave queues are, and the requests they dropped, for monitoring
+        async fn get_queue_utilization(
+            &self,
+            request: tonic::Request<super::GetQueueUtilizationRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetQueueUtilizationResponse>,
+            tonic::Status,
+        >;
     }
     /// ---------- Public gRPC surface exposed by the host ----------
     #[derive(Debug)]
@@ -275,6 +714,333 @@ pub mod reshard_service_server {
                     };
                     Box::pin(fut)
                 }
+                "/services.reshard.v1.ReshardService/RetrieveReshardStream" => {
+                    #[allow(non_camel_case_types)]
+                    struct RetrieveReshardStreamSvc<T: ReshardService>(pub Arc<T>);
+                    impl<
+                        T: ReshardService,
+                    > tonic::server::ServerStreamingService<
+                        super::RetrieveReshardStreamRequest,
+                    > for RetrieveReshardStreamSvc<T> {
+                        type Response = super::RetrieveReshardChunk;
+                        type ResponseStream = T::RetrieveReshardStreamStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RetrieveReshardStreamRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ReshardService>::retrieve_reshard_stream(
+                                        &inner,
+                                        request,
+                                    )
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RetrieveReshardStreamSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/services.reshard.v1.ReshardService/RetrieveAttestation" => {
+                    #[allow(non_camel_case_types)]
+                    struct RetrieveAttestationSvc<T: ReshardService>(pub Arc<T>);
+                    impl<
+                        T: ReshardService,
+                    > tonic::server::UnaryService<super::RetrieveAttestationRequest>
+                    for RetrieveAttestationSvc<T> {
+                        type Response = super::RetrieveAttestationResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RetrieveAttestationRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ReshardService>::retrieve_attestation(&inner, request)
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RetrieveAttestationSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/services.reshard.v1.ReshardService/GetBundleId" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetBundleIdSvc<T: ReshardService>(pub Arc<T>);
+                    impl<
+                        T: ReshardService,
+                    > tonic::server::UnaryService<super::GetBundleIdRequest>
+                    for GetBundleIdSvc<T> {
+                        type Response = super::GetBundleIdResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetBundleIdRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ReshardService>::get_bundle_id(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetBundleIdSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/services.reshard.v1.ReshardService/GetLiveAttestation" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetLiveAttestationSvc<T: ReshardService>(pub Arc<T>);
+                    impl<
+                        T: ReshardService,
+                    > tonic::server::UnaryService<super::GetLiveAttestationRequest>
+                    for GetLiveAttestationSvc<T> {
+                        type Response = super::GetLiveAttestationResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetLiveAttestationRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ReshardService>::get_live_attestation(&inner, request)
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetLiveAttestationSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/services.reshard.v1.ReshardService/VerifyBundle" => {
+                    #[allow(non_camel_case_types)]
+                    struct VerifyBundleSvc<T: ReshardService>(pub Arc<T>);
+                    impl<
+                        T: ReshardService,
+                    > tonic::server::UnaryService<super::VerifyBundleRequest>
+                    for VerifyBundleSvc<T> {
+                        type Response = super::VerifyBundleResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::VerifyBundleRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ReshardService>::verify_bundle(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = VerifyBundleSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/services.reshard.v1.ReshardService/RawRequest" => {
+                    #[allow(non_camel_case_types)]
+                    struct RawRequestSvc<T: ReshardService>(pub Arc<T>);
+                    impl<
+                        T: ReshardService,
+                    > tonic::server::UnaryService<super::RawRequestRequest>
+                    for RawRequestSvc<T> {
+                        type Response = super::RawRequestResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RawRequestRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ReshardService>::raw_request(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = RawRequestSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/services.reshard.v1.ReshardService/GetQueueUtilization" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetQueueUtilizationSvc<T: ReshardService>(pub Arc<T>);
+                    impl<
+                        T: ReshardService,
+                    > tonic::server::UnaryService<super::GetQueueUtilizationRequest>
+                    for GetQueueUtilizationSvc<T> {
+                        type Response = super::GetQueueUtilizationResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetQueueUtilizationRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ReshardService>::get_queue_utilization(
+                                        &inner,
+                                        request,
+                                    )
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetQueueUtilizationSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         let mut response = http::Response::new(
@@ -315,3 +1081,5 @@ pub mod reshard_service_server {
         const NAME: &'static str = SERVICE_NAME;
     }
 }
+/// Hex encoded sha256 of the `descriptor.bin` generated alongside this module.
+pub const FILE_DESCRIPTOR_SET_SHA256: &str = "168d53423f0ee34933ac788df583104444a1229172c6dcf3f1ce71f034472fe2";
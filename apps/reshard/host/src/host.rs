@@ -1,58 +1,113 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
 
+#[cfg(feature = "verify")]
+use crate::generated::reshard::BundleCheck;
 use crate::generated::{
     reshard::reshard_service_server::{ReshardService, ReshardServiceServer},
-    reshard::{RetrieveReshardRequest, RetrieveReshardResponse},
-    FILE_DESCRIPTOR_SET,
+    reshard::{
+        BundleFormat, GetBundleIdRequest, GetBundleIdResponse, GetLiveAttestationRequest,
+        GetLiveAttestationResponse, GetQueueUtilizationRequest, GetQueueUtilizationResponse,
+        QueueUtilization, RawRequestRequest, RawRequestResponse, RetrieveAttestationRequest,
+        RetrieveAttestationResponse, RetrieveReshardChunk, RetrieveReshardRequest,
+        RetrieveReshardResponse, RetrieveReshardStreamRequest, VerifyBundleRequest,
+        VerifyBundleResponse,
+    },
+    verify_descriptor, FILE_DESCRIPTOR_SET,
+};
+use crate::ReshardHostConfig;
+use health_check::{AppHealthCheckable, AppHealthResponse};
+use host_primitives::{
+    enclave_deadline, queue_metrics, AppHostBuilder, BorshCodec, EnclaveClient, RequestId,
+};
+use http_body_util::BodyExt;
+#[cfg(feature = "verify")]
+use qos_p256::P256Public;
+use reshard_app::service::{ReshardBundle, ReshardRequest, ReshardResponse, MAX_NONCE_LEN};
+#[cfg(feature = "verify")]
+use reshard_verify::EphemeralKey;
+use tokio::time::Instant;
+use tonic::{
+    body::Body,
+    codec::CompressionEncoding,
+    codegen::{http, BoxFuture, Context, Poll, Service},
+    metadata::{MetadataKey, MetadataMap, MetadataValue},
+    server::NamedService,
+    Code, Status,
 };
-use health_check::{spawn_k8s_health_checker, AppHealthCheckable, AppHealthResponse};
-use host_primitives::{spawn_queue_consumer, wait_for_sigterm, BorshCodec};
-use host_primitives::{EnclaveClient, GRPC_MAX_RECV_MSG_SIZE};
-use qos_core::io::SocketAddress;
-use reshard_app::service::{ReshardRequest, ReshardResponse};
-use tokio::sync::{mpsc, oneshot};
-use tonic::Status;
 
-type EnclaveQueueMsg = host_primitives::EnclaveQueueMsg<ReshardRequest, ReshardResponse>;
+/// Default and largest number of bundle bytes in a [`RetrieveReshardChunk`].
+pub const MAX_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Prefix of the gRPC trailers the app's [`ReshardResponse::WithMetadata`] is passed on under,
+/// e.g. `x-reshard-warning` for the key `warning`.
+pub const APP_METADATA_PREFIX: &str = "x-reshard-";
+
+/// Metadata attached by the app to a response, see [`ReshardResponse::into_parts`].
+type AppMetadata = BTreeMap<String, String>;
 
 /// Start the host server.
 pub async fn listen(
-    listen_addr: std::net::SocketAddr,
-    enclave_addr: SocketAddress,
+    ReshardHostConfig {
+        listen_addr,
+        enclave_addr,
+        max_message_size,
+        enable_reflection,
+        bundle_archive_path,
+        keepalive,
+        enclave_queue_capacity,
+        compression,
+        concurrency_limit,
+        runtime_config_path,
+        log_level,
+        enclave_timeout,
+        circuit_breaker,
+    }: ReshardHostConfig,
 ) -> Result<(), tonic::transport::Error> {
-    let reflection_service = tonic_reflection::server::Builder::configure()
-        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
-        .build_v1()
-        .expect("failed to start reflection service");
-
-    let (queue_tx, queue_rx) =
-        mpsc::channel::<Box<EnclaveQueueMsg>>(host_primitives::ENCLAVE_QUEUE_CAPACITY);
-    let enclave = Arc::new(EnclaveClient::new(queue_tx));
+    verify_descriptor(FILE_DESCRIPTOR_SET).unwrap_or_else(|e| panic!("{e}"));
+    let archive = bundle_archive_path.map(|path| Arc::new(BundleArchive::new(path)));
 
-    let app_checker = Health {
-        enclave: enclave.clone(),
-    };
-    let health_service = spawn_k8s_health_checker(Arc::new(app_checker)).await;
+    let mut builder = AppHostBuilder::<BorshCodec, ReshardRequest, ReshardResponse>::new(
+        listen_addr,
+        enclave_addr,
+    )
+    .keepalive(keepalive)
+    .max_request_size(max_message_size)
+    .max_response_size(max_message_size)
+    .queue_capacity(enclave_queue_capacity);
+    if enable_reflection {
+        builder = builder
+            .reflection(FILE_DESCRIPTOR_SET)
+            .reflection(health_check::pb::FILE_DESCRIPTOR_SET);
+    }
+    if let Some(concurrency_limit) = concurrency_limit {
+        builder = builder.concurrency_limit(concurrency_limit);
+    }
+    if let Some(circuit_breaker) = circuit_breaker {
+        builder = builder.circuit_breaker(circuit_breaker);
+    }
+    if let Some(path) = runtime_config_path {
+        builder = builder.runtime_config(path);
+    }
+    if let Some(log_level) = log_level {
+        builder = builder.log_level(log_level);
+    }
 
-    let host: Host = Host {
-        enclave: enclave.clone(),
-    };
-    spawn_queue_consumer::<BorshCodec, _, _>(enclave_addr, queue_rx);
-
-    println!("HostServer listening on {listen_addr}");
-
-    let (sigterm_sender, sigterm_receiver) = oneshot::channel();
-    tokio::task::spawn(wait_for_sigterm(sigterm_sender));
-
-    tonic::transport::Server::builder()
-        .add_service(reflection_service)
-        .add_service(health_service)
-        .add_service(
-            ReshardServiceServer::new(host).max_decoding_message_size(GRPC_MAX_RECV_MSG_SIZE),
-        )
-        .serve_with_shutdown(listen_addr, async {
-            sigterm_receiver.await.ok();
-            println!("SIGTERM received");
+    builder
+        .serve(Health::new, |enclave, router| {
+            router.add_service(
+                Host {
+                    enclave,
+                    archive,
+                    bundle_cache: tokio::sync::Mutex::default(),
+                    enclave_timeout,
+                }
+                .into_server(max_message_size, compression),
+            )
         })
         .await
 }
@@ -62,39 +117,674 @@ pub async fn listen(
 pub struct Host {
     /// Sender for enclave queue. Enclave queue is for messages waiting to be sent to the enclave.
     enclave: Arc<EnclaveClient<BorshCodec, ReshardRequest, ReshardResponse>>,
+    /// Where to save the bundle retrieved, if anywhere.
+    archive: Option<Arc<BundleArchive>>,
+    /// Last bundle retrieved from the enclave, shared by concurrent retrievals. Locked while a
+    /// retrieval refreshes it so only one copy of a new bundle is fetched.
+    bundle_cache: tokio::sync::Mutex<Option<CachedBundle>>,
+    /// Longest wait on the enclave for a call, on top of the client's deadline.
+    enclave_timeout: Option<Duration>,
+}
+
+/// A bundle retrieved from the enclave, with the id it was served under.
+#[derive(Debug, Clone)]
+struct CachedBundle {
+    id: Vec<u8>,
+    bundle: Arc<ReshardBundle>,
+    /// Borsh encoding of `bundle`, kept so streamed downloads resume on the same bytes.
+    encoded: Arc<[u8]>,
+}
+
+impl CachedBundle {
+    fn new(bundle: ReshardBundle) -> Result<Self, Status> {
+        let encoded: Arc<[u8]> = borsh::to_vec(&bundle)
+            .map_err(|_| Status::internal("failed to borsh encode bundle"))?
+            .into();
+        Ok(Self {
+            id: qos_crypto::sha_256(&encoded).to_vec(),
+            bundle: Arc::new(bundle),
+            encoded,
+        })
+    }
+}
+
+impl Host {
+    /// Create the host service sending requests to `enclave`, without archiving bundles.
+    pub fn new(enclave: Arc<EnclaveClient<BorshCodec, ReshardRequest, ReshardResponse>>) -> Self {
+        Self {
+            enclave,
+            archive: None,
+            bundle_cache: tokio::sync::Mutex::default(),
+            enclave_timeout: None,
+        }
+    }
+
+    /// Stop waiting on the enclave after `enclave_timeout`, even for clients without a
+    /// deadline. Calls always stop waiting shortly before the client's deadline, see
+    /// [`enclave_deadline`].
+    pub fn with_enclave_timeout(mut self, enclave_timeout: Option<Duration>) -> Self {
+        self.enclave_timeout = enclave_timeout;
+        self
+    }
+
+    /// Save the JSON bundle to `bundle_archive_path` when it is first retrieved, and again
+    /// whenever the enclave serves a new bundle, e.g. after a recompute.
+    pub fn with_bundle_archive(mut self, bundle_archive_path: Option<PathBuf>) -> Self {
+        self.archive = bundle_archive_path.map(|path| Arc::new(BundleArchive::new(path)));
+        self
+    }
+
+    /// When to stop waiting on the enclave for `request`.
+    fn deadline<T>(&self, request: &tonic::Request<T>) -> Option<Instant> {
+        enclave_deadline(request, self.enclave_timeout)
+    }
+
+    /// The gRPC service of the host, limiting messages to `max_message_size` bytes.
+    ///
+    /// With `compression`, responses are gzip compressed for clients that accept gzip and
+    /// gzip compressed requests are accepted. The hex fields of JSON bundles compress well, so
+    /// this saves most of the bandwidth of large share sets. Clients that do not ask for gzip
+    /// get uncompressed responses either way.
+    ///
+    /// App metadata is sent in the trailers of the response, see [`AppMetadataTrailers`].
+    pub fn into_server(
+        self,
+        max_message_size: usize,
+        compression: bool,
+    ) -> AppMetadataTrailers<ReshardServiceServer<Self>> {
+        let server = ReshardServiceServer::new(self)
+            .max_decoding_message_size(max_message_size)
+            .max_encoding_message_size(max_message_size);
+        let server = if compression {
+            server
+                .send_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Gzip)
+        } else {
+            server
+        };
+        AppMetadataTrailers { inner: server }
+    }
+
+    /// The bundle currently served by the enclave.
+    ///
+    /// Bundles are large, so the last one retrieved is kept and handed out to every caller
+    /// while the enclave still serves it, checked with a cheap bundle id round trip. Apps that
+    /// can't report a bundle id get a fresh copy on every call.
+    pub async fn bundle(
+        &self,
+        request_id: Option<RequestId>,
+    ) -> Result<Arc<ReshardBundle>, Status> {
+        Ok(self.cached_bundle(request_id, None).await?.0.bundle)
+    }
+
+    /// Like [`Self::bundle`], along with the bundle's id and borsh encoding, and the metadata
+    /// the app attached to its responses. Fails with `deadline_exceeded` at `deadline`.
+    async fn cached_bundle(
+        &self,
+        request_id: Option<RequestId>,
+        deadline: Option<Instant>,
+    ) -> Result<(CachedBundle, AppMetadata), Status> {
+        let (app_response, mut metadata) = self
+            .send(
+                ReshardRequest::RetrieveBundleId,
+                request_id.clone(),
+                deadline,
+            )
+            .await?;
+        let bundle_id = match app_response {
+            ReshardResponse::BundleId(bundle_id) => Some(bundle_id),
+            ReshardResponse::Error => None,
+            app_response => return Err(unexpected_response("bundle id", &app_response)),
+        };
+
+        let mut cache = self.bundle_cache.lock().await;
+        if let (Some(cached), Some(bundle_id)) = (&*cache, &bundle_id) {
+            if cached.id == *bundle_id {
+                return Ok((cached.clone(), metadata));
+            }
+        }
+
+        let (app_response, bundle_metadata) = self
+            .send(ReshardRequest::RetrieveBundle, request_id, deadline)
+            .await?;
+        let ReshardResponse::Bundle(bundle) = app_response else {
+            return Err(unexpected_response("bundle", &app_response));
+        };
+        metadata.extend(bundle_metadata);
+        let cached = CachedBundle::new(*bundle)?;
+        if bundle_id.is_some() {
+            *cache = Some(cached.clone());
+        }
+        Ok((cached, metadata))
+    }
+
+    /// Send `request` to the enclave, splitting the app's metadata off the response. Fails
+    /// with `deadline_exceeded` at `deadline`.
+    async fn send(
+        &self,
+        request: ReshardRequest,
+        request_id: Option<RequestId>,
+        deadline: Option<Instant>,
+    ) -> Result<(ReshardResponse, AppMetadata), Status> {
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        Ok(self
+            .enclave
+            .send_with_timeout(request, request_id, timeout)
+            .await?
+            .into_parts())
+    }
+}
+
+/// `message` as a gRPC response carrying the app's `metadata` under [`APP_METADATA_PREFIX`],
+/// for [`AppMetadataTrailers`] to send as trailers. Entries that are not valid ASCII gRPC
+/// metadata are logged and left out.
+fn response_with_metadata<T>(message: T, metadata: AppMetadata) -> tonic::Response<T> {
+    let mut trailers = MetadataMap::new();
+    for (key, value) in metadata {
+        let name = format!("{APP_METADATA_PREFIX}{key}");
+        match (
+            MetadataKey::from_bytes(name.as_bytes()),
+            MetadataValue::try_from(value.as_str()),
+        ) {
+            (Ok(name), Ok(value)) => {
+                trailers.insert(name, value);
+            }
+            _ => tracing::warn!("left out app metadata {key:?} that is not valid gRPC metadata"),
+        }
+    }
+
+    let mut response = tonic::Response::new(message);
+    if !trailers.is_empty() {
+        response.extensions_mut().insert(AppTrailers(trailers));
+    }
+    response
+}
+
+/// App metadata of a response, taken out of its extensions by [`AppMetadataTrailers`].
+#[derive(Debug, Clone)]
+struct AppTrailers(MetadataMap);
+
+/// The gRPC service `S`, sending the app metadata of its responses as trailers.
+///
+/// The app only knows its metadata once it answered, and trailers are where gRPC puts what is
+/// known after the response, e.g. the status. Handlers can't set trailers themselves, so they
+/// leave the metadata in the response extensions and it is appended to the trailers here.
+#[derive(Debug, Clone)]
+pub struct AppMetadataTrailers<S> {
+    inner: S,
+}
+
+impl<S: NamedService> NamedService for AppMetadataTrailers<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<http::Request<B>> for AppMetadataTrailers<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let (mut parts, body) = response.await?.into_parts();
+            let Some(AppTrailers(trailers)) = parts.extensions.remove() else {
+                return Ok(http::Response::from_parts(parts, body));
+            };
+
+            // Merged into the trailers of the body, which hold the gRPC status
+            let body = body.with_trailers(async move { Some(Ok(trailers.into_headers())) });
+            Ok(http::Response::from_parts(parts, Body::new(body)))
+        })
+    }
+}
+
+/// The chunks of a streamed bundle download, see [`ReshardService::retrieve_reshard_stream`].
+#[derive(Debug)]
+pub struct BundleChunks {
+    bundle: CachedBundle,
+    offset: usize,
+    chunk_size: usize,
+}
+
+impl Iterator for BundleChunks {
+    type Item = Result<RetrieveReshardChunk, Status>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let total_size = self.bundle.encoded.len();
+        if self.offset >= total_size {
+            return None;
+        }
+
+        let end = total_size.min(self.offset + self.chunk_size);
+        let chunk = RetrieveReshardChunk {
+            data: self.bundle.encoded[self.offset..end].to_vec(),
+            offset: self.offset as u64,
+            total_size: total_size as u64,
+            bundle_id: self.bundle.id.clone(),
+        };
+        self.offset = end;
+        Some(Ok(chunk))
+    }
 }
 
 #[tonic::async_trait]
 impl ReshardService for Host {
     async fn retrieve_reshard(
         &self,
-        _: tonic::Request<RetrieveReshardRequest>,
+        request: tonic::Request<RetrieveReshardRequest>,
     ) -> std::result::Result<tonic::Response<RetrieveReshardResponse>, Status> {
-        let app_response = self.enclave.send(ReshardRequest::RetrieveBundle).await?;
+        let request_id = RequestId::from_metadata(&request);
+        let deadline = self.deadline(&request);
+        let format = BundleFormat::try_from(request.into_inner().format)
+            .map_err(|e| Status::invalid_argument(format!("invalid bundle format: {e}")))?;
 
-        let ReshardResponse::Bundle(bundle) = app_response else {
-            return Err(Status::internal("received invalid response from app"));
+        let (cached, metadata) = self.cached_bundle(request_id, deadline).await?;
+
+        // The archive is always JSON, regardless of the format requested
+        let json_bundle = if format == BundleFormat::Json || self.archive.is_some() {
+            let json_bundle = serde_json::to_string(&*cached.bundle)
+                .map_err(|_| Status::internal("received invalid json bundle from app"))?;
+            if let Some(archive) = &self.archive {
+                archive.write_new(&cached.id, &json_bundle).await;
+            }
+            json_bundle
+        } else {
+            String::new()
         };
 
-        let reshard_bundle = serde_json::to_string(&*bundle)
-            .map_err(|_| Status::internal("received invalid json bundle from app"))?;
-        let response = RetrieveReshardResponse { reshard_bundle };
-        Ok(tonic::Response::new(response))
+        let response = match format {
+            BundleFormat::Json => RetrieveReshardResponse {
+                reshard_bundle: json_bundle,
+                bundle_id: cached.id,
+                ..Default::default()
+            },
+            BundleFormat::Borsh => RetrieveReshardResponse {
+                reshard_bundle_borsh: cached.encoded.to_vec(),
+                bundle_id: cached.id,
+                ..Default::default()
+            },
+        };
+        Ok(response_with_metadata(response, metadata))
+    }
+
+    type RetrieveReshardStreamStream = tokio_stream::Iter<BundleChunks>;
+
+    async fn retrieve_reshard_stream(
+        &self,
+        request: tonic::Request<RetrieveReshardStreamRequest>,
+    ) -> std::result::Result<tonic::Response<Self::RetrieveReshardStreamStream>, Status> {
+        let request_id = RequestId::from_metadata(&request);
+        let deadline = self.deadline(&request);
+        let request = request.into_inner();
+
+        let (bundle, metadata) = self.cached_bundle(request_id, deadline).await?;
+        if !request.bundle_id.is_empty() && request.bundle_id != bundle.id {
+            return Err(Status::aborted(
+                "bundle was recomputed since the download started, restart it from offset 0",
+            ));
+        }
+        let offset = usize::try_from(request.offset)
+            .ok()
+            .filter(|offset| *offset <= bundle.encoded.len())
+            .ok_or_else(|| {
+                Status::out_of_range(format!(
+                    "offset {} is past the end of the {} byte bundle",
+                    request.offset,
+                    bundle.encoded.len()
+                ))
+            })?;
+        let chunk_size = match request.max_chunk_size as usize {
+            0 => MAX_STREAM_CHUNK_SIZE,
+            max_chunk_size => max_chunk_size.min(MAX_STREAM_CHUNK_SIZE),
+        };
+
+        let chunks = tokio_stream::iter(BundleChunks {
+            bundle,
+            offset,
+            chunk_size,
+        });
+        Ok(response_with_metadata(chunks, metadata))
+    }
+
+    async fn retrieve_attestation(
+        &self,
+        request: tonic::Request<RetrieveAttestationRequest>,
+    ) -> std::result::Result<tonic::Response<RetrieveAttestationResponse>, Status> {
+        let request_id = RequestId::from_metadata(&request);
+        let deadline = self.deadline(&request);
+        let nonce = request.into_inner().nonce;
+        let app_request = if nonce.is_empty() {
+            ReshardRequest::RetrieveAttestation
+        } else if nonce.len() > MAX_NONCE_LEN {
+            return Err(Status::invalid_argument(format!(
+                "nonce is {} bytes, exceeding the limit of {MAX_NONCE_LEN} bytes",
+                nonce.len()
+            )));
+        } else {
+            ReshardRequest::AttestWithNonce(nonce)
+        };
+
+        let (app_response, metadata) = self.send(app_request, request_id, deadline).await?;
+
+        let attestation_doc = match app_response {
+            ReshardResponse::Attestation(attestation_doc) => attestation_doc,
+            ReshardResponse::AttestationFailed(e) => {
+                return Err(Status::internal(format!(
+                    "app failed to generate an attestation: {e}"
+                )))
+            }
+            other => return Err(unexpected_response("attestation", &other)),
+        };
+
+        let response = RetrieveAttestationResponse { attestation_doc };
+        Ok(response_with_metadata(response, metadata))
+    }
+
+    async fn get_bundle_id(
+        &self,
+        request: tonic::Request<GetBundleIdRequest>,
+    ) -> std::result::Result<tonic::Response<GetBundleIdResponse>, Status> {
+        let (app_response, metadata) = self
+            .send(
+                ReshardRequest::RetrieveBundleId,
+                RequestId::from_metadata(&request),
+                self.deadline(&request),
+            )
+            .await?;
+
+        let ReshardResponse::BundleId(bundle_id) = app_response else {
+            return Err(unexpected_response("bundle id", &app_response));
+        };
+
+        Ok(response_with_metadata(
+            GetBundleIdResponse { bundle_id },
+            metadata,
+        ))
+    }
+
+    async fn get_live_attestation(
+        &self,
+        request: tonic::Request<GetLiveAttestationRequest>,
+    ) -> std::result::Result<tonic::Response<GetLiveAttestationResponse>, Status> {
+        let request_id = RequestId::from_metadata(&request);
+        let timeout = self
+            .deadline(&request)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let attestation_doc = self
+            .enclave
+            .live_attestation_doc(request_id, timeout)
+            .await?;
+
+        Ok(tonic::Response::new(GetLiveAttestationResponse {
+            attestation_doc,
+        }))
+    }
+
+    #[cfg(feature = "verify")]
+    async fn verify_bundle(
+        &self,
+        request: tonic::Request<VerifyBundleRequest>,
+    ) -> std::result::Result<tonic::Response<VerifyBundleResponse>, Status> {
+        let request_id = RequestId::from_metadata(&request);
+        let deadline = self.deadline(&request);
+        let request = request.into_inner();
+        let ephemeral_key = match public_key("ephemeral", &request.ephemeral_public_key)? {
+            Some(ephemeral_pub) => EphemeralKey::Given(ephemeral_pub),
+            None => EphemeralKey::attested_now(),
+        };
+        let quorum_pub = public_key("quorum", &request.quorum_public_key)?;
+
+        let bundle = self.cached_bundle(request_id, deadline).await?.0.bundle;
+        let report = reshard_verify::verify_bundle(&bundle, ephemeral_key, quorum_pub.as_ref());
+
+        Ok(tonic::Response::new(VerifyBundleResponse {
+            passed: report.passed,
+            checks: report
+                .checks
+                .into_iter()
+                .map(|check| BundleCheck {
+                    name: check.name,
+                    passed: check.passed,
+                    expected: check.expected,
+                    computed: check.computed,
+                })
+                .collect(),
+            bundle_id: bundle.id(),
+        }))
+    }
+
+    #[cfg(not(feature = "verify"))]
+    async fn verify_bundle(
+        &self,
+        _request: tonic::Request<VerifyBundleRequest>,
+    ) -> std::result::Result<tonic::Response<VerifyBundleResponse>, Status> {
+        Err(Status::unimplemented(
+            "host was built without the `verify` feature",
+        ))
+    }
+
+    #[cfg(feature = "dev-rpc")]
+    async fn raw_request(
+        &self,
+        request: tonic::Request<RawRequestRequest>,
+    ) -> std::result::Result<tonic::Response<RawRequestResponse>, Status> {
+        let request_id = RequestId::from_metadata(&request);
+        let timeout = self
+            .deadline(&request)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let app_request = borsh::from_slice::<ReshardRequest>(&request.into_inner().request)
+            .map_err(|e| Status::invalid_argument(format!("invalid reshard request: {e}")))?;
+
+        let app_response = self
+            .enclave
+            .send_with_timeout(app_request, request_id, timeout)
+            .await?;
+        let response = borsh::to_vec(&app_response)
+            .map_err(|e| Status::internal(format!("unable to encode the reshard response: {e}")))?;
+
+        Ok(tonic::Response::new(RawRequestResponse { response }))
+    }
+
+    #[cfg(not(feature = "dev-rpc"))]
+    async fn raw_request(
+        &self,
+        _request: tonic::Request<RawRequestRequest>,
+    ) -> std::result::Result<tonic::Response<RawRequestResponse>, Status> {
+        Err(Status::unimplemented(
+            "host was built without the `dev-rpc` feature",
+        ))
+    }
+
+    async fn get_queue_utilization(
+        &self,
+        _request: tonic::Request<GetQueueUtilizationRequest>,
+    ) -> std::result::Result<tonic::Response<GetQueueUtilizationResponse>, Status> {
+        let metrics = queue_metrics();
+        let queues = metrics
+            .queue_utilization()
+            .into_iter()
+            .map(|(name, queue)| QueueUtilization {
+                name,
+                queued: queue.queued as u64,
+                capacity: queue.capacity as u64,
+            })
+            .collect();
+
+        Ok(tonic::Response::new(GetQueueUtilizationResponse {
+            queues,
+            abandoned_requests: metrics.abandoned_requests(),
+            dropped_responses: metrics.dropped_responses(),
+        }))
     }
 }
 
-struct Health {
+/// The `kind` public key in `bytes`, `None` if empty.
+#[cfg(feature = "verify")]
+fn public_key(kind: &str, bytes: &[u8]) -> Result<Option<P256Public>, Status> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    P256Public::from_bytes(bytes)
+        .map(Some)
+        .map_err(|e| Status::invalid_argument(format!("invalid {kind} public key: {e:?}")))
+}
+
+/// Status for an app `response` that is not the `expected` kind of response, shared by every RPC
+/// handler so clients see consistent codes.
+///
+/// [`ReshardResponse::Error`] means the app could not decode the request, most likely because
+/// it is older than the host and does not know it, so it maps to `unimplemented`.
+/// [`ReshardResponse::RateLimited`] maps to `resource_exhausted`, so clients back off, and
+/// [`ReshardResponse::Initializing`] to `unavailable`, so clients retry once the app is ready.
+/// Any other variant is a bug in the app and maps to `internal`.
+pub fn unexpected_response(expected: &str, response: &ReshardResponse) -> Status {
+    let got = match response {
+        ReshardResponse::Error => {
+            return Status::unimplemented(format!(
+                "app could not process the {expected} request, it may be older than this host"
+            ))
+        }
+        ReshardResponse::RateLimited(reason) => {
+            return Status::resource_exhausted(format!(
+                "app rate limited the {expected} request: {reason}"
+            ))
+        }
+        ReshardResponse::Initializing => {
+            return Status::unavailable(format!(
+                "app is still initializing and can not process the {expected} request yet"
+            ))
+        }
+        ReshardResponse::WithMetadata { response, .. } => {
+            return unexpected_response(expected, response)
+        }
+        ReshardResponse::Bundle(_) => "a bundle",
+        ReshardResponse::Health => "a health response",
+        ReshardResponse::Attestation(_) | ReshardResponse::AttestationFailed(_) => "an attestation",
+        ReshardResponse::Recomputed | ReshardResponse::RecomputeFailed(_) => "a recompute result",
+        ReshardResponse::BundleId(_) => "a bundle id",
+        ReshardResponse::MemberReplaced | ReshardResponse::ReplaceMemberFailed(_) => {
+            "a member replacement result"
+        }
+    };
+    Status::internal(format!(
+        "expected {expected} response from app but got {got}"
+    ))
+}
+
+/// Saves the JSON bundle to disk the first time it is retrieved, and again each time the bundle
+/// served changes, so the archive always holds the latest bundle handed out.
+#[derive(Debug)]
+struct BundleArchive {
+    path: PathBuf,
+    /// Id of the bundle last written, if any.
+    written: Mutex<Option<Vec<u8>>>,
+}
+
+impl BundleArchive {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            written: Mutex::new(None),
+        }
+    }
+
+    /// Atomically write `bundle` to the archive path unless a previous call already wrote the
+    /// bundle with id `id`. Errors are logged so a failed write never fails the request; the next
+    /// retrieval will retry.
+    ///
+    /// The write runs on the blocking pool, so it never holds up other requests on the runtime.
+    async fn write_new(self: &Arc<Self>, id: &[u8], bundle: &str) {
+        if self.lock().as_deref() == Some(id) {
+            return;
+        }
+
+        let archive = Arc::clone(self);
+        let id = id.to_vec();
+        let bundle = bundle.to_string();
+        if let Err(e) = tokio::task::spawn_blocking(move || archive.write(id, &bundle)).await {
+            tracing::warn!("bundle archive task failed: {e}");
+        }
+    }
+
+    /// Blocking part of [`Self::write_new`]. Concurrent writes wait on the lock, then skip the
+    /// write if the same bundle was written in the meantime.
+    fn write(&self, id: Vec<u8>, bundle: &str) {
+        let mut written = self.lock();
+        if written.as_ref() == Some(&id) {
+            return;
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        let result =
+            std::fs::write(&tmp_path, bundle).and_then(|()| std::fs::rename(&tmp_path, &self.path));
+        match result {
+            Ok(()) => *written = Some(id),
+            Err(e) => tracing::warn!("failed to archive bundle to {}: {e}", self.path.display()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Vec<u8>>> {
+        self.written.lock().expect("bundle archive lock poisoned")
+    }
+}
+
+/// Health check of the reshard app.
+///
+/// Fails with `unavailable` if the enclave can not be reached, e.g. the queue or socket is down,
+/// and with `internal` if the enclave answers with anything but a health response. An app that
+/// is still initializing is up but not ready, and an app that is up is ready once it serves a
+/// bundle id. Failing to retrieve the bundle id is an error too, unless the enclave became
+/// unavailable in the meantime, which only makes the app not ready.
+pub struct Health {
     enclave: Arc<EnclaveClient<BorshCodec, ReshardRequest, ReshardResponse>>,
 }
 
+impl Health {
+    /// Create a health check sending health requests to `enclave`.
+    pub fn new(enclave: Arc<EnclaveClient<BorshCodec, ReshardRequest, ReshardResponse>>) -> Self {
+        Self { enclave }
+    }
+}
+
 #[tonic::async_trait]
 impl AppHealthCheckable for Health {
     async fn app_health_check(&self) -> Result<tonic::Response<AppHealthResponse>, tonic::Status> {
-        let app_response = self.enclave.send(ReshardRequest::HealthRequest).await?;
+        let (app_response, _) = self
+            .enclave
+            .send(ReshardRequest::HealthRequest)
+            .await?
+            .into_parts();
+        if app_response == ReshardResponse::Initializing {
+            tracing::debug!("app is still initializing");
+            return Ok(tonic::Response::new(AppHealthResponse::not_ready()));
+        }
         if ReshardResponse::Health != app_response {
-            return Err(Status::internal("received invalid response from app"));
+            return Err(Status::internal(format!(
+                "expected a health response from app but got {app_response:?}"
+            )));
         }
 
-        Ok(tonic::Response::new(AppHealthResponse { code: 200 }))
+        let response = match self
+            .enclave
+            .send(ReshardRequest::RetrieveBundleId)
+            .await
+            .map(|response| response.into_parts().0)
+        {
+            Ok(ReshardResponse::BundleId(_)) => AppHealthResponse::ready(),
+            // Up, but without a bundle to serve
+            Ok(_) => AppHealthResponse::not_ready(),
+            // Went down since answering the health request
+            Err(status) if status.code() == Code::Unavailable => AppHealthResponse::not_ready(),
+            Err(status) => return Err(status),
+        };
+        Ok(tonic::Response::new(response))
     }
 }
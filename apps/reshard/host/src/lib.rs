@@ -1,6 +1,9 @@
 //! gRPC reshard app host service.
 
+use host_primitives::{CircuitBreaker, Keepalive};
+use logging::LogLevel;
 use qos_core::io::SocketAddress;
+use std::{path::PathBuf, time::Duration};
 
 pub mod generated {
     #![allow(missing_docs)]
@@ -10,22 +13,59 @@ pub mod generated {
     pub mod reshard;
 
     pub const FILE_DESCRIPTOR_SET: &[u8] = std::include_bytes!("generated/descriptor.bin");
+
+    /// Check that `descriptor` is the file descriptor set the [`reshard`] service code was
+    /// generated with. A mismatch means codegen output is stale or was only partially
+    /// regenerated, so reflection would advertise a schema that does not match the served types.
+    pub fn verify_descriptor(descriptor: &[u8]) -> Result<(), String> {
+        let checksum = qos_hex::encode(&qos_crypto::sha_256(descriptor));
+        if checksum != reshard::FILE_DESCRIPTOR_SET_SHA256 {
+            return Err(format!(
+                "file descriptor set sha256 {checksum} does not match {} from the generated \
+                 service code, rerun codegen",
+                reshard::FILE_DESCRIPTOR_SET_SHA256
+            ));
+        }
+
+        Ok(())
+    }
 }
 pub mod cli;
 mod host;
+pub use host::{unexpected_response, BundleChunks, Health, Host, MAX_STREAM_CHUNK_SIZE};
 
 /// Configuration for running the reshard gRPC host.
 pub struct ReshardHostConfig {
     listen_addr: std::net::SocketAddr,
     enclave_addr: SocketAddress,
+    /// Maximum size in bytes of gRPC messages the host will decode or encode.
+    max_message_size: usize,
+    /// Whether to serve the gRPC reflection service. Should be disabled in production.
+    enable_reflection: bool,
+    /// Where to save a JSON copy of the bundle when it is first retrieved and after it changes.
+    bundle_archive_path: Option<PathBuf>,
+    /// HTTP/2 and TCP keepalive settings for client connections.
+    keepalive: Keepalive,
+    /// Number of requests that can wait for the enclave before callers are held back.
+    enclave_queue_capacity: usize,
+    /// Whether to gzip compress responses for clients that accept it, see
+    /// [`Host::into_server`].
+    compression: bool,
+    /// Requests in progress to the enclave at once, unlimited if `None`.
+    concurrency_limit: Option<usize>,
+    /// Runtime config file re-read on SIGHUP, if any.
+    runtime_config_path: Option<PathBuf>,
+    /// Level of the host's logs, for the runtime config to change.
+    log_level: Option<LogLevel>,
+    /// Longest wait on the enclave for a call, see [`Host::with_enclave_timeout`]. Calls
+    /// with a client deadline stop waiting shortly before it either way.
+    enclave_timeout: Option<Duration>,
+    /// Fails calls fast while the enclave keeps failing, see [`CircuitBreaker`]. Calls always
+    /// wait on the enclave if `None`.
+    circuit_breaker: Option<CircuitBreaker>,
 }
 
 /// Run the reshard gRPC host
-pub async fn run(
-    ReshardHostConfig {
-        listen_addr,
-        enclave_addr,
-    }: ReshardHostConfig,
-) -> Result<(), tonic::transport::Error> {
-    host::listen(listen_addr, enclave_addr).await
+pub async fn run(config: ReshardHostConfig) -> Result<(), tonic::transport::Error> {
+    host::listen(config).await
 }
@@ -1,6 +1,5 @@
 use reshard_host::cli::CLI;
 
-#[tokio::main]
-async fn main() {
-    CLI::execute().await;
+fn main() {
+    CLI::execute();
 }
@@ -0,0 +1,7 @@
+//! Tells the crates depending on reshard_provision that it is linked, see the build script of
+//! reshard_host.
+
+fn main() {
+    println!("cargo::rerun-if-changed=build.rs");
+    println!("cargo::metadata=linked=true");
+}
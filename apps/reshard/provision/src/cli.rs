@@ -1,17 +1,45 @@
 //! CLI for reshard provisioning.
 
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use std::{
+    fs,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
 
-use crate::{run, Config};
+use crate::{
+    provisioner::{QosProvisioner, Terminal, YubikeyPolicy},
+    run, secrets,
+    verify::{verify, VerifyConfig},
+    Config,
+};
 
 #[derive(Parser, Debug)]
 #[command(
     name = "reshard_provision",
     version,
-    about = "Offline Yubikey provisioning ceremony orchestrator"
+    about = "Offline Yubikey provisioning ceremony orchestrator",
+    args_conflicts_with_subcommands = true
 )]
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Provision yubikeys when no subcommand is given
+    #[command(flatten)]
+    provision: Option<ProvisionArgs>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check that the yubikeys of a finished ceremony hold the public keys in its output root
+    Verify(VerifyArgs),
+    /// Decrypt a `{m}.secret.enc` file kept with `--secrets-passphrase-file`
+    DecryptSecret(DecryptSecretArgs),
+}
+
+#[derive(Args, Debug)]
+struct ProvisionArgs {
     /// Number of operators
     #[arg(long)]
     num_operators: usize,
@@ -27,6 +55,53 @@ struct Args {
     /// Include master *.secret files in output
     #[arg(long)]
     include_secrets: bool,
+
+    /// File holding a passphrase to encrypt the included secrets with. They are then written
+    /// as `{m}.secret.enc`, and the plaintext secrets never touch the output root
+    #[arg(long, requires = "include_secrets")]
+    secrets_passphrase_file: Option<PathBuf>,
+
+    /// File holding the PIN of the yubikeys, if it was changed from the default
+    #[arg(long)]
+    pin_file: Option<PathBuf>,
+
+    /// Skip the sobriety check before provisioning, for scripted ceremonies
+    #[arg(long)]
+    skip_safety_prompts: bool,
+
+    /// Attempts at provisioning a yubikey before asking to skip it or abort
+    #[arg(long, default_value_t = 3)]
+    max_attempts: usize,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// Number of operators
+    #[arg(long)]
+    num_operators: usize,
+
+    /// Keys per operator
+    #[arg(long, default_value_t = 3)]
+    keys_per_operator: usize,
+
+    /// Output root of the ceremony
+    #[arg(long)]
+    out: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct DecryptSecretArgs {
+    /// Encrypted secret, `{m}.secret.enc` in the output root
+    #[arg(long)]
+    secret: PathBuf,
+
+    /// File holding the passphrase the secret was encrypted with
+    #[arg(long)]
+    passphrase_file: PathBuf,
+
+    /// Path to write the decrypted secret to, readable by the owner only. Must not exist yet
+    #[arg(long)]
+    out: PathBuf,
 }
 
 /// Provision binary command line interface.
@@ -34,16 +109,96 @@ pub struct CLI;
 impl CLI {
     /// Execute the command line interface.
     pub fn execute() {
-        let args = Args::parse();
+        match Cli::parse() {
+            Cli {
+                command: Some(Command::Verify(args)),
+                ..
+            } => Self::verify(args),
+            Cli {
+                command: Some(Command::DecryptSecret(args)),
+                ..
+            } => Self::decrypt_secret(args),
+            Cli {
+                provision: Some(args),
+                ..
+            } => Self::provision(args),
+            Cli { .. } => unreachable!("clap requires provisioning args without a subcommand"),
+        }
+    }
+
+    fn provision(args: ProvisionArgs) {
+        let secrets_passphrase = args
+            .secrets_passphrase_file
+            .as_deref()
+            .map(read_passphrase)
+            .transpose()
+            .unwrap_or_else(|e| exit_with(&e));
         let cfg = Config {
             num_operators: args.num_operators,
             keys_per_operator: args.keys_per_operator,
             out: args.out,
             include_secrets: args.include_secrets,
+            secrets_passphrase,
+            yubikey_policy: YubikeyPolicy {
+                pin_file: args.pin_file,
+            },
+            skip_safety_prompts: args.skip_safety_prompts,
+            max_attempts: args.max_attempts,
         };
         if let Err(e) = run(cfg) {
             eprintln!("error: {e}");
             std::process::exit(1);
         }
     }
+
+    fn decrypt_secret(args: DecryptSecretArgs) {
+        let result = read_passphrase(&args.passphrase_file).and_then(|passphrase| {
+            let encrypted = fs::read(&args.secret)
+                .map_err(|e| format!("unable to read {}: {e}", args.secret.display()))?;
+            let secret = secrets::decrypt(&encrypted, &passphrase)?;
+            // The master secret is only ever readable by the operator decrypting it
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&args.out)
+                .and_then(|mut file| std::io::Write::write_all(&mut file, &secret))
+                .map_err(|e| format!("unable to write {}: {e}", args.out.display()))
+        });
+        if let Err(e) = result {
+            exit_with(&e);
+        }
+    }
+
+    fn verify(args: VerifyArgs) {
+        let cfg = VerifyConfig {
+            num_operators: args.num_operators,
+            keys_per_operator: args.keys_per_operator,
+            out: args.out,
+        };
+        match verify(&cfg, &QosProvisioner, &Terminal) {
+            Ok(report) => {
+                print!("{report}");
+                if !report.passed() {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// The passphrase in `path`, without a trailing newline.
+fn read_passphrase(path: &Path) -> Result<String, String> {
+    let passphrase = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read passphrase file {}: {e}", path.display()))?;
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn exit_with(error: &str) -> ! {
+    eprintln!("error: {error}");
+    std::process::exit(1);
 }
@@ -1,10 +1,16 @@
 pub mod cli;
+pub mod provisioner;
+pub mod secrets;
+pub mod verify;
 
-use dialoguer::{theme::ColorfulTheme, Confirm};
-use qos_client::cli::{advanced_provision_yubikey, generate_file_key};
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use tempdir::TempDir;
 
+use crate::provisioner::{Prompt, Provisioner, QosProvisioner, Terminal, YubikeyPolicy};
+
 /// Public configuration passed in from the CLI (or tests).
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -12,78 +18,322 @@ pub struct Config {
     pub keys_per_operator: usize,
     pub out: PathBuf,
     pub include_secrets: bool,
+    /// Passphrase to encrypt the secrets kept with [`Config::include_secrets`] with, see
+    /// [`secrets::encrypt`]. They are then written as `{m}.secret.enc` and the plaintext never
+    /// touches the output dir. `None` keeps them in plaintext as `{m}.secret`.
+    pub secrets_passphrase: Option<String>,
+    pub yubikey_policy: YubikeyPolicy,
+    /// Skip the sobriety check before provisioning, e.g. for scripted test ceremonies.
+    pub skip_safety_prompts: bool,
+    /// Attempts at provisioning a yubikey before the operator is asked to skip it or abort.
+    pub max_attempts: usize,
+}
+
+/// What a provisioning ceremony did, one result per operator in operator order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisionReport {
+    pub operators: Vec<OperatorResult>,
+}
+
+/// What the ceremony did for a single operator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorResult {
+    /// Operator number, starting at 1.
+    pub operator: usize,
+    pub outcome: OperatorOutcome,
+    /// Public key of the operator, `{m}.pub` in the output dir.
+    pub pub_path: PathBuf,
+    /// Master secret kept in the output dir, if [`Config::include_secrets`] and provisioned in
+    /// this run. Encrypted if [`Config::secrets_passphrase`] is set.
+    pub secret_path: Option<PathBuf>,
+}
+
+/// Whether an operator was provisioned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperatorOutcome {
+    /// The operator already had a public key from an earlier run and was skipped.
+    Skipped,
+    /// A new key was generated, with the outcome of each of its yubikeys.
+    Provisioned(Vec<KeyOutcome>),
+}
+
+/// Outcome of provisioning a single yubikey.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyOutcome {
+    Provisioned,
+    /// Skipped by the operator after [`Config::max_attempts`] failed, with the last error. At
+    /// least one yubikey of each operator must be provisioned, or the ceremony fails.
+    Skipped(String),
+}
+
+/// Run the provisioning ceremony with the yubikeys attached to this machine, prompting on the
+/// terminal.
+pub fn run(cfg: Config) -> Result<ProvisionReport, Box<dyn std::error::Error>> {
+    run_with(cfg, &QosProvisioner, &Terminal)
 }
 
-pub fn run(cfg: Config) -> Result<(), Box<dyn std::error::Error>> {
+/// Run the provisioning ceremony with `provisioner`, asking the operator through `prompt`.
+///
+/// Operators are provisioned one after the other on the single yubikey reader attached, as
+/// `qos_client` can't provision a yubikey in a chosen reader, see [`QosProvisioner`].
+pub fn run_with<P, Q>(
+    cfg: Config,
+    provisioner: &P,
+    prompt: &Q,
+) -> Result<ProvisionReport, Box<dyn std::error::Error>>
+where
+    P: Provisioner + Sync,
+    Q: Prompt + Sync,
+{
+    // Fail before anything is prompted for or any yubikey is touched
+    validate_output_dir(&cfg.out, cfg.num_operators)?;
+    if let Some(pin_file) = &cfg.yubikey_policy.pin_file {
+        if !pin_file.is_file() {
+            return Err(format!("PIN file {} does not exist", pin_file.display()).into());
+        }
+    }
+    if cfg.secrets_passphrase.is_some() && !cfg.include_secrets {
+        return Err("a secrets passphrase only applies when secrets are included".into());
+    }
+    if cfg.secrets_passphrase.as_deref() == Some("") {
+        return Err("the secrets passphrase is empty".into());
+    }
+    if cfg.keys_per_operator == 0 {
+        return Err("at least one yubikey per operator is required".into());
+    }
+    if cfg.max_attempts == 0 {
+        return Err("at least one provisioning attempt per yubikey is required".into());
+    }
+    let readers = provisioner.readers()?;
+    let reader = match readers.as_slice() {
+        [reader] => reader.clone(),
+        [] => return Err("no yubikey reader found".into()),
+        _ => {
+            return Err(format!(
+                "provisioning needs a single yubikey reader, attach only the yubikey to \
+                 provision, found: {}",
+                readers.join(", ")
+            )
+            .into())
+        }
+    };
+
     println!("YubiKey provisioning is about to start. This is serious.");
-    if confirm_yes("Are you inebriated?", true)? {
+    if cfg.skip_safety_prompts {
+        println!("Skipping safety prompts as requested.");
+    } else if prompt.confirm("Are you inebriated?", true)? {
         eprintln!("Aborting provisioning — please try again when sober.");
         return Err("operator indicated inebriation".into());
     }
 
-    // Ensure output directory exists
-    fs::create_dir_all(&cfg.out)?;
-
+    let mut pending = Vec::new();
+    let mut operators = Vec::new();
     for m in 1..=cfg.num_operators {
         let pub_path: PathBuf = cfg.out.join(format!("{m}.pub"));
         if pub_path.exists() {
             let skip_prompt =
                 format!("Found existing public key for operator {m}. Skip provisioning?");
-            let skip = confirm_yes(&skip_prompt, true)?;
+            let skip = prompt.confirm(&skip_prompt, true)?;
 
             if skip {
                 println!("Skipping operator {m}");
+                operators.push(OperatorResult {
+                    operator: m,
+                    outcome: OperatorOutcome::Skipped,
+                    pub_path,
+                    secret_path: None,
+                });
                 continue;
             }
         }
+        pending.push(m);
+    }
+
+    let ceremony = Ceremony {
+        cfg: &cfg,
+        provisioner,
+        prompt,
+        reader,
+    };
+    for m in pending {
+        operators.push(ceremony.provision_operator(m)?);
+    }
+    operators.sort_by_key(|result| result.operator);
+
+    let skipped_keys: usize = operators
+        .iter()
+        .map(|result| match &result.outcome {
+            OperatorOutcome::Skipped => 0,
+            OperatorOutcome::Provisioned(keys) => keys
+                .iter()
+                .filter(|key| matches!(key, KeyOutcome::Skipped(_)))
+                .count(),
+        })
+        .sum();
+    if skipped_keys == 0 {
+        println!("All operator yubikeys provisioned!");
+    } else {
+        println!("Every operator has a yubikey, but {skipped_keys} yubikeys were skipped.");
+    }
+    Ok(ProvisionReport { operators })
+}
+
+/// Provisions operators on the yubikey reader of the ceremony.
+struct Ceremony<'a, P, Q> {
+    cfg: &'a Config,
+    provisioner: &'a P,
+    prompt: &'a Q,
+    reader: String,
+}
+
+impl<P: Provisioner, Q: Prompt> Ceremony<'_, P, Q> {
+    fn provision_operator(&self, m: usize) -> Result<OperatorResult, String> {
+        let cfg = self.cfg;
+        let pub_path: PathBuf = cfg.out.join(format!("{m}.pub"));
 
         let tmp_dir = TempDir::new("reshard-secrets").unwrap();
         let tmp_secret_path = tmp_dir.path().join(format!("{m}.secret"));
 
-        generate_file_key(&tmp_secret_path, &pub_path);
+        self.provisioner.generate_key(&tmp_secret_path, &pub_path)?;
 
         // Provision configured number of yubikeys for this seed
+        let mut keys = Vec::with_capacity(cfg.keys_per_operator);
         for k in 1..=cfg.keys_per_operator {
-            let prompt = format!("Please insert yubikey {k} for operator {m}. Are you ready?");
-            while !confirm_yes(&prompt, false)? {
+            let ready_prompt =
+                format!("Please insert yubikey {k} for operator {m}. Are you ready?");
+            while !self.prompt.confirm(&ready_prompt, false)? {
                 println!("Oops that wasn't correct.");
             }
 
+            let mut attempts = 0;
             loop {
-                match advanced_provision_yubikey(&tmp_secret_path, None) {
+                match self.provisioner.provision_yubikey(
+                    &self.reader,
+                    &tmp_secret_path,
+                    &cfg.yubikey_policy,
+                ) {
                     Ok(()) => {
                         println!("Provisioned yubikey {k}, operator {m}");
+                        keys.push(KeyOutcome::Provisioned);
                         break;
                     }
                     Err(e) => {
-                        eprintln!("provisioning failed for yubikey {k}, operator {m}, {e:?}");
-                        continue;
+                        attempts += 1;
+                        eprintln!(
+                            "provisioning failed for yubikey {k}, operator {m} \
+                             (attempt {attempts} of {}), {e}",
+                            cfg.max_attempts
+                        );
+                        if attempts < cfg.max_attempts {
+                            continue;
+                        }
+
+                        let give_up_prompt = format!(
+                            "Provisioning yubikey {k} for operator {m} failed {attempts} times. \
+                             Skip this yubikey? No aborts the ceremony."
+                        );
+                        if !self.prompt.confirm(&give_up_prompt, false)? {
+                            return Err(format!(
+                                "aborted after provisioning yubikey {k} for operator {m} \
+                                 failed {attempts} times: {e}"
+                            ));
+                        }
+                        println!("Skipped yubikey {k}, operator {m}");
+                        keys.push(KeyOutcome::Skipped(e));
+                        break;
                     }
                 }
             }
         }
 
-        if cfg.include_secrets {
+        if !keys.contains(&KeyOutcome::Provisioned) {
+            // Nothing holds the key, so don't leave a public key a resumed run would skip
+            fs::remove_file(&pub_path)
+                .map_err(|e| format!("unable to remove {}: {e}", pub_path.display()))?;
+            return Err(format!(
+                "every yubikey of operator {m} was skipped, removed {}",
+                pub_path.display()
+            ));
+        }
+
+        let secret_path = if let Some(passphrase) = &cfg.secrets_passphrase {
+            // Encrypted in memory, so only the ciphertext is written to the output dir
+            let secret_path = cfg
+                .out
+                .join(format!("{m}.{}", secrets::ENCRYPTED_EXTENSION));
+            let secret = fs::read(&tmp_secret_path)
+                .map_err(|e| format!("unable to read secret of operator {m}: {e}"))?;
+            fs::write(&secret_path, secrets::encrypt(&secret, passphrase)?)
+                .map_err(|e| format!("unable to keep {}: {e}", secret_path.display()))?;
+            println!("Kept {} encrypted", secret_path.display());
+            Some(secret_path)
+        } else if cfg.include_secrets {
             let secret_path = cfg.out.join(format!("{m}.secret"));
-            fs::copy(&tmp_secret_path, &secret_path)?;
-            println!("Kept {}", secret_path.display())
+            fs::copy(&tmp_secret_path, &secret_path)
+                .map_err(|e| format!("unable to keep {}: {e}", secret_path.display()))?;
+            println!("Kept {}", secret_path.display());
+            Some(secret_path)
         } else {
             println!("Secret for operator {m} stayed in tmp/secrets and was removed)");
-        }
+            None
+        };
+
+        drop(tmp_dir); // tmp_dir drops out of scope here and is therefore removed but making it explicit
+        Ok(OperatorResult {
+            operator: m,
+            outcome: OperatorOutcome::Provisioned(keys),
+            pub_path,
+            secret_path,
+        })
+    }
+}
+
+/// Check that `out` can hold the ceremony output for `num_operators` operators, creating it if
+/// it does not exist.
+///
+/// `out` must be a writable directory that is either empty or only holds artifacts of this
+/// ceremony, e.g. from an interrupted run: `{m}.pub`, `{m}.secret` and `{m}.secret.enc` for
+/// operators `1..=num_operators`.
+pub fn validate_output_dir(out: &Path, num_operators: usize) -> Result<(), String> {
+    fs::create_dir_all(out)
+        .map_err(|e| format!("unable to create output dir {}: {e}", out.display()))?;
 
-        drop(tmp_dir) // tmp_dir drops out of scope here and is therefore removed but making it explicit
+    let entries = fs::read_dir(out)
+        .map_err(|e| format!("unable to read output dir {}: {e}", out.display()))?;
+    let mut unexpected = Vec::new();
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| format!("unable to read output dir {}: {e}", out.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.path().is_file() || !is_ceremony_artifact(&name, num_operators) {
+            unexpected.push(name);
+        }
+    }
+    if !unexpected.is_empty() {
+        unexpected.sort();
+        return Err(format!(
+            "output dir {} contains files that are not ceremony artifacts: {}",
+            out.display(),
+            unexpected.join(", ")
+        ));
     }
 
-    println!("All operator yubikeys provisioned!");
-    Ok(())
+    // Permissions alone don't tell, e.g. read-only mounts, so write a probe file
+    let probe = out.join(".reshard-provision-write-check");
+    fs::write(&probe, b"")
+        .and_then(|()| fs::remove_file(&probe))
+        .map_err(|e| format!("output dir {} is not writable: {e}", out.display()))
 }
 
-fn confirm_yes(prompt: &str, default_yes: bool) -> Result<bool, Box<dyn std::error::Error>> {
-    Ok(Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(prompt)
-        .default(default_yes)
-        .show_default(true)
-        .wait_for_newline(true)
-        .report(false)
-        .interact()?)
+/// Whether `name` is `{m}.pub`, `{m}.secret` or `{m}.secret.enc` for an operator `m` in
+/// `1..=num_operators`.
+fn is_ceremony_artifact(name: &str, num_operators: usize) -> bool {
+    let Some((operator, extension)) = name.split_once('.') else {
+        return false;
+    };
+    let is_operator = !operator.starts_with('0')
+        && operator
+            .parse::<usize>()
+            .is_ok_and(|m| (1..=num_operators).contains(&m));
+    is_operator && matches!(extension, "pub" | "secret" | secrets::ENCRYPTED_EXTENSION)
 }
@@ -0,0 +1,131 @@
+//! Backends for the hardware and operator interactions of a provisioning ceremony.
+
+use std::path::{Path, PathBuf};
+
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use qos_client::{
+    cli::{advanced_provision_yubikey, generate_file_key},
+    yubikey::pair_public_key,
+};
+use yubikey::{reader::Context, YubiKey};
+
+/// How keys are put onto each yubikey.
+///
+/// The touch and PIN policies of the provisioned keys are set by `qos_client` and are not
+/// configurable here.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct YubikeyPolicy {
+    /// File holding the PIN of the yubikeys, for yubikeys whose PIN was changed from the
+    /// default. `None` uses the default PIN.
+    pub pin_file: Option<PathBuf>,
+}
+
+/// Generates operator keys and provisions them onto yubikeys.
+pub trait Provisioner {
+    /// Generate a master seed at `secret_path` and write its public key to `pub_path`.
+    fn generate_key(&self, secret_path: &Path, pub_path: &Path) -> Result<(), String>;
+
+    /// Names of the yubikey readers attached. The ceremony runs when there is exactly one.
+    fn readers(&self) -> Result<Vec<String>, String>;
+
+    /// Provision the yubikey inserted in `reader` with the master seed at `secret_path`,
+    /// according to `policy`.
+    fn provision_yubikey(
+        &self,
+        reader: &str,
+        secret_path: &Path,
+        policy: &YubikeyPolicy,
+    ) -> Result<(), String>;
+
+    /// Read back the public key of the yubikey inserted in `reader`. `{m}.pub` files hold the
+    /// same bytes hex encoded.
+    fn public_key(&self, reader: &str) -> Result<Vec<u8>, String>;
+}
+
+/// Asks the operator running the ceremony to confirm each step.
+pub trait Prompt {
+    /// Ask the yes/no question `prompt`, defaulting to yes if `default_yes`.
+    fn confirm(&self, prompt: &str, default_yes: bool) -> Result<bool, String>;
+}
+
+/// [`Provisioner`] backed by the yubikey support in `qos_client`, with one reader per PC/SC
+/// reader attached to this machine. A yubikey is a reader of its own, so the yubikey must be
+/// inserted when the readers are listed.
+///
+/// `qos_client` provisions whichever yubikey it opens and can't be pointed at a reader, so
+/// provisioning fails rather than pick one if more than one yubikey is attached, and
+/// operators can't be provisioned on several readers at once. Public keys are read back from
+/// the selected reader.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QosProvisioner;
+
+impl Provisioner for QosProvisioner {
+    fn generate_key(&self, secret_path: &Path, pub_path: &Path) -> Result<(), String> {
+        generate_file_key(secret_path, pub_path);
+        Ok(())
+    }
+
+    fn readers(&self) -> Result<Vec<String>, String> {
+        let mut context = reader_context()?;
+        let readers = context
+            .iter()
+            .map_err(|e| format!("unable to list yubikey readers: {e:?}"))?;
+        Ok(readers.map(|r| r.name().into_owned()).collect())
+    }
+
+    fn provision_yubikey(
+        &self,
+        reader: &str,
+        secret_path: &Path,
+        policy: &YubikeyPolicy,
+    ) -> Result<(), String> {
+        let readers = self.readers()?;
+        if readers.len() > 1 {
+            return Err(format!(
+                "qos_client can't select reader {reader}, attach only the yubikey to provision, \
+                 found: {}",
+                readers.join(", ")
+            ));
+        }
+        advanced_provision_yubikey(secret_path, policy.pin_file.as_deref())
+            .map_err(|e| format!("{e:?}"))
+    }
+
+    fn public_key(&self, reader: &str) -> Result<Vec<u8>, String> {
+        let mut yubikey = open_reader(reader)?;
+        pair_public_key(&mut yubikey).map_err(|e| format!("{e:?}"))
+    }
+}
+
+fn reader_context() -> Result<Context, String> {
+    Context::open().map_err(|e| format!("unable to open PC/SC context: {e:?}"))
+}
+
+fn open_reader(reader: &str) -> Result<YubiKey, String> {
+    let mut context = reader_context()?;
+    let mut readers = context
+        .iter()
+        .map_err(|e| format!("unable to list yubikey readers: {e:?}"))?;
+    readers
+        .find(|r| r.name() == reader)
+        .ok_or_else(|| format!("yubikey reader {reader} not found"))?
+        .open()
+        .map_err(|e| format!("unable to open yubikey in {reader}: {e:?}"))
+}
+
+/// [`Prompt`] on the terminal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Terminal;
+
+impl Prompt for Terminal {
+    fn confirm(&self, prompt: &str, default_yes: bool) -> Result<bool, String> {
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(default_yes)
+            .show_default(true)
+            .wait_for_newline(true)
+            .report(false)
+            .interact()
+            .map_err(|e| e.to_string())
+    }
+}
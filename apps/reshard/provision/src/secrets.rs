@@ -0,0 +1,70 @@
+//! Passphrase encryption of the master secrets kept in the output dir, see
+//! [`crate::Config::secrets_passphrase`].
+//!
+//! An encrypted secret is [`MAGIC`], a random Argon2id salt and AES-256-GCM nonce, then the
+//! ciphertext. The key is derived from the passphrase and salt with Argon2id's default
+//! parameters, so each file has its own key.
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, OsRng},
+    Aes256Gcm, Key, KeyInit, Nonce,
+};
+use argon2::Argon2;
+use zeroize::Zeroizing;
+
+/// Leading bytes of an encrypted secret, identifying the format and its version.
+pub const MAGIC: &[u8; 8] = b"RSSECv1\0";
+
+/// Extension of encrypted secret files, which are named `{m}.secret.enc`.
+pub const ENCRYPTED_EXTENSION: &str = "secret.enc";
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Encrypt `secret` with a key derived from `passphrase`.
+pub fn encrypt(secret: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(&nonce, secret)
+        .map_err(|e| format!("unable to encrypt secret: {e}"))?;
+
+    let mut encrypted = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    encrypted.extend_from_slice(MAGIC);
+    encrypted.extend_from_slice(&salt);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+/// Decrypt a secret written by [`encrypt`].
+///
+/// Errors if `encrypted` is not an encrypted secret, or the passphrase is wrong. The secret is
+/// zeroized on drop.
+pub fn decrypt(encrypted: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let rest = encrypted
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or("not an encrypted secret")?;
+    if rest.len() < SALT_LEN + NONCE_LEN {
+        return Err("encrypted secret is truncated".to_string());
+    }
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| "unable to decrypt secret, wrong passphrase or corrupted file".to_string())
+}
+
+/// AES-256-GCM keyed with the Argon2id hash of `passphrase` and `salt`.
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm, String> {
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| format!("unable to derive key from passphrase: {e}"))?;
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key[..])))
+}
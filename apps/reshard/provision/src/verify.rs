@@ -0,0 +1,145 @@
+//! Re-check the yubikeys of a finished ceremony against its output dir.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::provisioner::{Prompt, Provisioner};
+
+/// Configuration for verifying a provisioned output dir.
+#[derive(Debug, Clone)]
+pub struct VerifyConfig {
+    pub num_operators: usize,
+    pub keys_per_operator: usize,
+    pub out: PathBuf,
+}
+
+/// Result of checking a single yubikey.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyCheck {
+    /// The yubikey holds the operator's public key.
+    Pass,
+    /// The yubikey holds a different public key.
+    Mismatch,
+    /// The yubikey or the operator's public key could not be read.
+    Error(String),
+}
+
+/// Pass/fail matrix of a verification, one row per operator and one column per yubikey.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub operators: Vec<Vec<KeyCheck>>,
+}
+
+impl KeyCheck {
+    fn outcome(&self) -> &'static str {
+        if *self == KeyCheck::Pass {
+            "pass"
+        } else {
+            "FAIL"
+        }
+    }
+}
+
+impl VerifyReport {
+    /// Whether every yubikey holds its operator's public key.
+    pub fn passed(&self) -> bool {
+        self.operators
+            .iter()
+            .flatten()
+            .all(|check| *check == KeyCheck::Pass)
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operator")?;
+        for k in 1..=self.operators.first().map_or(0, Vec::len) {
+            write!(f, "  key {k}")?;
+        }
+        writeln!(f)?;
+
+        for (m, checks) in self.operators.iter().enumerate() {
+            let mut row = format!("{:<8}", m + 1);
+            for check in checks {
+                row.push_str(&format!("  {:<5}", check.outcome()));
+            }
+            writeln!(f, "{}", row.trim_end())?;
+        }
+
+        for (m, checks) in self.operators.iter().enumerate() {
+            for (k, check) in checks.iter().enumerate() {
+                match check {
+                    KeyCheck::Pass => {}
+                    KeyCheck::Mismatch => writeln!(
+                        f,
+                        "yubikey {} of operator {} holds a different public key",
+                        k + 1,
+                        m + 1
+                    )?,
+                    KeyCheck::Error(e) => {
+                        writeln!(f, "yubikey {} of operator {}: {e}", k + 1, m + 1)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that every yubikey of every operator holds the public key in the operator's
+/// `{m}.pub`, asking the operator through `prompt` to insert each one in turn.
+///
+/// Nothing is written to the yubikeys. Keys of operators whose `{m}.pub` can not be read are
+/// reported as errors without being prompted for.
+pub fn verify(
+    cfg: &VerifyConfig,
+    provisioner: &impl Provisioner,
+    prompt: &impl Prompt,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let reader = provisioner
+        .readers()?
+        .into_iter()
+        .next()
+        .ok_or("no yubikey reader found")?;
+
+    let mut operators = Vec::with_capacity(cfg.num_operators);
+    for m in 1..=cfg.num_operators {
+        let pub_path = cfg.out.join(format!("{m}.pub"));
+        let expected = match read_public_key(&pub_path) {
+            Ok(expected) => expected,
+            Err(e) => {
+                operators.push(vec![KeyCheck::Error(e); cfg.keys_per_operator]);
+                continue;
+            }
+        };
+
+        let mut checks = Vec::with_capacity(cfg.keys_per_operator);
+        for k in 1..=cfg.keys_per_operator {
+            let ready_prompt =
+                format!("Please insert yubikey {k} for operator {m}. Are you ready?");
+            while !prompt.confirm(&ready_prompt, false)? {
+                println!("Oops that wasn't correct.");
+            }
+
+            let check = match provisioner.public_key(&reader) {
+                Ok(actual) if actual == expected => KeyCheck::Pass,
+                Ok(_) => KeyCheck::Mismatch,
+                Err(e) => KeyCheck::Error(format!("unable to read public key: {e}")),
+            };
+            println!("Checked yubikey {k}, operator {m}: {}", check.outcome());
+            checks.push(check);
+        }
+        operators.push(checks);
+    }
+
+    Ok(VerifyReport { operators })
+}
+
+fn read_public_key(pub_path: &Path) -> Result<Vec<u8>, String> {
+    let hex = fs::read_to_string(pub_path)
+        .map_err(|e| format!("unable to read {}: {e}", pub_path.display()))?;
+    qos_hex::decode(hex.trim())
+        .map_err(|e| format!("{} is not a hex public key: {e:?}", pub_path.display()))
+}
@@ -0,0 +1,332 @@
+//! CLI for offline reshard bundle verification.
+
+use clap::{Parser, Subcommand};
+use reshard_app::service::HashAlgorithm;
+use std::path::PathBuf;
+
+use crate::{bundle_pcrs, diff, reconstruct, run, share_hash, split, Config};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "reshard_verify",
+    version,
+    about = "Offline verification of a reshard bundle"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verify the bundle signature and contents. Also accepts a member file written by `split`
+    Verify(VerifyArgs),
+    /// Decrypt shares with member secrets and confirm they reconstruct the bundle's quorum key.
+    /// The quorum key is never printed
+    Reconstruct(ReconstructArgs),
+    /// Print the hex encoded hash of a decrypted share, the `share_hash` the bundle should
+    /// hold for it
+    ShareHash(ShareHashArgs),
+    /// Compare the quorum key, members, manifest hash and attestation PCRs of two bundles,
+    /// ignoring fields that differ on every run
+    Diff(DiffArgs),
+    /// Write one file per new share set member, named `<alias>.json`, holding the member's
+    /// encrypted share and share hash along with what is needed to verify them
+    Split(SplitArgs),
+    /// Print the PCRs of the bundle's attestation doc in hex, by index. The attestation doc's
+    /// certificate chain is not verified
+    Pcrs(PcrsArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    /// Path to the reshard bundle JSON
+    #[arg(long)]
+    bundle: PathBuf,
+
+    /// Hex encoded ephemeral public key file. Defaults to the key in the attestation doc, which
+    /// is then only trusted if the doc's certificate chain verifies and it attests to the
+    /// bundle's manifest
+    #[arg(long)]
+    ephemeral_pub: Option<PathBuf>,
+
+    /// Seconds since the unix epoch the attestation doc's certificate chain is verified at,
+    /// without `--ephemeral-pub`. Defaults to now
+    #[arg(long)]
+    attestation_time: Option<u64>,
+
+    /// Hex encoded quorum public key file the bundle is expected to reshard
+    #[arg(long)]
+    quorum_pub: Option<PathBuf>,
+
+    /// Print a machine readable JSON report instead of human readable lines
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReconstructArgs {
+    /// Path to the reshard bundle JSON
+    #[arg(long)]
+    bundle: PathBuf,
+
+    /// Directory of hex encoded member secrets named `<alias>.secret`
+    #[arg(long)]
+    secrets_dir: PathBuf,
+
+    /// Number of shares to reconstruct from
+    #[arg(long)]
+    threshold: usize,
+
+    /// Also check that no combination of fewer than `threshold` shares reconstructs the quorum
+    /// key. Share counts with more than `--max-safety-combinations` combinations are skipped and
+    /// fail the check
+    #[arg(long)]
+    check_threshold_safety: bool,
+
+    /// Most combinations checked per share count by `--check-threshold-safety`
+    #[arg(long, default_value_t = reconstruct::MAX_SAFETY_COMBINATIONS)]
+    max_safety_combinations: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct ShareHashArgs {
+    /// Path to the raw decrypted share
+    #[arg(long)]
+    share: PathBuf,
+
+    /// Hash algorithm of the bundle, its `hashAlgorithm`
+    #[arg(long, default_value_t = HashAlgorithm::Sha512)]
+    hash_algorithm: HashAlgorithm,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Path to the reshard bundle JSON to compare against
+    #[arg(long)]
+    old: PathBuf,
+
+    /// Path to the reshard bundle JSON to compare
+    #[arg(long)]
+    new: PathBuf,
+
+    /// Print a machine readable JSON report instead of human readable lines
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct SplitArgs {
+    /// Path to the reshard bundle JSON
+    #[arg(long)]
+    bundle: PathBuf,
+
+    /// Directory to write the member files to
+    #[arg(long)]
+    out_dir: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct PcrsArgs {
+    /// Path to the reshard bundle JSON, or a member file written by `split`
+    #[arg(long)]
+    bundle: PathBuf,
+
+    /// Print a JSON object of hex PCRs by index instead of one `PCR<index> <hex>` line each
+    #[arg(long)]
+    json: bool,
+}
+
+/// Verify binary command line interface.
+pub struct CLI;
+impl CLI {
+    /// Execute the command line interface.
+    pub fn execute() {
+        match Cli::parse().command {
+            Command::Verify(args) => verify(args),
+            Command::Reconstruct(args) => reconstruct(args),
+            Command::Diff(args) => diff(args),
+            Command::Split(args) => split(args),
+            Command::Pcrs(args) => pcrs(args),
+            Command::ShareHash(args) => match share_hash(&args.share, args.hash_algorithm) {
+                Ok(hash) => println!("{hash}"),
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+            },
+        }
+    }
+}
+
+fn verify(args: VerifyArgs) {
+    let cfg = Config {
+        bundle: args.bundle,
+        ephemeral_pub: args.ephemeral_pub,
+        quorum_pub: args.quorum_pub,
+        attestation_time: args.attestation_time,
+    };
+
+    let report = match run(cfg) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report serializes to json")
+        );
+    } else {
+        for check in &report.checks {
+            let outcome = if check.passed { "PASS" } else { "FAIL" };
+            println!("{outcome} {}", check.name);
+            if !check.passed {
+                println!("  expected: {}", check.expected);
+                println!("  computed: {}", check.computed);
+            }
+        }
+    }
+
+    if !report.passed {
+        std::process::exit(1);
+    }
+}
+
+fn reconstruct(args: ReconstructArgs) {
+    let cfg = reconstruct::Config {
+        bundle: args.bundle,
+        secrets_dir: args.secrets_dir,
+        threshold: args.threshold,
+        check_threshold_safety: args.check_threshold_safety,
+        max_safety_combinations: args.max_safety_combinations,
+    };
+
+    let report = match reconstruct::run(cfg) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let members = report.members_used.join(", ");
+    if report.matched {
+        println!("PASS quorum key reconstructed from shares of {members} matches the bundle");
+    } else {
+        println!(
+            "FAIL quorum key reconstructed from shares of {members} does not match the bundle"
+        );
+        if let Some(diagnosis) = &report.diagnosis {
+            println!("  likely cause: {diagnosis}");
+        }
+    }
+
+    let max_combinations = args.max_safety_combinations;
+    for safety in &report.threshold_safety {
+        let r = safety.shares;
+        if safety.skipped {
+            println!(
+                "SKIP r={r}: {} combinations exceeds the limit of {max_combinations}",
+                safety.combinations,
+            );
+            continue;
+        }
+
+        let outcome = if safety.matches == 0 { "PASS" } else { "FAIL" };
+        println!(
+            "{outcome} r={r}: reconstruct_errs={}, non-matching_reconstructions={}, matches={}",
+            safety.reconstruct_errors, safety.mismatches, safety.matches
+        );
+    }
+    let skipped = report.skipped_safety_checks();
+    if skipped > 0 {
+        println!(
+            "FAIL {skipped} of {} share counts were not checked, raise --max-safety-combinations \
+             to check them",
+            report.threshold_safety.len()
+        );
+    }
+
+    if !report.passed() {
+        std::process::exit(1);
+    }
+}
+
+fn diff(args: DiffArgs) {
+    let cfg = diff::Config {
+        old: args.old,
+        new: args.new,
+    };
+
+    let report = match diff::run(cfg) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report serializes to json")
+        );
+    } else {
+        for field in &report.fields {
+            let outcome = if field.differs { "DIFF" } else { "SAME" };
+            println!("{outcome} {}", field.name);
+            if field.differs {
+                println!("  old: {}", field.old);
+                println!("  new: {}", field.new);
+            }
+        }
+    }
+
+    if !report.identical {
+        std::process::exit(1);
+    }
+}
+
+fn split(args: SplitArgs) {
+    let cfg = split::Config {
+        bundle: args.bundle,
+        out_dir: args.out_dir,
+    };
+
+    match split::run(cfg) {
+        Ok(paths) => {
+            for path in paths {
+                println!("{}", path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn pcrs(args: PcrsArgs) {
+    let pcrs = match bundle_pcrs(&args.bundle) {
+        Ok(pcrs) => pcrs,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&pcrs).expect("pcrs serialize to json")
+        );
+    } else {
+        for (index, pcr) in &pcrs {
+            println!("PCR{index} {pcr}");
+        }
+    }
+}
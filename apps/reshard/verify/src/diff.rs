@@ -0,0 +1,130 @@
+//! Compare two reshard bundles, e.g. those of a ceremony and its rerun.
+
+use qos_core::protocol::QosHash;
+use reshard_app::service::ReshardBundle;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+};
+
+use crate::{attested_pcrs, load_bundle};
+
+/// Configuration for [`run`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Path to the bundle JSON compared against.
+    pub old: PathBuf,
+    /// Path to the bundle JSON compared.
+    pub new: PathBuf,
+}
+
+/// Outcome of comparing two bundles.
+///
+/// Only fields that identify a ceremony are compared. Fields that differ on every run even with
+/// identical inputs, like the encrypted shares, share hashes, signature and the attestation
+/// doc's timestamp and ephemeral key, are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffReport {
+    /// True when no compared field differs.
+    pub identical: bool,
+    /// Every compared field, whether it differs or not.
+    pub fields: Vec<FieldDiff>,
+}
+
+/// A compared field of both bundles. `old` and `new` are hex where they are digests or keys,
+/// and `absent` where only one bundle has the field.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldDiff {
+    /// Name of the field, e.g. `quorum_public_key` or the alias of a member.
+    pub name: String,
+    /// True when `old` and `new` are not the same.
+    pub differs: bool,
+    /// Value of the field in the old bundle.
+    pub old: String,
+    /// Value of the field in the new bundle.
+    pub new: String,
+}
+
+impl FieldDiff {
+    fn new(name: String, old: Option<String>, new: Option<String>) -> Self {
+        Self {
+            name,
+            differs: old != new,
+            old: old.unwrap_or_else(|| "absent".to_string()),
+            new: new.unwrap_or_else(|| "absent".to_string()),
+        }
+    }
+}
+
+/// Load both bundles of `cfg` and compare them, see [`diff_bundles`].
+pub fn run(cfg: Config) -> Result<DiffReport, Box<dyn std::error::Error>> {
+    let old = load_bundle(&cfg.old)?;
+    let new = load_bundle(&cfg.new)?;
+    Ok(diff_bundles(&old, &new)?)
+}
+
+/// Compare the quorum key, share set members, manifest hash and attestation PCRs of `old` and
+/// `new`.
+///
+/// Members are compared by alias, regardless of their order in the bundles. Errors if either
+/// attestation doc can not be parsed.
+pub fn diff_bundles(old: &ReshardBundle, new: &ReshardBundle) -> Result<DiffReport, String> {
+    let mut fields = vec![
+        FieldDiff::new(
+            "quorum_public_key".to_string(),
+            Some(qos_hex::encode(&old.quorum_public_key)),
+            Some(qos_hex::encode(&new.quorum_public_key)),
+        ),
+        FieldDiff::new(
+            "manifest_hash".to_string(),
+            Some(qos_hex::encode(&old.manifest_envelope.manifest.qos_hash())),
+            Some(qos_hex::encode(&new.manifest_envelope.manifest.qos_hash())),
+        ),
+    ];
+
+    fields.extend(diff_maps("member", &members(old), &members(new)));
+
+    let old_pcrs = attested_pcrs(&old.attestation_doc).map_err(|e| format!("old bundle: {e}"))?;
+    let new_pcrs = attested_pcrs(&new.attestation_doc).map_err(|e| format!("new bundle: {e}"))?;
+    fields.extend(diff_maps("pcr", &old_pcrs, &new_pcrs));
+
+    Ok(DiffReport {
+        identical: fields.iter().all(|field| !field.differs),
+        fields,
+    })
+}
+
+/// A [`FieldDiff`] named `{prefix} {key}` for every key in either map.
+fn diff_maps<K: Ord + fmt::Display>(
+    prefix: &str,
+    old: &BTreeMap<K, String>,
+    new: &BTreeMap<K, String>,
+) -> Vec<FieldDiff> {
+    let keys: BTreeSet<&K> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .map(|key| {
+            FieldDiff::new(
+                format!("{prefix} {key}"),
+                old.get(key).cloned(),
+                new.get(key).cloned(),
+            )
+        })
+        .collect()
+}
+
+/// Hex encoded pub key of each member, by alias.
+fn members(bundle: &ReshardBundle) -> BTreeMap<String, String> {
+    bundle
+        .member_outputs
+        .iter()
+        .map(|output| {
+            (
+                output.share_set_member.alias.clone(),
+                qos_hex::encode(&output.share_set_member.pub_key),
+            )
+        })
+        .collect()
+}
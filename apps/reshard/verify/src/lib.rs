@@ -0,0 +1,305 @@
+//! Offline verification of a reshard bundle: its signature, attestation and the shares of the
+//! new share set, without access to the enclave that produced it.
+
+pub mod cli;
+pub mod diff;
+pub mod reconstruct;
+pub mod split;
+
+use qos_core::protocol::QosHash;
+use qos_nsm::nitro::{attestation_doc_from_der, cert_from_pem, AWS_ROOT_CERT_PEM};
+use qos_p256::P256Public;
+use reshard_app::service::{HashAlgorithm, ReshardBundle};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Public configuration passed in from the CLI (or tests).
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Path to the bundle JSON, or a member file, to verify.
+    pub bundle: PathBuf,
+    /// Path to the hex ephemeral pub key the bundle must be signed by. `None` to use the one in
+    /// the attestation doc, see [`EphemeralKey::Attested`].
+    pub ephemeral_pub: Option<PathBuf>,
+    /// Path to the hex quorum pub key the bundle must be for, if any.
+    pub quorum_pub: Option<PathBuf>,
+    /// Time in seconds since the unix epoch the attestation doc's certificate chain is
+    /// verified at, when there is no `ephemeral_pub`. `None` for now.
+    pub attestation_time: Option<u64>,
+}
+
+/// The ephemeral key a bundle must be signed with, see [`verify_bundle`].
+pub enum EphemeralKey {
+    /// A key the verifier already trusts, e.g. one it saw the enclave boot with. The attestation
+    /// doc is not checked.
+    Given(P256Public),
+    /// The key in the bundle's attestation doc. It is only trusted once the doc's certificate
+    /// chain verifies against the AWS Nitro root certificate at `validation_time`, in seconds
+    /// since the unix epoch.
+    Attested {
+        /// Time the certificates of the chain must be valid at.
+        validation_time: u64,
+    },
+}
+
+impl EphemeralKey {
+    /// [`Self::Attested`], verifying the certificate chain at the current time.
+    pub fn attested_now() -> Self {
+        let validation_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is after the unix epoch")
+            .as_secs();
+        Self::Attested { validation_time }
+    }
+}
+
+/// Outcome of verifying a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    /// True when every check passed.
+    pub passed: bool,
+    /// Every check, in the order they ran.
+    pub checks: Vec<CheckResult>,
+}
+
+/// Outcome of a single verification check. `expected` and `computed` are hex where they
+/// are digests or keys.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckResult {
+    /// What was checked, e.g. `quorum_public_key`.
+    pub name: String,
+    /// True when `computed` is `expected`.
+    pub passed: bool,
+    /// Value the bundle must have.
+    pub expected: String,
+    /// Value found in the bundle.
+    pub computed: String,
+}
+
+impl CheckResult {
+    fn new(name: &str, expected: String, computed: String) -> Self {
+        Self {
+            name: name.to_string(),
+            passed: expected == computed,
+            expected,
+            computed,
+        }
+    }
+}
+
+/// Load the bundle and pub keys of `cfg` and verify the bundle, see [`verify_bundle`].
+pub fn run(cfg: Config) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let bundle = load_bundle(&cfg.bundle)?;
+
+    let ephemeral_pub = cfg
+        .ephemeral_pub
+        .map(|path| {
+            P256Public::from_hex_file(path)
+                .map_err(|e| format!("unable to load ephemeral pub key: {e:?}"))
+        })
+        .transpose()?;
+    let quorum_pub = cfg
+        .quorum_pub
+        .map(|path| {
+            P256Public::from_hex_file(path)
+                .map_err(|e| format!("unable to load quorum pub key: {e:?}"))
+        })
+        .transpose()?;
+    let ephemeral_key = match (ephemeral_pub, cfg.attestation_time) {
+        (Some(ephemeral_pub), _) => EphemeralKey::Given(ephemeral_pub),
+        (None, Some(validation_time)) => EphemeralKey::Attested { validation_time },
+        (None, None) => EphemeralKey::attested_now(),
+    };
+
+    Ok(verify_bundle(&bundle, ephemeral_key, quorum_pub.as_ref()))
+}
+
+/// Check `bundle` is signed by `ephemeral_key`, has distinct members and, if given, is for
+/// `quorum_pub`.
+///
+/// With an [`EphemeralKey::Attested`] key the attestation doc must also verify and attest to the
+/// bundle's manifest envelope, and the signature check fails if the doc does not verify, as
+/// anyone can make a doc holding their own key.
+///
+/// Only reads public parts of the bundle, so reports are safe to hand to anyone.
+pub fn verify_bundle(
+    bundle: &ReshardBundle,
+    ephemeral_key: EphemeralKey,
+    quorum_pub: Option<&P256Public>,
+) -> VerifyReport {
+    let mut checks = Vec::new();
+    let ephemeral_pub = match ephemeral_key {
+        EphemeralKey::Given(ephemeral_pub) => Some(ephemeral_pub),
+        EphemeralKey::Attested { validation_time } => {
+            let (attestation_checks, attested_pub) = check_attestation(bundle, validation_time);
+            checks.extend(attestation_checks);
+            attested_pub
+        }
+    };
+
+    checks.push(check_signature(bundle, ephemeral_pub.as_ref()));
+    checks.push(check_unique_members(bundle));
+
+    if let Some(quorum_pub) = quorum_pub {
+        checks.push(CheckResult::new(
+            "quorum_public_key",
+            qos_hex::encode(&quorum_pub.to_bytes()),
+            qos_hex::encode(&bundle.quorum_public_key),
+        ));
+    }
+
+    VerifyReport {
+        passed: checks.iter().all(|c| c.passed),
+        checks,
+    }
+}
+
+/// Read a bundle as returned by the reshard host, or reassemble it from a member file written
+/// by [`split::run`].
+pub fn load_bundle(path: &PathBuf) -> Result<ReshardBundle, Box<dyn std::error::Error>> {
+    let json = fs::read_to_string(path)?;
+    match serde_json::from_str(&json) {
+        Ok(bundle) => Ok(bundle),
+        Err(e) => match serde_json::from_str::<split::MemberFile>(&json) {
+            Ok(member_file) => Ok(member_file.bundle()?),
+            // Report why it is not a bundle, the common case
+            Err(_) => Err(e.into()),
+        },
+    }
+}
+
+/// Hex encoded `hash_algorithm` digest of the decrypted share in `share_path`, as the enclave
+/// computes each member output's `share_hash`.
+pub fn share_hash(
+    share_path: &PathBuf,
+    hash_algorithm: HashAlgorithm,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let share = fs::read(share_path)
+        .map_err(|e| format!("unable to read share {}: {e}", share_path.display()))?;
+    Ok(qos_hex::encode(&hash_algorithm.digest(&share)))
+}
+
+/// Fields of an attestation doc the verifier relies on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestedFields {
+    /// Raw encoded ephemeral public key, if the doc attests to one.
+    pub public_key: Option<Vec<u8>>,
+    /// User data, the hash of the manifest envelope for docs made by QOS.
+    pub user_data: Option<Vec<u8>>,
+}
+
+/// Parse the fields of `attestation_doc` the verifier relies on.
+///
+/// This does not verify the attestation doc's certificate chain.
+pub fn attested_fields(attestation_doc: &[u8]) -> Result<AttestedFields, String> {
+    let doc = qos_nsm::nitro::unsafe_attestation_doc_from_der(attestation_doc)
+        .map_err(|e| format!("unable to parse attestation doc: {e:?}"))?;
+
+    Ok(AttestedFields {
+        public_key: doc.public_key.map(|key| key.into_vec()),
+        user_data: doc.user_data.map(|data| data.into_vec()),
+    })
+}
+
+/// Hex encoded PCRs of `attestation_doc`, by index, e.g. to configure the PCRs a bundle is
+/// expected to attest to.
+///
+/// This does not verify the attestation doc's certificate chain.
+pub fn attested_pcrs(attestation_doc: &[u8]) -> Result<BTreeMap<usize, String>, String> {
+    let doc = qos_nsm::nitro::unsafe_attestation_doc_from_der(attestation_doc)
+        .map_err(|e| format!("unable to parse attestation doc: {e:?}"))?;
+
+    Ok(doc
+        .pcrs
+        .iter()
+        .map(|(index, pcr)| (*index, qos_hex::encode(pcr)))
+        .collect())
+}
+
+/// [`attested_pcrs`] of the attestation doc of the bundle or member file at `path`.
+pub fn bundle_pcrs(path: &PathBuf) -> Result<BTreeMap<usize, String>, Box<dyn std::error::Error>> {
+    let bundle = load_bundle(path)?;
+    Ok(attested_pcrs(&bundle.attestation_doc)?)
+}
+
+/// The bundle's attestation doc has a certificate chain up to the AWS Nitro root certificate,
+/// valid at `validation_time`, and its user data is the qos hash of the bundle's manifest
+/// envelope. Returns the checks, and the ephemeral key the doc attests to if the chain is valid.
+fn check_attestation(
+    bundle: &ReshardBundle,
+    validation_time: u64,
+) -> (Vec<CheckResult>, Option<P256Public>) {
+    let root_cert = cert_from_pem(AWS_ROOT_CERT_PEM).expect("AWS root certificate is valid PEM");
+    let expected = "valid certificate chain".to_string();
+    let doc = match attestation_doc_from_der(&bundle.attestation_doc, &root_cert, validation_time) {
+        Ok(doc) => doc,
+        Err(e) => {
+            let computed = format!("invalid certificate chain: {e:?}");
+            return (
+                vec![CheckResult::new("attestation_doc", expected, computed)],
+                None,
+            );
+        }
+    };
+    let chain = CheckResult::new("attestation_doc", expected.clone(), expected);
+
+    let manifest_hash = CheckResult::new(
+        "manifest_hash",
+        qos_hex::encode(&bundle.manifest_envelope.qos_hash()),
+        doc.user_data
+            .as_ref()
+            .map(|user_data| qos_hex::encode(user_data))
+            .unwrap_or_else(|| "absent".to_string()),
+    );
+    let ephemeral_pub = doc
+        .public_key
+        .and_then(|public_key| P256Public::from_bytes(&public_key).ok());
+
+    (vec![chain, manifest_hash], ephemeral_pub)
+}
+
+/// The ephemeral key signed `hash(borsh(member_outputs))`, see
+/// [`ReshardBundle::member_outputs_digest`]. Fails if there is no trusted ephemeral key.
+fn check_signature(bundle: &ReshardBundle, ephemeral_pub: Option<&P256Public>) -> CheckResult {
+    let digest = bundle.member_outputs_digest();
+
+    let validity = match ephemeral_pub.map(|key| key.verify(&digest, &bundle.signature)) {
+        Some(Ok(())) => "valid",
+        Some(Err(_)) => "invalid",
+        None => "unattested",
+    };
+    let digest = qos_hex::encode(&digest);
+
+    CheckResult::new(
+        "signature",
+        format!("valid signature over {digest}"),
+        format!("{validity} signature over {digest}"),
+    )
+}
+
+/// Every member has a distinct pub key and share hash.
+fn check_unique_members(bundle: &ReshardBundle) -> CheckResult {
+    let n = bundle.member_outputs.len();
+    let pub_keys: HashSet<_> = bundle
+        .member_outputs
+        .iter()
+        .map(|m| &m.share_set_member.pub_key)
+        .collect();
+    let share_hashes: HashSet<_> = bundle.member_outputs.iter().map(|m| m.share_hash).collect();
+
+    CheckResult::new(
+        "unique_members",
+        format!("{n} pub keys, {n} share hashes"),
+        format!(
+            "{} pub keys, {} share hashes",
+            pub_keys.len(),
+            share_hashes.len()
+        ),
+    )
+}
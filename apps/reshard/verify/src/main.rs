@@ -0,0 +1,7 @@
+//! Offline reshard bundle verification, see [`reshard_verify::cli`].
+
+use reshard_verify::cli::CLI;
+
+fn main() {
+    CLI::execute()
+}
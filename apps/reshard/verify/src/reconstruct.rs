@@ -0,0 +1,368 @@
+//! Offline quorum key recovery from a bundle and member secrets.
+
+use qos_core::protocol::services::genesis::GenesisMemberOutput;
+use qos_p256::P256Pair;
+use reshard_app::service::{alias_is_file_name, HashAlgorithm};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::load_bundle;
+
+/// Default [`Config::max_safety_combinations`].
+pub const MAX_SAFETY_COMBINATIONS: usize = 10_000;
+
+/// Configuration for [`run`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Path to the bundle JSON, or a member file, to reconstruct from.
+    pub bundle: PathBuf,
+    /// Directory with hex encoded member secrets named `<alias>.secret`.
+    pub secrets_dir: PathBuf,
+    /// Number of shares to reconstruct from.
+    pub threshold: usize,
+    /// Also check that no combination of fewer than `threshold` shares reconstructs the
+    /// quorum key.
+    pub check_threshold_safety: bool,
+    /// Threshold safety checks are skipped for share counts with more combinations than this.
+    pub max_safety_combinations: usize,
+}
+
+/// Outcome of a reconstruction. Never contains key material.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconstructReport {
+    /// Aliases of the members whose shares were used.
+    pub members_used: Vec<String>,
+    /// True if the reconstructed quorum key matches the bundle's `quorum_public_key`.
+    pub matched: bool,
+    /// Likely cause if the quorum key did not match.
+    pub diagnosis: Option<Diagnosis>,
+    /// Results of the threshold safety check, one entry per share count below the threshold.
+    /// Empty unless requested.
+    pub threshold_safety: Vec<ThresholdSafety>,
+}
+
+impl ReconstructReport {
+    /// True if the quorum key matched and every share count below the threshold was checked
+    /// without reproducing it. A skipped share count is not a pass, see
+    /// [`Self::skipped_safety_checks`].
+    pub fn passed(&self) -> bool {
+        self.matched
+            && self
+                .threshold_safety
+                .iter()
+                .all(|s| !s.skipped && s.matches == 0)
+    }
+
+    /// Number of share counts whose threshold safety check was skipped for having too many
+    /// combinations.
+    pub fn skipped_safety_checks(&self) -> usize {
+        self.threshold_safety.iter().filter(|s| s.skipped).count()
+    }
+}
+
+/// Likely cause of a failed reconstruction.
+///
+/// `shares_reconstruct` can't tell why shares don't add up to the quorum key, so the common
+/// causes are told apart by what the secrets dir holds and by retrying with more shares.
+/// [`run`] fails with the causes that prevent reconstruction and reports the others.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase", tag = "cause")]
+pub enum Diagnosis {
+    /// Fewer member secrets than the threshold were found.
+    InsufficientShares {
+        /// Number of member secrets found.
+        found: usize,
+        /// Threshold given.
+        threshold: usize,
+    },
+    /// Secrets that are not those of the bundle's share set, by alias.
+    MismatchedShareSet {
+        /// Aliases of the secrets that are not for the key of the member of that alias, or of no
+        /// member at all.
+        aliases: Vec<String>,
+    },
+    /// The quorum key only reconstructs with more shares than the threshold given.
+    WrongThreshold {
+        /// Threshold given.
+        threshold: usize,
+        /// Fewest shares the quorum key reconstructs from.
+        reconstructs_with: usize,
+    },
+    /// The quorum key does not reconstruct with any number of the available shares, e.g.
+    /// because the bundle is not the one the shares were encrypted for.
+    QuorumKeyMismatch {
+        /// Most shares tried.
+        shares: usize,
+    },
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientShares { found, threshold } => {
+                write!(
+                    f,
+                    "found secrets for {found} members but threshold is {threshold}"
+                )
+            }
+            Self::MismatchedShareSet { aliases } => write!(
+                f,
+                "secrets of {} are not those of the bundle's share set members",
+                aliases.join(", ")
+            ),
+            Self::WrongThreshold {
+                threshold,
+                reconstructs_with,
+            } => write!(
+                f,
+                "threshold {threshold} looks wrong: the quorum key reconstructs with \
+                 {reconstructs_with} shares"
+            ),
+            Self::QuorumKeyMismatch { shares } => write!(
+                f,
+                "the quorum key does not reconstruct from up to {shares} shares, the shares \
+                 were likely made for another bundle"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Diagnosis {}
+
+/// Reconstruction attempts with every combination of `shares` decrypted shares.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThresholdSafety {
+    /// Number of shares per combination.
+    pub shares: usize,
+    /// Number of combinations of `shares` decrypted shares.
+    pub combinations: usize,
+    /// True if there were more than [`Config::max_safety_combinations`] combinations to check.
+    pub skipped: bool,
+    /// Combinations that did not reconstruct a master seed.
+    pub reconstruct_errors: usize,
+    /// Combinations that reconstructed a master seed of another key.
+    pub mismatches: usize,
+    /// Combinations that reproduced the quorum key. Anything but 0 is a failure.
+    pub matches: usize,
+}
+
+/// Decrypt `threshold` shares with the member secrets found in `secrets_dir`, reconstruct the
+/// quorum key and compare it against the bundle's quorum public key.
+pub fn run(cfg: Config) -> Result<ReconstructReport, Box<dyn std::error::Error>> {
+    let bundle = load_bundle(&cfg.bundle)?;
+
+    // The safety check wants every share we can decrypt, reconstruction only needs threshold
+    let wanted = if cfg.check_threshold_safety {
+        bundle.member_outputs.len()
+    } else {
+        cfg.threshold
+    };
+
+    let mut members_used = Vec::with_capacity(wanted);
+    let mut shares = Vec::with_capacity(wanted);
+    let mut mismatched = Vec::new();
+    let mut outputs = bundle.member_outputs.iter();
+    for output in outputs.by_ref() {
+        if shares.len() == wanted {
+            break;
+        }
+
+        match decrypt_share(output, bundle.hash_algorithm, &cfg.secrets_dir)? {
+            MemberShare::Decrypted(share) => {
+                members_used.push(output.share_set_member.alias.clone());
+                shares.push(share);
+            }
+            MemberShare::Mismatched => mismatched.push(output.share_set_member.alias.clone()),
+            MemberShare::Missing => {}
+        }
+    }
+
+    if shares.len() < cfg.threshold {
+        mismatched.extend(unknown_secrets(&bundle.member_outputs, &cfg.secrets_dir)?);
+        if !mismatched.is_empty() {
+            mismatched.sort();
+            return Err(Diagnosis::MismatchedShareSet {
+                aliases: mismatched,
+            }
+            .into());
+        }
+        return Err(Diagnosis::InsufficientShares {
+            found: shares.len(),
+            threshold: cfg.threshold,
+        }
+        .into());
+    }
+    members_used.truncate(cfg.threshold);
+
+    let matched = qos_crypto::shamir::shares_reconstruct(&shares[..cfg.threshold])
+        .is_ok_and(|seed| reconstructs_quorum_key(&seed, &bundle.quorum_public_key));
+
+    let diagnosis = if matched {
+        None
+    } else {
+        // Diagnose with every share available
+        for output in outputs {
+            if let MemberShare::Decrypted(share) =
+                decrypt_share(output, bundle.hash_algorithm, &cfg.secrets_dir)?
+            {
+                shares.push(share);
+            }
+        }
+        Some(diagnose_mismatch(
+            &shares,
+            cfg.threshold,
+            &bundle.quorum_public_key,
+        ))
+    };
+
+    let threshold_safety = if cfg.check_threshold_safety {
+        (1..cfg.threshold)
+            .map(|r| {
+                check_threshold_safety(
+                    &shares,
+                    r,
+                    &bundle.quorum_public_key,
+                    cfg.max_safety_combinations,
+                )
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Ok(ReconstructReport {
+        members_used,
+        matched,
+        diagnosis,
+        threshold_safety,
+    })
+}
+
+/// Why the first `threshold` of `shares` don't reconstruct the quorum key.
+fn diagnose_mismatch(shares: &[Vec<u8>], threshold: usize, quorum_public_key: &[u8]) -> Diagnosis {
+    (threshold + 1..=shares.len())
+        .find(|&n| {
+            qos_crypto::shamir::shares_reconstruct(&shares[..n])
+                .is_ok_and(|seed| reconstructs_quorum_key(&seed, quorum_public_key))
+        })
+        .map_or(
+            Diagnosis::QuorumKeyMismatch {
+                shares: shares.len(),
+            },
+            |reconstructs_with| Diagnosis::WrongThreshold {
+                threshold,
+                reconstructs_with,
+            },
+        )
+}
+
+/// A member's share, as far as the secrets dir allows decrypting it.
+enum MemberShare {
+    Decrypted(Vec<u8>),
+    /// No secret for the member.
+    Missing,
+    /// The secret for the member's alias is not for the member's public key.
+    Mismatched,
+}
+
+/// Decrypt the member's share if their secret is in `secrets_dir`, checking it against the
+/// share hash computed with `hash_algorithm`.
+fn decrypt_share(
+    output: &GenesisMemberOutput,
+    hash_algorithm: HashAlgorithm,
+    secrets_dir: &Path,
+) -> Result<MemberShare, String> {
+    let alias = &output.share_set_member.alias;
+    if !alias_is_file_name(alias) {
+        return Err(format!("alias {alias:?} can not be used as a file name"));
+    }
+    let secret_path = secrets_dir.join(format!("{alias}.secret"));
+    if !secret_path.exists() {
+        return Ok(MemberShare::Missing);
+    }
+
+    let pair = P256Pair::from_hex_file(&secret_path)
+        .map_err(|e| format!("unable to load secret for '{alias}': {e:?}"))?;
+    if pair.public_key().to_bytes() != output.share_set_member.pub_key {
+        return Ok(MemberShare::Mismatched);
+    }
+    let share = pair
+        .decrypt(&output.encrypted_quorum_key_share)
+        .map_err(|e| format!("unable to decrypt share for '{alias}': {e:?}"))?;
+    if hash_algorithm.digest(&share) != output.share_hash {
+        return Err(format!("share hash mismatch for '{alias}'"));
+    }
+
+    Ok(MemberShare::Decrypted(share))
+}
+
+/// Aliases of the secrets in `secrets_dir` that belong to none of the members in `outputs`.
+fn unknown_secrets(
+    outputs: &[GenesisMemberOutput],
+    secrets_dir: &Path,
+) -> Result<Vec<String>, String> {
+    let entries = fs::read_dir(secrets_dir)
+        .map_err(|e| format!("unable to read {}: {e}", secrets_dir.display()))?;
+    let mut unknown = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("unable to read {}: {e}", secrets_dir.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(alias) = name.strip_suffix(".secret") else {
+            continue;
+        };
+        if !outputs.iter().any(|o| o.share_set_member.alias == alias) {
+            unknown.push(alias.to_string());
+        }
+    }
+    Ok(unknown)
+}
+
+fn reconstructs_quorum_key(seed: &[u8], quorum_public_key: &[u8]) -> bool {
+    // Wrong length => cannot match
+    let Ok(seed) = <[u8; 32]>::try_from(seed) else {
+        return false;
+    };
+
+    P256Pair::from_master_seed(&seed)
+        .map(|pair| pair.public_key().to_bytes() == quorum_public_key)
+        .unwrap_or(false)
+}
+
+fn check_threshold_safety(
+    shares: &[Vec<u8>],
+    r: usize,
+    quorum_public_key: &[u8],
+    max_combinations: usize,
+) -> ThresholdSafety {
+    let mut safety = ThresholdSafety {
+        shares: r,
+        combinations: n_choose_k(shares.len(), r),
+        skipped: false,
+        reconstruct_errors: 0,
+        mismatches: 0,
+        matches: 0,
+    };
+    if safety.combinations > max_combinations {
+        safety.skipped = true;
+        return safety;
+    }
+
+    for combo in qos_crypto::n_choose_k::combinations(shares, r) {
+        match qos_crypto::shamir::shares_reconstruct(&combo) {
+            Err(_) => safety.reconstruct_errors += 1,
+            Ok(seed) if reconstructs_quorum_key(&seed, quorum_public_key) => safety.matches += 1,
+            Ok(_) => safety.mismatches += 1,
+        }
+    }
+
+    safety
+}
+
+/// Number of `k` sized combinations of `n` items, saturating on overflow.
+fn n_choose_k(n: usize, k: usize) -> usize {
+    (0..k).fold(1usize, |acc, i| acc.saturating_mul(n - i) / (i + 1))
+}
@@ -0,0 +1,145 @@
+//! Split a reshard bundle into one file per new share set member, for distribution.
+//!
+//! The ephemeral key signs the outputs of every member at once, so a member file still carries
+//! the other members' outputs. Their shares are encrypted to their own keys, and the member's
+//! own pub key, encrypted share and share hash are kept apart so they are easy to find.
+
+use qos_core::protocol::services::{boot::ManifestEnvelope, genesis::GenesisMemberOutput};
+use reshard_app::service::{alias_is_file_name, HashAlgorithm, ReshardBundle};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::load_bundle;
+
+/// Configuration for [`run`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Path to the bundle JSON to split.
+    pub bundle: PathBuf,
+    /// Directory the member files are written to, created if missing.
+    pub out_dir: PathBuf,
+}
+
+/// The part of a bundle a single member needs, see [`split_bundle`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberFile {
+    /// The member's output: its pub key, encrypted quorum key share and share hash.
+    pub member_output: GenesisMemberOutput,
+    /// Position of `member_output` among the bundle's member outputs.
+    pub member_index: usize,
+    /// Outputs of the other members in bundle order, needed to recompute the signed digest.
+    pub other_member_outputs: Vec<GenesisMemberOutput>,
+    /// The bundle's quorum public key.
+    #[serde(with = "qos_hex::serde")]
+    pub quorum_public_key: Vec<u8>,
+    /// The bundle's attestation doc.
+    #[serde(with = "qos_hex::serde")]
+    pub attestation_doc: Vec<u8>,
+    /// The bundle's manifest envelope.
+    pub manifest_envelope: ManifestEnvelope,
+    /// The bundle's ephemeral key signature over all member outputs.
+    #[serde(with = "qos_hex::serde")]
+    pub signature: Vec<u8>,
+    /// The bundle's hash algorithm of the share hashes and signed digest.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+impl MemberFile {
+    /// Alias of the member the file is for.
+    pub fn alias(&self) -> &str {
+        &self.member_output.share_set_member.alias
+    }
+
+    /// The bundle the file was split from, to verify with [`crate::verify_bundle`].
+    ///
+    /// Errors if `member_index` is past the end of the member outputs.
+    pub fn bundle(&self) -> Result<ReshardBundle, String> {
+        if self.member_index > self.other_member_outputs.len() {
+            return Err(format!(
+                "member index {} is out of range for {} members",
+                self.member_index,
+                self.other_member_outputs.len() + 1
+            ));
+        }
+
+        let mut member_outputs = self.other_member_outputs.clone();
+        member_outputs.insert(self.member_index, self.member_output.clone());
+        Ok(ReshardBundle {
+            quorum_public_key: self.quorum_public_key.clone(),
+            attestation_doc: self.attestation_doc.clone(),
+            manifest_envelope: self.manifest_envelope.clone(),
+            member_outputs,
+            signature: self.signature.clone(),
+            hash_algorithm: self.hash_algorithm,
+        })
+    }
+}
+
+/// Split `bundle` into member files and write them to `<out_dir>/<alias>.json`, returning the
+/// paths written.
+pub fn run(cfg: Config) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let bundle = load_bundle(&cfg.bundle)?;
+    let files = split_bundle(&bundle)?;
+
+    fs::create_dir_all(&cfg.out_dir)
+        .map_err(|e| format!("unable to create {}: {e}", cfg.out_dir.display()))?;
+    let mut paths = Vec::with_capacity(files.len());
+    for file in &files {
+        let path = member_file_path(&cfg.out_dir, file.alias());
+        let json = serde_json::to_string_pretty(file)?;
+        fs::write(&path, json).map_err(|e| format!("unable to write {}: {e}", path.display()))?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// One [`MemberFile`] per member output of `bundle`, in bundle order.
+///
+/// Errors if an alias can not be used as a file name, or two members share an alias.
+pub fn split_bundle(bundle: &ReshardBundle) -> Result<Vec<MemberFile>, String> {
+    let mut aliases = HashSet::new();
+    for output in &bundle.member_outputs {
+        let alias = &output.share_set_member.alias;
+        if !alias_is_file_name(alias) {
+            return Err(format!("alias {alias:?} can not be used as a file name"));
+        }
+        if !aliases.insert(alias) {
+            return Err(format!("duplicate alias {alias:?}"));
+        }
+    }
+
+    Ok((0..bundle.member_outputs.len())
+        .map(|index| {
+            let mut other_member_outputs = bundle.member_outputs.clone();
+            let member_output = other_member_outputs.remove(index);
+            MemberFile {
+                member_output,
+                member_index: index,
+                other_member_outputs,
+                quorum_public_key: bundle.quorum_public_key.clone(),
+                attestation_doc: bundle.attestation_doc.clone(),
+                manifest_envelope: bundle.manifest_envelope.clone(),
+                signature: bundle.signature.clone(),
+                hash_algorithm: bundle.hash_algorithm,
+            }
+        })
+        .collect())
+}
+
+/// Path of the member file of `alias` in `dir`.
+pub fn member_file_path(dir: &Path, alias: &str) -> PathBuf {
+    dir.join(format!("{alias}.json"))
+}
+
+/// Read a member file as written by [`run`].
+pub fn load_member_file(path: &Path) -> Result<MemberFile, Box<dyn std::error::Error>> {
+    let json =
+        fs::read_to_string(path).map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    Ok(serde_json::from_str(&json)?)
+}
@@ -5,6 +5,8 @@
 use std::path::PathBuf;
 use std::path::Path;
 
+use sha2::{Digest, Sha256};
+
 fn main() {
     let crate_root = PathBuf::from(std::env!("CARGO_MANIFEST_DIR"));
     let repo_root = crate_root.parent().unwrap();
@@ -30,13 +32,38 @@ fn codegen(
     let proto_files: Vec<_> = proto_files.into_iter().map(|path| root_dir.join(path)).collect();
     let include_dirs: Vec<_> = include_dirs.into_iter().map(|path| root_dir.join(path)).collect();
 
+    let descriptor_path = out_dir.join("descriptor.bin");
     tonic_prost_build::configure()
-        .file_descriptor_set_path(out_dir.join("descriptor.bin"))
-        .out_dir(out_dir)
+        .file_descriptor_set_path(&descriptor_path)
+        .out_dir(&out_dir)
         .build_server(build_server)
         .build_client(build_client)
         .compile_protos(
             &proto_files,
             &include_dirs,
         ).unwrap();
+
+    stamp_descriptor_checksum(&out_dir, &descriptor_path);
+}
+
+/// Append the sha256 of the descriptor to each generated module, so the host can check at
+/// startup that the descriptor it serves was generated together with its service code.
+fn stamp_descriptor_checksum(out_dir: &Path, descriptor_path: &Path) {
+    let descriptor = std::fs::read(descriptor_path).unwrap();
+    let checksum: String = Sha256::digest(&descriptor)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    for entry in std::fs::read_dir(out_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "rs") {
+            let mut module = std::fs::read_to_string(&path).unwrap();
+            module.push_str(&format!(
+                "/// Hex encoded sha256 of the `descriptor.bin` generated alongside this module.\n\
+                 pub const FILE_DESCRIPTOR_SET_SHA256: &str = \"{checksum}\";\n"
+            ));
+            std::fs::write(&path, module).unwrap();
+        }
+    }
 }
@@ -1,15 +1,20 @@
 //! K8s compatible health check service. To use the health check service, something must
 //! implement [`AppHealthCheckable`].
 
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::watch, time::Instant};
 use tonic_health::{
     pb::health_server::HealthServer,
     server::{HealthReporter, HealthService},
-    ServingStatus,
 };
 
 /// Re-export the tonic health crate proto based types.
 pub use tonic_health::pb;
+/// Re-export the tonic health crate serving status.
+pub use tonic_health::ServingStatus;
 
 /// k8s terminology to check if a service is up, but not necessarily ready to serve traffic.
 pub const LIVENESS: &str = "liveness";
@@ -18,6 +23,10 @@ pub const READINESS: &str = "readiness";
 
 // Duration of sleep between app probes in seconds.
 const APP_PROBE_SLEEP_S: u64 = 5;
+// Cap on the sleep between app probes while the app is not serving, in seconds.
+const APP_PROBE_MAX_SLEEP_S: u64 = 60;
+// The probe loop is considered stalled if it does not tick within this many probe sleeps.
+const LIVENESS_MISSED_TICKS: u32 = 3;
 
 /// Something that can perform a health check on an app over a socket client.
 #[tonic::async_trait]
@@ -27,19 +36,234 @@ pub trait AppHealthCheckable {
 }
 
 /// Response to app_health_check
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct AppHealthResponse {
     /// HTTP status code. Assumes the only health response is 200 for backwards compatibility.
+    /// 200 means the app is up.
     pub code: i32,
+    /// Whether the app is ready to serve traffic, e.g. has loaded the state it serves. An app
+    /// that is up but not ready keeps the host out of rotation without being reported down.
+    pub ready: bool,
+}
+
+impl AppHealthResponse {
+    /// The app is up and ready to serve traffic.
+    pub fn ready() -> Self {
+        Self {
+            code: 200,
+            ready: true,
+        }
+    }
+
+    /// The app is up, but not ready to serve traffic.
+    pub fn not_ready() -> Self {
+        Self {
+            code: 200,
+            ready: false,
+        }
+    }
+}
+
+/// Sleep between app probes. Backs off exponentially from the probe interval, up to
+/// `APP_PROBE_MAX_SLEEP_S` or the interval if longer, while the app is down so a struggling
+/// enclave is not hammered.
+#[derive(Debug)]
+pub struct ProbeBackoff {
+    /// Consecutive probes that did not report serving.
+    failures: u32,
+    /// Sleep while the app is serving.
+    interval: Duration,
+}
+
+impl Default for ProbeBackoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(APP_PROBE_SLEEP_S))
+    }
+}
+
+impl ProbeBackoff {
+    /// Backoff from a probe `interval` instead of the default.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            failures: 0,
+            interval,
+        }
+    }
+
+    /// Back off from `interval` from the next sleep on, keeping the failures so far.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Record the result of a probe and return how long to sleep before the next one.
+    pub fn next_sleep(&mut self, status: ServingStatus) -> Duration {
+        let base = self.interval;
+        if status == ServingStatus::Serving {
+            self.failures = 0;
+            return base;
+        }
+
+        let factor = 1u32.checked_shl(self.failures).unwrap_or(u32::MAX);
+        self.failures = self.failures.saturating_add(1);
+        base.saturating_mul(factor)
+            .min(Duration::from_secs(APP_PROBE_MAX_SLEEP_S).max(base))
+    }
+}
+
+/// Sleep between app probes while the app is serving, see [`ProbeBackoff`]. Clones share the
+/// interval, so it can be changed while the checker runs, e.g. when the host reloads its config.
+#[derive(Debug, Clone)]
+pub struct ProbeInterval {
+    interval: Arc<watch::Sender<Duration>>,
+}
+
+impl Default for ProbeInterval {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(APP_PROBE_SLEEP_S))
+    }
+}
+
+impl ProbeInterval {
+    /// Probe every `interval` while the app is serving.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn new(interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "probe interval must not be zero");
+        Self {
+            interval: Arc::new(watch::channel(interval).0),
+        }
+    }
+
+    /// Probe every `interval` from the next probe on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn set(&self, interval: Duration) {
+        assert!(!interval.is_zero(), "probe interval must not be zero");
+        self.interval.send_replace(interval);
+    }
+
+    /// The current interval.
+    pub fn get(&self) -> Duration {
+        *self.interval.borrow()
+    }
+}
+
+/// Handle to manually take a host out of rotation, e.g. during maintenance, without stopping
+/// it. Returned alongside the health service by [`spawn_k8s_health_checker`].
+#[derive(Debug, Clone)]
+pub struct ReadinessControl {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl ReadinessControl {
+    /// Force `readiness` to `NotServing`, whatever the probes report, until [`Self::resume`].
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    /// Hand `readiness` back to the probes.
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    /// Whether `readiness` is currently held down by [`Self::pause`].
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
 }
 
 /// Spawn a backgrounds process to update the k8s `readiness` status and return the `HealthServer`
-/// gRPC service. This will probe the `app_check` every `APP_PROBE_SLEEP_S` seconds
-/// and update the health service with its response.
-pub async fn spawn_k8s_health_checker<T>(app_check: Arc<T>) -> HealthServer<HealthService>
+/// gRPC service, along with a [`ReadinessControl`] to override it. This will probe the
+/// `app_check` every `APP_PROBE_SLEEP_S` seconds, backing off while it is down (see
+/// [`ProbeBackoff`]), and update the health service with its response. An app that is up but
+/// not ready is probed every `APP_PROBE_SLEEP_S` seconds.
+///
+/// `liveness` reflects the probe loop itself: a watchdog reports `NotServing` if the loop has not
+/// ticked within `LIVENESS_MISSED_TICKS` of its probe sleeps, e.g. because a probe never returns.
+///
+/// Clients using the `Watch` RPC receive the current status on subscription and then one update
+/// per `readiness` or `liveness` transition.
+///
+/// Probing carries on while readiness is paused, so `liveness` and the per probe backoff are
+/// unaffected and readiness reflects the app again as soon as it is resumed.
+///
+/// Probe intervals, backoff and the liveness watchdog all run on the tokio clock, never the
+/// wall clock. Tests can start the checker in a paused runtime
+/// (`#[tokio::test(start_paused = true)]`) and step through probes deterministically with
+/// `tokio::time::advance`, or by sleeping and letting the runtime advance time while idle.
+pub async fn spawn_k8s_health_checker<T>(
+    app_check: Arc<T>,
+) -> (HealthServer<HealthService>, ReadinessControl)
 where
     T: AppHealthCheckable + Send + Sync + 'static,
 {
+    spawn_k8s_health_checker_multi(vec![app_check]).await
+}
+
+/// Like [`spawn_k8s_health_checker`], probing every `probe_interval` instead of every
+/// `APP_PROBE_SLEEP_S` seconds. Changes to the interval apply from the next probe on.
+pub async fn spawn_k8s_health_checker_with_interval<T>(
+    app_check: Arc<T>,
+    probe_interval: ProbeInterval,
+) -> (HealthServer<HealthService>, ReadinessControl)
+where
+    T: AppHealthCheckable + Send + Sync + 'static,
+{
+    spawn_checker(vec![app_check], probe_interval).await
+}
+
+/// Health service name reporting the status of dependency `index` of
+/// [`spawn_k8s_health_checker_multi`], e.g. `readiness/0`.
+pub fn dependency_readiness(index: usize) -> String {
+    format!("{READINESS}/{index}")
+}
+
+/// Health service name reporting whether dependency `index` of
+/// [`spawn_k8s_health_checker_multi`] is up, ready or not, e.g. `liveness/0`.
+pub fn dependency_liveness(index: usize) -> String {
+    format!("{LIVENESS}/{index}")
+}
+
+/// Like [`spawn_k8s_health_checker`], but `readiness` is `Serving` only while every one of
+/// `app_checks` is. Each dependency's own status is reported under
+/// [`dependency_readiness`] of its index, and whether it is up under [`dependency_liveness`],
+/// so an app that is up but not ready can be told from one that is down.
+///
+/// # Panics
+///
+/// Panics if `app_checks` is empty.
+pub async fn spawn_k8s_health_checker_multi(
+    app_checks: Vec<Arc<dyn AppHealthCheckable + Send + Sync>>,
+) -> (HealthServer<HealthService>, ReadinessControl) {
+    spawn_checker(app_checks, ProbeInterval::default()).await
+}
+
+/// Like [`spawn_k8s_health_checker_multi`], probing every `probe_interval`, see
+/// [`spawn_k8s_health_checker_with_interval`].
+///
+/// # Panics
+///
+/// Panics if `app_checks` is empty.
+pub async fn spawn_k8s_health_checker_multi_with_interval(
+    app_checks: Vec<Arc<dyn AppHealthCheckable + Send + Sync>>,
+    probe_interval: ProbeInterval,
+) -> (HealthServer<HealthService>, ReadinessControl) {
+    spawn_checker(app_checks, probe_interval).await
+}
+
+async fn spawn_checker(
+    app_checks: Vec<Arc<dyn AppHealthCheckable + Send + Sync>>,
+    probe_interval: ProbeInterval,
+) -> (HealthServer<HealthService>, ReadinessControl) {
+    assert!(
+        !app_checks.is_empty(),
+        "health checker needs at least one dependency"
+    );
+
     let reporter = HealthReporter::new();
     let service = HealthService::from_health_reporter(reporter.clone());
     let server = HealthServer::new(service);
@@ -50,25 +274,143 @@ where
     reporter
         .set_service_status(READINESS, ServingStatus::NotServing)
         .await;
+    for index in 0..app_checks.len() {
+        reporter
+            .set_service_status(dependency_readiness(index), ServingStatus::NotServing)
+            .await;
+        reporter
+            .set_service_status(dependency_liveness(index), ServingStatus::NotServing)
+            .await;
+    }
 
+    // Latest time the probe loop is expected to tick by
+    let deadline = Arc::new(Mutex::new(
+        Instant::now() + probe_interval.get() * LIVENESS_MISSED_TICKS,
+    ));
+    spawn_liveness_watchdog(reporter.clone(), deadline.clone());
+
+    let (paused_tx, mut paused_rx) = watch::channel(false);
+    let control = ReadinessControl {
+        paused: Arc::new(paused_tx),
+    };
+
+    let mut interval_rx = probe_interval.interval.subscribe();
     tokio::task::spawn(async move {
+        let mut readiness = ServingStatus::NotServing;
+        let mut dependencies = vec![AppStatus::default(); app_checks.len()];
+        let mut backoff = ProbeBackoff::new(*interval_rx.borrow_and_update());
         loop {
-            let status = match app_check
-                .app_health_check()
-                .await
-                .map(|resp| match resp.into_inner().code {
-                    200 => ServingStatus::Serving,
-                    _ => ServingStatus::NotServing,
-                })
-                .map_err(|_status| ServingStatus::NotServing)
+            for (index, app_check) in app_checks.iter().enumerate() {
+                let status = probe(app_check.as_ref()).await;
+                if status.up != dependencies[index].up {
+                    reporter
+                        .set_service_status(dependency_liveness(index), status.up)
+                        .await;
+                }
+                if status.ready != dependencies[index].ready {
+                    reporter
+                        .set_service_status(dependency_readiness(index), status.ready)
+                        .await;
+                }
+                dependencies[index] = status;
+            }
+
+            let status = if dependencies
+                .iter()
+                .all(|s| s.ready == ServingStatus::Serving)
             {
-                Ok(s) | Err(s) => s,
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            // A manual pause takes precedence over the probes
+            let reported = if *paused_rx.borrow_and_update() {
+                ServingStatus::NotServing
+            } else {
+                status
             };
-            reporter.set_service_status(READINESS, status).await;
+            // Every status set notifies watchers, so only push transitions
+            if reported != readiness {
+                reporter.set_service_status(READINESS, reported).await;
+                readiness = reported;
+            }
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(APP_PROBE_SLEEP_S)).await
+            // Only back off while a dependency is down: one that is up but not ready answers
+            // quickly, and should be seen as soon as it is ready
+            let up = if dependencies.iter().all(|s| s.up == ServingStatus::Serving) {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            backoff.set_interval(*interval_rx.borrow_and_update());
+            let sleep = backoff.next_sleep(up);
+            *deadline.lock().expect("liveness deadline lock poisoned") =
+                Instant::now() + sleep * LIVENESS_MISSED_TICKS;
+            // Wake early on pause or resume so the override applies promptly, and on a new
+            // interval so it applies without waiting out the old one. Once every control or
+            // interval is dropped `changed` errors and only the sleep is awaited.
+            tokio::select! {
+                () = tokio::time::sleep(sleep) => {}
+                Ok(()) = paused_rx.changed() => {}
+                Ok(()) = interval_rx.changed() => {}
+            }
         }
     });
 
-    server
+    (server, control)
+}
+
+/// Status of a dependency as of its last probe.
+#[derive(Debug, Clone, Copy)]
+struct AppStatus {
+    up: ServingStatus,
+    ready: ServingStatus,
+}
+
+impl Default for AppStatus {
+    fn default() -> Self {
+        Self {
+            up: ServingStatus::NotServing,
+            ready: ServingStatus::NotServing,
+        }
+    }
+}
+
+/// Probe `app_check` once, treating errors and non 200 codes as down.
+async fn probe(app_check: &(dyn AppHealthCheckable + Send + Sync)) -> AppStatus {
+    let serving = |serving: bool| {
+        if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        }
+    };
+    match app_check.app_health_check().await {
+        Ok(resp) if resp.get_ref().code == 200 => AppStatus {
+            up: ServingStatus::Serving,
+            ready: serving(resp.get_ref().ready),
+        },
+        _ => AppStatus::default(),
+    }
+}
+
+/// Periodically compare the probe loop's `deadline` to now and report `liveness` transitions.
+fn spawn_liveness_watchdog(reporter: HealthReporter, deadline: Arc<Mutex<Instant>>) {
+    tokio::task::spawn(async move {
+        let mut liveness = ServingStatus::Serving;
+        loop {
+            tokio::time::sleep(Duration::from_secs(APP_PROBE_SLEEP_S)).await;
+
+            let deadline = *deadline.lock().expect("liveness deadline lock poisoned");
+            let status = if Instant::now() > deadline {
+                ServingStatus::NotServing
+            } else {
+                ServingStatus::Serving
+            };
+            if status != liveness {
+                reporter.set_service_status(LIVENESS, status).await;
+                liveness = status;
+            }
+        }
+    });
 }
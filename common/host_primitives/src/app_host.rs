@@ -0,0 +1,427 @@
+//! Reusable gRPC host server for secure apps.
+
+use std::{
+    fmt::Debug, marker::PhantomData, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration,
+};
+
+use health_check::{
+    spawn_k8s_health_checker_multi_with_interval, AppHealthCheckable, ProbeInterval,
+};
+use logging::LogLevel;
+use qos_core::io::SocketAddress;
+use tokio::sync::{mpsc, oneshot};
+use tonic::transport::{server::Router, Server};
+
+use crate::{
+    enclave_client_timeout, queue_metrics, spawn_batching_queue_consumer, spawn_queue_consumer,
+    spawn_sighup_reload, wait_for_sigterm, Batching, CircuitBreaker, ConcurrencyLimit, Decode,
+    EnclaveClient, EnclaveConnection, EnclaveQueueMsg, Encode, Retry, RuntimeSettings,
+    ENCLAVE_QUEUE_CAPACITY, GRPC_MAX_RECV_MSG_SIZE, HEALTH_QUEUE_CAPACITY,
+};
+
+/// Connection keepalive settings for the host server. Keeps idle client connections from being
+/// dropped by load balancers between requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// Interval between HTTP/2 pings on idle connections. `None` disables pings.
+    pub http2_interval: Option<Duration>,
+    /// How long to wait for a HTTP/2 ping to be acknowledged before closing the connection.
+    pub http2_timeout: Option<Duration>,
+    /// TCP keepalive time for accepted connections. `None` disables TCP keepalive.
+    pub tcp: Option<Duration>,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            http2_interval: Some(Duration::from_secs(30)),
+            http2_timeout: Some(Duration::from_secs(10)),
+            tcp: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Builder for a secure app host server.
+///
+/// Wires up everything hosts have in common: the enclave queue and its consumer, the k8s
+/// health checker, the optional reflection service and SIGTERM handling. Apps only provide
+/// their health check and register their own gRPC services.
+#[derive(Debug)]
+pub struct AppHostBuilder<Codec, Req, Resp> {
+    listen_addr: SocketAddr,
+    enclave_addr: SocketAddress,
+    file_descriptor_sets: Vec<&'static [u8]>,
+    keepalive: Keepalive,
+    max_request_size: usize,
+    max_response_size: usize,
+    queue_capacity: usize,
+    batching: Option<Batching>,
+    retry: Option<Retry>,
+    concurrency_limit: ConcurrencyLimit,
+    circuit_breaker: Option<CircuitBreaker>,
+    runtime_config: Option<PathBuf>,
+    log_level: Option<LogLevel>,
+    extra_apps: Vec<ExtraApp>,
+    _phantom: PhantomData<(Codec, Req, Resp)>,
+}
+
+/// Settings of the enclave clients of a host, shared by all of its apps.
+#[derive(Debug, Clone, Copy)]
+struct ClientSettings {
+    max_request_size: usize,
+    max_response_size: usize,
+    queue_capacity: usize,
+    retry: Option<Retry>,
+}
+
+/// Starts the enclave clients of an app registered with [`AppHostBuilder::add_app`]. Called
+/// with the name its queue is registered under, returns the app's health check and what adds
+/// its services to the server.
+struct ExtraApp(Box<dyn FnOnce(String, ClientSettings) -> (AppHealthCheck, RegisterApp) + Send>);
+
+type AppHealthCheck = Arc<dyn AppHealthCheckable + Send + Sync>;
+type RegisterApp = Box<dyn FnOnce(Router) -> Router + Send>;
+
+impl Debug for ExtraApp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ExtraApp")
+    }
+}
+
+impl<Codec, Req, Resp> AppHostBuilder<Codec, Req, Resp>
+where
+    Codec: Encode<Req> + Decode<Resp> + Send + Sync + 'static,
+    Req: Send + 'static,
+    Resp: Send + Debug + 'static,
+{
+    /// Create a builder for a host listening on `listen_addr` and forwarding requests to the
+    /// enclave at `enclave_addr`.
+    pub fn new(listen_addr: SocketAddr, enclave_addr: SocketAddress) -> Self {
+        Self {
+            listen_addr,
+            enclave_addr,
+            file_descriptor_sets: Vec::new(),
+            keepalive: Keepalive::default(),
+            max_request_size: GRPC_MAX_RECV_MSG_SIZE,
+            max_response_size: GRPC_MAX_RECV_MSG_SIZE,
+            queue_capacity: ENCLAVE_QUEUE_CAPACITY,
+            batching: None,
+            retry: None,
+            concurrency_limit: ConcurrencyLimit::unlimited(),
+            circuit_breaker: None,
+            runtime_config: None,
+            log_level: None,
+            extra_apps: Vec::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Serve the gRPC reflection service for the encoded `file_descriptor_set`.
+    ///
+    /// Call once per set to reflect the services of several, e.g. one generated per service
+    /// or those of apps added with [`Self::add_app`]. The reflection service lists the
+    /// services of every set registered.
+    pub fn reflection(mut self, file_descriptor_set: &'static [u8]) -> Self {
+        self.file_descriptor_sets.push(file_descriptor_set);
+        self
+    }
+
+    /// Use `keepalive` for client connections instead of [`Keepalive::default`].
+    pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Reject requests to the enclave that encode to more than `max_request_size` bytes.
+    /// Defaults to [`GRPC_MAX_RECV_MSG_SIZE`].
+    pub fn max_request_size(mut self, max_request_size: usize) -> Self {
+        self.max_request_size = max_request_size;
+        self
+    }
+
+    /// Reject app responses of more than `max_response_size` bytes with `out_of_range` rather
+    /// than decoding them. Defaults to [`GRPC_MAX_RECV_MSG_SIZE`].
+    pub fn max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    /// Queue up to `queue_capacity` requests for the enclave. Defaults to
+    /// [`ENCLAVE_QUEUE_CAPACITY`].
+    ///
+    /// Requests are sent to the enclave one at a time, so the queue absorbs bursts. Once it is
+    /// full, callers wait for room, which holds their gRPC calls open and pushes back on clients.
+    /// A larger queue smooths bursts for slow enclaves at the cost of more requests waiting, and
+    /// longer latency for the last ones; a smaller queue makes clients feel backpressure sooner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `queue_capacity` is 0.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        assert!(
+            queue_capacity > 0,
+            "enclave queue capacity must be at least 1"
+        );
+        self.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// Coalesce queued requests into batches according to `batching`. The enclave app must
+    /// process requests with a [`crate::BatchProcessor`]. [`Self::max_request_size`] then limits
+    /// the encoded batch, see [`crate::spawn_batching_queue_consumer`].
+    pub fn batching(mut self, batching: Batching) -> Self {
+        self.batching = Some(batching);
+        self
+    }
+
+    /// Retry requests that fail to reach the enclave according to `retry`. By default requests
+    /// are attempted once.
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Allow up to `concurrency_limit` requests of the app of this builder in progress to the
+    /// enclave at once, see [`ConcurrencyLimit`]. Unlimited by default, so only the queue
+    /// capacity holds callers back. Health checks are never limited.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `concurrency_limit` is 0.
+    pub fn concurrency_limit(self, concurrency_limit: usize) -> Self {
+        self.concurrency_limit.set(concurrency_limit);
+        self
+    }
+
+    /// Fail requests of the app of this builder fast while `circuit_breaker` is open, see
+    /// [`CircuitBreaker`]. Health checks are never failed fast, so they keep reporting on the
+    /// enclave during an outage.
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Re-read the [`crate::RuntimeConfig`] at `path` on SIGHUP and apply it without dropping
+    /// connections, see [`spawn_sighup_reload`]. The file is only read on SIGHUP, so it need not
+    /// exist at startup.
+    pub fn runtime_config(mut self, path: PathBuf) -> Self {
+        self.runtime_config = Some(path);
+        self
+    }
+
+    /// Let a runtime config change the level of the host's logs through `log_level`, see
+    /// [`Self::runtime_config`].
+    pub fn log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = Some(log_level);
+        self
+    }
+
+    /// Also serve the gRPC services of the enclave app at `enclave_addr`, which may use other
+    /// request and response types than the app of this builder.
+    ///
+    /// `health` and `register` work as they do for [`Self::serve`]: `register` adds the
+    /// services to the server with an enclave client of their own, with its own queue and
+    /// connection, and `health` builds the app's health check from another one. The clients
+    /// share the queue capacity, message size limits and retries of this builder, but never
+    /// batch. The host is only ready while every app is, see [`Self::serve`].
+    pub fn add_app<C, Q, P, H, T, F>(
+        mut self,
+        enclave_addr: SocketAddress,
+        health: H,
+        register: F,
+    ) -> Self
+    where
+        C: Encode<Q> + Decode<P> + Send + Sync + 'static,
+        Q: Send + 'static,
+        P: Send + Debug + 'static,
+        H: FnOnce(Arc<EnclaveClient<C, Q, P>>) -> T + Send + 'static,
+        T: AppHealthCheckable + Send + Sync + 'static,
+        F: FnOnce(Arc<EnclaveClient<C, Q, P>>, Router) -> Router + Send + 'static,
+    {
+        self.extra_apps
+            .push(ExtraApp(Box::new(move |name, settings| {
+                let health_enclave = spawn_enclave_client::<C, Q, P>(
+                    &format!("{name}-health"),
+                    enclave_addr.clone(),
+                    settings,
+                    HEALTH_QUEUE_CAPACITY,
+                    None,
+                );
+                let enclave = spawn_enclave_client::<C, Q, P>(
+                    &name,
+                    enclave_addr,
+                    settings,
+                    settings.queue_capacity,
+                    None,
+                );
+                let health: AppHealthCheck = Arc::new(health(Arc::new(health_enclave)));
+                let register: RegisterApp =
+                    Box::new(move |router| register(Arc::new(enclave), router));
+                (health, register)
+            })));
+        self
+    }
+
+    /// Start the host server and serve until SIGTERM, reloading the runtime config on SIGHUP if
+    /// one was given.
+    ///
+    /// `health` builds the app health check from an enclave client and `register` adds the
+    /// app specific gRPC services to the server. The health check gets its own small queue and
+    /// enclave connection, so a flood of data requests can not starve health probes and flip
+    /// readiness. Its client reports that connection's [`crate::ConnectionState`], so health
+    /// checks can reflect connection health directly.
+    ///
+    /// With apps added with [`Self::add_app`], `readiness` is only `Serving` while every app's
+    /// health check is, see [`health_check::spawn_k8s_health_checker_multi`]. The app of this
+    /// builder is dependency 0, the added ones follow in the order added.
+    ///
+    /// The queues are registered with [`crate::queue_metrics`] as `enclave` and `health`, and
+    /// those of apps added with [`Self::add_app`] as `app-1` and `app-1-health`, `app-2` and
+    /// `app-2-health`, ... in the order added.
+    pub async fn serve<H, T, F>(
+        mut self,
+        health: H,
+        register: F,
+    ) -> Result<(), tonic::transport::Error>
+    where
+        H: FnOnce(Arc<EnclaveClient<Codec, Req, Resp>>) -> T,
+        T: AppHealthCheckable + Send + Sync + 'static,
+        F: FnOnce(Arc<EnclaveClient<Codec, Req, Resp>>, Router) -> Router,
+    {
+        let reflection_service = (!self.file_descriptor_sets.is_empty()).then(|| {
+            self.file_descriptor_sets
+                .iter()
+                .fold(
+                    tonic_reflection::server::Builder::configure(),
+                    |builder, file_descriptor_set| {
+                        builder.register_encoded_file_descriptor_set(file_descriptor_set)
+                    },
+                )
+                .build_v1()
+                .expect("failed to start reflection service")
+        });
+
+        let settings = self.client_settings();
+        let (extra_health, extra_apps): (Vec<_>, Vec<_>) = std::mem::take(&mut self.extra_apps)
+            .into_iter()
+            .enumerate()
+            .map(|(index, app)| (app.0)(format!("app-{}", index + 1), settings))
+            .unzip();
+
+        let probe_interval = ProbeInterval::default();
+        let health_enclave = Arc::new(self.spawn_enclave_client("health", HEALTH_QUEUE_CAPACITY));
+        let mut app_checks: Vec<AppHealthCheck> = vec![Arc::new(health(health_enclave))];
+        app_checks.extend(extra_health);
+        let (health_service, readiness) =
+            spawn_k8s_health_checker_multi_with_interval(app_checks, probe_interval.clone()).await;
+
+        let mut enclave = self
+            .spawn_enclave_client("enclave", self.queue_capacity)
+            .with_concurrency_limit(self.concurrency_limit.clone());
+        if let Some(circuit_breaker) = self.circuit_breaker.clone() {
+            enclave = enclave.with_circuit_breaker(circuit_breaker);
+        }
+        let enclave = Arc::new(enclave);
+
+        if let Some(path) = self.runtime_config.clone() {
+            spawn_sighup_reload(
+                path,
+                RuntimeSettings {
+                    log_level: self.log_level.clone(),
+                    probe_interval,
+                    concurrency_limit: self.concurrency_limit.clone(),
+                    readiness: Some(readiness),
+                },
+            );
+        }
+
+        tracing::info!("HostServer listening on {}", self.listen_addr);
+
+        let (sigterm_sender, sigterm_receiver) = oneshot::channel();
+        tokio::task::spawn(wait_for_sigterm(sigterm_sender));
+
+        let router = Server::builder()
+            .http2_keepalive_interval(self.keepalive.http2_interval)
+            .http2_keepalive_timeout(self.keepalive.http2_timeout)
+            .tcp_keepalive(self.keepalive.tcp)
+            .add_optional_service(reflection_service)
+            .add_service(health_service);
+
+        let router = extra_apps
+            .into_iter()
+            .fold(register(enclave, router), |router, register| {
+                register(router)
+            });
+        router
+            .serve_with_shutdown(self.listen_addr, async {
+                sigterm_receiver.await.ok();
+                tracing::info!("SIGTERM received");
+            })
+            .await
+    }
+
+    fn client_settings(&self) -> ClientSettings {
+        ClientSettings {
+            max_request_size: self.max_request_size,
+            max_response_size: self.max_response_size,
+            queue_capacity: self.queue_capacity,
+            retry: self.retry,
+        }
+    }
+
+    /// Create an enclave client for the app of this builder, see [`spawn_enclave_client`].
+    fn spawn_enclave_client(
+        &self,
+        name: &str,
+        queue_capacity: usize,
+    ) -> EnclaveClient<Codec, Req, Resp> {
+        spawn_enclave_client::<Codec, Req, Resp>(
+            name,
+            self.enclave_addr.clone(),
+            self.client_settings(),
+            queue_capacity,
+            self.batching,
+        )
+    }
+}
+
+/// Create an enclave client with its own queue of `queue_capacity` and connection to the
+/// enclave at `enclave_addr`, and spawn the queue consumer serving it. The queue is registered
+/// with [`queue_metrics`] under `name`.
+fn spawn_enclave_client<Codec, Req, Resp>(
+    name: &str,
+    enclave_addr: SocketAddress,
+    settings: ClientSettings,
+    queue_capacity: usize,
+    batching: Option<Batching>,
+) -> EnclaveClient<Codec, Req, Resp>
+where
+    Codec: Encode<Req> + Decode<Resp> + Send + Sync + 'static,
+    Req: Send + 'static,
+    Resp: Send + Debug + 'static,
+{
+    let (queue_tx, queue_rx) = mpsc::channel::<Box<EnclaveQueueMsg<Req, Resp>>>(queue_capacity);
+    queue_metrics().register_queue(name, &queue_tx);
+    let mut connection = EnclaveConnection::new(enclave_addr, enclave_client_timeout())
+        .with_max_response_size(settings.max_response_size);
+    if let Some(retry) = settings.retry {
+        connection = connection.with_retry(retry);
+    }
+    let connection = Arc::new(connection);
+
+    match batching {
+        Some(batching) => spawn_batching_queue_consumer::<Codec, _, _>(
+            connection.clone(),
+            queue_rx,
+            settings.max_request_size,
+            batching,
+        ),
+        None => spawn_queue_consumer::<Codec, _, _>(
+            connection.clone(),
+            queue_rx,
+            settings.max_request_size,
+        ),
+    }
+
+    EnclaveClient::new(queue_tx).with_connection(connection)
+}
@@ -0,0 +1,232 @@
+//! Coalescing queued enclave requests into a single round trip.
+//!
+//! A batch is sent to the enclave as a [`BatchRequest`] in place of a single encoded app
+//! request, and answered with a [`BatchResponse`]. The app must wrap its processor in a
+//! [`BatchProcessor`] to understand batches.
+
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use qos_core::server::RequestProcessor;
+use tonic::Status;
+use tracing::Instrument;
+
+use crate::{
+    proxy_round_trip, queue_metrics, Decode, EnclaveConnection, EnclaveQueueMsg, Encode, RequestId,
+};
+
+/// Bytes borsh adds for the length prefix of a `Vec`, once for [`BatchRequest::requests`] and
+/// once per request in it.
+const LEN_PREFIX: usize = 4;
+
+/// How the queue consumer coalesces requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batching {
+    /// Maximum number of requests sent in one round trip.
+    pub max_batch_size: usize,
+    /// How long to wait for more requests after the first one of a batch is received.
+    pub window: Duration,
+}
+
+/// Batch of encoded app requests sent to the enclave in one `ProxyRequest`.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct BatchRequest {
+    /// Encoded app requests.
+    pub requests: Vec<Vec<u8>>,
+}
+
+/// Encoded app responses to a [`BatchRequest`], in request order.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct BatchResponse {
+    /// Encoded app responses.
+    pub responses: Vec<Vec<u8>>,
+}
+
+/// Enclave side [`RequestProcessor`] that unpacks a [`BatchRequest`] and passes each request to
+/// the wrapped processor.
+#[derive(Debug)]
+pub struct BatchProcessor<P> {
+    inner: P,
+}
+
+impl<P> BatchProcessor<P> {
+    /// Process batches with `inner`.
+    pub fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+impl<P: RequestProcessor> RequestProcessor for BatchProcessor<P> {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        // An empty response does not match the batch size, so the host reports an error for
+        // every request in the batch
+        let responses = match BatchRequest::try_from_slice(&request) {
+            Ok(batch) => batch
+                .requests
+                .into_iter()
+                .map(|request| self.inner.process(request))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        borsh::to_vec(&BatchResponse { responses }).expect("batch response encodes to borsh")
+    }
+}
+
+/// Like [`crate::spawn_queue_consumer`], but sends up to `batching.max_batch_size` queued
+/// messages to the enclave in one round trip. The enclave app must process requests with a
+/// [`BatchProcessor`].
+///
+/// `max_request_size` limits the encoded [`BatchRequest`]: a batch is sent early rather than
+/// take a request that would push it past the limit, and requests too large for a batch of
+/// their own are rejected with `invalid_argument`.
+pub fn spawn_batching_queue_consumer<Codec, Req, Resp>(
+    connection: Arc<EnclaveConnection>,
+    mut queue_rx: tokio::sync::mpsc::Receiver<Box<EnclaveQueueMsg<Req, Resp>>>,
+    max_request_size: usize,
+    batching: Batching,
+) where
+    Resp: Send + Debug + 'static,
+    Req: Send + 'static,
+    Codec: Encode<Req> + Decode<Resp>,
+{
+    tokio::task::spawn(async move {
+        // Request that did not fit in the previous batch, to start the next one with
+        let mut next = None;
+        loop {
+            let first = match next.take() {
+                Some(first) => first,
+                None => {
+                    let queue_msg = queue_rx.recv().await.expect("failed to receive message");
+                    match encode_msg::<Codec, _, _>(*queue_msg, max_request_size) {
+                        Some(first) => first,
+                        None => continue,
+                    }
+                }
+            };
+            let mut size = LEN_PREFIX + first.framed_len();
+            let mut batch = vec![first];
+
+            let deadline = tokio::time::Instant::now() + batching.window;
+            while batch.len() < batching.max_batch_size {
+                let queue_msg = match tokio::time::timeout_at(deadline, queue_rx.recv()).await {
+                    Ok(Some(queue_msg)) => queue_msg,
+                    Ok(None) | Err(_) => break,
+                };
+                let Some(msg) = encode_msg::<Codec, _, _>(*queue_msg, max_request_size) else {
+                    continue;
+                };
+                if size + msg.framed_len() > max_request_size {
+                    next = Some(msg);
+                    break;
+                }
+                size += msg.framed_len();
+                batch.push(msg);
+            }
+
+            send_batch::<Codec, _>(batch, Arc::clone(&connection)).await;
+        }
+    });
+}
+
+/// A queued request, encoded for a [`BatchRequest`].
+struct EncodedMsg<Resp> {
+    data: Vec<u8>,
+    request_id: RequestId,
+    response_tx: tokio::sync::oneshot::Sender<Result<Resp, Status>>,
+}
+
+impl<Resp> EncodedMsg<Resp> {
+    /// Bytes the request takes up in an encoded [`BatchRequest`].
+    fn framed_len(&self) -> usize {
+        LEN_PREFIX + self.data.len()
+    }
+}
+
+/// Encode `queue_msg` for a batch. Returns `None` if its caller is no longer waiting, or after
+/// rejecting it if a batch of just this request encodes to more than `max_request_size` bytes.
+fn encode_msg<Codec, Req, Resp>(
+    queue_msg: EnclaveQueueMsg<Req, Resp>,
+    max_request_size: usize,
+) -> Option<EncodedMsg<Resp>>
+where
+    Codec: Encode<Req>,
+{
+    tracing::debug!(request_id = %queue_msg.request_id, "enclave request dequeued");
+
+    // Skip callers that are no longer waiting, see `spawn_queue_consumer`
+    if queue_msg.response_tx.is_closed() {
+        queue_metrics().record_abandoned_request();
+        return None;
+    }
+
+    let data = Codec::encode(&queue_msg.request);
+    let batch_size = 2 * LEN_PREFIX + data.len();
+    if batch_size > max_request_size {
+        let _ = queue_msg
+            .response_tx
+            .send(Err(Status::invalid_argument(format!(
+                "encoded request is {} bytes, {batch_size} bytes in a batch, exceeding the limit \
+                 of {max_request_size} bytes",
+                data.len()
+            ))));
+        return None;
+    }
+
+    Some(EncodedMsg {
+        data,
+        request_id: queue_msg.request_id,
+        response_tx: queue_msg.response_tx,
+    })
+}
+
+/// Send `batch` to the enclave and respond to each caller.
+async fn send_batch<Codec, Resp>(batch: Vec<EncodedMsg<Resp>>, connection: Arc<EnclaveConnection>)
+where
+    Codec: Decode<Resp>,
+{
+    let batch_len = batch.len();
+    let mut requests = Vec::with_capacity(batch_len);
+    let mut response_txs = Vec::with_capacity(batch_len);
+    for msg in batch {
+        requests.push(msg.data);
+        response_txs.push((msg.request_id, msg.response_tx));
+    }
+
+    let data = borsh::to_vec(&BatchRequest { requests }).expect("batch request encodes to borsh");
+    let span = tracing::info_span!("enclave_batch", size = batch_len);
+    let responses = proxy_round_trip(data, connection)
+        .instrument(span)
+        .await
+        .and_then(|data| {
+            let batch = BatchResponse::try_from_slice(&data).map_err(|e| {
+                Status::internal(format!("Failed to deserialize batch response: {e:?}"))
+            })?;
+            if batch.responses.len() != batch_len {
+                return Err(Status::internal(format!(
+                    "Expected {batch_len} batch responses but got {}",
+                    batch.responses.len()
+                )));
+            }
+            Ok(batch.responses)
+        });
+
+    match responses {
+        Ok(responses) => {
+            for ((request_id, response_tx), data) in response_txs.into_iter().zip(responses) {
+                let response = Codec::decode(&data)
+                    .map_err(|e| Status::internal(format!("Failed to decode app response: {e:?}")));
+                if response_tx.send(response).is_err() {
+                    queue_metrics().record_dropped_response(&request_id);
+                }
+            }
+        }
+        Err(status) => {
+            for (request_id, response_tx) in response_txs {
+                if response_tx.send(Err(status.clone())).is_err() {
+                    queue_metrics().record_dropped_response(&request_id);
+                }
+            }
+        }
+    }
+}
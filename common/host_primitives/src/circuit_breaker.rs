@@ -0,0 +1,200 @@
+//! Fast-failing requests to an enclave that keeps failing, so callers don't each wait out the
+//! queue and socket timeouts during an outage.
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tonic::{Code, Status};
+
+/// State of a [`CircuitBreaker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through, and consecutive failures are counted.
+    Closed,
+    /// Requests fail fast with `unavailable` until the cooldown is over.
+    Open,
+    /// The cooldown is over. The next request probes the enclave, others fail fast while it
+    /// is in progress.
+    HalfOpen,
+}
+
+/// Trips after a number of consecutive enclave failures, then fails requests fast with
+/// `unavailable` for a cooldown, see [`crate::EnclaveClient::with_circuit_breaker`]. After the
+/// cooldown a single request probes the enclave: the circuit closes if it succeeds, and opens
+/// for another cooldown if it fails.
+///
+/// A failure is an `unavailable` status, the enclave or its queue consumer could not be
+/// reached. Any other outcome means the enclave answered and counts as a success, except
+/// `deadline_exceeded`: the caller gave up first, so it counts as neither.
+///
+/// Clones share the state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe request is in progress.
+    Probing,
+}
+
+impl CircuitBreaker {
+    /// Open the circuit for `cooldown` after `failure_threshold` consecutive failures.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure_threshold` is 0.
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        assert!(
+            failure_threshold > 0,
+            "circuit breaker failure threshold must be at least 1"
+        );
+        Self {
+            inner: Arc::new(Inner {
+                failure_threshold,
+                cooldown,
+                state: Mutex::new(State::Closed { failures: 0 }),
+            }),
+        }
+    }
+
+    /// The current state.
+    pub fn state(&self) -> CircuitState {
+        match *self.inner.lock() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if Instant::now() < until => CircuitState::Open,
+            State::Open { .. } | State::Probing => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Let a request through, or fail it with `unavailable` if the circuit is open or a probe
+    /// is in progress. The outcome of the request is recorded with [`CircuitPermit::record`].
+    pub fn acquire(&self) -> Result<CircuitPermit, Status> {
+        let mut state = self.inner.lock();
+        let probe = match *state {
+            State::Closed { .. } => false,
+            State::Open { until } => {
+                let now = Instant::now();
+                if now < until {
+                    return Err(Status::unavailable(format!(
+                        "enclave circuit breaker is open after {} consecutive failures, \
+                         retrying in {:?}",
+                        self.inner.failure_threshold,
+                        until - now
+                    )));
+                }
+                tracing::info!("enclave circuit breaker half-open, probing the enclave");
+                *state = State::Probing;
+                true
+            }
+            State::Probing => {
+                return Err(Status::unavailable(
+                    "enclave circuit breaker is half-open, waiting on a probe of the enclave",
+                ))
+            }
+        };
+
+        Ok(CircuitPermit {
+            inner: self.inner.clone(),
+            probe,
+            recorded: false,
+        })
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("circuit breaker lock poisoned")
+    }
+}
+
+/// A request let through by a [`CircuitBreaker`]. Dropping it without recording an outcome,
+/// e.g. because the caller gave up, counts as neither success nor failure.
+#[derive(Debug)]
+pub struct CircuitPermit {
+    inner: Arc<Inner>,
+    /// Whether this is the request probing a half-open circuit.
+    probe: bool,
+    recorded: bool,
+}
+
+impl CircuitPermit {
+    /// Record the `result` of the request, see [`CircuitBreaker`] for what counts as a
+    /// failure.
+    pub fn record<T>(mut self, result: &Result<T, Status>) {
+        let failed = match result {
+            Err(status) if status.code() == Code::DeadlineExceeded => return,
+            Err(status) => status.code() == Code::Unavailable,
+            Ok(_) => false,
+        };
+        self.recorded = true;
+
+        let mut state = self.inner.lock();
+        if !failed {
+            if !matches!(*state, State::Closed { .. }) {
+                tracing::info!("enclave answered, closing circuit breaker");
+            }
+            *state = State::Closed { failures: 0 };
+            return;
+        }
+
+        let cooldown = self.inner.cooldown;
+        match *state {
+            State::Closed { failures } if failures + 1 >= self.inner.failure_threshold => {
+                tracing::warn!(
+                    "enclave failed {} consecutive times, opening circuit breaker for \
+                     {cooldown:?}",
+                    failures + 1
+                );
+                *state = State::Open {
+                    until: Instant::now() + cooldown,
+                };
+            }
+            State::Closed { failures } => {
+                *state = State::Closed {
+                    failures: failures + 1,
+                };
+            }
+            State::Probing if self.probe => {
+                tracing::warn!("enclave probe failed, opening circuit breaker for {cooldown:?}");
+                *state = State::Open {
+                    until: Instant::now() + cooldown,
+                };
+            }
+            // A request let through before the circuit opened, the circuit is open already
+            State::Open { .. } | State::Probing => {}
+        }
+    }
+}
+
+impl Drop for CircuitPermit {
+    fn drop(&mut self) {
+        if !self.probe || self.recorded {
+            return;
+        }
+        // Let the next request probe instead
+        let mut state = self.inner.lock();
+        if matches!(*state, State::Probing) {
+            *state = State::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+}
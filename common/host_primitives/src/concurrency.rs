@@ -0,0 +1,115 @@
+//! Limit on the requests an enclave client has in progress at once, adjustable while the host
+//! runs.
+
+use std::{
+    pin::pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::Notify;
+
+/// Caps the requests in progress through an [`crate::EnclaveClient`], see
+/// [`crate::EnclaveClient::with_concurrency_limit`]. Requests over the limit wait for one in
+/// progress to finish, before they take a place in the queue.
+///
+/// Clones share the limit. Lowering it never cancels requests in progress, new requests just
+/// wait until fewer than the new limit are.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    limit: AtomicUsize,
+    in_flight: AtomicUsize,
+    /// Notified when a permit is released or the limit changes.
+    changed: Notify,
+}
+
+impl ConcurrencyLimit {
+    /// Allow up to `limit` requests in progress at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn new(limit: usize) -> Self {
+        assert!(limit > 0, "concurrency limit must be at least 1");
+        Self {
+            inner: Arc::new(Inner {
+                limit: AtomicUsize::new(limit),
+                in_flight: AtomicUsize::new(0),
+                changed: Notify::new(),
+            }),
+        }
+    }
+
+    /// No limit until one is set with [`Self::set`].
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    /// Allow up to `limit` requests in progress at once from now on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn set(&self, limit: usize) {
+        assert!(limit > 0, "concurrency limit must be at least 1");
+        self.inner.limit.store(limit, Ordering::SeqCst);
+        self.inner.changed.notify_waiters();
+    }
+
+    /// The current limit, `usize::MAX` if unlimited.
+    pub fn limit(&self) -> usize {
+        self.inner.limit.load(Ordering::SeqCst)
+    }
+
+    /// Requests currently in progress.
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait until a request can start under the limit. The request counts as in progress until
+    /// the returned permit is dropped.
+    pub async fn acquire(&self) -> ConcurrencyPermit {
+        loop {
+            // Registered before checking, so a release in between is not missed
+            let mut changed = pin!(self.inner.changed.notified());
+            changed.as_mut().enable();
+
+            let in_flight = self.inner.in_flight.load(Ordering::SeqCst);
+            if in_flight < self.limit() {
+                if self
+                    .inner
+                    .in_flight
+                    .compare_exchange(in_flight, in_flight + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return ConcurrencyPermit {
+                        inner: self.inner.clone(),
+                    };
+                }
+                continue;
+            }
+
+            changed.await;
+        }
+    }
+}
+
+/// A request in progress under a [`ConcurrencyLimit`], released on drop.
+#[derive(Debug)]
+pub struct ConcurrencyPermit {
+    inner: Arc<Inner>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.inner.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.inner.changed.notify_waiters();
+    }
+}
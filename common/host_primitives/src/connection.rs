@@ -0,0 +1,131 @@
+//! Reusable qos socket client with connection health tracking.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use qos_core::{
+    client::{Client, ClientError},
+    io::{SocketAddress, TimeVal},
+};
+
+/// Health of the connection to the enclave, as of the most recent request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No request has been sent yet.
+    Unknown,
+    /// The most recent request reached the enclave.
+    Connected,
+    /// The most recent request failed to reach the enclave.
+    Disconnected,
+}
+
+/// How to retry requests that fail to reach the enclave, e.g. because it is momentarily busy.
+///
+/// Only socket errors are retried. Errors decoding or interpreting the enclave's response are
+/// returned immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry. Doubles with every further retry.
+    pub base_delay: Duration,
+}
+
+impl Retry {
+    /// Delay before retrying after `failed_attempts` attempts have failed.
+    pub fn delay(&self, failed_attempts: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(failed_attempts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.base_delay.saturating_mul(factor)
+    }
+}
+
+/// Owns the qos socket [`Client`] used to reach the enclave. The client is reused while requests
+/// succeed and only rebuilt after a request fails to reach the enclave.
+#[derive(Debug)]
+pub struct EnclaveConnection {
+    addr: SocketAddress,
+    timeout: TimeVal,
+    /// Locked for the duration of a request, requests are sent one at a time.
+    client: Mutex<Client>,
+    /// Separate from `client` so reading the state never waits on an in flight request.
+    state: Mutex<ConnectionState>,
+    builds: AtomicUsize,
+    retry: Option<Retry>,
+    max_response_size: Option<usize>,
+}
+
+impl EnclaveConnection {
+    /// Create a connection to the enclave at `addr` with socket `timeout`.
+    pub fn new(addr: SocketAddress, timeout: TimeVal) -> Self {
+        let client = Client::new(addr.clone(), timeout);
+        Self {
+            addr,
+            timeout,
+            client: Mutex::new(client),
+            state: Mutex::new(ConnectionState::Unknown),
+            builds: AtomicUsize::new(1),
+            retry: None,
+            max_response_size: None,
+        }
+    }
+
+    /// Retry requests that fail to reach the enclave according to `retry`. By default requests
+    /// are attempted once.
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Retry policy for requests over this connection, if any.
+    pub fn retry(&self) -> Option<Retry> {
+        self.retry
+    }
+
+    /// Reject app responses of more than `max_response_size` bytes instead of decoding them. By
+    /// default responses of any size are decoded.
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = Some(max_response_size);
+        self
+    }
+
+    /// Largest app response accepted over this connection, if limited.
+    pub fn max_response_size(&self) -> Option<usize> {
+        self.max_response_size
+    }
+
+    /// Send `request` to the enclave, blocking until it responds. On failure the client is
+    /// rebuilt for the next request.
+    pub fn send(&self, request: &[u8]) -> Result<Vec<u8>, ClientError> {
+        let mut client = self.client.lock().expect("enclave client lock poisoned");
+
+        let result = client.send(request);
+        let state = match result {
+            Ok(_) => ConnectionState::Connected,
+            Err(_) => {
+                *client = Client::new(self.addr.clone(), self.timeout);
+                self.builds.fetch_add(1, Ordering::SeqCst);
+                ConnectionState::Disconnected
+            }
+        };
+        *self.state.lock().expect("connection state lock poisoned") = state;
+
+        result
+    }
+
+    /// State of the connection as of the most recent request.
+    pub fn state(&self) -> ConnectionState {
+        *self.state.lock().expect("connection state lock poisoned")
+    }
+
+    /// Number of times a client has been built, including the initial one.
+    pub fn builds(&self) -> usize {
+        self.builds.load(Ordering::SeqCst)
+    }
+}
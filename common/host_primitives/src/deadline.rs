@@ -0,0 +1,54 @@
+//! Deadlines of gRPC calls, so hosts stop waiting on the enclave once the client would no longer
+//! take the answer.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// gRPC metadata key clients send the time left until their deadline in.
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// How long before the client's deadline the host gives up on the enclave. The gRPC server
+/// cancels the call at the deadline itself, so this leaves the host time to answer with
+/// `deadline_exceeded` instead.
+pub const DEADLINE_MARGIN: Duration = Duration::from_millis(20);
+
+/// The time left until the deadline the client of `request` set, if it set a valid one.
+///
+/// The [`GRPC_TIMEOUT_HEADER`] is a number of up to 8 digits followed by its unit, one of `H`,
+/// `M`, `S`, `m`, `u` or `n` for hours down to nanoseconds.
+pub fn grpc_timeout<T>(request: &tonic::Request<T>) -> Option<Duration> {
+    let timeout = request.metadata().get(GRPC_TIMEOUT_HEADER)?.to_str().ok()?;
+    if !timeout.is_ascii() || timeout.len() < 2 {
+        return None;
+    }
+    let (value, unit) = timeout.split_at(timeout.len() - 1);
+    if value.len() > 8 {
+        return None;
+    }
+    let value: u64 = value.parse().ok()?;
+
+    Some(match unit {
+        "H" => Duration::from_secs(value * 60 * 60),
+        "M" => Duration::from_secs(value * 60),
+        "S" => Duration::from_secs(value),
+        "m" => Duration::from_millis(value),
+        "u" => Duration::from_micros(value),
+        "n" => Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+/// When to stop waiting on the enclave for `request`: [`DEADLINE_MARGIN`] before the client's
+/// deadline, or after `max_timeout`, whichever comes first. `None` if neither is set.
+pub fn enclave_deadline<T>(
+    request: &tonic::Request<T>,
+    max_timeout: Option<Duration>,
+) -> Option<Instant> {
+    let client_timeout = grpc_timeout(request).map(|t| t.saturating_sub(DEADLINE_MARGIN));
+    let timeout = match (client_timeout, max_timeout) {
+        (Some(client), Some(max)) => client.min(max),
+        (client, max) => client.or(max)?,
+    };
+    Some(Instant::now() + timeout)
+}
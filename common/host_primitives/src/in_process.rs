@@ -0,0 +1,77 @@
+//! In-process enclave transport for tests. Serves the enclave queue by calling the app's
+//! [`RequestProcessor`] directly instead of going through a socket and the QOS proxy.
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use qos_core::server::RequestProcessor;
+use tonic::Status;
+
+use crate::{
+    queue_metrics, Decode, EnclaveClient, EnclaveQueueMsg, Encode, ENCLAVE_QUEUE_CAPACITY,
+};
+
+impl<Codec, Req, Resp> EnclaveClient<Codec, Req, Resp>
+where
+    Codec: Encode<Req> + Decode<Resp> + 'static,
+    Req: Send + 'static,
+    Resp: Send + Debug + 'static,
+{
+    /// Create an enclave client whose requests are answered by `processor` in this process.
+    ///
+    /// Requests are encoded and responses decoded with `Codec` exactly as over a socket, so
+    /// host code behaves the same as against a real enclave. Must be called within a tokio
+    /// runtime.
+    pub fn in_process<P>(processor: P) -> Self
+    where
+        P: RequestProcessor + Send + 'static,
+    {
+        let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(ENCLAVE_QUEUE_CAPACITY);
+        spawn_in_process_queue_consumer::<Codec, _, _, _>(processor, queue_rx);
+        Self::new(queue_tx)
+    }
+}
+
+/// Spawn a consumer task that answers messages from the enclave queue with `processor`.
+pub fn spawn_in_process_queue_consumer<Codec, Req, Resp, P>(
+    processor: P,
+    mut queue_rx: tokio::sync::mpsc::Receiver<Box<EnclaveQueueMsg<Req, Resp>>>,
+) where
+    Codec: Encode<Req> + Decode<Resp>,
+    Req: Send + 'static,
+    Resp: Send + Debug + 'static,
+    P: RequestProcessor + Send + 'static,
+{
+    let processor = Arc::new(Mutex::new(processor));
+    tokio::task::spawn(async move {
+        while let Some(queue_msg) = queue_rx.recv().await {
+            tracing::debug!(request_id = %queue_msg.request_id, "enclave request dequeued");
+            if queue_msg.response_tx.is_closed() {
+                queue_metrics().record_abandoned_request();
+                continue;
+            }
+
+            let data = Codec::encode(&queue_msg.request);
+            let processor = Arc::clone(&processor);
+            // `process` is blocking, like a real enclave round trip
+            let response = tokio::task::spawn_blocking(move || {
+                processor
+                    .lock()
+                    .expect("in process enclave lock poisoned")
+                    .process(data)
+            })
+            .await
+            .map_err(|e| Status::internal(format!("Failed to join blocking task: {e:?}")))
+            .and_then(|data| {
+                Codec::decode(&data)
+                    .map_err(|e| Status::internal(format!("Failed to decode app response: {e:?}")))
+            });
+
+            if queue_msg.response_tx.send(response).is_err() {
+                queue_metrics().record_dropped_response(&queue_msg.request_id);
+            }
+        }
+    });
+}
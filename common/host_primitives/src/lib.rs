@@ -1,7 +1,7 @@
 //! Primitives for building Turnkey secure app gRPC host servers.
 
 use std::sync::Arc;
-use std::{fmt::Debug, marker::PhantomData};
+use std::{fmt::Debug, future::Future, marker::PhantomData, path::PathBuf, time::Duration};
 
 use borsh::{BorshDeserialize, BorshSerialize};
 use prost::Message;
@@ -9,16 +9,54 @@ use qos_core::{
     io::{TimeVal, TimeValLike},
     protocol::{msg::ProtocolMsg, ENCLAVE_APP_SOCKET_CLIENT_TIMEOUT_SECS},
 };
+use qos_nsm::types::NsmResponse;
 use tokio::{
     signal::unix::{signal, SignalKind},
     sync::oneshot,
+    task::JoinHandle,
 };
 use tonic::Status;
+use tracing::Instrument;
 
-/// Buffer size for socket message queue.
+mod app_host;
+pub use app_host::{AppHostBuilder, Keepalive};
+mod batch;
+mod circuit_breaker;
+pub use circuit_breaker::{CircuitBreaker, CircuitPermit, CircuitState};
+mod concurrency;
+pub use concurrency::{ConcurrencyLimit, ConcurrencyPermit};
+mod connection;
+pub use batch::{
+    spawn_batching_queue_consumer, BatchProcessor, BatchRequest, BatchResponse, Batching,
+};
+pub use connection::{ConnectionState, EnclaveConnection, Retry};
+mod deadline;
+pub use deadline::{enclave_deadline, grpc_timeout, DEADLINE_MARGIN, GRPC_TIMEOUT_HEADER};
+mod negotiation;
+pub use negotiation::{
+    choose_codec, codec_answer, codec_offer, negotiate_codec, spawn_negotiating_queue_consumer,
+    CodecId, CodecSet, NamedCodec, Negotiated,
+};
+mod metrics;
+pub use metrics::{queue_metrics, DroppedResponseHookGuard, QueueMetrics, QueueUtilization};
+mod reload;
+pub use reload::{RuntimeConfig, RuntimeSettings};
+mod request_id;
+pub use request_id::{RequestId, REQUEST_ID_HEADER};
+#[cfg(feature = "test-util")]
+mod in_process;
+#[cfg(feature = "test-util")]
+pub use in_process::spawn_in_process_queue_consumer;
+
+/// Default buffer size for socket message queue, see [`AppHostBuilder::queue_capacity`].
 pub static ENCLAVE_QUEUE_CAPACITY: usize = 12;
 
-/// Maximum gRPC message size. Set to 25MB (25*1024*1024)
+/// Buffer size for the health check message queue. Health probes are infrequent, so this only
+/// needs to absorb a few concurrent checks.
+pub static HEALTH_QUEUE_CAPACITY: usize = 2;
+
+/// Default maximum gRPC message size. Set to 25MB (25*1024*1024). Hosts should
+/// let operators override this.
 pub static GRPC_MAX_RECV_MSG_SIZE: usize = 26_214_400;
 
 /// A type that can be encoded to bytes
@@ -72,12 +110,17 @@ pub struct EnclaveQueueMsg<Req, Resp> {
     pub response_tx: tokio::sync::oneshot::Sender<Result<Resp, Status>>,
     /// The request message.
     pub request: Req,
+    /// Correlates the enqueue and dequeue log events of this request.
+    pub request_id: RequestId,
 }
 
 /// Client for the enclave queue.
 #[derive(Debug)]
 pub struct EnclaveClient<Codec, Req, Resp> {
     queue_tx: tokio::sync::mpsc::Sender<Box<EnclaveQueueMsg<Req, Resp>>>,
+    connection: Option<Arc<EnclaveConnection>>,
+    concurrency_limit: Option<ConcurrencyLimit>,
+    circuit_breaker: Option<CircuitBreaker>,
     _phantom: PhantomData<Codec>,
 }
 
@@ -89,28 +132,164 @@ where
     pub fn new(queue_tx: tokio::sync::mpsc::Sender<Box<EnclaveQueueMsg<Req, Resp>>>) -> Self {
         Self {
             queue_tx,
+            connection: None,
+            concurrency_limit: None,
+            circuit_breaker: None,
             _phantom: PhantomData::<Codec>,
         }
     }
 
+    /// Report the state of `connection`, the connection the queue consumer sends over, from
+    /// [`Self::connection_state`].
+    pub fn with_connection(mut self, connection: Arc<EnclaveConnection>) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Hold requests back until they are under `concurrency_limit`, see [`ConcurrencyLimit`].
+    pub fn with_concurrency_limit(mut self, concurrency_limit: ConcurrencyLimit) -> Self {
+        self.concurrency_limit = Some(concurrency_limit);
+        self
+    }
+
+    /// Fail requests fast with `unavailable` while `circuit_breaker` is open, rather than
+    /// waiting on an enclave that keeps failing, see [`CircuitBreaker`].
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Number of requests that can currently be queued without waiting for room.
+    pub fn available_capacity(&self) -> usize {
+        self.queue_tx.capacity()
+    }
+
+    /// How full the client's queue is, see also [`QueueMetrics::register_queue`].
+    pub fn queue_utilization(&self) -> QueueUtilization {
+        QueueUtilization::of(&self.queue_tx)
+    }
+
+    /// State of the queue consumer's connection to the enclave, if known to this client.
+    pub fn connection_state(&self) -> Option<ConnectionState> {
+        self.connection.as_ref().map(|c| c.state())
+    }
+
+    /// Fetch a fresh attestation document from QOS in the enclave, see [`live_attestation_doc`].
+    ///
+    /// The request is sent straight over the client's connection, bypassing the queue, and
+    /// fails with `failed_precondition` if the client has none, see [`Self::with_connection`].
+    /// Otherwise it is bounded like [`Self::send_with_timeout`]: it counts towards the
+    /// concurrency limit and circuit breaker, fails with `deadline_exceeded` after `timeout`
+    /// and is logged under `request_id`.
+    pub async fn live_attestation_doc(
+        &self,
+        request_id: Option<RequestId>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>, tonic::Status> {
+        let connection = self.connection.clone().ok_or_else(|| {
+            Status::failed_precondition("enclave client has no connection for live attestation")
+        })?;
+        let request_id = request_id.unwrap_or_else(RequestId::generate);
+        let span = tracing::info_span!("live_attestation", request_id = %request_id);
+        tracing::debug!(parent: &span, request_id = %request_id, "live attestation requested");
+
+        self.bounded(timeout, live_attestation_doc(connection).instrument(span))
+            .await
+    }
+
     /// Send a message to the enclave and wait for the response
     pub async fn send(&self, req: Req) -> Result<Resp, tonic::Status> {
-        send_queue_msg::<Codec, _, _>(req, &self.queue_tx).await
+        self.send_with_id(req, None).await
+    }
+
+    /// Like [`Self::send`], logging the request under `request_id`, e.g. one taken from the gRPC
+    /// call with [`RequestId::from_metadata`]. One is generated if `None`.
+    pub async fn send_with_id(
+        &self,
+        req: Req,
+        request_id: Option<RequestId>,
+    ) -> Result<Resp, tonic::Status> {
+        self.send_with_timeout(req, request_id, None).await
+    }
+
+    /// Like [`Self::send_with_id`], failing with `deadline_exceeded` if the enclave has not
+    /// answered within `timeout`, e.g. the time left of the gRPC call, see
+    /// [`enclave_deadline`]. Time waiting for the concurrency limit and in the queue counts
+    /// towards it. A request still queued at the timeout is skipped by the consumer, one in
+    /// progress has its response dropped, see [`queue_metrics`].
+    ///
+    /// Fails with `unavailable` right away while the client's circuit breaker is open, see
+    /// [`Self::with_circuit_breaker`].
+    pub async fn send_with_timeout(
+        &self,
+        req: Req,
+        request_id: Option<RequestId>,
+        timeout: Option<Duration>,
+    ) -> Result<Resp, tonic::Status> {
+        self.bounded(
+            timeout,
+            send_queue_msg::<Codec, _, _>(req, request_id, &self.queue_tx),
+        )
+        .await
+    }
+
+    /// Run `request` under the client's circuit breaker and concurrency limit, failing with
+    /// `deadline_exceeded` if it has not finished within `timeout`.
+    async fn bounded<T>(
+        &self,
+        timeout: Option<Duration>,
+        request: impl Future<Output = Result<T, tonic::Status>>,
+    ) -> Result<T, tonic::Status> {
+        let permit = self
+            .circuit_breaker
+            .as_ref()
+            .map(CircuitBreaker::acquire)
+            .transpose()?;
+
+        let limited = async {
+            let _permit = match &self.concurrency_limit {
+                Some(concurrency_limit) => Some(concurrency_limit.acquire().await),
+                None => None,
+            };
+            request.await
+        };
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, limited)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Status::deadline_exceeded(format!(
+                        "enclave did not respond within {timeout:?}"
+                    )))
+                }),
+            None => limited.await,
+        };
+        if let Some(permit) = permit {
+            permit.record(&result);
+        }
+        result
     }
 }
 
 /// Send a message to secure app via socket connection.
 ///
 /// You likely do not want to transform the error since we want to preserve the
-/// unavailable error code to indicate the enclave queue is full.
+/// unavailable error code to indicate the enclave queue is full or the enclave can not be
+/// reached. If the queue consumer is no longer running the status is `unavailable` with the
+/// message "enclave consumer not running". Responses the host can not make sense of are
+/// `internal`.
+///
+/// The request is logged under `request_id`, or a generated [`RequestId`] if `None`.
 pub async fn send_queue_msg<Codec, Req, Resp>(
     request: Req,
+    request_id: Option<RequestId>,
     queue_tx: &tokio::sync::mpsc::Sender<Box<EnclaveQueueMsg<Req, Resp>>>,
 ) -> Result<Resp, tonic::Status>
 where
     Codec: Encode<Req> + Decode<Resp>,
 {
     let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+    let request_id = request_id.unwrap_or_else(RequestId::generate);
+    tracing::debug!(request_id = %request_id, "enclave request enqueued");
 
     // Send the message to the enclave queue, blocking if the queue is full
     // https://linear.app/turnkey/issue/INF-50/return-unavailable-status-code-if-enclave-queue-is-full
@@ -118,88 +297,200 @@ where
         .send(Box::new(EnclaveQueueMsg {
             request,
             response_tx,
+            request_id,
         }))
         .await
-        .map_err(|e| Status::unavailable(format!("send_queue_msg: channel may be full: {e:?}")))?;
+        .map_err(|_| consumer_not_running())?;
 
     response_rx.await.map_err(|e| {
-        Status::internal(format!(
+        // The consumer exited, e.g. panicked, while holding the request
+        if queue_tx.is_closed() {
+            return consumer_not_running();
+        }
+        // The queue consumer dropped the request without answering, so the enclave is not reachable
+        Status::unavailable(format!(
             "send_queue_msg: failed waiting for response: {e:?}"
         ))
     })?
 }
 
+/// Status of requests sent to a queue whose consumer task has exited. No request will be
+/// answered again until the host restarts.
+fn consumer_not_running() -> Status {
+    Status::unavailable("enclave consumer not running")
+}
+
 /// Send a message to a secure app via QOS proxy.
+///
+/// Requests that encode to more than `max_request_size` bytes are rejected with
+/// `invalid_argument` without contacting the enclave. Failures to reach the enclave are retried
+/// according to the connection's [`Retry`] policy, failures to decode the response are not.
+///
+/// With a `timeout`, the request fails with `deadline_exceeded` if the enclave has not answered
+/// within it, retries included. The blocking socket send can not be interrupted though: it
+/// carries on in the background and holds the connection until the socket timeout.
 pub async fn send_proxy_request<Codec, Req, Resp>(
     request: Req,
-    client: Arc<qos_core::client::Client>,
+    connection: Arc<EnclaveConnection>,
+    max_request_size: usize,
+    timeout: Option<Duration>,
 ) -> Result<Resp, tonic::Status>
 where
     Resp: Send + 'static,
     Codec: Encode<Req> + Decode<Resp>,
 {
-    let qos_request = ProtocolMsg::ProxyRequest {
-        data: Codec::encode(&request),
+    let data = Codec::encode(&request);
+    if data.len() > max_request_size {
+        return Err(Status::invalid_argument(format!(
+            "encoded request is {} bytes, exceeding the limit of {max_request_size} bytes",
+            data.len()
+        )));
+    }
+    let round_trip = proxy_round_trip(data, connection);
+    let encoded_app_response = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, round_trip)
+            .await
+            .map_err(|_| {
+                Status::deadline_exceeded(format!("enclave did not respond within {timeout:?}"))
+            })??,
+        None => round_trip.await?,
     };
 
-    // We use spawn_blocking here because `qos_core::client::Client::send` is blocking
-    let response = tokio::task::spawn_blocking(move || {
-        let encoded_qos_request = borsh::to_vec(&qos_request)
-            .map_err(|e| Status::internal(format!("Failed to serialize qos request: {e:?}")))?;
+    Codec::decode(&encoded_app_response)
+        .map_err(|e| Status::internal(format!("Failed to decode app response: {e:?}")))
+}
 
-        let encoded_qos_response = client
-            .send(&encoded_qos_request)
-            .map_err(|e| Status::internal(format!("Failed to query enclave: {e:?}")))?;
-        let qos_response = ProtocolMsg::try_from_slice(&encoded_qos_response).map_err(|e| {
-            Status::internal(format!("Failed to deserialized enclave response: {e:?}"))
-        })?;
+/// Send encoded app request `data` to the enclave in a `ProxyRequest` and return the data of
+/// the `ProxyResponse`.
+///
+/// Failures to reach the enclave are retried according to the connection's [`Retry`] policy.
+/// Responses above the connection's [`EnclaveConnection::max_response_size`] are rejected with
+/// `out_of_range` before anything decodes them.
+pub(crate) async fn proxy_round_trip(
+    data: Vec<u8>,
+    connection: Arc<EnclaveConnection>,
+) -> Result<Vec<u8>, tonic::Status> {
+    let max_response_size = connection.max_response_size();
+    match protocol_round_trip(ProtocolMsg::ProxyRequest { data }, connection).await? {
+        ProtocolMsg::ProxyResponse { data } => match max_response_size {
+            Some(max) if data.len() > max => Err(Status::out_of_range(format!(
+                "app response is {} bytes, exceeding the limit of {max} bytes",
+                data.len()
+            ))),
+            _ => Ok(data),
+        },
+        other => Err(Status::internal(format!(
+            "Expected a ProtocolMsg::ProxyResponse but got {other:?}"
+        ))),
+    }
+}
 
-        let encoded_app_response = match qos_response {
-            ProtocolMsg::ProxyResponse { data } => data,
-            other => {
-                return Err(Status::internal(format!(
-                    "Expected a ProtocolMsg::ProxyResponse but got {other:?}"
-                )))
-            }
-        };
+/// Fetch a fresh attestation document from QOS in the enclave, as opposed to one produced by
+/// the app, by sending a `LiveAttestationDocRequest` over `connection`.
+///
+/// Failures to reach the enclave are retried according to the connection's [`Retry`] policy.
+pub async fn live_attestation_doc(
+    connection: Arc<EnclaveConnection>,
+) -> Result<Vec<u8>, tonic::Status> {
+    match protocol_round_trip(ProtocolMsg::LiveAttestationDocRequest, connection).await? {
+        ProtocolMsg::LiveAttestationDocResponse {
+            nsm_response: NsmResponse::Attestation { document },
+            ..
+        } => Ok(document),
+        ProtocolMsg::LiveAttestationDocResponse { nsm_response, .. } => Err(Status::internal(
+            format!("Expected an attestation from the NSM but got {nsm_response:?}"),
+        )),
+        other => Err(Status::internal(format!(
+            "Expected a ProtocolMsg::LiveAttestationDocResponse but got {other:?}"
+        ))),
+    }
+}
 
-        let response = Codec::decode(&encoded_app_response)
-            .map_err(|e| Status::internal(format!("Failed to decode app response: {e:?}")))?;
+/// Send `request` to QOS in the enclave and return its response.
+///
+/// Failures to reach the enclave are retried according to the connection's [`Retry`] policy.
+async fn protocol_round_trip(
+    request: ProtocolMsg,
+    connection: Arc<EnclaveConnection>,
+) -> Result<ProtocolMsg, tonic::Status> {
+    let encoded_qos_request = Arc::new(
+        borsh::to_vec(&request)
+            .map_err(|e| Status::internal(format!("Failed to serialize qos request: {e:?}")))?,
+    );
 
-        Ok(response)
-    })
-    .await
-    .map_err(|e| Status::internal(format!("Failed to join blocking task: {e:?}")))?;
+    let retry = connection.retry();
+    let max_attempts = retry.map_or(1, |r| r.max_attempts.max(1));
+    let mut attempts = 0;
+    let encoded_qos_response = loop {
+        attempts += 1;
+        let connection = Arc::clone(&connection);
+        let encoded_qos_request = Arc::clone(&encoded_qos_request);
+        // We use spawn_blocking here because `EnclaveConnection::send` is blocking
+        let result = tokio::task::spawn_blocking(move || connection.send(&encoded_qos_request))
+            .await
+            .map_err(|e| Status::internal(format!("Failed to join blocking task: {e:?}")))?;
 
-    response
+        match (result, retry) {
+            (Ok(response), _) => break response,
+            (Err(_), Some(retry)) if attempts < max_attempts => {
+                tokio::time::sleep(retry.delay(attempts)).await;
+            }
+            // The enclave could not be reached, as opposed to answering with something invalid
+            (Err(e), _) => {
+                return Err(Status::unavailable(format!(
+                    "Failed to query enclave: {e:?}"
+                )))
+            }
+        }
+    };
+
+    ProtocolMsg::try_from_slice(&encoded_qos_response)
+        .map_err(|e| Status::internal(format!("Failed to deserialized enclave response: {e:?}")))
 }
 
 /// Spawn a consumer task to read from the enclave message queue and send messages to the enclave.
+/// Requests that encode to more than `max_request_size` bytes are rejected before being sent.
+/// Requests and responses of callers that stopped waiting are counted in [`queue_metrics`], which
+/// also calls the hooks of [`QueueMetrics::on_dropped_response`].
 pub fn spawn_queue_consumer<Codec, Req, Resp>(
-    enclave_addr: qos_core::io::SocketAddress,
+    connection: Arc<EnclaveConnection>,
     mut queue_rx: tokio::sync::mpsc::Receiver<Box<EnclaveQueueMsg<Req, Resp>>>,
+    max_request_size: usize,
 ) where
     Resp: Send + Debug + 'static,
     Req: Send + 'static,
     Codec: Encode<Req> + Decode<Resp>,
 {
     tokio::task::spawn(async move {
-        let client = Arc::new(qos_core::client::Client::new(
-            enclave_addr,
-            enclave_client_timeout(),
-        ));
-
         loop {
             let queue_msg = queue_rx.recv().await.expect("failed to receive message");
-            let enclave_resp =
-                send_proxy_request::<Codec, _, _>(queue_msg.request, Arc::clone(&client)).await;
+            let request_id = &queue_msg.request_id;
+            let span = tracing::info_span!("enclave_request", request_id = %request_id);
+            tracing::debug!(parent: &span, request_id = %request_id, "enclave request dequeued");
 
-            if let Err(e) = queue_msg.response_tx.send(enclave_resp) {
+            // The caller is no longer waiting for a response, e.g. the client disconnected, so
+            // don't spend enclave capacity on the request.
+            if queue_msg.response_tx.is_closed() {
+                tracing::debug!(parent: &span, "caller gone, skipping enclave request");
+                queue_metrics().record_abandoned_request();
+                continue;
+            }
+
+            let enclave_resp = send_proxy_request::<Codec, _, _>(
+                queue_msg.request,
+                Arc::clone(&connection),
+                max_request_size,
+                None,
+            )
+            .instrument(span)
+            .await;
+
+            if queue_msg.response_tx.send(enclave_resp).is_err() {
                 // This happens when the receiver (inside the tonic handler) is de-allocated. This can
                 // happen if the request timeout is reached or tonic handler exits for some other reason
                 // We continue here to ensure we can keep consuming messages from the queue.
                 // See <https://docs.rs/tokio/latest/tokio/sync/oneshot/struct.Sender.html#impl-Sender%3CT%3E>
-                eprint!("queue consumer failed to send to caller: {e:?}")
+                queue_metrics().record_dropped_response(&queue_msg.request_id);
             };
         }
     });
@@ -216,6 +507,27 @@ pub async fn wait_for_sigterm(sender: oneshot::Sender<()>) {
         .expect("failed to create SIGTERM signal handler")
         .recv()
         .await;
-    println!("SIGTERM signal handled, forwarding to host server");
+    tracing::info!("SIGTERM signal handled, forwarding to host server");
     let _ = sender.send(());
 }
+
+/// On every SIGHUP, re-read the [`RuntimeConfig`] at `path` and apply it to `settings`. A config
+/// that fails to load is logged and changes nothing.
+///
+/// The SIGHUP handler is installed before returning, so signals sent once this returns are not
+/// missed.
+///
+/// # Panics
+///
+/// Panics if the SIGHUP handler can not be installed.
+pub fn spawn_sighup_reload(path: PathBuf, settings: RuntimeSettings) -> JoinHandle<()> {
+    let mut sighup = signal(SignalKind::hangup()).expect("failed to create SIGHUP signal handler");
+    tokio::task::spawn(async move {
+        while sighup.recv().await.is_some() {
+            tracing::info!("SIGHUP received, reloading {}", path.display());
+            if let Err(e) = RuntimeConfig::load(&path).and_then(|config| settings.apply(&config)) {
+                tracing::warn!("failed to reload the runtime config: {e}");
+            }
+        }
+    })
+}
@@ -0,0 +1,191 @@
+//! Counters and gauges of enclave queues, for hosts to export to their metrics system.
+//!
+//! Counters are process wide and only ever increase, so exporters can read them at any interval
+//! and report them as monotonic counters. Gauges are read from the queues when asked for.
+//! Hosts that want to know which responses were dropped register a hook with
+//! [`QueueMetrics::on_dropped_response`], for as long as they hold the returned guard.
+
+use std::{
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::sync::mpsc;
+
+use crate::RequestId;
+
+static QUEUE_METRICS: QueueMetrics = QueueMetrics {
+    abandoned_requests: AtomicU64::new(0),
+    dropped_responses: AtomicU64::new(0),
+    queues: Mutex::new(Vec::new()),
+    dropped_response_hooks: Mutex::new(Vec::new()),
+    next_hook_id: AtomicU64::new(0),
+};
+
+/// Reads the utilization of a registered queue, `None` once the queue is gone.
+type QueueGauge = Box<dyn Fn() -> Option<QueueUtilization> + Send + Sync>;
+
+/// Called with the request id of every dropped response, see
+/// [`QueueMetrics::on_dropped_response`].
+type DroppedResponseHook = Arc<dyn Fn(&RequestId) + Send + Sync>;
+
+/// Counters shared by every queue consumer of the process, and the queues registered with
+/// [`QueueMetrics::register_queue`], see [`queue_metrics`].
+pub struct QueueMetrics {
+    abandoned_requests: AtomicU64,
+    dropped_responses: AtomicU64,
+    queues: Mutex<Vec<(String, QueueGauge)>>,
+    /// Registered hooks by id, in registration order.
+    dropped_response_hooks: Mutex<Vec<(u64, DroppedResponseHook)>>,
+    next_hook_id: AtomicU64,
+}
+
+impl fmt::Debug for QueueMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueMetrics")
+            .field("abandoned_requests", &self.abandoned_requests())
+            .field("dropped_responses", &self.dropped_responses())
+            .field("queues", &self.queue_utilization())
+            .finish_non_exhaustive()
+    }
+}
+
+/// How full an enclave queue is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueUtilization {
+    /// Requests waiting for the consumer. The request the consumer is working on is not counted.
+    pub queued: usize,
+    /// Requests the queue holds before callers wait for room.
+    pub capacity: usize,
+}
+
+impl QueueUtilization {
+    /// Current utilization of the queue `queue_tx` sends to.
+    pub fn of<T>(queue_tx: &mpsc::Sender<T>) -> Self {
+        let capacity = queue_tx.max_capacity();
+        Self {
+            queued: capacity - queue_tx.capacity(),
+            capacity,
+        }
+    }
+
+    /// Fraction of the queue in use, from 0 to 1.
+    pub fn ratio(&self) -> f64 {
+        self.queued as f64 / self.capacity as f64
+    }
+}
+
+impl QueueMetrics {
+    /// Requests skipped by a consumer because the caller stopped waiting before the request
+    /// was dequeued, e.g. it timed out or disconnected while the queue was backed up.
+    pub fn abandoned_requests(&self) -> u64 {
+        self.abandoned_requests.load(Ordering::Relaxed)
+    }
+
+    /// Responses computed by the enclave but dropped because the caller stopped waiting while
+    /// the request was in flight. Each one is enclave capacity spent for nothing.
+    pub fn dropped_responses(&self) -> u64 {
+        self.dropped_responses.load(Ordering::Relaxed)
+    }
+
+    /// Report the utilization of the queue `queue_tx` sends to under `name` from
+    /// [`Self::queue_utilization`], for as long as any sender of the queue is alive.
+    pub fn register_queue<T: Send + 'static>(
+        &self,
+        name: impl Into<String>,
+        queue_tx: &mpsc::Sender<T>,
+    ) {
+        let queue_tx = queue_tx.downgrade();
+        let gauge: QueueGauge =
+            Box::new(move || queue_tx.upgrade().map(|tx| QueueUtilization::of(&tx)));
+        self.queues
+            .lock()
+            .expect("queue metrics lock poisoned")
+            .push((name.into(), gauge));
+    }
+
+    /// Utilization of every registered queue that is still alive, by name, in registration
+    /// order.
+    pub fn queue_utilization(&self) -> Vec<(String, QueueUtilization)> {
+        let mut utilization = Vec::new();
+        self.queues
+            .lock()
+            .expect("queue metrics lock poisoned")
+            .retain(|(name, gauge)| match gauge() {
+                Some(queue) => {
+                    utilization.push((name.clone(), queue));
+                    true
+                }
+                None => false,
+            });
+        utilization
+    }
+
+    /// Call `hook` with the request id of every response dropped from now on, see
+    /// [`Self::dropped_responses`], until the returned guard is dropped. Hooks run on the queue
+    /// consumer, so they should return quickly. A hook that panics is logged and does not stop
+    /// the consumer.
+    #[must_use = "the hook is removed when the guard is dropped"]
+    pub fn on_dropped_response(
+        &self,
+        hook: impl Fn(&RequestId) + Send + Sync + 'static,
+    ) -> DroppedResponseHookGuard<'_> {
+        let id = self.next_hook_id.fetch_add(1, Ordering::Relaxed);
+        self.dropped_response_hooks
+            .lock()
+            .expect("queue metrics lock poisoned")
+            .push((id, Arc::new(hook)));
+        DroppedResponseHookGuard { metrics: self, id }
+    }
+
+    pub(crate) fn record_abandoned_request(&self) {
+        self.abandoned_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the dropped response to `request_id` and tell the hooks. Never panics, as it is
+    /// called from queue consumers that must keep consuming.
+    pub(crate) fn record_dropped_response(&self, request_id: &RequestId) {
+        self.dropped_responses.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(request_id = %request_id, "caller gone, dropping enclave response");
+
+        // Hooks run outside the lock, so they can register and remove hooks themselves
+        let hooks: Vec<DroppedResponseHook> = match self.dropped_response_hooks.lock() {
+            Ok(hooks) => hooks.iter().map(|(_, hook)| Arc::clone(hook)).collect(),
+            Err(_) => {
+                tracing::error!("dropped response hooks lock poisoned");
+                return;
+            }
+        };
+        for hook in &hooks {
+            if catch_unwind(AssertUnwindSafe(|| hook(request_id))).is_err() {
+                tracing::error!(request_id = %request_id, "dropped response hook panicked");
+            }
+        }
+    }
+}
+
+/// Removes its hook from [`QueueMetrics::on_dropped_response`] when dropped. A hook already
+/// running on a queue consumer finishes.
+#[derive(Debug)]
+pub struct DroppedResponseHookGuard<'a> {
+    metrics: &'a QueueMetrics,
+    id: u64,
+}
+
+impl Drop for DroppedResponseHookGuard<'_> {
+    fn drop(&mut self) {
+        // Never panic in drop, a poisoned lock only means a hook stays registered
+        if let Ok(mut hooks) = self.metrics.dropped_response_hooks.lock() {
+            hooks.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+/// Queue counters of this process.
+pub fn queue_metrics() -> &'static QueueMetrics {
+    &QUEUE_METRICS
+}
@@ -0,0 +1,317 @@
+//! Agreeing on the codec of app requests and responses at runtime.
+//!
+//! Before its first app request, a negotiating queue consumer sends the enclave a codec offer in
+//! place of an encoded app request, listing the codecs the host supports. The app answers with
+//! the newest codec it supports too, see [`codec_offer`] and [`codec_answer`], and the consumer
+//! uses it for every later request. Apps that predate negotiation answer the offer with
+//! something that is not a codec answer, in which case the consumer falls back to the codec
+//! both sides used before negotiation existed. This lets hosts and apps move to a new codec
+//! one at a time.
+
+use std::{fmt::Debug, marker::PhantomData, sync::Arc};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use tonic::Status;
+use tracing::Instrument;
+
+use crate::{
+    proxy_round_trip, queue_metrics, BorshCodec, Decode, EnclaveConnection, EnclaveQueueMsg,
+    Encode, ProstCodec,
+};
+
+/// Prefix of codec offers and answers. No app request or response encoding starts with it, so
+/// apps that predate negotiation reject offers instead of processing them.
+const NEGOTIATION_MAGIC: [u8; 8] = *b"\xffcodecs\x00";
+
+/// Codecs a host and app can agree on. Later codecs are newer and preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CodecId {
+    /// [`BorshCodec`]
+    Borsh,
+    /// [`ProstCodec`]
+    Prost,
+}
+
+impl CodecId {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Borsh => 0,
+            Self::Prost => 1,
+        }
+    }
+
+    /// `None` for codecs newer than this build.
+    fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Borsh),
+            1 => Some(Self::Prost),
+            _ => None,
+        }
+    }
+}
+
+/// Codec offer sent by the host. Codecs are sent as raw ids so apps can skip ids newer than
+/// they are.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct CodecOffer {
+    magic: [u8; 8],
+    codecs: Vec<u8>,
+}
+
+/// Answer of the app to a [`CodecOffer`], `None` if it supports none of the offered codecs.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct CodecAnswer {
+    magic: [u8; 8],
+    codec: Option<u8>,
+}
+
+/// A codec that can take part in negotiation.
+pub trait NamedCodec {
+    /// Id the codec is negotiated under.
+    const ID: CodecId;
+}
+
+impl NamedCodec for BorshCodec {
+    const ID: CodecId = CodecId::Borsh;
+}
+
+impl NamedCodec for ProstCodec {
+    const ID: CodecId = CodecId::Prost;
+}
+
+/// Codecs for `Req` and `Resp` one of which is chosen at runtime.
+pub trait CodecSet<Req, Resp> {
+    /// Codecs in the set.
+    fn supported() -> Vec<CodecId>;
+
+    /// Codec to use with apps that predate negotiation.
+    fn fallback() -> CodecId;
+
+    /// Encode `value` with `codec`, `None` if `codec` is not in the set.
+    fn encode_with(codec: CodecId, value: &Req) -> Option<Vec<u8>>;
+
+    /// Decode `bytes` with `codec`.
+    fn decode_with(codec: CodecId, bytes: &[u8]) -> Result<Resp, String>;
+}
+
+impl<C, Req, Resp> CodecSet<Req, Resp> for C
+where
+    C: NamedCodec + Encode<Req> + Decode<Resp>,
+{
+    fn supported() -> Vec<CodecId> {
+        vec![C::ID]
+    }
+
+    fn fallback() -> CodecId {
+        C::ID
+    }
+
+    fn encode_with(codec: CodecId, value: &Req) -> Option<Vec<u8>> {
+        (codec == C::ID).then(|| C::encode(value))
+    }
+
+    fn decode_with(codec: CodecId, bytes: &[u8]) -> Result<Resp, String> {
+        if codec != C::ID {
+            return Err(format!("codec {codec:?} is not supported"));
+        }
+        C::decode(bytes)
+    }
+}
+
+/// [`CodecSet`] of the codecs of `Legacy` and `Next`, falling back to `Legacy`.
+///
+/// Also an [`Encode`] and [`Decode`] with the codec of `Legacy`, so the same host code works
+/// with consumers that do not negotiate.
+#[derive(Debug, Clone)]
+pub struct Negotiated<Legacy, Next> {
+    _phantom: PhantomData<(Legacy, Next)>,
+}
+
+impl<Legacy, Next, Req, Resp> CodecSet<Req, Resp> for Negotiated<Legacy, Next>
+where
+    Legacy: CodecSet<Req, Resp>,
+    Next: CodecSet<Req, Resp>,
+{
+    fn supported() -> Vec<CodecId> {
+        let mut supported = Legacy::supported();
+        supported.extend(Next::supported());
+        supported
+    }
+
+    fn fallback() -> CodecId {
+        Legacy::fallback()
+    }
+
+    fn encode_with(codec: CodecId, value: &Req) -> Option<Vec<u8>> {
+        Legacy::encode_with(codec, value).or_else(|| Next::encode_with(codec, value))
+    }
+
+    fn decode_with(codec: CodecId, bytes: &[u8]) -> Result<Resp, String> {
+        if Legacy::supported().contains(&codec) {
+            Legacy::decode_with(codec, bytes)
+        } else {
+            Next::decode_with(codec, bytes)
+        }
+    }
+}
+
+impl<Legacy: Encode<T>, Next, T> Encode<T> for Negotiated<Legacy, Next> {
+    fn encode(value: &T) -> Vec<u8> {
+        Legacy::encode(value)
+    }
+}
+
+impl<Legacy: Decode<T>, Next, T> Decode<T> for Negotiated<Legacy, Next> {
+    fn decode(bytes: &[u8]) -> Result<T, String> {
+        Legacy::decode(bytes)
+    }
+}
+
+/// Newest codec in both `ours` and `theirs`, if any.
+pub fn choose_codec(ours: &[CodecId], theirs: &[CodecId]) -> Option<CodecId> {
+    ours.iter()
+        .filter(|codec| theirs.contains(codec))
+        .max()
+        .copied()
+}
+
+/// App side: the codecs offered by the host if `request` is a codec offer, leaving out codecs
+/// newer than this build. Apps answer an offer with [`codec_answer`] instead of processing it.
+pub fn codec_offer(request: &[u8]) -> Option<Vec<CodecId>> {
+    let offer = CodecOffer::try_from_slice(request).ok()?;
+    (offer.magic == NEGOTIATION_MAGIC).then(|| {
+        offer
+            .codecs
+            .into_iter()
+            .filter_map(CodecId::from_u8)
+            .collect()
+    })
+}
+
+/// App side: answer to a codec offer, `codec` being the one chosen with [`choose_codec`].
+pub fn codec_answer(codec: Option<CodecId>) -> Vec<u8> {
+    borsh::to_vec(&CodecAnswer {
+        magic: NEGOTIATION_MAGIC,
+        codec: codec.map(CodecId::to_u8),
+    })
+    .expect("codec answer encodes to borsh")
+}
+
+/// Agree on one of `Codecs` with the app behind `connection`.
+///
+/// Apps that predate negotiation get [`CodecSet::fallback`]. Fails with `failed_precondition`
+/// if the app supports none of `Codecs`.
+pub async fn negotiate_codec<Codecs, Req, Resp>(
+    connection: Arc<EnclaveConnection>,
+) -> Result<CodecId, Status>
+where
+    Codecs: CodecSet<Req, Resp>,
+{
+    let supported = Codecs::supported();
+    let offer = borsh::to_vec(&CodecOffer {
+        magic: NEGOTIATION_MAGIC,
+        codecs: supported.iter().map(|codec| codec.to_u8()).collect(),
+    })
+    .expect("codec offer encodes to borsh");
+
+    let data = proxy_round_trip(offer, connection).await?;
+    let answer = match CodecAnswer::try_from_slice(&data) {
+        Ok(answer) if answer.magic == NEGOTIATION_MAGIC => answer,
+        // The app does not know about negotiation, so it still uses the codec from before
+        _ => return Ok(Codecs::fallback()),
+    };
+
+    let codec = answer.codec.ok_or_else(|| {
+        Status::failed_precondition(format!("app supports none of the codecs {supported:?}"))
+    })?;
+    CodecId::from_u8(codec)
+        .filter(|codec| supported.contains(codec))
+        .ok_or_else(|| {
+            Status::failed_precondition(format!("app chose codec {codec}, which was not offered"))
+        })
+}
+
+/// Like [`crate::spawn_queue_consumer`], but encodes requests with a codec of `Codecs` agreed on
+/// with the app, see [`negotiate_codec`].
+///
+/// The codec is negotiated when the first request is received and kept for the life of the
+/// consumer. If negotiation fails the request is answered with the error and the next request
+/// negotiates again.
+pub fn spawn_negotiating_queue_consumer<Codecs, Req, Resp>(
+    connection: Arc<EnclaveConnection>,
+    mut queue_rx: tokio::sync::mpsc::Receiver<Box<EnclaveQueueMsg<Req, Resp>>>,
+    max_request_size: usize,
+) where
+    Resp: Send + Debug + 'static,
+    Req: Send + 'static,
+    Codecs: CodecSet<Req, Resp>,
+{
+    tokio::task::spawn(async move {
+        let mut codec = None;
+        loop {
+            let queue_msg = queue_rx.recv().await.expect("failed to receive message");
+            let request_id = &queue_msg.request_id;
+            let span = tracing::info_span!("enclave_request", request_id = %request_id);
+            tracing::debug!(parent: &span, request_id = %request_id, "enclave request dequeued");
+
+            // Skip callers that are no longer waiting, see `spawn_queue_consumer`
+            if queue_msg.response_tx.is_closed() {
+                queue_metrics().record_abandoned_request();
+                continue;
+            }
+
+            let negotiated = match codec {
+                Some(negotiated) => negotiated,
+                None => match negotiate_codec::<Codecs, Req, Resp>(Arc::clone(&connection))
+                    .instrument(span.clone())
+                    .await
+                {
+                    Ok(negotiated) => {
+                        tracing::info!(parent: &span, ?negotiated, "negotiated app codec");
+                        *codec.insert(negotiated)
+                    }
+                    Err(status) => {
+                        let _ = queue_msg.response_tx.send(Err(status));
+                        continue;
+                    }
+                },
+            };
+
+            let response = send_negotiated_request::<Codecs, _, _>(
+                negotiated,
+                queue_msg.request,
+                Arc::clone(&connection),
+                max_request_size,
+            )
+            .instrument(span)
+            .await;
+            if queue_msg.response_tx.send(response).is_err() {
+                queue_metrics().record_dropped_response(&queue_msg.request_id);
+            }
+        }
+    });
+}
+
+/// [`crate::send_proxy_request`] with the negotiated `codec`.
+async fn send_negotiated_request<Codecs, Req, Resp>(
+    codec: CodecId,
+    request: Req,
+    connection: Arc<EnclaveConnection>,
+    max_request_size: usize,
+) -> Result<Resp, Status>
+where
+    Codecs: CodecSet<Req, Resp>,
+{
+    let data = Codecs::encode_with(codec, &request)
+        .ok_or_else(|| Status::internal(format!("negotiated codec {codec:?} is not supported")))?;
+    if data.len() > max_request_size {
+        return Err(Status::invalid_argument(format!(
+            "encoded request is {} bytes, exceeding the limit of {max_request_size} bytes",
+            data.len()
+        )));
+    }
+    let encoded_app_response = proxy_round_trip(data, connection).await?;
+
+    Codecs::decode_with(codec, &encoded_app_response)
+        .map_err(|e| Status::internal(format!("Failed to decode app response: {e:?}")))
+}
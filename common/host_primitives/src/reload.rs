@@ -0,0 +1,130 @@
+//! Settings a host can change while it runs, re-read from a config file on SIGHUP, see
+//! [`crate::spawn_sighup_reload`] and [`crate::AppHostBuilder::runtime_config`].
+
+use std::{fs, path::Path, str::FromStr, time::Duration};
+
+use health_check::{ProbeInterval, ReadinessControl};
+use logging::LogLevel;
+use tracing::level_filters::LevelFilter;
+
+use crate::ConcurrencyLimit;
+
+/// Contents of a runtime config file. The file is JSON with any of the settings, e.g.
+/// `{"logLevel": "debug", "probeIntervalSecs": 10, "concurrencyLimit": 32, "outOfRotation":
+/// true}`. Settings left out keep their current value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    /// Level events are logged at, see [`LogLevel::set`].
+    pub log_level: Option<LevelFilter>,
+    /// Sleep between health probes while the app is serving, see [`ProbeInterval::set`].
+    pub probe_interval: Option<Duration>,
+    /// Requests in progress to the enclave at once, see [`ConcurrencyLimit::set`].
+    pub concurrency_limit: Option<usize>,
+    /// Whether readiness is held at `NotServing`, e.g. during maintenance, see
+    /// [`ReadinessControl::pause`].
+    pub out_of_rotation: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RuntimeConfigFile {
+    log_level: Option<String>,
+    probe_interval_secs: Option<u64>,
+    concurrency_limit: Option<usize>,
+    out_of_rotation: Option<bool>,
+}
+
+impl RuntimeConfig {
+    /// Parse the `json` of a runtime config file.
+    ///
+    /// Errors if a setting is unknown or invalid, so a typo fails the whole reload rather than
+    /// being applied in part.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let file: RuntimeConfigFile =
+            serde_json::from_str(json).map_err(|e| format!("invalid runtime config: {e}"))?;
+
+        let log_level = file
+            .log_level
+            .map(|level| {
+                LevelFilter::from_str(&level).map_err(|_| {
+                    format!(
+                        "invalid log level {level:?}, expected one of: off, error, warn, info, \
+                         debug, trace"
+                    )
+                })
+            })
+            .transpose()?;
+        if file.probe_interval_secs == Some(0) {
+            return Err("probe interval must be at least 1 second".to_string());
+        }
+        if file.concurrency_limit == Some(0) {
+            return Err("concurrency limit must be at least 1".to_string());
+        }
+
+        Ok(Self {
+            log_level,
+            probe_interval: file.probe_interval_secs.map(Duration::from_secs),
+            concurrency_limit: file.concurrency_limit,
+            out_of_rotation: file.out_of_rotation,
+        })
+    }
+
+    /// Read and parse the runtime config file at `path`, see [`Self::parse`].
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+        Self::parse(&json)
+    }
+}
+
+/// Handles to the settings of a running host that a [`RuntimeConfig`] changes.
+#[derive(Debug, Clone)]
+pub struct RuntimeSettings {
+    /// Level of the host's logs, `None` if the host can not change it.
+    pub log_level: Option<LogLevel>,
+    /// Interval of the host's health probes.
+    pub probe_interval: ProbeInterval,
+    /// Limit on the app's requests in progress to the enclave.
+    pub concurrency_limit: ConcurrencyLimit,
+    /// Readiness of the host, `None` if the host can not take itself out of rotation.
+    pub readiness: Option<ReadinessControl>,
+}
+
+impl RuntimeSettings {
+    /// Apply the settings `config` has. None of them drop connections or requests in progress.
+    ///
+    /// Errors if the log level or rotation can not be changed, after applying the other
+    /// settings.
+    pub fn apply(&self, config: &RuntimeConfig) -> Result<(), String> {
+        if let Some(probe_interval) = config.probe_interval {
+            self.probe_interval.set(probe_interval);
+            tracing::info!("probe interval set to {probe_interval:?}");
+        }
+        if let Some(concurrency_limit) = config.concurrency_limit {
+            self.concurrency_limit.set(concurrency_limit);
+            tracing::info!("concurrency limit set to {concurrency_limit}");
+        }
+        if let Some(out_of_rotation) = config.out_of_rotation {
+            let readiness = self
+                .readiness
+                .as_ref()
+                .ok_or("this host can not be taken out of rotation")?;
+            if out_of_rotation {
+                readiness.pause();
+                tracing::info!("taken out of rotation, readiness held at not serving");
+            } else {
+                readiness.resume();
+                tracing::info!("back in rotation");
+            }
+        }
+        if let Some(level) = config.log_level {
+            self.log_level
+                .as_ref()
+                .ok_or("log level can not be changed by this host")?
+                .set(level)?;
+            tracing::info!("log level set to {level}");
+        }
+
+        Ok(())
+    }
+}
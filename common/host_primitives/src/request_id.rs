@@ -0,0 +1,51 @@
+//! Correlation ids linking a gRPC call to its enclave round trip in the host's logs.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// gRPC metadata key clients can set to choose the request id of a call.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Identifies a request as it moves through the enclave queue. Recorded on the enqueue and
+/// dequeue log events and on the span covering the enclave round trip.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Use `id` as the request id.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    /// Generate a request id that is unique within this host process.
+    pub fn generate() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(format!(
+            "{:x}-{:x}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    /// The request id in the [`REQUEST_ID_HEADER`] metadata of `request`, if set and valid ASCII.
+    pub fn from_metadata<T>(request: &tonic::Request<T>) -> Option<Self> {
+        request
+            .metadata()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .map(Self::new)
+    }
+
+    /// The request id as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
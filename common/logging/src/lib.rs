@@ -0,0 +1,103 @@
+//! Log output of the host and app binaries. Binaries log through `tracing` and call [`init`]
+//! once on startup with the `--log-format` they were given.
+
+use std::{fmt, io, str::FromStr};
+
+use tracing::Subscriber;
+use tracing_subscriber::{
+    filter::LevelFilter, fmt::MakeWriter, layer::SubscriberExt, reload, Registry,
+};
+
+/// Format of log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines, for reading logs directly.
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log pipelines.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "invalid log format {s:?}, expected \"json\" or \"pretty\""
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pretty => write!(f, "pretty"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Handle to change the level of a subscriber from [`reloadable_subscriber`] or [`init`] while
+/// it is in use, e.g. when a host reloads its config.
+#[derive(Debug, Clone)]
+pub struct LogLevel(reload::Handle<LevelFilter, Registry>);
+
+impl LogLevel {
+    /// Log events at `level` and above from now on.
+    ///
+    /// Errors if the subscriber is gone.
+    pub fn set(&self, level: LevelFilter) -> Result<(), String> {
+        self.0
+            .reload(level)
+            .map_err(|e| format!("unable to change the log level: {e}"))
+    }
+
+    /// Level events are currently logged at, `None` if the subscriber is gone.
+    pub fn current(&self) -> Option<LevelFilter> {
+        self.0.clone_current()
+    }
+}
+
+/// Subscriber writing `format` lines for events at info level and above to `writer`.
+pub fn subscriber<W>(format: LogFormat, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    reloadable_subscriber(format, writer).0
+}
+
+/// Like [`subscriber`], along with a [`LogLevel`] to change the level it logs at.
+pub fn reloadable_subscriber<W>(
+    format: LogFormat,
+    writer: W,
+) -> (Box<dyn Subscriber + Send + Sync>, LogLevel)
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let (level, handle) = reload::Layer::new(LevelFilter::INFO);
+    let registry = Registry::default().with(level);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(writer);
+    let subscriber: Box<dyn Subscriber + Send + Sync> = match format {
+        LogFormat::Pretty => Box::new(registry.with(layer)),
+        LogFormat::Json => Box::new(registry.with(layer.json())),
+    };
+    (subscriber, LogLevel(handle))
+}
+
+/// Log `format` lines to stdout for the rest of the process, returning a [`LogLevel`] to change
+/// the level later.
+///
+/// # Panics
+///
+/// Panics if logging was already initialized.
+pub fn init(format: LogFormat) -> LogLevel {
+    let (subscriber, level) = reloadable_subscriber(format, io::stdout);
+    tracing::subscriber::set_global_default(subscriber).expect("logging was already initialized");
+    level
+}
@@ -2,33 +2,125 @@
 //! One-file integration test for the reshard stack (simulator_enclave + reshard_app + reshard_host).
 use futures::FutureExt;
 use std::{
-    fs,
+    fs, io,
     path::{Path, PathBuf},
     process::Command,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use borsh::to_vec as borsh_to_vec;
 
-use reshard_host::generated::reshard::reshard_service_client::ReshardServiceClient;
+use health_check::pb::health_client::HealthClient;
+use host_primitives::Keepalive;
+use reshard_app::service::{ReshardBundle, ReshardProcessor, ReshardRequest, ReshardResponse};
+use reshard_host::generated::reshard::{
+    reshard_service_client::ReshardServiceClient, GetBundleIdRequest,
+};
 
-use qos_core::protocol::services::boot::{Manifest, ManifestEnvelope};
+use qos_core::{
+    handles::Handles,
+    protocol::services::boot::{Manifest, ManifestEnvelope, Namespace, QuorumMember, ShareSet},
+    server::RequestProcessor,
+};
+use qos_p256::P256Public;
 use tempdir::TempDir;
-use tonic::transport::Channel;
+use tonic::{codec::CompressionEncoding, transport::Channel};
 
 pub mod qos_simulator;
 
+/// Directory holding the reshard fixtures, relative to the e2e crate.
+pub const RESHARD_FIXTURES: &str = "./fixtures/reshard";
 /// Local host IP address.
 pub const LOCAL_HOST: &str = "127.0.0.1";
 /// Max gRPC message size (25MB).
 pub const GRPC_MAX_RECV_MSG_SIZE: usize = 26_214_400;
+/// Log file names of the child processes spawned by [`execute_with_config`].
+pub const CHILD_LOGS: [&str; 2] = ["reshard_app.log", "reshard_host.log"];
+
+/// Log sink shared between the test and the tracing subscriber.
+#[derive(Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl LogBuffer {
+    /// Everything logged so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
 /// Arguments passed to the user test callback.
 pub struct TestArgs {
     /// Reshard gRPC client.
     pub reshard_client: ReshardServiceClient<Channel>,
+    /// URI of the reshard host, for connecting additional gRPC clients.
+    pub host_addr: String,
+    /// Socket the reshard app listens on. Removing it cuts the app off from the QOS simulator.
+    pub app_sock: PathBuf,
 }
 
-/// Kills a child process on drop.
+/// Configuration for bringing up the stack in [`execute_with_config`].
+#[derive(Debug, Clone)]
+pub struct ExecuteConfig {
+    /// Max gRPC message size the reshard host is started with.
+    pub max_message_size: usize,
+    /// Whether the reshard host serves the gRPC reflection service.
+    pub enable_reflection: bool,
+    /// Path the reshard host archives the latest bundle retrieved to.
+    pub bundle_archive_path: Option<PathBuf>,
+    /// Keepalive settings the reshard host is started with. Durations are passed in whole
+    /// seconds.
+    pub keepalive: Keepalive,
+    /// Deadline for bringing up the stack and running the test. The stack is torn down and the
+    /// test fails once it passes.
+    pub timeout: Duration,
+    /// Path of the reshard host binary to run.
+    pub host_binary: PathBuf,
+    /// Directory the child processes' stdout and stderr are written to, see [`CHILD_LOGS`].
+    /// Defaults to a temporary directory. The logs are printed if the test fails.
+    pub log_dir: Option<PathBuf>,
+    /// Vsock `(cid, port)` the host uses to reach the QOS simulator instead of a unix socket.
+    /// The `reshard_host` binary must be built with the `vsock` feature.
+    #[cfg(feature = "vsock")]
+    pub enclave_vsock: Option<(u32, u32)>,
+}
+
+impl Default for ExecuteConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: GRPC_MAX_RECV_MSG_SIZE,
+            enable_reflection: true,
+            bundle_archive_path: None,
+            keepalive: Keepalive::default(),
+            timeout: Duration::from_secs(120),
+            host_binary: PathBuf::from("../target/debug/reshard_host"),
+            log_dir: None,
+            #[cfg(feature = "vsock")]
+            enclave_vsock: None,
+        }
+    }
+}
+
+/// How long [`execute_with_config`] waits for the reshard host to answer a first request, see
+/// [`connect_reshard_client`].
+pub const HOST_READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long [`ChildWrapper`] waits for a child to exit after SIGTERM before killing it.
+const CHILD_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stops a child process on drop: SIGTERM first so it can shut down gracefully, then SIGKILL
+/// if it has not exited within [`CHILD_EXIT_TIMEOUT`]. The child is always reaped.
 #[derive(Debug)]
 pub struct ChildWrapper(std::process::Child);
 impl From<std::process::Child> for ChildWrapper {
@@ -38,7 +130,20 @@ impl From<std::process::Child> for ChildWrapper {
 }
 impl Drop for ChildWrapper {
     fn drop(&mut self) {
+        // SAFETY: signals our own, not yet reaped, child so the pid cannot have been reused
+        unsafe { libc::kill(self.0.id() as libc::pid_t, libc::SIGTERM) };
+
+        let deadline = std::time::Instant::now() + CHILD_EXIT_TIMEOUT;
+        while std::time::Instant::now() < deadline {
+            match self.0.try_wait() {
+                Ok(Some(_)) => return,
+                Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+                Err(_) => break,
+            }
+        }
+
         let _ = self.0.kill();
+        let _ = self.0.wait();
     }
 }
 
@@ -48,69 +153,115 @@ where
     F: Fn(TestArgs) -> T,
     T: std::future::Future<Output = ()>,
 {
-    let tmp_dir = TempDir::new("testharness").unwrap();
+    execute_with_config(ExecuteConfig::default(), test).await
+}
+
+/// Bring up the stack configured by `config`, run `test`, then tear down.
+///
+/// # Panics
+///
+/// Panics if the stack fails to come up, the test panics or both together take longer than
+/// `config.timeout`. The child processes' logs are printed first.
+pub async fn execute_with_config<F, T>(config: ExecuteConfig, test: F)
+where
+    F: Fn(TestArgs) -> T,
+    T: std::future::Future<Output = ()>,
+{
+    // TempDir adds a random suffix, the pid tells apart the stacks of concurrent test binaries
+    let tmp_dir = TempDir::new(&format!("testharness-{}", std::process::id())).unwrap();
+    let log_dir = config
+        .log_dir
+        .clone()
+        .unwrap_or_else(|| tmp_dir.path().to_path_buf());
+    let timeout = config.timeout;
+
+    let stack = std::panic::AssertUnwindSafe(run_stack(config, tmp_dir.path(), &log_dir, test))
+        .catch_unwind();
+    // Dropping the stack future on timeout kills the child processes
+    match tokio::time::timeout(timeout, stack).await {
+        Ok(Ok(())) => {}
+        Ok(Err(panic)) => {
+            print_child_logs(&log_dir);
+            std::panic::resume_unwind(panic);
+        }
+        Err(_) => {
+            print_child_logs(&log_dir);
+            panic!("e2e stack setup and test did not finish within {timeout:?}");
+        }
+    }
+}
 
-    // Socket paths
-    let app_sock = tmp_dir.path().join(".reshard.app.sock");
-    let enc_sock = tmp_dir.path().join(".reshard.enclave.sock");
+async fn run_stack<F, T>(config: ExecuteConfig, tmp_dir: &Path, log_dir: &Path, test: F)
+where
+    F: Fn(TestArgs) -> T,
+    T: std::future::Future<Output = ()>,
+{
+    let (app_sock, enc_sock) = stack_socket_paths(tmp_dir).unwrap_or_else(|e| panic!("{e}"));
 
     // Minimal manifest envelope
-    let manifest_path = tmp_dir.path().join(".manifest_envelope");
+    let manifest_path = tmp_dir.join(".manifest_envelope");
     write_minimal_manifest(&manifest_path);
 
     // 1) simulator_enclave
     let _join_handle = qos_simulator::spawn_qos_simulator(qos_simulator::QosSimulatorConfig {
         enclave_sock: enc_sock.to_str().unwrap().to_string(),
         app_sock: app_sock.to_str().unwrap().to_string(),
+        #[cfg(feature = "vsock")]
+        enclave_vsock: config.enclave_vsock,
+        mock_attestation: None,
     });
 
     // 2) reshard_app
-    let new_share_dir = Path::new("./fixtures/reshard/new-share-set");
-    let threshold = load_threshold(&new_share_dir.join("quorum_threshold"));
-    let members = joined_pubkeys(new_share_dir);
-    let quorum_secret = "./fixtures/reshard/quorum.secret";
-    let ephemeral_secret = "./fixtures/reshard/ephemeral.secret";
-    let _app: ChildWrapper = Command::new("../target/debug/reshard_app")
-        .arg("--usock")
-        .arg(&app_sock)
-        .arg("--quorum-file")
-        .arg(quorum_secret)
-        .arg("--ephemeral-file")
-        .arg(ephemeral_secret)
-        .arg("--manifest-file")
-        .arg(&manifest_path)
-        .arg("--threshold")
-        .arg(&threshold)
-        .arg("--members")
-        .arg(&members)
-        .arg("--mock-nsm")
-        .spawn()
-        .expect("spawn reshard_app")
-        .into();
+    let mut app_cmd = reshard_app_command(&app_sock, &manifest_path);
+    let _app = spawn_logged(&mut app_cmd, &log_dir.join(CHILD_LOGS[0]));
 
     // 3) reshard_host
     let host_port = qos_test_primitives::find_free_port().expect("find free port");
-    let _host: ChildWrapper = Command::new("../target/debug/reshard_host")
+    let mut host_cmd = Command::new(&config.host_binary);
+    if !config.enable_reflection {
+        host_cmd.arg("--disable-reflection");
+    }
+    if let Some(path) = &config.bundle_archive_path {
+        host_cmd.arg("--bundle-archive-path").arg(path);
+    }
+    #[cfg(feature = "vsock")]
+    if let Some((cid, port)) = config.enclave_vsock {
+        host_cmd
+            .arg("--cid")
+            .arg(cid.to_string())
+            .arg("--port")
+            .arg(port.to_string());
+    } else {
+        host_cmd.arg("--usock").arg(&enc_sock);
+    }
+    #[cfg(not(feature = "vsock"))]
+    host_cmd.arg("--usock").arg(&enc_sock);
+    host_cmd
         .arg("--host-ip")
         .arg(LOCAL_HOST)
         .arg("--host-port")
         .arg(host_port.to_string())
-        .arg("--usock")
-        .arg(&enc_sock)
-        .spawn()
-        .expect("spawn reshard_host")
-        .into();
-    qos_test_primitives::wait_until_port_is_bound(host_port);
+        .arg("--max-message-size")
+        .arg(config.max_message_size.to_string())
+        .arg("--http2-keepalive-interval-secs")
+        .arg(keepalive_secs(config.keepalive.http2_interval))
+        .arg("--http2-keepalive-timeout-secs")
+        .arg(keepalive_secs(config.keepalive.http2_timeout))
+        .arg("--tcp-keepalive-secs")
+        .arg(keepalive_secs(config.keepalive.tcp));
+    let _host = spawn_logged(&mut host_cmd, &log_dir.join(CHILD_LOGS[1]));
 
     let host_addr = format!("http://{LOCAL_HOST}:{host_port}");
 
-    let reshard = ReshardServiceClient::connect(host_addr)
+    let reshard = connect_reshard_client(&host_addr, HOST_READY_TIMEOUT)
         .await
-        .unwrap()
+        .unwrap_or_else(|e| panic!("{e}"))
         .max_decoding_message_size(GRPC_MAX_RECV_MSG_SIZE);
 
     let test_args = TestArgs {
         reshard_client: reshard,
+        host_addr,
+        app_sock,
     };
 
     // Run the user test and ensure cleanup.
@@ -120,9 +271,201 @@ where
     assert!(res.is_ok(), "test body panicked");
 }
 
+/// Paths of the app and enclave sockets of a stack in `dir`.
+///
+/// Errors if either already exists, e.g. a stale socket left behind or another stack using
+/// `dir`, rather than letting the stack fail later on a confusing bind or connect error.
+pub fn stack_socket_paths(dir: &Path) -> Result<(PathBuf, PathBuf), String> {
+    let app_sock = dir.join(".reshard.app.sock");
+    let enc_sock = dir.join(".reshard.enclave.sock");
+    for sock in [&app_sock, &enc_sock] {
+        if sock.exists() {
+            return Err(format!(
+                "socket {} already exists, another e2e stack may be using it",
+                sock.display()
+            ));
+        }
+    }
+    Ok((app_sock, enc_sock))
+}
+
+/// Health client connected to the reshard host at `host_addr`, e.g. [`TestArgs::host_addr`].
+///
+/// The client has its own connection to the host. Drop it before the test returns, the host
+/// otherwise waits on the open connection while shutting down at teardown.
+pub async fn connect_health_client(host_addr: &str) -> HealthClient<Channel> {
+    let channel = Channel::from_shared(host_addr.to_string())
+        .expect("valid host address")
+        .connect()
+        .await
+        .expect("connect to host");
+    HealthClient::new(channel)
+}
+
+/// Command running the reshard app binary on `app_sock` with the reshard fixtures, the mock NSM
+/// and the manifest envelope at `manifest_path`.
+pub fn reshard_app_command(app_sock: &Path, manifest_path: &Path) -> Command {
+    let new_share_dir = Path::new("./fixtures/reshard/new-share-set");
+    let threshold = load_threshold(&new_share_dir.join("quorum_threshold"));
+    let members = joined_pubkeys(new_share_dir);
+    let quorum_secret = "./fixtures/reshard/quorum.secret";
+    let ephemeral_secret = "./fixtures/reshard/ephemeral.secret";
+
+    let mut app_cmd = Command::new("../target/debug/reshard_app");
+    app_cmd
+        .arg("--usock")
+        .arg(app_sock)
+        .arg("--quorum-file")
+        .arg(quorum_secret)
+        .arg("--ephemeral-file")
+        .arg(ephemeral_secret)
+        .arg("--manifest-file")
+        .arg(manifest_path)
+        .arg("--threshold")
+        .arg(&threshold)
+        .arg("--members")
+        .arg(&members)
+        .arg("--mock-nsm");
+    app_cmd
+}
+
+/// Spawn `cmd` with its stdout and stderr written to `log`.
+fn spawn_logged(cmd: &mut Command, log: &Path) -> ChildWrapper {
+    let stdout = fs::File::create(log).expect("create child log");
+    let stderr = stdout.try_clone().expect("clone child log");
+
+    cmd.stdout(stdout)
+        .stderr(stderr)
+        .spawn()
+        .unwrap_or_else(|e| panic!("spawn {:?}: {e}", cmd.get_program()))
+        .into()
+}
+
+/// Print the child process logs in `log_dir`, for debugging a failed test.
+fn print_child_logs(log_dir: &Path) {
+    for name in CHILD_LOGS {
+        if let Ok(log) = fs::read_to_string(log_dir.join(name)) {
+            eprintln!("---- {name} ----\n{log}");
+        }
+    }
+}
+
+/// Reshard client connected to the host at `host_addr` once the host answers a first request.
+///
+/// A bound port does not mean the host is serving yet, nor that the app behind it is up, so
+/// connecting and a first `GetBundleId` are retried with exponential backoff until both succeed.
+/// Statuses other than `unavailable` mean the host is serving and are not retried. Errors with
+/// the last failure if the host is not ready within `timeout`.
+///
+/// The client accepts gzip compressed responses, as production clients fetching large bundles
+/// should.
+pub async fn connect_reshard_client(
+    host_addr: &str,
+    timeout: Duration,
+) -> Result<ReshardServiceClient<Channel>, String> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut backoff = Duration::from_millis(50);
+    loop {
+        let last_error = match ReshardServiceClient::connect(host_addr.to_string()).await {
+            Ok(client) => {
+                let mut client = client.accept_compressed(CompressionEncoding::Gzip);
+                match client.get_bundle_id(GetBundleIdRequest::default()).await {
+                    Ok(_) => return Ok(client),
+                    Err(status) if status.code() != tonic::Code::Unavailable => return Ok(client),
+                    Err(status) => format!("first request failed: {status}"),
+                }
+            }
+            Err(e) => format!("connect failed: {e}"),
+        };
+
+        if tokio::time::Instant::now() + backoff > deadline {
+            return Err(format!(
+                "reshard host at {host_addr} not ready within {timeout:?}: {last_error}"
+            ));
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(1));
+    }
+}
+
+/// [`Handles`] for the reshard fixture quorum and ephemeral keys, with a minimal manifest
+/// envelope written into `dir`.
+pub fn reshard_fixture_handles(dir: &Path) -> Handles {
+    let manifest_path = dir.join(".manifest_envelope");
+    write_minimal_manifest(&manifest_path);
+
+    Handles::new(
+        format!("{RESHARD_FIXTURES}/ephemeral.secret"),
+        format!("{RESHARD_FIXTURES}/quorum.secret"),
+        manifest_path.to_str().unwrap().to_string(),
+        "pivot not used".to_string(),
+    )
+}
+
+/// The reshard fixture new share set, with members aliased by their pub key file names.
+pub fn reshard_fixture_share_set() -> ShareSet {
+    let new_share_dir = Path::new(RESHARD_FIXTURES).join("new-share-set");
+    let threshold = load_threshold(&new_share_dir.join("quorum_threshold"));
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&new_share_dir)
+        .expect("failed to read dir")
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("pub"))
+        .collect();
+    files.sort();
+
+    let members = files
+        .into_iter()
+        .map(|p| QuorumMember {
+            alias: p.file_stem().unwrap().to_str().unwrap().to_string(),
+            pub_key: P256Public::from_hex_file(p.to_str().unwrap())
+                .expect("load member pub key")
+                .to_bytes(),
+        })
+        .collect();
+
+    ShareSet {
+        threshold: threshold.parse().expect("parse threshold"),
+        members,
+    }
+}
+
+/// Build a bundle in process from the reshard fixtures with the mock NSM.
+pub fn reshard_fixture_bundle(dir: &Path) -> ReshardBundle {
+    let mut processor = ReshardProcessor::new(
+        &reshard_fixture_handles(dir),
+        &reshard_fixture_share_set(),
+        Box::new(qos_nsm::mock::MockNsm),
+    )
+    .expect("reshard processor");
+
+    let request = borsh_to_vec(&ReshardRequest::RetrieveBundle).unwrap();
+    match borsh::from_slice(&processor.process(request)).unwrap() {
+        ReshardResponse::Bundle(bundle) => *bundle,
+        other => panic!("unexpected response {other:?}"),
+    }
+}
+
+/// Keepalive duration as the reshard host CLI expects it, 0 being disabled.
+fn keepalive_secs(duration: Option<Duration>) -> String {
+    duration.map_or(0, |d| d.as_secs()).to_string()
+}
+
+/// Public key of the reshard fixture quorum key, as the manifest declares it.
+pub fn reshard_fixture_quorum_key() -> Vec<u8> {
+    P256Public::from_hex_file(format!("{RESHARD_FIXTURES}/quorum.pub"))
+        .expect("load quorum pub key")
+        .to_bytes()
+}
+
 fn write_minimal_manifest(path: &PathBuf) {
     let env = ManifestEnvelope {
         manifest: Manifest {
+            namespace: Namespace {
+                quorum_key: reshard_fixture_quorum_key(),
+                ..Default::default()
+            },
             ..Default::default()
         },
         ..Default::default()
@@ -138,7 +481,9 @@ fn load_threshold(th_path: &Path) -> String {
         .to_string()
 }
 
-fn joined_pubkeys(dir: &Path) -> String {
+/// Read the `*.pub` files in `dir` in file name order and join them with `;`, as expected
+/// by the reshard app `--members` option.
+pub fn joined_pubkeys(dir: &Path) -> String {
     // collect *.pub files
     let mut files: Vec<PathBuf> = fs::read_dir(dir)
         .expect("failed to read dir")
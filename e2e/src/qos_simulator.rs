@@ -2,13 +2,21 @@
 
 use borsh::BorshDeserialize;
 use qos_core::{
-    client::Client,
-    io::{SocketAddress, TimeVal, TimeValLike},
+    io::SocketAddress,
     protocol::msg::ProtocolMsg,
     server::{RequestProcessor, SocketServer},
 };
 use qos_nsm::types::NsmResponse;
-use std::thread::{self, JoinHandle};
+use std::{
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How long the simulator waits for the enclave app to accept a connection and answer.
+const APP_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Configuration for QOS simulator.
 pub struct QosSimulatorConfig {
@@ -17,6 +25,109 @@ pub struct QosSimulatorConfig {
     /// Unix socket path the enclave app to proxy too is expected to be
     /// listening on.
     pub app_sock: String,
+    /// Vsock `(cid, port)` the QOS simulator listens on instead of `enclave_sock`.
+    #[cfg(feature = "vsock")]
+    pub enclave_vsock: Option<(u32, u32)>,
+    /// Attestation doc to answer live attestation requests with. `None` answers with a borsh
+    /// encoded `"MOCK_DOCUMENT"`, which is enough for liveness but can not be parsed.
+    pub mock_attestation: Option<MockAttestation>,
+}
+
+/// Fields of a mock attestation doc, see [`MockAttestation::document`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockAttestation {
+    /// Raw encoded ephemeral public key the doc attests to.
+    pub public_key: Vec<u8>,
+    /// User data of the doc, the manifest envelope hash for QOS.
+    pub user_data: Vec<u8>,
+}
+
+impl MockAttestation {
+    /// An attestation doc embedding the fields, shaped like a Nitro one: a COSE Sign1 structure
+    /// whose payload is the CBOR encoded doc. It parses with
+    /// `qos_nsm::nitro::unsafe_attestation_doc_from_der`, but the signature and certificates
+    /// are zeros, so it fails any check of the certificate chain.
+    ///
+    /// The doc is deterministic, the same fields always give the same bytes.
+    pub fn document(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        cbor_head(&mut payload, CBOR_MAP, 9);
+        cbor_text(&mut payload, "module_id");
+        cbor_text(&mut payload, "qos-simulator");
+        cbor_text(&mut payload, "digest");
+        cbor_text(&mut payload, "SHA384");
+        cbor_text(&mut payload, "timestamp");
+        cbor_head(&mut payload, CBOR_UINT, 0);
+        cbor_text(&mut payload, "pcrs");
+        cbor_head(&mut payload, CBOR_MAP, 3);
+        for index in 0..3 {
+            cbor_head(&mut payload, CBOR_UINT, index);
+            cbor_bytes(&mut payload, &[0; 48]);
+        }
+        cbor_text(&mut payload, "certificate");
+        cbor_bytes(&mut payload, &[]);
+        cbor_text(&mut payload, "cabundle");
+        cbor_head(&mut payload, CBOR_ARRAY, 0);
+        cbor_text(&mut payload, "public_key");
+        cbor_bytes(&mut payload, &self.public_key);
+        cbor_text(&mut payload, "user_data");
+        cbor_bytes(&mut payload, &self.user_data);
+        cbor_text(&mut payload, "nonce");
+        payload.push(CBOR_NULL);
+
+        // Protected header `{alg: ES384}`
+        let mut protected = Vec::new();
+        cbor_head(&mut protected, CBOR_MAP, 1);
+        cbor_head(&mut protected, CBOR_UINT, 1);
+        cbor_head(&mut protected, CBOR_NEGATIVE, 34);
+
+        let mut document = Vec::new();
+        cbor_head(&mut document, CBOR_ARRAY, 4);
+        cbor_bytes(&mut document, &protected);
+        cbor_head(&mut document, CBOR_MAP, 0);
+        cbor_bytes(&mut document, &payload);
+        cbor_bytes(&mut document, &[0; 96]);
+        document
+    }
+}
+
+const CBOR_UINT: u8 = 0;
+const CBOR_NEGATIVE: u8 = 1;
+const CBOR_BYTES: u8 = 2;
+const CBOR_TEXT: u8 = 3;
+const CBOR_ARRAY: u8 = 4;
+const CBOR_MAP: u8 = 5;
+const CBOR_NULL: u8 = 0xf6;
+
+/// Append the head of a CBOR item of `major` type with argument `value`, its length or value.
+fn cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend([major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(value.to_be_bytes());
+        }
+    }
+}
+
+fn cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    cbor_head(out, CBOR_BYTES, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn cbor_text(out: &mut Vec<u8>, text: &str) {
+    cbor_head(out, CBOR_TEXT, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
 }
 
 /// Spawn a QOS simulator. This will simulate QOS proxying requests from the host to application binary.
@@ -24,21 +135,111 @@ pub fn spawn_qos_simulator(
     QosSimulatorConfig {
         enclave_sock,
         app_sock,
+        #[cfg(feature = "vsock")]
+        enclave_vsock,
+        mock_attestation,
     }: QosSimulatorConfig,
 ) -> JoinHandle<()> {
     thread::spawn(move || {
+        #[cfg(feature = "vsock")]
+        let enclave_sock_addr = match enclave_vsock {
+            Some((cid, port)) => SocketAddress::new_vsock(cid, port, qos_core::io::VMADDR_NO_FLAGS),
+            None => SocketAddress::new_unix(&enclave_sock),
+        };
+        #[cfg(not(feature = "vsock"))]
         let enclave_sock_addr = SocketAddress::new_unix(&enclave_sock);
 
-        let app_sock_addr = SocketAddress::new_unix(&app_sock);
         let processor = Processor {
-            app_client: Client::new(app_sock_addr, TimeVal::seconds(1)),
+            app: AppConnection {
+                app_sock: PathBuf::from(app_sock),
+                timeout: APP_TIMEOUT,
+            },
+            mock_attestation,
         };
         SocketServer::listen(enclave_sock_addr, processor).unwrap();
     })
 }
 
 struct Processor {
-    app_client: Client,
+    app: AppConnection,
+    mock_attestation: Option<MockAttestation>,
+}
+
+/// Connection to the enclave app, framing messages like the QOS socket client: a little endian
+/// `u64` length followed by that many bytes.
+///
+/// A loaded socket can transfer part of a frame per read or write, so frames are reassembled
+/// explicitly rather than assuming the app's response arrives in one piece.
+struct AppConnection {
+    app_sock: PathBuf,
+    timeout: Duration,
+}
+
+impl AppConnection {
+    /// Send `request` to the app and return its complete response.
+    fn send(&self, request: &[u8]) -> io::Result<Vec<u8>> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        write_frame(&mut stream, request)?;
+        read_frame(&mut stream)
+    }
+
+    /// Connect to the app, retrying until the timeout in case it is still starting.
+    fn connect(&self) -> io::Result<UnixStream> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match UnixStream::connect(&self.app_sock) {
+                Ok(stream) => return Ok(stream),
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Write `data` to `writer` as a single frame.
+pub fn write_frame(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.flush()
+}
+
+/// Read a single frame from `reader`, reassembling it from as many reads as it arrives in.
+///
+/// Fails with `UnexpectedEof` if the connection is closed part way through the frame.
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; size_of::<u64>()];
+    read_full(reader, &mut len, "length")?;
+    let len = usize::try_from(u64::from_le_bytes(len))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut data = vec![0; len];
+    read_full(reader, &mut data, "data")?;
+    Ok(data)
+}
+
+/// Fill `buf` from `reader`, retrying short and interrupted reads.
+fn read_full(reader: &mut impl Read, buf: &mut [u8], part: &str) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "connection closed after {filled} of {} bytes of frame {part}",
+                        buf.len()
+                    ),
+                ))
+            }
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
 }
 
 impl RequestProcessor for Processor {
@@ -48,17 +249,21 @@ impl RequestProcessor for Processor {
 
         match msg_req {
             ProtocolMsg::ProxyRequest { data } => {
-                let resp_data = self.app_client.send(&data).expect("Client error");
+                let resp_data = self
+                    .app
+                    .send(&data)
+                    .unwrap_or_else(|e| panic!("enclave_stub: app connection error: {e}"));
 
                 borsh::to_vec(&ProtocolMsg::ProxyResponse { data: resp_data })
                     .expect("enclave_stub: Failed to serialize response")
             }
             ProtocolMsg::LiveAttestationDocRequest => {
-                let data_string = borsh::to_vec(&"MOCK_DOCUMENT".to_string())
-                    .expect("unable to serialize mock document");
-                let nsm_response = NsmResponse::Attestation {
-                    document: data_string,
+                let document = match &self.mock_attestation {
+                    Some(mock_attestation) => mock_attestation.document(),
+                    None => borsh::to_vec(&"MOCK_DOCUMENT".to_string())
+                        .expect("unable to serialize mock document"),
                 };
+                let nsm_response = NsmResponse::Attestation { document };
 
                 borsh::to_vec(&ProtocolMsg::LiveAttestationDocResponse {
                     nsm_response,
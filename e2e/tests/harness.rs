@@ -0,0 +1,223 @@
+//! Tests for the e2e harness itself.
+use std::{
+    fs,
+    os::unix::{fs::PermissionsExt, net::UnixListener},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use e2e::{
+    connect_reshard_client, stack_socket_paths, ChildWrapper, ExecuteConfig, TestArgs, CHILD_LOGS,
+    LOCAL_HOST,
+};
+use futures::FutureExt;
+use host_primitives::{BorshCodec, EnclaveClient};
+use qos_core::server::RequestProcessor;
+use reshard_app::service::{ReshardRequest, ReshardResponse};
+use reshard_host::{
+    generated::reshard::{reshard_service_server::ReshardServiceServer, GetBundleIdRequest},
+    Host,
+};
+use tempdir::TempDir;
+
+/// Write an executable shell script named `name` with `body` into `dir`.
+fn write_script(dir: &Path, name: &str, body: &str) -> std::path::PathBuf {
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// App that answers every request with a fixed bundle id.
+struct BundleIdApp;
+
+impl RequestProcessor for BundleIdApp {
+    fn process(&mut self, _request: Vec<u8>) -> Vec<u8> {
+        borsh::to_vec(&ReshardResponse::BundleId(vec![7; 32])).unwrap()
+    }
+}
+
+/// True if a process, including a zombie, exists with `pid`.
+fn process_exists(pid: u32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+#[tokio::test]
+async fn execute_times_out_and_tears_down_hung_stack() {
+    let tmp_dir = TempDir::new("e2e_harness").unwrap();
+    let pid_file = tmp_dir.path().join("host.pid");
+
+    let config = ExecuteConfig {
+        timeout: Duration::from_secs(3),
+        // Records its pid and sleeps without ever binding a port
+        host_binary: write_script(
+            tmp_dir.path(),
+            "hanging_host.sh",
+            &format!("echo $$ > {}\nexec sleep 1000", pid_file.display()),
+        ),
+        ..Default::default()
+    };
+    let result =
+        std::panic::AssertUnwindSafe(e2e::execute_with_config(config, |_: TestArgs| async {
+            panic!("test body must not run")
+        }))
+        .catch_unwind()
+        .await;
+
+    let panic = result.expect_err("execute should time out");
+    let message = panic.downcast_ref::<String>().unwrap();
+    assert!(message.contains("did not finish within 3s"), "{message}");
+
+    let pid = fs::read_to_string(&pid_file).unwrap();
+    assert!(
+        !process_exists(pid.trim().parse().unwrap()),
+        "hung host was not stopped and reaped"
+    );
+}
+
+#[tokio::test]
+async fn execute_captures_failing_child_logs() {
+    let tmp_dir = TempDir::new("e2e_harness").unwrap();
+    let log_dir = tmp_dir.path().join("logs");
+    fs::create_dir(&log_dir).unwrap();
+
+    let config = ExecuteConfig {
+        timeout: Duration::from_secs(3),
+        host_binary: write_script(
+            tmp_dir.path(),
+            "failing_host.sh",
+            "echo \"host failed to start\" >&2\nexit 1",
+        ),
+        log_dir: Some(log_dir.clone()),
+        ..Default::default()
+    };
+    let result =
+        std::panic::AssertUnwindSafe(e2e::execute_with_config(config, |_: TestArgs| async {
+            panic!("test body must not run")
+        }))
+        .catch_unwind()
+        .await;
+    assert!(result.is_err(), "execute should fail");
+
+    let host_log = fs::read_to_string(log_dir.join(CHILD_LOGS[1])).unwrap();
+    assert_eq!(host_log, "host failed to start\n");
+}
+
+#[test]
+fn child_wrapper_reaps_child_on_drop() {
+    let child = std::process::Command::new("sleep")
+        .arg("1000")
+        .spawn()
+        .unwrap();
+    let pid = child.id();
+
+    drop(ChildWrapper::from(child));
+
+    assert!(!process_exists(pid), "child {pid} left behind after drop");
+}
+
+#[test]
+fn stack_socket_paths_rejects_stale_socket() {
+    let tmp_dir = TempDir::new("e2e_harness").unwrap();
+    let (app_sock, enc_sock) = stack_socket_paths(tmp_dir.path()).unwrap();
+    assert_ne!(app_sock, enc_sock);
+
+    // Left behind by a stack that did not clean up
+    let _listener = UnixListener::bind(&enc_sock).unwrap();
+
+    let err = stack_socket_paths(tmp_dir.path()).unwrap_err();
+    assert_eq!(
+        err,
+        format!(
+            "socket {} already exists, another e2e stack may be using it",
+            enc_sock.display()
+        )
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn execute_runs_concurrent_stacks_on_distinct_sockets() {
+    let app_socks: Arc<Mutex<Vec<PathBuf>>> = Arc::default();
+    let stack = || {
+        let app_socks = app_socks.clone();
+        e2e::execute(move |mut args: TestArgs| {
+            let app_socks = app_socks.clone();
+            async move {
+                // Each stack serves its own bundle end to end
+                args.reshard_client
+                    .get_bundle_id(tonic::Request::new(GetBundleIdRequest {}))
+                    .await
+                    .unwrap();
+                app_socks.lock().unwrap().push(args.app_sock);
+            }
+        })
+    };
+
+    tokio::join!(stack(), stack());
+
+    let app_socks = app_socks.lock().unwrap();
+    assert_eq!(app_socks.len(), 2);
+    assert_ne!(app_socks[0], app_socks[1]);
+    for sock in app_socks.iter() {
+        let dir = sock
+            .parent()
+            .unwrap()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(
+            dir.starts_with(&format!("testharness-{}", std::process::id())),
+            "{dir}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connect_reshard_client_waits_for_delayed_host() {
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("{LOCAL_HOST}:{port}").parse().unwrap();
+    // The host comes up a while after the client starts connecting
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let enclave =
+            EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(BundleIdApp);
+        tonic::transport::Server::builder()
+            .add_service(ReshardServiceServer::new(Host::new(Arc::new(enclave))))
+            .serve(listen_addr)
+            .await
+            .unwrap();
+    });
+
+    let mut client = connect_reshard_client(
+        &format!("http://{LOCAL_HOST}:{port}"),
+        Duration::from_secs(10),
+    )
+    .await
+    .unwrap();
+    let bundle_id = client
+        .get_bundle_id(tonic::Request::new(GetBundleIdRequest {}))
+        .await
+        .unwrap()
+        .into_inner()
+        .bundle_id;
+    assert_eq!(bundle_id, vec![7; 32]);
+}
+
+#[tokio::test]
+async fn connect_reshard_client_gives_up_after_timeout() {
+    // Nothing listens here
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let host_addr = format!("http://{LOCAL_HOST}:{port}");
+
+    let err = connect_reshard_client(&host_addr, Duration::from_millis(300))
+        .await
+        .unwrap_err();
+    assert!(
+        err.starts_with(&format!(
+            "reshard host at {host_addr} not ready within 300ms: connect failed"
+        )),
+        "{err}"
+    );
+}
@@ -0,0 +1,368 @@
+//! Integration tests for the k8s health check service.
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use health_check::{
+    dependency_liveness, dependency_readiness,
+    pb::{
+        health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+        HealthCheckResponse,
+    },
+    spawn_k8s_health_checker, spawn_k8s_health_checker_multi, AppHealthCheckable,
+    AppHealthResponse, ProbeBackoff, LIVENESS, READINESS,
+};
+use tokio_stream::StreamExt;
+
+/// App check whose health can be flipped by the test.
+#[derive(Default)]
+struct ToggleHealth {
+    healthy: AtomicBool,
+    probes: AtomicUsize,
+}
+
+/// App check that is up, but not ready until flipped by the test.
+#[derive(Default)]
+struct WarmingUpHealth {
+    ready: AtomicBool,
+    probes: AtomicUsize,
+}
+
+#[tonic::async_trait]
+impl AppHealthCheckable for WarmingUpHealth {
+    async fn app_health_check(&self) -> Result<tonic::Response<AppHealthResponse>, tonic::Status> {
+        self.probes.fetch_add(1, Ordering::SeqCst);
+        if self.ready.load(Ordering::SeqCst) {
+            Ok(tonic::Response::new(AppHealthResponse::ready()))
+        } else {
+            Ok(tonic::Response::new(AppHealthResponse::not_ready()))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl AppHealthCheckable for ToggleHealth {
+    async fn app_health_check(&self) -> Result<tonic::Response<AppHealthResponse>, tonic::Status> {
+        self.probes.fetch_add(1, Ordering::SeqCst);
+        if self.healthy.load(Ordering::SeqCst) {
+            Ok(tonic::Response::new(AppHealthResponse::ready()))
+        } else {
+            Err(tonic::Status::unavailable("app is down"))
+        }
+    }
+}
+
+/// App check that never returns, stalling the probe loop.
+struct StalledHealth;
+
+#[tonic::async_trait]
+impl AppHealthCheckable for StalledHealth {
+    async fn app_health_check(&self) -> Result<tonic::Response<AppHealthResponse>, tonic::Status> {
+        std::future::pending().await
+    }
+}
+
+/// Next status from a health `Watch` stream.
+async fn next_status(stream: &mut tonic::Streaming<HealthCheckResponse>) -> ServingStatus {
+    let response = tokio::time::timeout(Duration::from_secs(60), stream.next())
+        .await
+        .expect("timed out waiting for a health update")
+        .expect("watch stream ended")
+        .expect("watch stream errored");
+    ServingStatus::try_from(response.status).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn readiness_watch_observes_transition() {
+    let app_check = Arc::new(ToggleHealth::default());
+    let (health, _readiness) = spawn_k8s_health_checker(app_check.clone()).await;
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(health)
+            .serve(listen_addr),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+
+    let channel = tonic::transport::Channel::from_shared(format!("http://127.0.0.1:{port}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = HealthClient::new(channel);
+    let mut stream = client
+        .watch(HealthCheckRequest {
+            service: READINESS.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(next_status(&mut stream).await, ServingStatus::NotServing);
+
+    app_check.healthy.store(true, Ordering::SeqCst);
+    assert_eq!(next_status(&mut stream).await, ServingStatus::Serving);
+}
+
+#[test]
+fn probe_backoff_grows_while_not_serving_and_resets() {
+    use health_check::ServingStatus::{NotServing, Serving};
+
+    let mut backoff = ProbeBackoff::default();
+    let sleeps: Vec<u64> = (0..6)
+        .map(|_| backoff.next_sleep(NotServing).as_secs())
+        .collect();
+    assert_eq!(sleeps, [5, 10, 20, 40, 60, 60]);
+
+    assert_eq!(backoff.next_sleep(Serving).as_secs(), 5);
+    assert_eq!(backoff.next_sleep(NotServing).as_secs(), 5);
+    assert_eq!(backoff.next_sleep(NotServing).as_secs(), 10);
+}
+
+// Time is paused so the watchdog fires as soon as the test is waiting on it
+#[tokio::test(start_paused = true)]
+async fn liveness_drops_when_probe_loop_stalls() {
+    let (health, _readiness) = spawn_k8s_health_checker(Arc::new(StalledHealth)).await;
+
+    // Bind before serving, a blocking port wait would stall this single threaded runtime
+    let incoming =
+        tonic::transport::server::TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = incoming.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(health)
+            .serve_with_incoming(incoming),
+    );
+
+    let channel = tonic::transport::Channel::from_shared(format!("http://{addr}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut stream = HealthClient::new(channel)
+        .watch(HealthCheckRequest {
+            service: LIVENESS.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(next_status(&mut stream).await, ServingStatus::Serving);
+    assert_eq!(next_status(&mut stream).await, ServingStatus::NotServing);
+}
+
+// Time is paused, so the runtime jumps from probe to probe instead of waiting them out
+#[tokio::test(start_paused = true)]
+async fn probes_follow_interval_and_backoff_in_paused_time() {
+    let healthy = Arc::new(ToggleHealth::default());
+    healthy.healthy.store(true, Ordering::SeqCst);
+    let unhealthy = Arc::new(ToggleHealth::default());
+    let (_health, _readiness) = spawn_k8s_health_checker(healthy.clone()).await;
+    let (_health, _readiness) = spawn_k8s_health_checker(unhealthy.clone()).await;
+
+    tokio::time::sleep(Duration::from_secs(62)).await;
+
+    // Every 5s at 0s, 5s, ..., 60s
+    assert_eq!(healthy.probes.load(Ordering::SeqCst), 13);
+    // Backing off at 0s, 5s, 15s and 35s, the next one is due at 75s
+    assert_eq!(unhealthy.probes.load(Ordering::SeqCst), 4);
+
+    tokio::time::advance(Duration::from_secs(13)).await;
+    tokio::task::yield_now().await;
+    assert_eq!(unhealthy.probes.load(Ordering::SeqCst), 5);
+}
+
+// Time is paused, so the runtime jumps from probe to probe instead of waiting them out
+#[tokio::test(start_paused = true)]
+async fn up_but_not_ready_app_is_probed_without_backoff() {
+    let warming_up = Arc::new(WarmingUpHealth::default());
+    let (_health, _readiness) = spawn_k8s_health_checker(warming_up.clone()).await;
+
+    tokio::time::sleep(Duration::from_secs(62)).await;
+
+    // Every 5s at 0s, 5s, ..., 60s, like an app that is ready
+    assert_eq!(warming_up.probes.load(Ordering::SeqCst), 13);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn readiness_requires_all_dependencies() {
+    let up = Arc::new(ToggleHealth::default());
+    up.healthy.store(true, Ordering::SeqCst);
+    let down = Arc::new(ToggleHealth::default());
+    let (health, _readiness) = spawn_k8s_health_checker_multi(vec![up, down.clone()]).await;
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(health)
+            .serve(listen_addr),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+
+    let channel = tonic::transport::Channel::from_shared(format!("http://127.0.0.1:{port}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = HealthClient::new(channel);
+
+    // Wait for the first probe of the healthy dependency, which may already have happened
+    let mut up_stream = client
+        .watch(HealthCheckRequest {
+            service: dependency_readiness(0),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    while next_status(&mut up_stream).await != ServingStatus::Serving {}
+
+    for (service, expected) in [
+        (dependency_readiness(1), ServingStatus::NotServing),
+        (READINESS.to_string(), ServingStatus::NotServing),
+    ] {
+        let status = client
+            .check(HealthCheckRequest { service })
+            .await
+            .unwrap()
+            .into_inner()
+            .status;
+        assert_eq!(ServingStatus::try_from(status).unwrap(), expected);
+    }
+
+    let mut readiness = client
+        .watch(HealthCheckRequest {
+            service: READINESS.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(next_status(&mut readiness).await, ServingStatus::NotServing);
+    down.healthy.store(true, Ordering::SeqCst);
+    assert_eq!(next_status(&mut readiness).await, ServingStatus::Serving);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn paused_readiness_overrides_successful_probes() {
+    let app_check = Arc::new(ToggleHealth::default());
+    app_check.healthy.store(true, Ordering::SeqCst);
+    let (health, readiness) = spawn_k8s_health_checker(app_check.clone()).await;
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(health)
+            .serve(listen_addr),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+
+    let channel = tonic::transport::Channel::from_shared(format!("http://127.0.0.1:{port}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = HealthClient::new(channel);
+    let mut stream = client
+        .watch(HealthCheckRequest {
+            service: READINESS.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    while next_status(&mut stream).await != ServingStatus::Serving {}
+
+    readiness.pause();
+    assert!(readiness.is_paused());
+    assert_eq!(next_status(&mut stream).await, ServingStatus::NotServing);
+
+    // Readiness is still held down after the next successful probe
+    let probes = app_check.probes.load(Ordering::SeqCst);
+    while app_check.probes.load(Ordering::SeqCst) == probes {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let status = client
+        .check(HealthCheckRequest {
+            service: READINESS.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .status;
+    assert_eq!(
+        ServingStatus::try_from(status).unwrap(),
+        ServingStatus::NotServing
+    );
+
+    readiness.resume();
+    assert_eq!(next_status(&mut stream).await, ServingStatus::Serving);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn up_but_not_ready_app_is_live_but_not_ready() {
+    let app_check = Arc::new(WarmingUpHealth::default());
+    let (health, _readiness) = spawn_k8s_health_checker_multi(vec![app_check.clone()]).await;
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(health)
+            .serve(listen_addr),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+
+    let channel = tonic::transport::Channel::from_shared(format!("http://127.0.0.1:{port}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = HealthClient::new(channel);
+
+    // Wait for the first probe, which may already have happened
+    let mut up_stream = client
+        .watch(HealthCheckRequest {
+            service: dependency_liveness(0),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    while next_status(&mut up_stream).await != ServingStatus::Serving {}
+
+    for (service, expected) in [
+        (LIVENESS.to_string(), ServingStatus::Serving),
+        (dependency_readiness(0), ServingStatus::NotServing),
+        (READINESS.to_string(), ServingStatus::NotServing),
+    ] {
+        let status = client
+            .check(HealthCheckRequest {
+                service: service.clone(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .status;
+        assert_eq!(
+            ServingStatus::try_from(status).unwrap(),
+            expected,
+            "{service}"
+        );
+    }
+
+    let mut readiness = client
+        .watch(HealthCheckRequest {
+            service: READINESS.to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(next_status(&mut readiness).await, ServingStatus::NotServing);
+    app_check.ready.store(true, Ordering::SeqCst);
+    assert_eq!(next_status(&mut readiness).await, ServingStatus::Serving);
+}
@@ -0,0 +1,1721 @@
+//! Integration tests for shared host primitives.
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use e2e::LogBuffer;
+use health_check::{
+    dependency_readiness,
+    pb::{health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest},
+    spawn_k8s_health_checker, AppHealthCheckable, AppHealthResponse, ProbeInterval, READINESS,
+};
+use host_primitives::{
+    choose_codec, codec_answer, codec_offer, enclave_client_timeout, enclave_deadline,
+    grpc_timeout, queue_metrics, send_proxy_request, spawn_batching_queue_consumer,
+    spawn_in_process_queue_consumer, spawn_negotiating_queue_consumer, spawn_queue_consumer,
+    spawn_sighup_reload, AppHostBuilder, BatchProcessor, Batching, BorshCodec, CircuitBreaker,
+    CircuitState, CodecId, CodecSet, ConcurrencyLimit, ConnectionState, Decode, EnclaveClient,
+    EnclaveConnection, EnclaveQueueMsg, Encode, Negotiated, ProstCodec, QueueUtilization,
+    RequestId, Retry, RuntimeConfig, RuntimeSettings, DEADLINE_MARGIN, ENCLAVE_QUEUE_CAPACITY,
+    GRPC_MAX_RECV_MSG_SIZE, GRPC_TIMEOUT_HEADER,
+};
+use logging::LogFormat;
+use qos_core::{
+    io::SocketAddress,
+    protocol::msg::ProtocolMsg,
+    server::{RequestProcessor, SocketServer},
+};
+use reshard_app::service::{ReshardBundle, ReshardRequest, ReshardResponse};
+use reshard_host::generated::reshard::{
+    reshard_service_client::ReshardServiceClient,
+    reshard_service_server::{ReshardService, ReshardServiceServer},
+    GetBundleIdRequest, GetBundleIdResponse, GetLiveAttestationRequest, GetLiveAttestationResponse,
+    GetQueueUtilizationRequest, GetQueueUtilizationResponse, RawRequestRequest, RawRequestResponse,
+    RetrieveAttestationRequest, RetrieveAttestationResponse, RetrieveReshardChunk,
+    RetrieveReshardRequest, RetrieveReshardResponse, RetrieveReshardStreamRequest,
+    VerifyBundleRequest, VerifyBundleResponse,
+};
+use tempdir::TempDir;
+use tonic_reflection::pb::v1alpha::{
+    server_reflection_client::ServerReflectionClient,
+    server_reflection_server::{ServerReflection, ServerReflectionServer},
+    ServerReflectionRequest, ServerReflectionResponse,
+};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::util::SubscriberInitExt;
+
+type Enclave = Arc<EnclaveClient<BorshCodec, ReshardRequest, ReshardResponse>>;
+
+/// Enclave that answers every proxied request with [`ReshardResponse::Health`].
+struct HealthyEnclave;
+
+impl RequestProcessor for HealthyEnclave {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        let ProtocolMsg::ProxyRequest { .. } = ProtocolMsg::try_from_slice(&request).unwrap()
+        else {
+            panic!("expected a proxy request");
+        };
+        let data = borsh::to_vec(&ReshardResponse::Health).unwrap();
+        borsh::to_vec(&ProtocolMsg::ProxyResponse { data }).unwrap()
+    }
+}
+
+/// Enclave that answers every proxied request with `size` bytes of data.
+struct OversizedEnclave {
+    size: usize,
+}
+
+impl RequestProcessor for OversizedEnclave {
+    fn process(&mut self, _request: Vec<u8>) -> Vec<u8> {
+        let data = vec![0; self.size];
+        borsh::to_vec(&ProtocolMsg::ProxyResponse { data }).unwrap()
+    }
+}
+
+/// [`HealthyEnclave`] that counts the requests it processes.
+struct CountingEnclave {
+    requests: Arc<AtomicUsize>,
+}
+
+impl RequestProcessor for CountingEnclave {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        HealthyEnclave.process(request)
+    }
+}
+
+/// [`CountingEnclave`] that takes `delay` to process each request.
+struct SlowEnclave {
+    counting: CountingEnclave,
+    delay: Duration,
+}
+
+impl RequestProcessor for SlowEnclave {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        thread::sleep(self.delay);
+        self.counting.process(request)
+    }
+}
+
+/// [`HealthyEnclave`] that holds each `RetrieveBundle` request until the test releases it.
+/// Health requests are answered right away so health probes never hold the enclave.
+struct GatedEnclave {
+    received: Arc<AtomicUsize>,
+    release: std::sync::mpsc::Receiver<()>,
+}
+
+impl RequestProcessor for GatedEnclave {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        let ProtocolMsg::ProxyRequest { data } = ProtocolMsg::try_from_slice(&request).unwrap()
+        else {
+            panic!("expected a proxy request");
+        };
+        if ReshardRequest::try_from_slice(&data).unwrap() == ReshardRequest::RetrieveBundle {
+            self.received.fetch_add(1, Ordering::SeqCst);
+            self.release.recv().unwrap();
+        }
+        HealthyEnclave.process(request)
+    }
+}
+
+/// App that answers every request with [`ReshardResponse::Health`].
+struct HealthyApp;
+
+impl RequestProcessor for HealthyApp {
+    fn process(&mut self, _request: Vec<u8>) -> Vec<u8> {
+        borsh::to_vec(&ReshardResponse::Health).unwrap()
+    }
+}
+
+/// Enclave that proxies requests to a [`BatchProcessor`] wrapping [`HealthyApp`], counting the
+/// round trips and keeping the size of the largest batch.
+struct BatchingEnclave {
+    app: BatchProcessor<HealthyApp>,
+    round_trips: Arc<AtomicUsize>,
+    largest_batch: Arc<AtomicUsize>,
+}
+
+impl RequestProcessor for BatchingEnclave {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        self.round_trips.fetch_add(1, Ordering::SeqCst);
+        let ProtocolMsg::ProxyRequest { data } = ProtocolMsg::try_from_slice(&request).unwrap()
+        else {
+            panic!("expected a proxy request");
+        };
+        self.largest_batch.fetch_max(data.len(), Ordering::SeqCst);
+        let data = self.app.process(data);
+        borsh::to_vec(&ProtocolMsg::ProxyResponse { data }).unwrap()
+    }
+}
+
+/// Message encodable with both [`BorshCodec`] and [`ProstCodec`].
+#[derive(Clone, PartialEq, prost::Message, BorshSerialize, BorshDeserialize)]
+struct Echo {
+    #[prost(string, tag = "1")]
+    text: String,
+}
+
+/// Enclave whose app negotiates a codec out of `supported` and echoes each request, prefixed
+/// with the codec it was decoded with. Apps that predate negotiation have no `supported`
+/// codecs and only speak borsh.
+struct CodecEnclave {
+    supported: Option<Vec<CodecId>>,
+    codec: CodecId,
+}
+
+impl CodecEnclave {
+    fn new(supported: Option<Vec<CodecId>>) -> Self {
+        Self {
+            supported,
+            codec: CodecId::Borsh,
+        }
+    }
+
+    fn echo(&mut self, data: &[u8]) -> Vec<u8> {
+        if let (Some(supported), Some(offered)) = (&self.supported, codec_offer(data)) {
+            let codec = choose_codec(supported, &offered);
+            if let Some(codec) = codec {
+                self.codec = codec;
+            }
+            return codec_answer(codec);
+        }
+
+        type Codecs = Negotiated<BorshCodec, ProstCodec>;
+        let Ok(Echo { text }) = <Codecs as CodecSet<Echo, Echo>>::decode_with(self.codec, data)
+        else {
+            return b"unknown request".to_vec();
+        };
+        let response = Echo {
+            text: format!("{:?}: {text}", self.codec),
+        };
+        <Codecs as CodecSet<Echo, Echo>>::encode_with(self.codec, &response).unwrap()
+    }
+}
+
+impl RequestProcessor for CodecEnclave {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        let ProtocolMsg::ProxyRequest { data } = ProtocolMsg::try_from_slice(&request).unwrap()
+        else {
+            panic!("expected a proxy request");
+        };
+        let data = self.echo(&data);
+        borsh::to_vec(&ProtocolMsg::ProxyResponse { data }).unwrap()
+    }
+}
+
+/// Echo `text` through a negotiating queue consumer for `Codecs`, with an enclave negotiating
+/// out of `supported`.
+async fn negotiated_echo<Codecs>(
+    supported: Option<Vec<CodecId>>,
+    text: &str,
+) -> Result<Echo, tonic::Status>
+where
+    Codecs: CodecSet<Echo, Echo> + Encode<Echo> + Decode<Echo>,
+{
+    let tmp_dir = TempDir::new("negotiating_queue_consumer").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    let enclave = CodecEnclave::new(supported);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+    spawn_negotiating_queue_consumer::<Codecs, Echo, Echo>(
+        Arc::new(EnclaveConnection::new(
+            SocketAddress::new_unix(&enclave_sock),
+            enclave_client_timeout(),
+        )),
+        queue_rx,
+        GRPC_MAX_RECV_MSG_SIZE,
+    );
+
+    let client = EnclaveClient::<Codecs, _, _>::new(queue_tx);
+    let first = client
+        .send(Echo {
+            text: text.to_string(),
+        })
+        .await?;
+    // Later requests keep the negotiated codec
+    let second = client
+        .send(Echo {
+            text: text.to_string(),
+        })
+        .await?;
+    assert_eq!(first, second);
+    Ok(first)
+}
+
+/// Trivial app service that reports whether the enclave answered a health request.
+struct Trivial {
+    enclave: Enclave,
+}
+
+#[tonic::async_trait]
+impl ReshardService for Trivial {
+    async fn retrieve_reshard(
+        &self,
+        _: tonic::Request<RetrieveReshardRequest>,
+    ) -> Result<tonic::Response<RetrieveReshardResponse>, tonic::Status> {
+        let response = self.enclave.send(ReshardRequest::HealthRequest).await?;
+        Ok(tonic::Response::new(RetrieveReshardResponse {
+            reshard_bundle: format!("{response:?}"),
+            ..Default::default()
+        }))
+    }
+
+    type RetrieveReshardStreamStream =
+        tokio_stream::Empty<Result<RetrieveReshardChunk, tonic::Status>>;
+
+    async fn retrieve_reshard_stream(
+        &self,
+        _: tonic::Request<RetrieveReshardStreamRequest>,
+    ) -> Result<tonic::Response<Self::RetrieveReshardStreamStream>, tonic::Status> {
+        Err(tonic::Status::unimplemented("no bundle stream"))
+    }
+
+    async fn retrieve_attestation(
+        &self,
+        _: tonic::Request<RetrieveAttestationRequest>,
+    ) -> Result<tonic::Response<RetrieveAttestationResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented("no attestation"))
+    }
+
+    async fn get_bundle_id(
+        &self,
+        _: tonic::Request<GetBundleIdRequest>,
+    ) -> Result<tonic::Response<GetBundleIdResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented("no bundle id"))
+    }
+
+    async fn get_live_attestation(
+        &self,
+        _: tonic::Request<GetLiveAttestationRequest>,
+    ) -> Result<tonic::Response<GetLiveAttestationResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented("no live attestation"))
+    }
+
+    async fn verify_bundle(
+        &self,
+        _: tonic::Request<VerifyBundleRequest>,
+    ) -> Result<tonic::Response<VerifyBundleResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented("no bundle verification"))
+    }
+
+    async fn raw_request(
+        &self,
+        _: tonic::Request<RawRequestRequest>,
+    ) -> Result<tonic::Response<RawRequestResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented("no raw requests"))
+    }
+
+    async fn get_queue_utilization(
+        &self,
+        _: tonic::Request<GetQueueUtilizationRequest>,
+    ) -> Result<tonic::Response<GetQueueUtilizationResponse>, tonic::Status> {
+        Err(tonic::Status::unimplemented("no queue utilization"))
+    }
+}
+
+/// Stand-in for the service of a second app: answers each reflection request with its host as
+/// echoed by the enclave.
+struct EchoReflection {
+    enclave: Arc<EnclaveClient<BorshCodec, Echo, Echo>>,
+}
+
+#[tonic::async_trait]
+impl ServerReflection for EchoReflection {
+    type ServerReflectionInfoStream =
+        tokio_stream::Once<Result<ServerReflectionResponse, tonic::Status>>;
+
+    async fn server_reflection_info(
+        &self,
+        request: tonic::Request<tonic::Streaming<ServerReflectionRequest>>,
+    ) -> Result<tonic::Response<Self::ServerReflectionInfoStream>, tonic::Status> {
+        let request = request
+            .into_inner()
+            .message()
+            .await?
+            .ok_or_else(|| tonic::Status::invalid_argument("no request"))?;
+        let echo = self.enclave.send(Echo { text: request.host }).await?;
+        Ok(tonic::Response::new(tokio_stream::once(Ok(
+            ServerReflectionResponse {
+                valid_host: echo.text,
+                ..Default::default()
+            },
+        ))))
+    }
+}
+
+struct Health;
+
+#[tonic::async_trait]
+impl AppHealthCheckable for Health {
+    async fn app_health_check(&self) -> Result<tonic::Response<AppHealthResponse>, tonic::Status> {
+        Ok(tonic::Response::new(AppHealthResponse::ready()))
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_host_builder_serves_registered_service() {
+    let tmp_dir = TempDir::new("app_host_builder").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, HealthyEnclave).unwrap());
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        AppHostBuilder::<BorshCodec, ReshardRequest, ReshardResponse>::new(
+            listen_addr,
+            SocketAddress::new_unix(&enclave_sock),
+        )
+        .serve(
+            |_| Health,
+            |enclave, router| router.add_service(ReshardServiceServer::new(Trivial { enclave })),
+        ),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+
+    let mut client = ReshardServiceClient::connect(format!("http://127.0.0.1:{port}"))
+        .await
+        .unwrap();
+    let response = client
+        .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert_eq!(response.reshard_bundle, "Health");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_host_builder_serves_services_of_several_apps() {
+    let tmp_dir = TempDir::new("app_host_builder").unwrap();
+    let reshard_sock = tmp_dir.path().join("reshard.sock");
+    let reshard_sock = reshard_sock.to_str().unwrap().to_string();
+    let echo_sock = tmp_dir.path().join("echo.sock");
+    let echo_sock = echo_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&reshard_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, HealthyEnclave).unwrap());
+    let enclave_addr = SocketAddress::new_unix(&echo_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, CodecEnclave::new(None)).unwrap());
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        AppHostBuilder::<BorshCodec, ReshardRequest, ReshardResponse>::new(
+            listen_addr,
+            SocketAddress::new_unix(&reshard_sock),
+        )
+        .add_app(
+            SocketAddress::new_unix(&echo_sock),
+            |_| Health,
+            |enclave: Arc<EnclaveClient<BorshCodec, Echo, Echo>>, router| {
+                router.add_service(ServerReflectionServer::new(EchoReflection { enclave }))
+            },
+        )
+        .serve(
+            |_| Health,
+            |enclave, router| router.add_service(ReshardServiceServer::new(Trivial { enclave })),
+        ),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+    let host_addr = format!("http://127.0.0.1:{port}");
+
+    let mut reshard = ReshardServiceClient::connect(host_addr.clone())
+        .await
+        .unwrap();
+    let response = reshard
+        .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.reshard_bundle, "Health");
+
+    let channel = tonic::transport::Channel::from_shared(host_addr)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut echo = ServerReflectionClient::new(channel);
+    let response = echo
+        .server_reflection_info(tokio_stream::once(ServerReflectionRequest {
+            host: "hello".to_string(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .message()
+        .await
+        .unwrap()
+        .expect("echo response");
+    assert_eq!(response.valid_host, "Borsh: hello");
+}
+
+/// Health check of an app whose enclave is down.
+struct DownHealth;
+
+#[tonic::async_trait]
+impl AppHealthCheckable for DownHealth {
+    async fn app_health_check(&self) -> Result<tonic::Response<AppHealthResponse>, tonic::Status> {
+        Err(tonic::Status::unavailable("enclave is down"))
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_host_builder_readiness_covers_added_apps() {
+    let tmp_dir = TempDir::new("app_host_builder").unwrap();
+    let reshard_sock = tmp_dir.path().join("reshard.sock");
+    let reshard_sock = reshard_sock.to_str().unwrap().to_string();
+    // Nothing listens here, the added app's enclave is down
+    let echo_sock = tmp_dir.path().join("echo.sock");
+    let echo_sock = echo_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&reshard_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, HealthyEnclave).unwrap());
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        AppHostBuilder::<BorshCodec, ReshardRequest, ReshardResponse>::new(
+            listen_addr,
+            SocketAddress::new_unix(&reshard_sock),
+        )
+        .add_app(
+            SocketAddress::new_unix(&echo_sock),
+            |_: Arc<EnclaveClient<BorshCodec, Echo, Echo>>| DownHealth,
+            |_, router| router,
+        )
+        .serve(
+            |_| Health,
+            |enclave, router| router.add_service(ReshardServiceServer::new(Trivial { enclave })),
+        ),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+
+    let channel = tonic::transport::Channel::from_shared(format!("http://127.0.0.1:{port}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let client = HealthClient::new(channel);
+    let status = |service: String| {
+        let mut client = client.clone();
+        async move {
+            let status = client
+                .check(HealthCheckRequest { service })
+                .await
+                .unwrap()
+                .into_inner()
+                .status;
+            ServingStatus::try_from(status).unwrap()
+        }
+    };
+
+    // Wait for the first probe of the app of the builder
+    while status(dependency_readiness(0)).await != ServingStatus::Serving {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        status(dependency_readiness(1)).await,
+        ServingStatus::NotServing
+    );
+    assert_eq!(
+        status(READINESS.to_string()).await,
+        ServingStatus::NotServing
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_host_builder_reflects_several_descriptor_sets() {
+    use tonic_reflection::pb::v1::{
+        server_reflection_client::ServerReflectionClient,
+        server_reflection_request::MessageRequest, server_reflection_response::MessageResponse,
+        ServerReflectionRequest,
+    };
+
+    let tmp_dir = TempDir::new("app_host_builder").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, HealthyEnclave).unwrap());
+
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        AppHostBuilder::<BorshCodec, ReshardRequest, ReshardResponse>::new(
+            listen_addr,
+            SocketAddress::new_unix(&enclave_sock),
+        )
+        .reflection(reshard_host::generated::FILE_DESCRIPTOR_SET)
+        .reflection(health_check::pb::FILE_DESCRIPTOR_SET)
+        .serve(
+            |_| Health,
+            |enclave, router| router.add_service(ReshardServiceServer::new(Trivial { enclave })),
+        ),
+    );
+    qos_test_primitives::wait_until_port_is_bound(port);
+
+    let channel = tonic::transport::Channel::from_shared(format!("http://127.0.0.1:{port}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = ServerReflectionClient::new(channel);
+    let requests = [
+        MessageRequest::ListServices(String::new()),
+        MessageRequest::FileContainingSymbol(
+            "services.reshard.v1.ReshardService.RetrieveReshard".to_string(),
+        ),
+        MessageRequest::FileContainingSymbol("grpc.health.v1.Health.Check".to_string()),
+    ]
+    .map(|message_request| ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(message_request),
+    });
+    let mut responses = client
+        .server_reflection_info(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let response = responses.message().await.unwrap().unwrap();
+    let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+        panic!("unexpected reflection response: {response:?}");
+    };
+    let services: Vec<String> = list.service.into_iter().map(|s| s.name).collect();
+    assert!(
+        services.contains(&"services.reshard.v1.ReshardService".to_string()),
+        "{services:?}"
+    );
+    assert!(
+        services.contains(&"grpc.health.v1.Health".to_string()),
+        "{services:?}"
+    );
+
+    // Methods of both services resolve to their files
+    for _ in 0..2 {
+        let response = responses.message().await.unwrap().unwrap();
+        let Some(MessageResponse::FileDescriptorResponse(files)) = response.message_response else {
+            panic!("unexpected reflection response: {response:?}");
+        };
+        assert!(!files.file_descriptor_proto.is_empty());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_host_health_checks_bypass_saturated_data_queue() {
+    const DATA_REQUESTS: usize = 2 * ENCLAVE_QUEUE_CAPACITY;
+
+    let tmp_dir = TempDir::new("app_host_health_queue").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let enclave = SlowEnclave {
+        counting: CountingEnclave {
+            requests: requests.clone(),
+        },
+        delay: Duration::from_millis(100),
+    };
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let (health_tx, health_rx) = tokio::sync::oneshot::channel::<Enclave>();
+    let (data_tx, data_rx) = tokio::sync::oneshot::channel::<Enclave>();
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        AppHostBuilder::<BorshCodec, ReshardRequest, ReshardResponse>::new(
+            listen_addr,
+            SocketAddress::new_unix(&enclave_sock),
+        )
+        .serve(
+            |enclave| {
+                health_tx.send(enclave).unwrap();
+                Health
+            },
+            |enclave, router| {
+                data_tx.send(enclave).unwrap();
+                router
+            },
+        ),
+    );
+    let health_enclave = health_rx.await.unwrap();
+    let data_enclave = data_rx.await.unwrap();
+
+    // Fill the data queue, and keep more requests waiting for room in it
+    for _ in 0..DATA_REQUESTS {
+        let data_enclave = data_enclave.clone();
+        tokio::spawn(async move { data_enclave.send(ReshardRequest::HealthRequest).await });
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let processed_before = requests.load(Ordering::SeqCst);
+    let response = health_enclave
+        .send(ReshardRequest::HealthRequest)
+        .await
+        .unwrap();
+    assert_eq!(response, ReshardResponse::Health);
+
+    // Only requests already at the enclave were processed ahead of the health check, not the
+    // backlog of queued data requests
+    let processed_ahead = requests.load(Ordering::SeqCst) - processed_before;
+    assert!(
+        processed_ahead < ENCLAVE_QUEUE_CAPACITY,
+        "health check waited for {processed_ahead} requests"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn app_host_queue_capacity_applies_backpressure() {
+    let tmp_dir = TempDir::new("app_host_queue_capacity").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let received = Arc::new(AtomicUsize::new(0));
+    let (release_tx, release_rx) = std::sync::mpsc::channel();
+    let enclave = GatedEnclave {
+        received: received.clone(),
+        release: release_rx,
+    };
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let (data_tx, data_rx) = tokio::sync::oneshot::channel::<Enclave>();
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("127.0.0.1:{port}").parse().unwrap();
+    tokio::spawn(
+        AppHostBuilder::<BorshCodec, ReshardRequest, ReshardResponse>::new(
+            listen_addr,
+            SocketAddress::new_unix(&enclave_sock),
+        )
+        .queue_capacity(1)
+        .serve(
+            |_| Health,
+            |enclave, router| {
+                data_tx.send(enclave).unwrap();
+                router
+            },
+        ),
+    );
+    let enclave = data_rx.await.unwrap();
+    assert_eq!(enclave.available_capacity(), 1);
+
+    let send = |enclave: Enclave| {
+        tokio::spawn(async move { enclave.send(ReshardRequest::RetrieveBundle).await })
+    };
+
+    // The first request is taken off the queue and held by the enclave
+    let first = send(enclave.clone());
+    while received.load(Ordering::SeqCst) == 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    // The second fills the queue, so the third has to wait for room
+    let second = send(enclave.clone());
+    while enclave.available_capacity() > 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let third = send(enclave.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(enclave.available_capacity(), 0);
+    assert!(!third.is_finished());
+
+    for _ in 0..3 {
+        release_tx.send(()).unwrap();
+    }
+    for request in [first, second, third] {
+        assert_eq!(request.await.unwrap().unwrap(), ReshardResponse::Health);
+    }
+    assert_eq!(received.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn send_proxy_request_rejects_oversized_request() {
+    let tmp_dir = TempDir::new("send_proxy_request").unwrap();
+    // Nothing listens here, any attempt to reach the enclave fails with `Internal`
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let connection = Arc::new(EnclaveConnection::new(
+        SocketAddress::new_unix(enclave_sock.to_str().unwrap()),
+        enclave_client_timeout(),
+    ));
+
+    // borsh encodes a 4 byte length prefix
+    let request = vec![0u8; 1021];
+    let status =
+        send_proxy_request::<BorshCodec, Vec<u8>, ReshardResponse>(request, connection, 1024, None)
+            .await
+            .unwrap_err();
+
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "encoded request is 1025 bytes, exceeding the limit of 1024 bytes"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_proxy_request_rejects_oversized_response() {
+    let tmp_dir = TempDir::new("send_proxy_request_response").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    let enclave = OversizedEnclave { size: 1025 };
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let connection = Arc::new(
+        EnclaveConnection::new(
+            SocketAddress::new_unix(&enclave_sock),
+            enclave_client_timeout(),
+        )
+        .with_max_response_size(1024),
+    );
+    let status = send_proxy_request::<BorshCodec, _, ReshardResponse>(
+        ReshardRequest::HealthRequest,
+        connection.clone(),
+        GRPC_MAX_RECV_MSG_SIZE,
+        None,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(status.code(), tonic::Code::OutOfRange, "{status}");
+    assert_eq!(
+        status.message(),
+        "app response is 1025 bytes, exceeding the limit of 1024 bytes"
+    );
+    // The enclave was reached, only its response was refused
+    assert_eq!(connection.state(), ConnectionState::Connected);
+}
+
+#[tokio::test]
+async fn queue_consumer_skips_abandoned_requests() {
+    let tmp_dir = TempDir::new("queue_consumer").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let enclave = CountingEnclave {
+        requests: requests.clone(),
+    };
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+    spawn_queue_consumer::<BorshCodec, ReshardRequest, ReshardResponse>(
+        Arc::new(EnclaveConnection::new(
+            SocketAddress::new_unix(&enclave_sock),
+            enclave_client_timeout(),
+        )),
+        queue_rx,
+        GRPC_MAX_RECV_MSG_SIZE,
+    );
+
+    // The caller gave up on this request before it was consumed
+    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+    drop(response_rx);
+    queue_tx
+        .send(Box::new(EnclaveQueueMsg {
+            response_tx,
+            request: ReshardRequest::HealthRequest,
+            request_id: RequestId::generate(),
+        }))
+        .await
+        .unwrap();
+
+    // Queued after the abandoned request, so it is consumed after it
+    let client = EnclaveClient::<BorshCodec, _, _>::new(queue_tx);
+    let response = client.send(ReshardRequest::HealthRequest).await.unwrap();
+
+    assert_eq!(response, ReshardResponse::Health);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn queue_metrics_count_abandoned_requests() {
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+    spawn_in_process_queue_consumer::<BorshCodec, ReshardRequest, ReshardResponse, _>(
+        HealthyApp, queue_rx,
+    );
+    // Counters are process wide, so other tests may add to them concurrently
+    let abandoned = queue_metrics().abandoned_requests();
+
+    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+    drop(response_rx);
+    queue_tx
+        .send(Box::new(EnclaveQueueMsg {
+            response_tx,
+            request: ReshardRequest::HealthRequest,
+            request_id: RequestId::generate(),
+        }))
+        .await
+        .unwrap();
+
+    // Consumed after the abandoned request
+    let client = EnclaveClient::<BorshCodec, _, _>::new(queue_tx);
+    assert_eq!(
+        client.send(ReshardRequest::HealthRequest).await.unwrap(),
+        ReshardResponse::Health
+    );
+    assert!(queue_metrics().abandoned_requests() > abandoned);
+}
+
+/// App that signals `started` on each request, then waits on `release` before answering.
+struct GatedApp {
+    started: std::sync::mpsc::Sender<()>,
+    release: std::sync::mpsc::Receiver<()>,
+}
+
+impl RequestProcessor for GatedApp {
+    fn process(&mut self, _request: Vec<u8>) -> Vec<u8> {
+        self.started.send(()).unwrap();
+        self.release.recv().unwrap();
+        borsh::to_vec(&ReshardResponse::Health).unwrap()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queue_metrics_call_hooks_on_dropped_responses() {
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel();
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+    spawn_in_process_queue_consumer::<BorshCodec, ReshardRequest, ReshardResponse, _>(
+        GatedApp {
+            started: started_tx,
+            release: release_rx,
+        },
+        queue_rx,
+    );
+
+    // Hooks are process wide, so they only note the requests of this test
+    let dropped = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let noted = dropped.clone();
+    let noting_hook = queue_metrics().on_dropped_response(move |request_id| {
+        if request_id.as_str().starts_with("dropped-hook-test") {
+            noted.lock().unwrap().push(request_id.clone());
+        }
+    });
+    let _panicking_hook = queue_metrics().on_dropped_response(|request_id| {
+        if request_id.as_str() == "dropped-hook-test-panic" {
+            panic!("hook panicked");
+        }
+    });
+    // Hooks may register and remove hooks themselves
+    let _reentrant_hook = queue_metrics().on_dropped_response(|request_id| {
+        if request_id.as_str() == "dropped-hook-test-reentrant" {
+            drop(queue_metrics().on_dropped_response(|_| {}));
+        }
+    });
+
+    let drop_response = |id: &'static str| {
+        let queue_tx = queue_tx.clone();
+        let started_rx = &started_rx;
+        let release_tx = &release_tx;
+        async move {
+            let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+            queue_tx
+                .send(Box::new(EnclaveQueueMsg {
+                    response_tx,
+                    request: ReshardRequest::HealthRequest,
+                    request_id: RequestId::new(id),
+                }))
+                .await
+                .unwrap();
+            // The caller gives up while the app works on the request
+            started_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+            drop(response_rx);
+            release_tx.send(()).unwrap();
+        }
+    };
+    for id in [
+        "dropped-hook-test",
+        "dropped-hook-test-panic",
+        "dropped-hook-test-reentrant",
+    ] {
+        drop_response(id).await;
+    }
+    // A removed hook is not called anymore, once the consumer is done with the responses above
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while dropped.lock().unwrap().len() < 3 {
+        assert!(Instant::now() < deadline, "hooks were not called");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    drop(noting_hook);
+    drop_response("dropped-hook-test-removed").await;
+
+    // The consumer survives the panicking hook and keeps answering
+    release_tx.send(()).unwrap();
+    let client = EnclaveClient::<BorshCodec, _, _>::new(queue_tx);
+    assert_eq!(
+        client.send(ReshardRequest::HealthRequest).await.unwrap(),
+        ReshardResponse::Health
+    );
+    assert_eq!(
+        *dropped.lock().unwrap(),
+        [
+            RequestId::new("dropped-hook-test"),
+            RequestId::new("dropped-hook-test-panic"),
+            RequestId::new("dropped-hook-test-reentrant"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn queue_metrics_report_queue_utilization() {
+    // No consumer, so everything sent stays queued
+    let (queue_tx, _queue_rx) = tokio::sync::mpsc::channel(4);
+    queue_metrics().register_queue("utilization-test", &queue_tx);
+    // The registry is process wide, so other tests may register queues concurrently
+    let reported = || {
+        queue_metrics()
+            .queue_utilization()
+            .into_iter()
+            .find(|(name, _)| name == "utilization-test")
+            .map(|(_, utilization)| utilization)
+    };
+    assert_eq!(
+        reported(),
+        Some(QueueUtilization {
+            queued: 0,
+            capacity: 4
+        })
+    );
+
+    let mut response_rxs = Vec::new();
+    for _ in 0..3 {
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        response_rxs.push(response_rx);
+        queue_tx
+            .send(Box::new(EnclaveQueueMsg {
+                response_tx,
+                request: ReshardRequest::HealthRequest,
+                request_id: RequestId::generate(),
+            }))
+            .await
+            .unwrap();
+    }
+    let expected = QueueUtilization {
+        queued: 3,
+        capacity: 4,
+    };
+    assert_eq!(reported(), Some(expected));
+    assert_eq!(expected.ratio(), 0.75);
+
+    let client = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::new(queue_tx);
+    assert_eq!(client.queue_utilization(), expected);
+
+    // Dropped once the last sender is gone
+    drop(client);
+    assert_eq!(reported(), None);
+}
+
+#[tokio::test]
+async fn send_reports_exited_queue_consumer() {
+    // A consumer that was never started, or has exited between requests
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+    drop(queue_rx);
+    let client = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::new(queue_tx);
+
+    let status = client
+        .send(ReshardRequest::HealthRequest)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    assert_eq!(status.message(), "enclave consumer not running");
+
+    // A consumer that dies holding a request
+    let (queue_tx, mut queue_rx) = tokio::sync::mpsc::channel(2);
+    let consumer = tokio::spawn(async move {
+        let _queue_msg: Box<EnclaveQueueMsg<ReshardRequest, ReshardResponse>> =
+            queue_rx.recv().await.unwrap();
+        panic!("consumer crashed");
+    });
+    let client = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::new(queue_tx);
+
+    let status = client
+        .send(ReshardRequest::HealthRequest)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    assert_eq!(status.message(), "enclave consumer not running");
+    assert!(consumer.await.unwrap_err().is_panic());
+}
+
+#[tokio::test]
+async fn queue_consumer_logs_request_id() {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing_subscriber::filter::LevelFilter::DEBUG)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    // The test runs on a current thread runtime, so the consumer task logs to this subscriber
+    let _guard = subscriber.set_default();
+
+    let tmp_dir = TempDir::new("queue_consumer_request_id").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, HealthyEnclave).unwrap());
+
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+    spawn_queue_consumer::<BorshCodec, ReshardRequest, ReshardResponse>(
+        Arc::new(EnclaveConnection::new(
+            SocketAddress::new_unix(&enclave_sock),
+            enclave_client_timeout(),
+        )),
+        queue_rx,
+        GRPC_MAX_RECV_MSG_SIZE,
+    );
+
+    let client = EnclaveClient::<BorshCodec, _, _>::new(queue_tx);
+    client
+        .send_with_id(
+            ReshardRequest::HealthRequest,
+            Some(RequestId::new("test-request-id")),
+        )
+        .await
+        .unwrap();
+    // Without an id one is generated
+    client.send(ReshardRequest::HealthRequest).await.unwrap();
+
+    let logs = logs.contents();
+    let request_ids: Vec<&str> = logs
+        .lines()
+        .filter(|line| line.contains("enclave request enqueued"))
+        .map(|line| {
+            let (_, id) = line.split_once("request_id=").expect("request id logged");
+            id.split_whitespace().next().unwrap()
+        })
+        .collect();
+    assert_eq!(request_ids.len(), 2, "{logs}");
+    assert_eq!(request_ids[0], "test-request-id");
+    assert_ne!(request_ids[1], request_ids[0]);
+
+    for request_id in request_ids {
+        assert!(
+            logs.lines()
+                .any(|line| line.contains("enclave request dequeued")
+                    && line.contains(&format!("request_id={request_id}"))),
+            "no dequeue event for {request_id}: {logs}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn batching_queue_consumer_coalesces_requests() {
+    const REQUESTS: usize = 8;
+
+    let tmp_dir = TempDir::new("batching_queue_consumer").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let round_trips = Arc::new(AtomicUsize::new(0));
+    let enclave = BatchingEnclave {
+        app: BatchProcessor::new(HealthyApp),
+        round_trips: round_trips.clone(),
+        largest_batch: Arc::default(),
+    };
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(REQUESTS);
+    spawn_batching_queue_consumer::<BorshCodec, ReshardRequest, ReshardResponse>(
+        Arc::new(EnclaveConnection::new(
+            SocketAddress::new_unix(&enclave_sock),
+            enclave_client_timeout(),
+        )),
+        queue_rx,
+        GRPC_MAX_RECV_MSG_SIZE,
+        Batching {
+            max_batch_size: REQUESTS,
+            window: Duration::from_millis(100),
+        },
+    );
+
+    let client = EnclaveClient::<BorshCodec, _, _>::new(queue_tx);
+    let responses = futures::future::join_all(
+        (0..REQUESTS).map(|_| client.send(ReshardRequest::HealthRequest)),
+    )
+    .await;
+
+    for response in responses {
+        assert_eq!(response.unwrap(), ReshardResponse::Health);
+    }
+    assert!(round_trips.load(Ordering::SeqCst) < REQUESTS);
+}
+
+#[tokio::test]
+async fn batching_queue_consumer_keeps_batches_within_request_size() {
+    const MAX_REQUEST_SIZE: usize = 1024;
+
+    let tmp_dir = TempDir::new("batching_queue_consumer").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let round_trips = Arc::new(AtomicUsize::new(0));
+    let largest_batch = Arc::new(AtomicUsize::new(0));
+    let enclave = BatchingEnclave {
+        app: BatchProcessor::new(HealthyApp),
+        round_trips: round_trips.clone(),
+        largest_batch: largest_batch.clone(),
+    };
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(2);
+    spawn_batching_queue_consumer::<BorshCodec, Vec<u8>, ReshardResponse>(
+        Arc::new(EnclaveConnection::new(
+            SocketAddress::new_unix(&enclave_sock),
+            enclave_client_timeout(),
+        )),
+        queue_rx,
+        MAX_REQUEST_SIZE,
+        Batching {
+            max_batch_size: 2,
+            window: Duration::from_millis(100),
+        },
+    );
+
+    // Each fits on its own, both together don't
+    let client = EnclaveClient::<BorshCodec, _, _>::new(queue_tx);
+    let responses = futures::future::join_all((0..2).map(|_| client.send(vec![0u8; 600]))).await;
+
+    for response in responses {
+        assert_eq!(response.unwrap(), ReshardResponse::Health);
+    }
+    assert_eq!(round_trips.load(Ordering::SeqCst), 2);
+    assert!(largest_batch.load(Ordering::SeqCst) <= MAX_REQUEST_SIZE);
+
+    // A request too large for a batch of its own is rejected before the enclave
+    let status = client.send(vec![0u8; MAX_REQUEST_SIZE]).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(round_trips.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn enclave_connection_reuses_client_until_failure() {
+    let tmp_dir = TempDir::new("enclave_connection").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, HealthyEnclave).unwrap());
+
+    let connection = Arc::new(EnclaveConnection::new(
+        SocketAddress::new_unix(&enclave_sock),
+        enclave_client_timeout(),
+    ));
+    assert_eq!(connection.state(), ConnectionState::Unknown);
+
+    for _ in 0..3 {
+        let response = send_proxy_request::<BorshCodec, _, ReshardResponse>(
+            ReshardRequest::HealthRequest,
+            connection.clone(),
+            GRPC_MAX_RECV_MSG_SIZE,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response, ReshardResponse::Health);
+    }
+    assert_eq!(connection.state(), ConnectionState::Connected);
+    assert_eq!(connection.builds(), 1);
+
+    // Nothing listens here, so the request fails and the client is rebuilt
+    let missing_sock = tmp_dir.path().join("missing.sock");
+    let connection = Arc::new(EnclaveConnection::new(
+        SocketAddress::new_unix(missing_sock.to_str().unwrap()),
+        enclave_client_timeout(),
+    ));
+    send_proxy_request::<BorshCodec, _, ReshardResponse>(
+        ReshardRequest::HealthRequest,
+        connection.clone(),
+        GRPC_MAX_RECV_MSG_SIZE,
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(connection.state(), ConnectionState::Disconnected);
+    assert_eq!(connection.builds(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_proxy_request_retries_until_enclave_is_reachable() {
+    let tmp_dir = TempDir::new("send_proxy_request_retry").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let connection = Arc::new(
+        EnclaveConnection::new(
+            SocketAddress::new_unix(&enclave_sock),
+            enclave_client_timeout(),
+        )
+        .with_retry(Retry {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(20),
+        }),
+    );
+    let request = tokio::spawn(send_proxy_request::<BorshCodec, _, ReshardResponse>(
+        ReshardRequest::HealthRequest,
+        connection.clone(),
+        GRPC_MAX_RECV_MSG_SIZE,
+        None,
+    ));
+
+    // Only start the enclave once the first attempt failed
+    while connection.builds() < 2 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let requests = Arc::new(AtomicUsize::new(0));
+    let enclave = CountingEnclave {
+        requests: requests.clone(),
+    };
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(response, ReshardResponse::Health);
+    assert_eq!(connection.state(), ConnectionState::Connected);
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // The enclave was reached, so failing to decode its response is not retried
+    let status = send_proxy_request::<BorshCodec, _, ReshardBundle>(
+        ReshardRequest::HealthRequest,
+        connection.clone(),
+        GRPC_MAX_RECV_MSG_SIZE,
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Internal);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_proxy_request_enforces_per_call_timeout() {
+    let tmp_dir = TempDir::new("send_proxy_request_timeout").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let enclave_sock = enclave_sock.to_str().unwrap().to_string();
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let enclave = SlowEnclave {
+        counting: CountingEnclave {
+            requests: requests.clone(),
+        },
+        delay: Duration::from_millis(500),
+    };
+    let enclave_addr = SocketAddress::new_unix(&enclave_sock);
+    thread::spawn(move || SocketServer::listen(enclave_addr, enclave).unwrap());
+
+    let connection = Arc::new(EnclaveConnection::new(
+        SocketAddress::new_unix(&enclave_sock),
+        enclave_client_timeout(),
+    ));
+    let status = send_proxy_request::<BorshCodec, _, ReshardResponse>(
+        ReshardRequest::HealthRequest,
+        connection.clone(),
+        GRPC_MAX_RECV_MSG_SIZE,
+        Some(Duration::from_millis(50)),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded, "{status}");
+
+    // A deadline the enclave can meet
+    let response = send_proxy_request::<BorshCodec, _, ReshardResponse>(
+        ReshardRequest::HealthRequest,
+        connection,
+        GRPC_MAX_RECV_MSG_SIZE,
+        Some(Duration::from_secs(5)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response, ReshardResponse::Health);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn choose_codec_picks_newest_common_codec() {
+    let both = [CodecId::Borsh, CodecId::Prost];
+    assert_eq!(choose_codec(&both, &both), Some(CodecId::Prost));
+    assert_eq!(
+        choose_codec(&both, &[CodecId::Prost, CodecId::Borsh]),
+        Some(CodecId::Prost)
+    );
+    assert_eq!(choose_codec(&both, &[CodecId::Borsh]), Some(CodecId::Borsh));
+    assert_eq!(choose_codec(&[CodecId::Prost], &[CodecId::Borsh]), None);
+    assert_eq!(choose_codec(&both, &[]), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negotiating_queue_consumer_agrees_on_codec() {
+    type Both = Negotiated<BorshCodec, ProstCodec>;
+
+    // Matched codec sets use the newest codec
+    let response = negotiated_echo::<Both>(Some(vec![CodecId::Borsh, CodecId::Prost]), "hi")
+        .await
+        .unwrap();
+    assert_eq!(response.text, "Prost: hi");
+
+    // An older app without the newest codec
+    let response = negotiated_echo::<Both>(Some(vec![CodecId::Borsh]), "hi")
+        .await
+        .unwrap();
+    assert_eq!(response.text, "Borsh: hi");
+
+    // An app that predates negotiation gets the fallback codec
+    let response = negotiated_echo::<Both>(None, "hi").await.unwrap();
+    assert_eq!(response.text, "Borsh: hi");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn negotiating_queue_consumer_rejects_mismatched_codecs() {
+    let status = negotiated_echo::<ProstCodec>(Some(vec![CodecId::Borsh]), "hi")
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert!(status.message().contains("none of the codecs"), "{status}");
+}
+
+#[tokio::test]
+async fn live_attestation_requires_connection() {
+    let (queue_tx, _queue_rx) = tokio::sync::mpsc::channel(1);
+    let client = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::new(queue_tx);
+
+    let status = client.live_attestation_doc(None, None).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn live_attestation_fails_fast_while_circuit_breaker_is_open() {
+    let tmp_dir = TempDir::new("live_attestation").unwrap();
+    // Nothing listens here, so only a request the breaker lets through would fail differently
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let connection = Arc::new(EnclaveConnection::new(
+        SocketAddress::new_unix(enclave_sock.to_str().unwrap()),
+        enclave_client_timeout(),
+    ));
+    let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+    let (queue_tx, _queue_rx) = tokio::sync::mpsc::channel(1);
+    let client = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::new(queue_tx)
+        .with_connection(connection)
+        .with_circuit_breaker(breaker.clone());
+
+    breaker
+        .acquire()
+        .unwrap()
+        .record(&Err::<(), _>(tonic::Status::unavailable("enclave down")));
+    assert_eq!(breaker.state(), CircuitState::Open);
+
+    let status = client.live_attestation_doc(None, None).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    assert!(
+        status.message().contains("circuit breaker is open"),
+        "{status}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sighup_reloads_runtime_config() {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let (subscriber, log_level) =
+        logging::reloadable_subscriber(LogFormat::Json, move || writer.clone());
+    let settings = RuntimeSettings {
+        log_level: Some(log_level.clone()),
+        probe_interval: ProbeInterval::default(),
+        concurrency_limit: ConcurrencyLimit::unlimited(),
+        readiness: None,
+    };
+    let tmp = TempDir::new("runtime_config").unwrap();
+    let path = tmp.path().join("runtime.json");
+    std::fs::write(
+        &path,
+        r#"{"logLevel": "debug", "probeIntervalSecs": 1, "concurrencyLimit": 4}"#,
+    )
+    .unwrap();
+    let _reload = spawn_sighup_reload(path, settings.clone());
+    assert_eq!(log_level.current(), Some(LevelFilter::INFO));
+
+    // SAFETY: signals this process, which handles SIGHUP from here on
+    unsafe { libc::kill(libc::getpid(), libc::SIGHUP) };
+    // The log level is applied last
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while log_level.current() != Some(LevelFilter::DEBUG) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("log level was not reloaded");
+    assert_eq!(settings.probe_interval.get(), Duration::from_secs(1));
+    assert_eq!(settings.concurrency_limit.limit(), 4);
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::debug!("enclave request dequeued");
+    });
+    assert!(
+        logs.contents().contains("enclave request dequeued"),
+        "{}",
+        logs.contents()
+    );
+}
+
+#[tokio::test]
+async fn runtime_config_takes_host_out_of_rotation() {
+    let (_health, readiness) = spawn_k8s_health_checker(Arc::new(Health)).await;
+    let mut settings = RuntimeSettings {
+        log_level: None,
+        probe_interval: ProbeInterval::default(),
+        concurrency_limit: ConcurrencyLimit::unlimited(),
+        readiness: Some(readiness.clone()),
+    };
+
+    let out = RuntimeConfig::parse(r#"{"outOfRotation": true}"#).unwrap();
+    settings.apply(&out).unwrap();
+    assert!(readiness.is_paused());
+    // Other settings leave rotation alone
+    let probe = RuntimeConfig::parse(r#"{"probeIntervalSecs": 10}"#).unwrap();
+    settings.apply(&probe).unwrap();
+    assert!(readiness.is_paused());
+    settings
+        .apply(&RuntimeConfig::parse(r#"{"outOfRotation": false}"#).unwrap())
+        .unwrap();
+    assert!(!readiness.is_paused());
+
+    settings.readiness = None;
+    let err = settings.apply(&out).unwrap_err();
+    assert_eq!(err, "this host can not be taken out of rotation");
+}
+
+#[test]
+fn runtime_config_rejects_invalid_settings() {
+    assert_eq!(
+        RuntimeConfig::parse(r#"{"probeIntervalSecs": 10}"#).unwrap(),
+        RuntimeConfig {
+            probe_interval: Some(Duration::from_secs(10)),
+            ..RuntimeConfig::default()
+        }
+    );
+
+    let err = RuntimeConfig::parse(r#"{"logLevel": "loud"}"#).unwrap_err();
+    assert!(err.contains("invalid log level \"loud\""), "{err}");
+    let err = RuntimeConfig::parse(r#"{"concurrencyLimit": 0}"#).unwrap_err();
+    assert_eq!(err, "concurrency limit must be at least 1");
+    let err = RuntimeConfig::parse(r#"{"probeIntervalSecs": 0}"#).unwrap_err();
+    assert_eq!(err, "probe interval must be at least 1 second");
+    let err = RuntimeConfig::parse(r#"{"logLevl": "debug"}"#).unwrap_err();
+    assert!(err.contains("unknown field `logLevl`"), "{err}");
+}
+
+#[tokio::test]
+async fn concurrency_limit_holds_requests_over_the_limit() {
+    let limit = ConcurrencyLimit::new(2);
+    let first = limit.acquire().await;
+    let _second = limit.acquire().await;
+    assert_eq!(limit.in_flight(), 2);
+
+    let waiting = tokio::spawn({
+        let limit = limit.clone();
+        async move { limit.acquire().await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+
+    // Raising the limit lets the waiting request start
+    limit.set(3);
+    let third = tokio::time::timeout(Duration::from_secs(1), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(limit.in_flight(), 3);
+
+    // Lowering it holds new requests back until enough finish
+    limit.set(1);
+    let waiting = tokio::spawn({
+        let limit = limit.clone();
+        async move { limit.acquire().await }
+    });
+    drop(first);
+    drop(third);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+    assert_eq!(limit.in_flight(), 1);
+}
+
+#[test]
+fn grpc_timeout_parses_client_deadlines() {
+    let with_timeout = |timeout: &str| {
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert(GRPC_TIMEOUT_HEADER, timeout.parse().unwrap());
+        request
+    };
+
+    let cases = [
+        ("1H", Some(Duration::from_secs(3600))),
+        ("2M", Some(Duration::from_secs(120))),
+        ("5S", Some(Duration::from_secs(5))),
+        ("300m", Some(Duration::from_millis(300))),
+        ("7u", Some(Duration::from_micros(7))),
+        ("99999999n", Some(Duration::from_nanos(99_999_999))),
+        ("123456789S", None),
+        ("5s", None),
+        ("S", None),
+        ("-5S", None),
+    ];
+    for (timeout, expected) in cases {
+        assert_eq!(grpc_timeout(&with_timeout(timeout)), expected, "{timeout}");
+    }
+    assert_eq!(grpc_timeout(&tonic::Request::new(())), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn enclave_deadline_takes_earliest_timeout() {
+    let mut request = tonic::Request::new(());
+    assert_eq!(enclave_deadline(&request, None), None);
+
+    let now = tokio::time::Instant::now();
+    assert_eq!(
+        enclave_deadline(&request, Some(Duration::from_secs(5))),
+        Some(now + Duration::from_secs(5))
+    );
+
+    request.set_timeout(Duration::from_secs(1));
+    let client_deadline = now + Duration::from_secs(1) - DEADLINE_MARGIN;
+    assert_eq!(enclave_deadline(&request, None), Some(client_deadline));
+    assert_eq!(
+        enclave_deadline(&request, Some(Duration::from_secs(5))),
+        Some(client_deadline)
+    );
+    assert_eq!(
+        enclave_deadline(&request, Some(Duration::from_millis(100))),
+        Some(now + Duration::from_millis(100))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn circuit_breaker_opens_probes_and_closes() {
+    let cooldown = Duration::from_secs(10);
+    let breaker = CircuitBreaker::new(2, cooldown);
+    let unavailable = || Err::<(), _>(tonic::Status::unavailable("enclave down"));
+    assert_eq!(breaker.state(), CircuitState::Closed);
+
+    // Closed: failures only trip the breaker once consecutive
+    breaker.acquire().unwrap().record(&unavailable());
+    breaker.acquire().unwrap().record(&Ok(()));
+    breaker.acquire().unwrap().record(&unavailable());
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    // Answers with other statuses mean the enclave is up
+    breaker
+        .acquire()
+        .unwrap()
+        .record(&Err::<(), _>(tonic::Status::internal("bad response")));
+    breaker.acquire().unwrap().record(&unavailable());
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    breaker.acquire().unwrap().record(&unavailable());
+
+    // Open: requests fail fast until the cooldown is over
+    assert_eq!(breaker.state(), CircuitState::Open);
+    let status = breaker.acquire().unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    assert!(
+        status.message().contains("circuit breaker is open"),
+        "{status}"
+    );
+    tokio::time::advance(cooldown).await;
+
+    // Half-open: a single probe goes through, a failed one opens the breaker again
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    let probe = breaker.acquire().unwrap();
+    let status = breaker.acquire().unwrap_err();
+    assert!(status.message().contains("half-open"), "{status}");
+    probe.record(&unavailable());
+    assert_eq!(breaker.state(), CircuitState::Open);
+    tokio::time::advance(cooldown).await;
+
+    // A probe the caller gave up on lets the next request probe instead
+    drop(breaker.acquire().unwrap());
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    let probe = breaker.acquire().unwrap();
+    probe.record(&Err::<(), _>(tonic::Status::deadline_exceeded("gave up")));
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+    // A successful probe closes the breaker
+    breaker.acquire().unwrap().record(&Ok(()));
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    breaker.acquire().unwrap().record(&unavailable());
+    assert_eq!(breaker.state(), CircuitState::Closed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn enclave_client_fails_fast_while_circuit_breaker_is_open() {
+    let cooldown = Duration::from_secs(10);
+    let breaker = CircuitBreaker::new(3, cooldown);
+    let up = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let sent = Arc::new(AtomicUsize::new(0));
+
+    let (queue_tx, mut queue_rx) = tokio::sync::mpsc::channel(2);
+    let (consumer_up, consumer_sent) = (up.clone(), sent.clone());
+    tokio::spawn(async move {
+        while let Some(queue_msg) = queue_rx.recv().await {
+            let queue_msg: Box<EnclaveQueueMsg<ReshardRequest, ReshardResponse>> = queue_msg;
+            consumer_sent.fetch_add(1, Ordering::SeqCst);
+            let response = if consumer_up.load(Ordering::SeqCst) {
+                Ok(ReshardResponse::Health)
+            } else {
+                Err(tonic::Status::unavailable("Failed to query enclave"))
+            };
+            let _ = queue_msg.response_tx.send(response);
+        }
+    });
+    let client = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::new(queue_tx)
+        .with_circuit_breaker(breaker.clone());
+
+    for _ in 0..3 {
+        let status = client
+            .send(ReshardRequest::HealthRequest)
+            .await
+            .unwrap_err();
+        assert_eq!(status.message(), "Failed to query enclave");
+    }
+    assert_eq!(breaker.state(), CircuitState::Open);
+
+    // Requests fail without reaching the queue
+    let status = client
+        .send(ReshardRequest::HealthRequest)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    assert!(
+        status.message().contains("circuit breaker is open"),
+        "{status}"
+    );
+    assert_eq!(sent.load(Ordering::SeqCst), 3);
+
+    // The enclave recovers, the probe after the cooldown closes the breaker
+    up.store(true, Ordering::SeqCst);
+    tokio::time::advance(cooldown).await;
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    let response = client.send(ReshardRequest::HealthRequest).await.unwrap();
+    assert_eq!(response, ReshardResponse::Health);
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    assert_eq!(sent.load(Ordering::SeqCst), 4);
+}
@@ -0,0 +1,56 @@
+//! Integration tests for the log output of the host and app binaries.
+use e2e::LogBuffer;
+use logging::{subscriber, LogFormat};
+
+#[test]
+fn log_format_parses_flag_values() {
+    assert_eq!("json".parse(), Ok(LogFormat::Json));
+    assert_eq!("pretty".parse(), Ok(LogFormat::Pretty));
+    assert!("yaml".parse::<LogFormat>().unwrap_err().contains("yaml"));
+
+    // The default is a valid flag value
+    let default = LogFormat::default().to_string();
+    assert_eq!(default.parse(), Ok(LogFormat::Pretty));
+}
+
+#[test]
+fn json_format_emits_json_lines() {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    tracing::subscriber::with_default(subscriber(LogFormat::Json, move || writer.clone()), || {
+        tracing::info!(request_id = "abc", "bundle retrieved");
+        tracing::warn!("failed to archive bundle");
+        // Below the default level
+        tracing::debug!("enclave request dequeued");
+    });
+
+    let lines: Vec<serde_json::Value> = logs
+        .contents()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line}")))
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["level"], "INFO");
+    assert_eq!(lines[0]["fields"]["message"], "bundle retrieved");
+    assert_eq!(lines[0]["fields"]["request_id"], "abc");
+    assert_eq!(lines[1]["level"], "WARN");
+    assert_eq!(lines[1]["fields"]["message"], "failed to archive bundle");
+}
+
+#[test]
+fn pretty_format_emits_plain_lines() {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    tracing::subscriber::with_default(
+        subscriber(LogFormat::Pretty, move || writer.clone()),
+        || tracing::info!(request_id = "abc", "bundle retrieved"),
+    );
+
+    let logs = logs.contents();
+    assert!(logs.contains("INFO"), "{logs}");
+    assert!(
+        logs.contains("bundle retrieved request_id=\"abc\""),
+        "{logs}"
+    );
+    assert!(serde_json::from_str::<serde_json::Value>(logs.trim()).is_err());
+}
@@ -0,0 +1,197 @@
+//! Integration tests for the QOS simulator used by the e2e tests.
+use std::{
+    io::{self, Read, Write},
+    os::unix::net::UnixListener,
+    thread,
+    time::Duration,
+};
+
+use borsh::BorshDeserialize;
+use e2e::{
+    qos_simulator::{
+        read_frame, spawn_qos_simulator, write_frame, MockAttestation, QosSimulatorConfig,
+    },
+    reshard_fixture_bundle, RESHARD_FIXTURES,
+};
+use qos_core::{
+    client::Client,
+    io::{SocketAddress, TimeVal, TimeValLike},
+    protocol::msg::ProtocolMsg,
+};
+use qos_nsm::types::NsmResponse;
+use qos_p256::P256Pair;
+use reshard_verify::{attested_fields, verify_bundle, AttestedFields, EphemeralKey};
+use tempdir::TempDir;
+
+/// Reader handing out `bytes` a few at a time, interrupted every other read, like a loaded
+/// socket.
+struct Fragmented {
+    bytes: Vec<u8>,
+    pos: usize,
+    reads: usize,
+}
+
+impl Fragmented {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            pos: 0,
+            reads: 0,
+        }
+    }
+}
+
+impl Read for Fragmented {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        if self.reads.is_multiple_of(2) {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+
+        let len = (self.reads % 5 + 1)
+            .min(buf.len())
+            .min(self.bytes.len() - self.pos);
+        buf[..len].copy_from_slice(&self.bytes[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::new();
+    write_frame(&mut frame, data).unwrap();
+    frame
+}
+
+#[test]
+fn read_frame_reassembles_fragments() {
+    let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let mut reader = Fragmented::new(frame(&data));
+
+    assert_eq!(read_frame(&mut reader).unwrap(), data);
+    assert!(reader.reads > 100);
+}
+
+#[test]
+fn read_frame_rejects_truncated_frame() {
+    let mut truncated = frame(&[7; 10]);
+    truncated.truncate(truncated.len() - 7);
+
+    let err = read_frame(&mut Fragmented::new(truncated)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(err.to_string().contains("3 of 10 bytes"), "{err}");
+
+    // Closed before the length is complete
+    let err = read_frame(&mut Fragmented::new(vec![1, 0])).unwrap_err();
+    assert!(err.to_string().contains("frame length"), "{err}");
+}
+
+#[test]
+fn simulator_reassembles_fragmented_app_responses() {
+    let tmp_dir = TempDir::new("qos_simulator").unwrap();
+    let app_sock = tmp_dir.path().join("app.sock");
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let response: Vec<u8> = (0..=255).cycle().take(64 * 1024).collect();
+
+    // App that answers each request with `response`, written out in small delayed fragments
+    let listener = UnixListener::bind(&app_sock).unwrap();
+    let app_response = response.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            assert_eq!(read_frame(&mut stream).unwrap(), b"request");
+            for chunk in frame(&app_response).chunks(4096 + 3) {
+                stream.write_all(chunk).unwrap();
+                stream.flush().unwrap();
+                thread::sleep(Duration::from_millis(2));
+            }
+        }
+    });
+
+    spawn_qos_simulator(QosSimulatorConfig {
+        enclave_sock: enclave_sock.to_str().unwrap().to_string(),
+        app_sock: app_sock.to_str().unwrap().to_string(),
+        #[cfg(feature = "vsock")]
+        enclave_vsock: None,
+        mock_attestation: None,
+    });
+
+    let client = Client::new(
+        SocketAddress::new_unix(enclave_sock.to_str().unwrap()),
+        TimeVal::seconds(5),
+    );
+    let request = borsh::to_vec(&ProtocolMsg::ProxyRequest {
+        data: b"request".to_vec(),
+    })
+    .unwrap();
+    for _ in 0..3 {
+        let ProtocolMsg::ProxyResponse { data } =
+            ProtocolMsg::try_from_slice(&client.send(&request).unwrap()).unwrap()
+        else {
+            panic!("expected a proxy response");
+        };
+        assert_eq!(data, response);
+    }
+}
+
+#[test]
+fn simulator_serves_configured_mock_attestation() {
+    let tmp_dir = TempDir::new("qos_simulator").unwrap();
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let ephemeral_pub = P256Pair::from_hex_file(format!("{RESHARD_FIXTURES}/ephemeral.secret"))
+        .unwrap()
+        .public_key()
+        .to_bytes();
+    let mock_attestation = MockAttestation {
+        public_key: ephemeral_pub.clone(),
+        user_data: vec![7; 32],
+    };
+    assert_eq!(mock_attestation.document(), mock_attestation.document());
+
+    spawn_qos_simulator(QosSimulatorConfig {
+        enclave_sock: enclave_sock.to_str().unwrap().to_string(),
+        app_sock: tmp_dir
+            .path()
+            .join("app.sock")
+            .to_str()
+            .unwrap()
+            .to_string(),
+        #[cfg(feature = "vsock")]
+        enclave_vsock: None,
+        mock_attestation: Some(mock_attestation.clone()),
+    });
+
+    let client = Client::new(
+        SocketAddress::new_unix(enclave_sock.to_str().unwrap()),
+        TimeVal::seconds(5),
+    );
+    let request = borsh::to_vec(&ProtocolMsg::LiveAttestationDocRequest).unwrap();
+    let ProtocolMsg::LiveAttestationDocResponse {
+        nsm_response: NsmResponse::Attestation { document },
+        ..
+    } = ProtocolMsg::try_from_slice(&client.send(&request).unwrap()).unwrap()
+    else {
+        panic!("expected a live attestation doc");
+    };
+    assert_eq!(document, mock_attestation.document());
+
+    assert_eq!(
+        attested_fields(&document).unwrap(),
+        AttestedFields {
+            public_key: Some(ephemeral_pub),
+            user_data: Some(vec![7; 32]),
+        }
+    );
+
+    // The simulator's doc is not signed by AWS, so the verifier does not trust the key in it
+    let mut bundle = reshard_fixture_bundle(tmp_dir.path());
+    bundle.attestation_doc = document;
+    let report = verify_bundle(&bundle, EphemeralKey::attested_now(), None);
+    assert!(!report.passed);
+    let check = |name| report.checks.iter().find(|c| c.name == name).unwrap();
+    assert!(!check("attestation_doc").passed);
+    assert_eq!(
+        check("signature").computed.split(' ').next(),
+        Some("unattested")
+    );
+}
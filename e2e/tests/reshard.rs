@@ -1,11 +1,20 @@
 //! Integration test for reshard app
+use prost::Message;
 use reshard_app::service::ReshardBundle;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use reshard_host::generated::reshard::reshard_service_client::ReshardServiceClient;
-use reshard_host::generated::reshard::RetrieveReshardRequest;
+use reshard_host::generated::reshard::{
+    BundleFormat, GetBundleIdRequest, GetLiveAttestationRequest, RetrieveAttestationRequest,
+    RetrieveReshardRequest, RetrieveReshardStreamRequest, VerifyBundleRequest,
+};
 
-use e2e::TestArgs;
+use e2e::{connect_health_client, ExecuteConfig, TestArgs};
+use health_check::{
+    pb::{health_check_response::ServingStatus, HealthCheckRequest, HealthCheckResponse},
+    READINESS,
+};
 use qos_p256::{P256Pair, P256Public};
 
 #[tokio::test]
@@ -14,7 +23,7 @@ async fn reshard_e2e_json() {
         let mut client: ReshardServiceClient<_> = args.reshard_client;
 
         let resp = client
-            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest {}))
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
             .await
             .unwrap()
             .into_inner();
@@ -136,3 +145,408 @@ async fn reshard_e2e_json() {
     }
     e2e::execute(test).await;
 }
+
+#[tokio::test]
+async fn reshard_e2e_max_message_size() {
+    // The bundle has a deterministic size, so learn the encoded response length with the
+    // default limit and then bring the stack up with limits right around it.
+    static RESPONSE_LEN: AtomicUsize = AtomicUsize::new(0);
+
+    e2e::execute(|args: TestArgs| async move {
+        let mut client = args.reshard_client;
+        let resp = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        RESPONSE_LEN.store(resp.encoded_len(), Ordering::SeqCst);
+    })
+    .await;
+
+    let response_len = RESPONSE_LEN.load(Ordering::SeqCst);
+    assert!(response_len > 0);
+
+    // Exactly at the limit the response is still served
+    let config = ExecuteConfig {
+        max_message_size: response_len,
+        ..Default::default()
+    };
+    e2e::execute_with_config(config, |args: TestArgs| async move {
+        let mut client = args.reshard_client;
+        client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .expect("response at the limit is served");
+    })
+    .await;
+
+    // One byte over the limit the host refuses to encode it
+    let config = ExecuteConfig {
+        max_message_size: response_len - 1,
+        ..Default::default()
+    };
+    e2e::execute_with_config(config, |args: TestArgs| async move {
+        let mut client = args.reshard_client;
+        let status = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .expect_err("response over the limit is rejected");
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_e2e_bundle_archive() {
+    let tmp_dir = tempdir::TempDir::new("bundle_archive").unwrap();
+    let archive_path = tmp_dir.path().join("bundle.json");
+
+    let config = ExecuteConfig {
+        bundle_archive_path: Some(archive_path.clone()),
+        ..Default::default()
+    };
+    e2e::execute_with_config(config, |args: TestArgs| {
+        let archive_path = archive_path.clone();
+        async move {
+            let mut client = args.reshard_client;
+            let resp = client
+                .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+                .await
+                .unwrap()
+                .into_inner();
+
+            let archived = std::fs::read_to_string(&archive_path).expect("bundle archived");
+            assert_eq!(archived, resp.reshard_bundle);
+
+            // Later retrievals must not write the archive again
+            std::fs::write(&archive_path, "sentinel").unwrap();
+            for _ in 0..2 {
+                client
+                    .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(std::fs::read_to_string(&archive_path).unwrap(), "sentinel");
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_e2e_bundle_formats() {
+    e2e::execute(|args: TestArgs| async move {
+        let mut client = args.reshard_client;
+
+        let json_resp = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest {
+                format: BundleFormat::Json.into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(json_resp.reshard_bundle_borsh.is_empty());
+        let json_bundle: ReshardBundle =
+            serde_json::from_str(&json_resp.reshard_bundle).expect("valid JSON");
+
+        let borsh_resp = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest {
+                format: BundleFormat::Borsh.into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(borsh_resp.reshard_bundle.is_empty());
+        let borsh_bundle: ReshardBundle =
+            borsh::from_slice(&borsh_resp.reshard_bundle_borsh).expect("valid borsh");
+
+        assert_eq!(json_bundle, borsh_bundle);
+
+        let status = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest { format: 42 }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_e2e_attestation_matches_bundle() {
+    e2e::execute(|args: TestArgs| async move {
+        let mut client = args.reshard_client;
+
+        let attestation_doc = client
+            .retrieve_attestation(tonic::Request::new(RetrieveAttestationRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .attestation_doc;
+        assert!(!attestation_doc.is_empty());
+
+        let resp = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        let bundle: ReshardBundle = serde_json::from_str(&resp.reshard_bundle).expect("valid JSON");
+        assert_eq!(attestation_doc, bundle.attestation_doc);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_e2e_bundle_id() {
+    e2e::execute(|args: TestArgs| async move {
+        let mut client = args.reshard_client;
+
+        let bundle_id = client
+            .get_bundle_id(tonic::Request::new(GetBundleIdRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .bundle_id;
+
+        for format in [BundleFormat::Json, BundleFormat::Borsh] {
+            let resp = client
+                .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest {
+                    format: format.into(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(resp.bundle_id, bundle_id, "{format:?}");
+        }
+
+        let resp = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        let bundle: ReshardBundle = serde_json::from_str(&resp.reshard_bundle).expect("valid JSON");
+        assert_eq!(bundle.id(), bundle_id);
+    })
+    .await;
+}
+
+/// Wait for `stream` to report `expected`, skipping other statuses.
+async fn wait_for_status(
+    stream: &mut tonic::Streaming<HealthCheckResponse>,
+    expected: ServingStatus,
+) {
+    loop {
+        let response = tokio::time::timeout(std::time::Duration::from_secs(60), stream.message())
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for {expected:?}"))
+            .expect("watch stream errored")
+            .expect("watch stream ended");
+        if ServingStatus::try_from(response.status).unwrap() == expected {
+            return;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reshard_e2e_readiness_follows_app() {
+    e2e::execute(|args: TestArgs| async move {
+        let mut health = connect_health_client(&args.host_addr).await;
+        let mut readiness = health
+            .watch(HealthCheckRequest {
+                service: READINESS.to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        wait_for_status(&mut readiness, ServingStatus::Serving).await;
+
+        // The QOS simulator can no longer reach the app
+        std::fs::remove_file(&args.app_sock).unwrap();
+        wait_for_status(&mut readiness, ServingStatus::NotServing).await;
+
+        drop(readiness);
+        drop(health);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_e2e_verify_bundle() {
+    e2e::execute(|args: TestArgs| async move {
+        let mut client = args.reshard_client;
+        let fixture_key = |name: &str| {
+            P256Public::from_hex_file(format!("./fixtures/reshard/{name}.pub"))
+                .unwrap()
+                .to_bytes()
+        };
+
+        let resp = client
+            .verify_bundle(tonic::Request::new(VerifyBundleRequest {
+                ephemeral_public_key: fixture_key("ephemeral"),
+                quorum_public_key: fixture_key("quorum"),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(resp.passed, "{:?}", resp.checks);
+        let checks: Vec<_> = resp.checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(checks, ["signature", "unique_members", "quorum_public_key"]);
+        let bundle_id = client
+            .get_bundle_id(tonic::Request::new(GetBundleIdRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .bundle_id;
+        assert_eq!(resp.bundle_id, bundle_id);
+
+        // Without a key the bundle's attestation doc must verify, which the mock doc does not
+        let resp = client
+            .verify_bundle(tonic::Request::new(VerifyBundleRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!resp.passed);
+        let check = |name| resp.checks.iter().find(|c| c.name == name).unwrap();
+        assert!(!check("attestation_doc").passed);
+        assert!(!check("signature").passed);
+
+        // Keys that don't decode are rejected before the bundle is checked
+        let status = client
+            .verify_bundle(tonic::Request::new(VerifyBundleRequest {
+                ephemeral_public_key: vec![1, 2, 3],
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_e2e_stream_resumes_from_offset() {
+    e2e::execute(|args: TestArgs| async move {
+        let mut client = args.reshard_client;
+        let expected = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest {
+                format: BundleFormat::Borsh.into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // Drop the download after a few chunks, like a lost connection would
+        let request = RetrieveReshardStreamRequest {
+            max_chunk_size: 256,
+            ..Default::default()
+        };
+        let mut stream = client
+            .retrieve_reshard_stream(tonic::Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut received = Vec::new();
+        let mut bundle_id = Vec::new();
+        for _ in 0..3 {
+            let chunk = stream.message().await.unwrap().expect("stream ended early");
+            assert_eq!(chunk.offset, received.len() as u64);
+            assert!(chunk.data.len() <= 256);
+            received.extend(chunk.data);
+            bundle_id = chunk.bundle_id;
+        }
+        drop(stream);
+
+        let request = RetrieveReshardStreamRequest {
+            offset: received.len() as u64,
+            bundle_id: bundle_id.clone(),
+            max_chunk_size: 256,
+        };
+        let mut stream = client
+            .retrieve_reshard_stream(tonic::Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        while let Some(chunk) = stream.message().await.unwrap() {
+            assert_eq!(chunk.offset, received.len() as u64);
+            assert_eq!(chunk.total_size, expected.reshard_bundle_borsh.len() as u64);
+            received.extend(chunk.data);
+        }
+
+        assert_eq!(received, expected.reshard_bundle_borsh);
+        assert_eq!(bundle_id, expected.bundle_id);
+        let bundle: ReshardBundle = borsh::from_slice(&received).unwrap();
+        assert_eq!(bundle.id(), bundle_id);
+
+        // Resuming a bundle that is no longer served, or past its end, fails
+        let status = client
+            .retrieve_reshard_stream(tonic::Request::new(RetrieveReshardStreamRequest {
+                offset: 256,
+                bundle_id: vec![0; 32],
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Aborted);
+        let status = client
+            .retrieve_reshard_stream(tonic::Request::new(RetrieveReshardStreamRequest {
+                offset: received.len() as u64 + 1,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_e2e_live_attestation() {
+    e2e::execute(|args: TestArgs| async move {
+        let mut client = args.reshard_client;
+
+        let attestation_doc = client
+            .get_live_attestation(tonic::Request::new(GetLiveAttestationRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .attestation_doc;
+
+        // The QOS simulator answers with a mock document, not the app's attestation
+        let mock_document = borsh::to_vec(&"MOCK_DOCUMENT".to_string()).unwrap();
+        assert_eq!(attestation_doc, mock_document);
+    })
+    .await;
+}
+
+/// Exercises the host's vsock enclave address path against the QOS simulator listening on the
+/// local vsock loopback.
+///
+/// Requires a vsock capable environment (e.g. the `vsock_loopback` kernel module loaded) and a
+/// `reshard_host` built with the `vsock` feature:
+///
+/// ```sh
+/// cargo build --features mock --bin reshard_app
+/// cargo build -p reshard_host --features vsock
+/// cargo test -p e2e --features vsock --test reshard reshard_e2e_vsock
+/// ```
+#[cfg(feature = "vsock")]
+#[tokio::test]
+async fn reshard_e2e_vsock() {
+    // VMADDR_CID_LOCAL, routes to the local vsock loopback
+    const LOCAL_CID: u32 = 1;
+    let port = u32::from(qos_test_primitives::find_free_port().expect("find free port"));
+
+    let config = ExecuteConfig {
+        enclave_vsock: Some((LOCAL_CID, port)),
+        ..Default::default()
+    };
+    e2e::execute_with_config(config, |args: TestArgs| async move {
+        let mut client = args.reshard_client;
+        let resp = client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .expect("retrieve reshard over vsock")
+            .into_inner();
+        let _: ReshardBundle = serde_json::from_str(&resp.reshard_bundle).expect("valid JSON");
+    })
+    .await;
+}
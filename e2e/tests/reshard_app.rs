@@ -0,0 +1,1278 @@
+//! Integration tests for the reshard enclave app.
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use base64::Engine;
+use borsh::BorshDeserialize;
+use e2e::{
+    joined_pubkeys, reshard_app_command, reshard_fixture_handles, reshard_fixture_quorum_key,
+    reshard_fixture_share_set, RESHARD_FIXTURES,
+};
+use qos_core::server::RequestProcessor;
+use qos_core::{
+    io::SocketAddress,
+    protocol::{
+        services::boot::{Manifest, ManifestEnvelope, Namespace, QuorumMember},
+        QosHash,
+    },
+};
+use qos_nsm::{
+    mock::MockNsm,
+    types::{NsmRequest, NsmResponse},
+    NsmProvider,
+};
+use qos_p256::{P256Pair, P256Public};
+use reshard_app::{
+    cli::{
+        decode_manifest_envelope_base64, parse_share_set, read_in_memory_keys,
+        read_manifest_envelope, read_members_file, read_threshold_file, share_set_summary,
+    },
+    ensure_socket_unused,
+    keys::InMemoryKeys,
+    rate_limit::{RateLimit, RateLimitedProcessor, ReshardRateLimits},
+    service::{
+        sign_checked, MemberReplacement, ReplacementApproval, ReshardBundle, ReshardProcessor,
+        ReshardRequest, ReshardResponse, MAX_ATTESTATION_DOC_LEN, MAX_NONCE_LEN,
+        MIN_ATTESTATION_DOC_LEN,
+    },
+    socket_address::{socket_address_from_args, MAX_UNIX_SOCKET_PATH_LEN},
+    ReshardAppConfig,
+};
+use tempdir::TempDir;
+
+/// NSM that records every request before answering it with the [`MockNsm`].
+#[derive(Clone, Default)]
+struct SpyNsm {
+    requests: Arc<Mutex<Vec<NsmRequest>>>,
+}
+
+impl NsmProvider for SpyNsm {
+    fn nsm_process_request(&self, request: NsmRequest) -> NsmResponse {
+        self.requests.lock().unwrap().push(request.clone());
+        MockNsm.nsm_process_request(request)
+    }
+
+    fn timestamp_ms(&self) -> Result<u64, qos_nsm::nitro::AttestError> {
+        MockNsm.timestamp_ms()
+    }
+}
+
+/// NSM whose attestation documents carry the requested nonce in the clear. Attestations
+/// without a nonce, e.g. of the bundle, get the mock document.
+struct NonceEchoNsm;
+
+impl NsmProvider for NonceEchoNsm {
+    fn nsm_process_request(&self, request: NsmRequest) -> NsmResponse {
+        match request {
+            NsmRequest::Attestation {
+                nonce: Some(nonce), ..
+            } => NsmResponse::Attestation {
+                document: [b"attestation nonce=".as_slice(), &nonce].concat(),
+            },
+            other => MockNsm.nsm_process_request(other),
+        }
+    }
+
+    fn timestamp_ms(&self) -> Result<u64, qos_nsm::nitro::AttestError> {
+        MockNsm.timestamp_ms()
+    }
+}
+
+/// NSM that answers attestation requests with a fixed document.
+struct FixedDocumentNsm(Vec<u8>);
+
+impl NsmProvider for FixedDocumentNsm {
+    fn nsm_process_request(&self, request: NsmRequest) -> NsmResponse {
+        match request {
+            NsmRequest::Attestation { .. } => NsmResponse::Attestation {
+                document: self.0.clone(),
+            },
+            other => MockNsm.nsm_process_request(other),
+        }
+    }
+
+    fn timestamp_ms(&self) -> Result<u64, qos_nsm::nitro::AttestError> {
+        MockNsm.timestamp_ms()
+    }
+}
+
+fn fixture_members() -> Vec<String> {
+    joined_pubkeys(&Path::new(RESHARD_FIXTURES).join("new-share-set"))
+        .split(';')
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn reshard_processor_rejects_duplicate_member_key() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+
+    let mut share_set = reshard_fixture_share_set();
+    let duplicate = share_set.members[0].pub_key.clone();
+    share_set.members[1].pub_key = duplicate;
+
+    let err = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm))
+        .err()
+        .expect("duplicate member key is rejected");
+    assert!(
+        err.contains(&format!(
+            "duplicate pub key for member '{}'",
+            share_set.members[1].alias
+        )),
+        "unexpected error: {err}"
+    );
+}
+
+#[test]
+fn new_names_member_with_unparseable_pub_key() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+
+    // The right length for an encryption and signing key, but not points on the curve, so the
+    // key is rejected when parsed, before any share is encrypted to it
+    let mut share_set = reshard_fixture_share_set();
+    share_set.members[2].pub_key = vec![4; 130];
+
+    let err = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm))
+        .err()
+        .expect("member key that can not be parsed is rejected");
+    let expected = format!(
+        "bad member pubkey for member 2 '{}': ",
+        share_set.members[2].alias
+    );
+    assert!(err.starts_with(&expected), "unexpected error: {err}");
+}
+
+#[test]
+fn reshard_processor_rejects_attestation_doc_of_bad_size() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+
+    let err = ReshardProcessor::new(&handles, &share_set, Box::new(FixedDocumentNsm(Vec::new())))
+        .err()
+        .expect("empty attestation doc is rejected");
+    assert_eq!(
+        err,
+        format!(
+            "NSM returned a 0 byte attestation document, expected {MIN_ATTESTATION_DOC_LEN} to \
+             {MAX_ATTESTATION_DOC_LEN} bytes"
+        )
+    );
+
+    // Too small to be a signed Nitro attestation document
+    let undersized = vec![0; MIN_ATTESTATION_DOC_LEN - 1];
+    let err = ReshardProcessor::new(&handles, &share_set, Box::new(FixedDocumentNsm(undersized)))
+        .err()
+        .expect("undersized attestation doc is rejected");
+    assert!(
+        err.contains(&format!(
+            "NSM returned a {} byte attestation document",
+            MIN_ATTESTATION_DOC_LEN - 1
+        )),
+        "unexpected error: {err}"
+    );
+
+    let oversized = vec![0; MAX_ATTESTATION_DOC_LEN + 1];
+    let err = ReshardProcessor::new(&handles, &share_set, Box::new(FixedDocumentNsm(oversized)))
+        .err()
+        .expect("oversized attestation doc is rejected");
+    assert!(
+        err.contains(&format!(
+            "NSM returned a {} byte attestation document",
+            MAX_ATTESTATION_DOC_LEN + 1
+        )),
+        "unexpected error: {err}"
+    );
+
+    // The bounds themselves are accepted
+    for len in [MIN_ATTESTATION_DOC_LEN, MAX_ATTESTATION_DOC_LEN] {
+        let nsm = FixedDocumentNsm(vec![0; len]);
+        ReshardProcessor::new(&handles, &share_set, Box::new(nsm)).unwrap();
+    }
+}
+
+#[test]
+fn reshard_processor_maps_shares_to_members_in_order() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+
+    let mut share_set = reshard_fixture_share_set();
+    share_set.members.reverse();
+    share_set.members.rotate_left(1);
+
+    let mut processor = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm)).unwrap();
+    let request = borsh::to_vec(&ReshardRequest::RetrieveBundle).unwrap();
+    let ReshardResponse::Bundle(bundle) = borsh::from_slice(&processor.process(request)).unwrap()
+    else {
+        panic!("expected a bundle");
+    };
+
+    assert_eq!(bundle.member_outputs.len(), share_set.members.len());
+    for (output, member) in bundle.member_outputs.iter().zip(&share_set.members) {
+        assert_eq!(&output.share_set_member, member);
+
+        // Each member decrypts the share meant for them
+        let secret = format!(
+            "{RESHARD_FIXTURES}/new-share-set-secrets/{}.secret",
+            member.alias
+        );
+        let share = P256Pair::from_hex_file(secret)
+            .unwrap()
+            .decrypt(&output.encrypted_quorum_key_share)
+            .unwrap();
+        assert_eq!(qos_crypto::sha_512(&share), output.share_hash);
+    }
+}
+
+/// Retrieve the bundle and attestation doc, checking they belong to the same run.
+fn retrieve_bundle(processor: &mut ReshardProcessor) -> ReshardBundle {
+    let request = borsh::to_vec(&ReshardRequest::RetrieveBundle).unwrap();
+    let ReshardResponse::Bundle(bundle) = borsh::from_slice(&processor.process(request)).unwrap()
+    else {
+        panic!("expected a bundle");
+    };
+    let request = borsh::to_vec(&ReshardRequest::RetrieveAttestation).unwrap();
+    let ReshardResponse::Attestation(attestation_doc) =
+        borsh::from_slice(&processor.process(request)).unwrap()
+    else {
+        panic!("expected an attestation");
+    };
+    assert_eq!(attestation_doc, bundle.attestation_doc);
+    *bundle
+}
+
+fn recompute(processor: &mut ReshardProcessor) -> ReshardResponse {
+    let request = borsh::to_vec(&ReshardRequest::Recompute).unwrap();
+    borsh::from_slice(&processor.process(request)).unwrap()
+}
+
+fn retrieve_bundle_id(processor: &mut ReshardProcessor) -> Vec<u8> {
+    let request = borsh::to_vec(&ReshardRequest::RetrieveBundleId).unwrap();
+    let ReshardResponse::BundleId(bundle_id) =
+        borsh::from_slice(&processor.process(request)).unwrap()
+    else {
+        panic!("expected a bundle id");
+    };
+    bundle_id
+}
+
+/// Deliver a new manifest envelope with namespace `nonce` to the handles in `dir`, replacing the
+/// file like an atomic write would.
+fn deliver_manifest_envelope(dir: &Path, nonce: u32) -> ManifestEnvelope {
+    let manifest_envelope = ManifestEnvelope {
+        manifest: Manifest {
+            namespace: Namespace {
+                nonce,
+                quorum_key: reshard_fixture_quorum_key(),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let new_manifest_path = dir.join(".manifest_envelope.new");
+    std::fs::write(
+        &new_manifest_path,
+        borsh::to_vec(&manifest_envelope).unwrap(),
+    )
+    .unwrap();
+    std::fs::rename(&new_manifest_path, dir.join(".manifest_envelope")).unwrap();
+    manifest_envelope
+}
+
+#[test]
+fn reshard_processor_recompute_swaps_bundle() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+    let mut processor = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm)).unwrap();
+    let before = retrieve_bundle(&mut processor);
+
+    let manifest_envelope = deliver_manifest_envelope(tmp_dir.path(), 7);
+    let manifest_path = tmp_dir.path().join(".manifest_envelope");
+
+    // Nothing changes until the recompute
+    assert_eq!(retrieve_bundle(&mut processor), before);
+
+    assert_eq!(recompute(&mut processor), ReshardResponse::Recomputed);
+    let after = retrieve_bundle(&mut processor);
+    assert_ne!(after, before);
+    assert_eq!(after.manifest_envelope, manifest_envelope);
+    assert_eq!(after.member_outputs.len(), share_set.members.len());
+
+    // A failed recompute keeps serving the current bundle
+    std::fs::remove_file(&manifest_path).unwrap();
+    let ReshardResponse::RecomputeFailed(e) = recompute(&mut processor) else {
+        panic!("expected the recompute to fail");
+    };
+    assert!(e.contains("get_manifest_envelope"), "{e}");
+    assert_eq!(retrieve_bundle(&mut processor), after);
+}
+
+#[test]
+fn reshard_processor_rejects_manifest_for_another_quorum_key() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+    let mut processor = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm)).unwrap();
+    let before = retrieve_bundle(&mut processor);
+    assert_eq!(before.quorum_public_key, reshard_fixture_quorum_key());
+
+    // A manifest approved for another quorum key than the one loaded
+    let other_key = P256Pair::generate().unwrap().public_key().to_bytes();
+    let mut manifest_envelope = before.manifest_envelope.clone();
+    manifest_envelope.manifest.namespace.quorum_key = other_key.clone();
+    std::fs::write(
+        tmp_dir.path().join(".manifest_envelope"),
+        borsh::to_vec(&manifest_envelope).unwrap(),
+    )
+    .unwrap();
+
+    let expected = format!(
+        "quorum key {} does not match the manifest's quorum key {}",
+        qos_hex::encode(&reshard_fixture_quorum_key()),
+        qos_hex::encode(&other_key)
+    );
+    let err = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm))
+        .err()
+        .expect("mismatched quorum key is rejected");
+    assert_eq!(err, expected);
+
+    // Nor is it picked up by a recompute
+    assert_eq!(
+        recompute(&mut processor),
+        ReshardResponse::RecomputeFailed(expected)
+    );
+    assert_eq!(retrieve_bundle(&mut processor), before);
+}
+
+#[test]
+fn reshard_processor_bundle_id_tracks_bundle() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+    let mut processor = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm)).unwrap();
+
+    let bundle_id = retrieve_bundle_id(&mut processor);
+    assert_eq!(bundle_id, retrieve_bundle(&mut processor).id());
+    assert_eq!(bundle_id.len(), 32);
+    // Stable while the bundle is
+    assert_eq!(retrieve_bundle_id(&mut processor), bundle_id);
+    let _ = deliver_manifest_envelope(tmp_dir.path(), 7);
+    assert_eq!(retrieve_bundle_id(&mut processor), bundle_id);
+
+    assert_eq!(recompute(&mut processor), ReshardResponse::Recomputed);
+    let new_bundle_id = retrieve_bundle_id(&mut processor);
+    assert_ne!(new_bundle_id, bundle_id);
+    assert_eq!(new_bundle_id, retrieve_bundle(&mut processor).id());
+}
+
+fn attest_with_nonce(processor: &mut ReshardProcessor, nonce: &[u8]) -> ReshardResponse {
+    let request = borsh::to_vec(&ReshardRequest::AttestWithNonce(nonce.to_vec())).unwrap();
+    borsh::from_slice(&processor.process(request)).unwrap()
+}
+
+#[test]
+fn reshard_processor_attests_with_nonce() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+    let mut processor =
+        ReshardProcessor::new(&handles, &share_set, Box::new(NonceEchoNsm)).unwrap();
+    let bundle = retrieve_bundle(&mut processor);
+
+    let ReshardResponse::Attestation(document) = attest_with_nonce(&mut processor, b"fresh") else {
+        panic!("expected an attestation");
+    };
+    assert_eq!(document, b"attestation nonce=fresh");
+    assert_ne!(document, bundle.attestation_doc);
+
+    let ReshardResponse::AttestationFailed(e) =
+        attest_with_nonce(&mut processor, &[0; MAX_NONCE_LEN + 1])
+    else {
+        panic!("expected an oversized nonce to be rejected");
+    };
+    assert!(e.contains("nonce must be"), "{e}");
+    assert!(matches!(
+        attest_with_nonce(&mut processor, &[]),
+        ReshardResponse::AttestationFailed(_)
+    ));
+}
+
+#[test]
+fn reshard_processor_binds_nonce_attestation_to_bundle() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let spy = SpyNsm::default();
+    let mut processor = ReshardProcessor::new(
+        &handles,
+        &reshard_fixture_share_set(),
+        Box::new(spy.clone()),
+    )
+    .unwrap();
+    let bundle = retrieve_bundle(&mut processor);
+
+    // Not recomputed, so attestations stay bound to the bundle's manifest
+    let _ = deliver_manifest_envelope(tmp_dir.path(), 7);
+    let ReshardResponse::Attestation(_) = attest_with_nonce(&mut processor, b"fresh") else {
+        panic!("expected an attestation");
+    };
+
+    let requests = spy.requests.lock().unwrap();
+    let NsmRequest::Attestation {
+        user_data,
+        nonce,
+        public_key,
+    } = requests.last().unwrap()
+    else {
+        panic!("expected an attestation request");
+    };
+    assert_eq!(
+        user_data.as_deref(),
+        Some(bundle.manifest_envelope.qos_hash().as_slice())
+    );
+    assert_eq!(nonce.as_deref(), Some(b"fresh".as_slice()));
+    let ephemeral_pub =
+        P256Public::from_hex_file(format!("{RESHARD_FIXTURES}/ephemeral.pub")).unwrap();
+    assert_eq!(
+        public_key.as_deref(),
+        Some(ephemeral_pub.to_bytes().as_slice())
+    );
+}
+
+#[test]
+fn sign_checked_rejects_signature_from_other_key() {
+    let ephemeral_pair =
+        P256Pair::from_hex_file(format!("{RESHARD_FIXTURES}/ephemeral.secret")).unwrap();
+    let digest = qos_crypto::sha_512(b"member outputs");
+
+    let signature = sign_checked(&ephemeral_pair, &ephemeral_pair.public_key(), &digest).unwrap();
+    ephemeral_pair
+        .public_key()
+        .verify(&digest, &signature)
+        .unwrap();
+
+    // Signing with the wrong key must be caught before the signature is used
+    let bad_signer = P256Pair::generate().unwrap();
+    let err = sign_checked(&bad_signer, &ephemeral_pair.public_key(), &digest).unwrap_err();
+    assert!(
+        err.contains("ephemeral signature self-check failed"),
+        "{err}"
+    );
+}
+
+#[test]
+fn parse_share_set_accepts_fixture_members() {
+    let members = fixture_members();
+    let share_set = parse_share_set("3", &members.join(";"), None, false).unwrap();
+
+    assert_eq!(share_set, reshard_fixture_share_set());
+}
+
+#[test]
+fn parse_share_set_uses_member_aliases() {
+    let members = fixture_members().join(";");
+    let share_set = parse_share_set("3", &members, Some("alice;bob; carol ;dave"), false).unwrap();
+
+    let aliases: Vec<&str> = share_set.members.iter().map(|m| m.alias.as_str()).collect();
+    assert_eq!(aliases, ["alice", "bob", "carol", "dave"]);
+
+    // Only the aliases differ from the generated scheme
+    let mut generated = reshard_fixture_share_set();
+    for (member, alias) in generated.members.iter_mut().zip(aliases) {
+        member.alias = alias.to_string();
+    }
+    assert_eq!(share_set, generated);
+}
+
+#[test]
+fn parse_share_set_rejects_mismatched_member_aliases() {
+    let members = fixture_members().join(";");
+
+    let err = parse_share_set("3", &members, Some("alice;bob;carol"), false).unwrap_err();
+    assert!(
+        err.contains("--member-aliases has 3 aliases, but --members has 4 members"),
+        "{err}"
+    );
+
+    let err = parse_share_set("3", &members, Some("alice;bob;carol;dave;erin"), false).unwrap_err();
+    assert!(err.contains("has 5 aliases"), "{err}");
+
+    let err = parse_share_set("3", &members, Some("alice;;carol;dave"), false).unwrap_err();
+    assert!(err.contains("empty alias in --member-aliases[1]"), "{err}");
+
+    let err = parse_share_set("3", &members, Some("alice;bob;alice;dave"), false).unwrap_err();
+    assert!(
+        err.contains("duplicate alias in --member-aliases[2]"),
+        "{err}"
+    );
+
+    // Secrets are named after aliases, which must not reach outside their directory
+    for alias in ["..", "../bob", "a/b", "a\\b", ".hidden"] {
+        let aliases = format!("alice;{alias};carol;dave");
+        let err = parse_share_set("3", &members, Some(&aliases), false).unwrap_err();
+        assert_eq!(
+            err,
+            format!("alias {alias:?} in --member-aliases[1] can not be used as a file name")
+        );
+    }
+}
+
+#[test]
+fn parse_share_set_without_member_aliases_generates_them() {
+    let share_set = parse_share_set("3", &fixture_members().join(";"), None, false).unwrap();
+
+    let aliases: Vec<&str> = share_set.members.iter().map(|m| m.alias.as_str()).collect();
+    assert_eq!(
+        aliases,
+        ["reshard-1", "reshard-2", "reshard-3", "reshard-4"]
+    );
+}
+
+#[test]
+fn members_file_matches_inline_members() {
+    let members = fixture_members();
+    let inline = parse_share_set("3", &members.join(";"), None, false).unwrap();
+
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let newline_file = tmp_dir.path().join("members.txt");
+    std::fs::write(&newline_file, format!("{}\n\n", members.join("\n"))).unwrap();
+    let semicolon_file = tmp_dir.path().join("members-semicolon.txt");
+    std::fs::write(&semicolon_file, format!(" {} \n", members.join(";\n"))).unwrap();
+
+    for file in [newline_file, semicolon_file] {
+        let from_file = read_members_file(file.to_str().unwrap()).unwrap();
+        assert_eq!(
+            parse_share_set("3", &from_file, None, false).unwrap(),
+            inline
+        );
+    }
+
+    let missing = tmp_dir.path().join("missing.txt");
+    let err = read_members_file(missing.to_str().unwrap()).unwrap_err();
+    assert!(err.contains("unable to read --members-file"), "{err}");
+}
+
+#[test]
+fn threshold_file_matches_inline_threshold() {
+    let members = fixture_members().join(";");
+    let threshold_file = Path::new(RESHARD_FIXTURES).join("new-share-set/quorum_threshold");
+
+    let threshold = read_threshold_file(threshold_file.to_str().unwrap()).unwrap();
+    assert_eq!(threshold, "3");
+    assert_eq!(
+        parse_share_set(&threshold, &members, None, false).unwrap(),
+        parse_share_set("3", &members, None, false).unwrap()
+    );
+
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let missing = tmp_dir.path().join("missing");
+    let err = read_threshold_file(missing.to_str().unwrap()).unwrap_err();
+    assert!(err.contains("unable to read --threshold-file"), "{err}");
+}
+
+#[test]
+fn share_set_summary_describes_share_set() {
+    let share_set = reshard_fixture_share_set();
+    let summary = share_set_summary(&share_set);
+
+    assert!(
+        summary.contains(&format!("members: {}", share_set.members.len())),
+        "{summary}"
+    );
+    assert!(summary.contains("threshold: 3"), "{summary}");
+    assert!(
+        summary.contains(&format!(
+            "fingerprint: {}",
+            qos_hex::encode(&share_set.qos_hash())
+        )),
+        "{summary}"
+    );
+
+    // Any change to the share set changes the fingerprint
+    let mut reordered = share_set.clone();
+    reordered.members.swap(0, 1);
+    assert_ne!(share_set_summary(&reordered), summary);
+}
+
+#[test]
+fn parse_share_set_rejects_duplicate_members() {
+    let mut members = fixture_members();
+    members[2] = members[0].clone();
+
+    let err = parse_share_set("2", &members.join(";"), None, false).unwrap_err();
+    assert!(
+        err.contains("duplicate public key in --members[2]"),
+        "{err}"
+    );
+}
+
+#[test]
+fn parse_share_set_rejects_invalid_point() {
+    let mut members = fixture_members();
+    // Right length, but not points on the curve
+    members[1] = format!("04{}", "00".repeat(129));
+
+    let err = parse_share_set("2", &members.join(";"), None, false).unwrap_err();
+    assert!(
+        err.contains("invalid P256 public key in --members[1]"),
+        "{err}"
+    );
+}
+
+#[test]
+fn parse_share_set_threshold_one_requires_unsafe_flag() {
+    let members = fixture_members().join(";");
+
+    let err = parse_share_set("1", &members, None, false).unwrap_err();
+    assert!(
+        err.contains("--threshold must be in 2..=len(--members)"),
+        "{err}"
+    );
+
+    let share_set = parse_share_set("1", &members, None, true).unwrap();
+    assert_eq!(share_set.threshold, 1);
+
+    // The unsafe flag does not relax anything else
+    assert!(parse_share_set("0", &members, None, true).is_err());
+}
+
+/// App config for the reshard fixtures, with a minimal manifest envelope written into `dir`.
+fn fixture_config(dir: &Path, nsm: Box<dyn NsmProvider + Send>) -> ReshardAppConfig {
+    // Writes the manifest envelope
+    reshard_fixture_handles(dir);
+    let app_sock = dir.join("reshard.app.sock");
+
+    ReshardAppConfig {
+        addr: SocketAddress::new_unix(app_sock.to_str().unwrap()),
+        quorum_file: format!("{RESHARD_FIXTURES}/quorum.secret"),
+        ephemeral_file: format!("{RESHARD_FIXTURES}/ephemeral.secret"),
+        keys: None,
+        manifest_file: dir.join(".manifest_envelope").to_str().unwrap().to_string(),
+        manifest_envelope: None,
+        share_set: reshard_fixture_share_set(),
+        nsm,
+    }
+}
+
+#[test]
+fn app_config_builds_processor() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let config = fixture_config(tmp_dir.path(), Box::new(MockNsm));
+    let members = config.share_set.members.len();
+
+    let mut processor = config.processor().unwrap();
+
+    let request = borsh::to_vec(&ReshardRequest::RetrieveBundle).unwrap();
+    let ReshardResponse::Bundle(bundle) = borsh::from_slice(&processor.process(request)).unwrap()
+    else {
+        panic!("expected a bundle");
+    };
+    let quorum_pub = P256Public::from_hex_file(format!("{RESHARD_FIXTURES}/quorum.pub")).unwrap();
+    assert_eq!(bundle.quorum_public_key, quorum_pub.to_bytes());
+    assert_eq!(bundle.member_outputs.len(), members);
+}
+
+#[test]
+fn app_config_builds_processor_from_in_memory_keys() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let secret =
+        |name: &str| std::fs::read_to_string(format!("{RESHARD_FIXTURES}/{name}.secret")).unwrap();
+    // As piped to --keys-stdin
+    let piped = format!(
+        "{}\n{}\n",
+        secret("quorum").trim(),
+        secret("ephemeral").trim()
+    );
+    let keys = read_in_memory_keys(piped.as_bytes()).unwrap();
+
+    // No key files to fall back to
+    let mut config = fixture_config(tmp_dir.path(), Box::new(MockNsm));
+    let missing = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+    config.quorum_file = missing("quorum.secret");
+    config.ephemeral_file = missing("ephemeral.secret");
+    config.keys = Some(Box::new(keys));
+
+    let mut processor = config.processor().unwrap();
+    let bundle = retrieve_bundle(&mut processor);
+    let quorum_pub = P256Public::from_hex_file(format!("{RESHARD_FIXTURES}/quorum.pub")).unwrap();
+    assert_eq!(bundle.quorum_public_key, quorum_pub.to_bytes());
+
+    // The keys are still there to recompute
+    let request = borsh::to_vec(&ReshardRequest::Recompute).unwrap();
+    let response: ReshardResponse = borsh::from_slice(&processor.process(request)).unwrap();
+    assert_eq!(response, ReshardResponse::Recomputed);
+}
+
+#[test]
+fn in_memory_keys_reject_invalid_secrets() {
+    let seed = "11".repeat(32);
+
+    let err = InMemoryKeys::from_hex("not hex", &seed).unwrap_err();
+    assert!(err.starts_with("quorum secret is not valid hex"), "{err}");
+    let err = InMemoryKeys::from_hex(&seed, &"11".repeat(16)).unwrap_err();
+    assert_eq!(
+        err,
+        "ephemeral secret is 16 bytes, expected a 32 byte master seed"
+    );
+
+    let err = read_in_memory_keys(seed.as_bytes()).unwrap_err();
+    assert_eq!(
+        err,
+        "expected the quorum and ephemeral secrets on 2 lines of stdin, got 1"
+    );
+}
+
+#[test]
+fn app_config_stores_manifest_envelope_from_stdin() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let from_file = fixture_config(tmp_dir.path(), Box::new(MockNsm));
+    let manifest_bytes = std::fs::read(&from_file.manifest_file).unwrap();
+
+    // Piped in instead, the manifest file does not exist yet
+    let stdin_dir = tmp_dir.path().join("stdin");
+    std::fs::create_dir(&stdin_dir).unwrap();
+    let manifest_file = stdin_dir.join(".manifest_envelope");
+    let from_stdin = ReshardAppConfig {
+        addr: from_file.addr.clone(),
+        quorum_file: from_file.quorum_file.clone(),
+        ephemeral_file: from_file.ephemeral_file.clone(),
+        keys: None,
+        manifest_file: manifest_file.to_str().unwrap().to_string(),
+        manifest_envelope: Some(read_manifest_envelope(&manifest_bytes[..]).unwrap()),
+        share_set: from_file.share_set.clone(),
+        nsm: Box::new(MockNsm),
+    };
+
+    let from_file = retrieve_bundle(&mut from_file.processor().unwrap());
+    let from_stdin = retrieve_bundle(&mut from_stdin.processor().unwrap());
+
+    assert_eq!(std::fs::read(&manifest_file).unwrap(), manifest_bytes);
+    assert_eq!(from_stdin.manifest_envelope, from_file.manifest_envelope);
+    assert_eq!(from_stdin.quorum_public_key, from_file.quorum_public_key);
+    let members = |bundle: &ReshardBundle| -> Vec<QuorumMember> {
+        bundle
+            .member_outputs
+            .iter()
+            .map(|output| output.share_set_member.clone())
+            .collect()
+    };
+    assert_eq!(members(&from_stdin), members(&from_file));
+}
+
+#[test]
+fn manifest_envelope_decodes_from_base64() {
+    let manifest_envelope = ManifestEnvelope {
+        manifest: Manifest {
+            namespace: Namespace {
+                quorum_key: reshard_fixture_quorum_key(),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let b64 = base64::engine::general_purpose::STANDARD
+        .encode(borsh::to_vec(&manifest_envelope).unwrap());
+
+    assert_eq!(
+        decode_manifest_envelope_base64(&format!("{b64}\n")).unwrap(),
+        manifest_envelope
+    );
+
+    let err = decode_manifest_envelope_base64("not base64!").unwrap_err();
+    assert!(
+        err.starts_with("invalid base64 in --manifest-base64"),
+        "{err}"
+    );
+    let err = decode_manifest_envelope_base64("bm90IGFuIGVudmVsb3Bl").unwrap_err();
+    assert!(err.starts_with("invalid manifest envelope"), "{err}");
+    let err = read_manifest_envelope(&b"not an envelope"[..]).unwrap_err();
+    assert!(err.starts_with("invalid manifest envelope"), "{err}");
+}
+
+#[test]
+fn run_attests_with_provided_nsm() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+
+    let spy = SpyNsm::default();
+    let nsm = spy.clone();
+    let dir = tmp_dir.path().to_path_buf();
+    // `run` serves forever, the thread is left running
+    std::thread::spawn(move || reshard_app::run(fixture_config(&dir, Box::new(nsm))));
+
+    let start = Instant::now();
+    while spy.requests.lock().unwrap().is_empty() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "no NSM request made"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let manifest_envelope = handles.get_manifest_envelope().unwrap();
+    let ephemeral_pub =
+        P256Public::from_hex_file(format!("{RESHARD_FIXTURES}/ephemeral.pub")).unwrap();
+    assert_eq!(
+        *spy.requests.lock().unwrap(),
+        [NsmRequest::Attestation {
+            user_data: Some(manifest_envelope.qos_hash().to_vec()),
+            nonce: None,
+            public_key: Some(ephemeral_pub.to_bytes()),
+        }]
+    );
+}
+
+#[test]
+fn app_removes_socket_on_sigterm() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    // Writes the manifest envelope
+    reshard_fixture_handles(tmp_dir.path());
+    let app_sock = tmp_dir.path().join("reshard.app.sock");
+
+    let mut app = reshard_app_command(&app_sock, &tmp_dir.path().join(".manifest_envelope"))
+        .spawn()
+        .unwrap();
+
+    let start = Instant::now();
+    while !app_sock.exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "app socket never created"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // SAFETY: signals our own, not yet reaped, child
+    unsafe { libc::kill(app.id() as libc::pid_t, libc::SIGTERM) };
+    let status = app.wait().unwrap();
+
+    assert!(status.success(), "app exited with {status}");
+    assert!(!app_sock.exists(), "app socket left behind");
+}
+
+#[test]
+fn second_app_on_same_socket_fails() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    // Writes the manifest envelope
+    reshard_fixture_handles(tmp_dir.path());
+    let app_sock = tmp_dir.path().join("reshard.app.sock");
+    let manifest_path = tmp_dir.path().join(".manifest_envelope");
+
+    let mut first = reshard_app_command(&app_sock, &manifest_path)
+        .spawn()
+        .unwrap();
+    let start = Instant::now();
+    while !app_sock.exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "app socket never created"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let second = reshard_app_command(&app_sock, &manifest_path)
+        .output()
+        .unwrap();
+    assert!(!second.status.success(), "second app exited with success");
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(stderr.contains("is already in use"), "{stderr}");
+
+    // The first app still serves its socket
+    assert!(app_sock.exists(), "first app's socket was removed");
+    assert!(std::os::unix::net::UnixStream::connect(&app_sock).is_ok());
+
+    // SAFETY: signals our own, not yet reaped, child
+    unsafe { libc::kill(first.id() as libc::pid_t, libc::SIGTERM) };
+    assert!(first.wait().unwrap().success());
+}
+
+#[test]
+fn ensure_socket_unused_removes_stale_socket() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let socket_file = tmp_dir.path().join("reshard.app.sock");
+
+    // Nothing there yet
+    ensure_socket_unused(&socket_file).unwrap();
+
+    let listener = std::os::unix::net::UnixListener::bind(&socket_file).unwrap();
+    let err = ensure_socket_unused(&socket_file).unwrap_err();
+    assert!(err.contains("is already in use"), "{err}");
+    assert!(socket_file.exists());
+
+    // The file outlives the listener
+    drop(listener);
+    assert!(socket_file.exists());
+    ensure_socket_unused(&socket_file).unwrap();
+    assert!(!socket_file.exists(), "stale socket left behind");
+
+    std::fs::write(&socket_file, "not a socket").unwrap();
+    let err = ensure_socket_unused(&socket_file).unwrap_err();
+    assert!(err.contains("is not a socket"), "{err}");
+}
+
+#[test]
+fn app_logs_json_when_selected() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    // Writes the manifest envelope
+    reshard_fixture_handles(tmp_dir.path());
+    let app_sock = tmp_dir.path().join("reshard.app.sock");
+    let log_path = tmp_dir.path().join("reshard_app.log");
+
+    let mut app = reshard_app_command(&app_sock, &tmp_dir.path().join(".manifest_envelope"))
+        .arg("--log-format")
+        .arg("json")
+        .stdout(std::fs::File::create(&log_path).unwrap())
+        .spawn()
+        .unwrap();
+
+    let start = Instant::now();
+    while !app_sock.exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "app socket never created"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // SAFETY: signals our own, not yet reaped, child
+    unsafe { libc::kill(app.id() as libc::pid_t, libc::SIGTERM) };
+    assert!(app.wait().unwrap().success());
+
+    let logs = std::fs::read_to_string(&log_path).unwrap();
+    let messages: Vec<String> = logs
+        .lines()
+        .map(|line| {
+            let line: serde_json::Value =
+                serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line}"));
+            line["fields"]["message"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert!(
+        messages
+            .iter()
+            .any(|m| m.contains("Starting Reshard server")),
+        "{logs}"
+    );
+    assert!(
+        messages.iter().any(|m| m.contains("SIGTERM received")),
+        "{logs}"
+    );
+}
+
+fn replace_member(
+    processor: &mut ReshardProcessor,
+    replacement: MemberReplacement,
+    approvers: &[&str],
+) -> ReshardResponse {
+    let approvals = approvers
+        .iter()
+        .map(|alias| {
+            let pair = P256Pair::from_hex_file(format!(
+                "{RESHARD_FIXTURES}/new-share-set-secrets/{alias}.secret"
+            ))
+            .unwrap();
+            ReplacementApproval {
+                member_pub_key: pair.public_key().to_bytes(),
+                signature: pair.sign(&replacement.digest()).unwrap(),
+            }
+        })
+        .collect();
+    let request = borsh::to_vec(&ReshardRequest::ReplaceMember {
+        replacement,
+        approvals,
+    })
+    .unwrap();
+    borsh::from_slice(&processor.process(request)).unwrap()
+}
+
+#[test]
+fn reshard_processor_replaces_member_with_same_share() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+    let mut processor = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm)).unwrap();
+    let before = retrieve_bundle(&mut processor);
+
+    let new_pair = P256Pair::generate().unwrap();
+    let replacement = MemberReplacement {
+        bundle_id: retrieve_bundle_id(&mut processor),
+        alias: "reshard-2".to_string(),
+        replacement: QuorumMember {
+            alias: "reshard-2-new".to_string(),
+            pub_key: new_pair.public_key().to_bytes(),
+        },
+    };
+    let response = replace_member(
+        &mut processor,
+        replacement.clone(),
+        &["reshard-1", "reshard-3", "reshard-4"],
+    );
+    assert_eq!(response, ReshardResponse::MemberReplaced);
+
+    let after = retrieve_bundle(&mut processor);
+    assert_eq!(retrieve_bundle_id(&mut processor), after.id());
+    assert_eq!(after.quorum_public_key, before.quorum_public_key);
+    assert_eq!(after.attestation_doc, before.attestation_doc);
+    for i in [0, 2, 3] {
+        assert_eq!(after.member_outputs[i], before.member_outputs[i]);
+    }
+
+    // The replacement decrypts the share the lost key was sent
+    let output = &after.member_outputs[1];
+    assert_eq!(output.share_set_member, replacement.replacement);
+    let old_share = P256Pair::from_hex_file(format!(
+        "{RESHARD_FIXTURES}/new-share-set-secrets/reshard-2.secret"
+    ))
+    .unwrap()
+    .decrypt(&before.member_outputs[1].encrypted_quorum_key_share)
+    .unwrap();
+    let new_share = new_pair
+        .decrypt(&output.encrypted_quorum_key_share)
+        .unwrap();
+    assert_eq!(new_share, old_share);
+    assert_eq!(qos_crypto::sha_512(&new_share), output.share_hash);
+
+    // The updated outputs are signed by the ephemeral key
+    let digest = qos_crypto::sha_512(&borsh::to_vec(&after.member_outputs).unwrap());
+    handles
+        .get_ephemeral_key()
+        .unwrap()
+        .public_key()
+        .verify(&digest, &after.signature)
+        .unwrap();
+
+    // Recomputing keeps the replacement
+    assert_eq!(recompute(&mut processor), ReshardResponse::Recomputed);
+    let recomputed = retrieve_bundle(&mut processor);
+    assert_eq!(
+        recomputed.member_outputs[1].share_set_member,
+        replacement.replacement
+    );
+}
+
+#[test]
+fn reshard_processor_rejects_unapproved_member_replacement() {
+    let tmp_dir = TempDir::new("reshard_app").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+    let mut processor = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm)).unwrap();
+    let before = retrieve_bundle(&mut processor);
+
+    let replacement = MemberReplacement {
+        bundle_id: before.id(),
+        alias: "reshard-2".to_string(),
+        replacement: QuorumMember {
+            alias: "reshard-2".to_string(),
+            pub_key: P256Pair::generate().unwrap().public_key().to_bytes(),
+        },
+    };
+
+    // Fewer approvals than the threshold, counting each member once
+    let ReshardResponse::ReplaceMemberFailed(e) = replace_member(
+        &mut processor,
+        replacement.clone(),
+        &["reshard-1", "reshard-3", "reshard-3"],
+    ) else {
+        panic!("expected the replacement to fail");
+    };
+    assert!(
+        e.contains("approved by 2 members, but the threshold is 3"),
+        "{e}"
+    );
+
+    // Approvals for another bundle
+    let stale = MemberReplacement {
+        bundle_id: vec![0; 32],
+        ..replacement.clone()
+    };
+    let ReshardResponse::ReplaceMemberFailed(e) = replace_member(
+        &mut processor,
+        stale,
+        &["reshard-1", "reshard-3", "reshard-4"],
+    ) else {
+        panic!("expected the replacement to fail");
+    };
+    assert!(e.contains("not for the bundle currently served"), "{e}");
+
+    // Replacing with the key of another member would hand them two shares
+    let duplicate = MemberReplacement {
+        replacement: share_set.members[0].clone(),
+        ..replacement.clone()
+    };
+    let ReshardResponse::ReplaceMemberFailed(e) = replace_member(
+        &mut processor,
+        duplicate,
+        &["reshard-1", "reshard-3", "reshard-4"],
+    ) else {
+        panic!("expected the replacement to fail");
+    };
+    assert!(e.contains("already used by member 'reshard-1'"), "{e}");
+
+    // Aliases the verify tool can't name member files after
+    for alias in ["", "../reshard-2"] {
+        let bad_alias = MemberReplacement {
+            replacement: QuorumMember {
+                alias: alias.to_string(),
+                pub_key: P256Pair::generate().unwrap().public_key().to_bytes(),
+            },
+            ..replacement.clone()
+        };
+        let ReshardResponse::ReplaceMemberFailed(e) = replace_member(
+            &mut processor,
+            bad_alias,
+            &["reshard-1", "reshard-3", "reshard-4"],
+        ) else {
+            panic!("expected the replacement to fail");
+        };
+        assert!(e.contains("can not be used as a file name"), "{e}");
+    }
+
+    assert_eq!(retrieve_bundle(&mut processor), before);
+}
+
+/// App that answers every request as healthy.
+struct HealthyApp;
+
+impl RequestProcessor for HealthyApp {
+    fn process(&mut self, _request: Vec<u8>) -> Vec<u8> {
+        borsh::to_vec(&ReshardResponse::Health).unwrap()
+    }
+}
+
+#[test]
+fn rate_limited_processor_rejects_excess_requests() {
+    let limits = ReshardRateLimits {
+        retrieve_bundle: RateLimit {
+            burst: 2,
+            per_second: 20.0,
+        },
+        ..Default::default()
+    };
+    let mut processor = RateLimitedProcessor::new(HealthyApp, limits);
+    let mut process = |request: &ReshardRequest| {
+        let response = processor.process(borsh::to_vec(request).unwrap());
+        ReshardResponse::try_from_slice(&response).unwrap()
+    };
+
+    for _ in 0..2 {
+        assert_eq!(
+            process(&ReshardRequest::RetrieveBundle),
+            ReshardResponse::Health
+        );
+    }
+    assert_eq!(
+        process(&ReshardRequest::RetrieveBundle),
+        ReshardResponse::RateLimited("RetrieveBundle requests are over their rate limit".into())
+    );
+
+    // Other request types have buckets of their own, health checks are never limited
+    assert_eq!(
+        process(&ReshardRequest::RetrieveAttestation),
+        ReshardResponse::Health
+    );
+    for _ in 0..100 {
+        assert_eq!(
+            process(&ReshardRequest::HealthRequest),
+            ReshardResponse::Health
+        );
+    }
+
+    // Refilled at 20 per second
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        process(&ReshardRequest::RetrieveBundle),
+        ReshardResponse::Health
+    );
+}
+
+#[test]
+fn socket_address_from_args_rejects_invalid_combinations() {
+    let too_long = "s".repeat(MAX_UNIX_SOCKET_PATH_LEN + 1);
+    let cases = [
+        (
+            None,
+            None,
+            None,
+            false,
+            "either --usock or --cid and --port",
+        ),
+        (Some(3), None, None, false, "--cid requires --port"),
+        (None, Some(5005), None, false, "--port requires --cid"),
+        (
+            Some(3),
+            Some(5005),
+            Some("app.sock"),
+            false,
+            "can not be combined",
+        ),
+        (
+            Some(3),
+            None,
+            Some("app.sock"),
+            false,
+            "can not be combined",
+        ),
+        (
+            None,
+            Some(5005),
+            Some("app.sock"),
+            false,
+            "can not be combined",
+        ),
+        (None, None, Some("app.sock"), true, "only applies to vsock"),
+        (None, None, Some(""), false, "must not be empty"),
+        (
+            None,
+            None,
+            Some(too_long.as_str()),
+            false,
+            "longer than the limit",
+        ),
+    ];
+    for (cid, port, usock, vsock_to_host, message) in cases {
+        let err = socket_address_from_args(cid, port, usock, vsock_to_host).unwrap_err();
+        assert!(
+            err.contains(message),
+            "{cid:?} {port:?} {usock:?} {vsock_to_host}: {err}"
+        );
+    }
+}
+
+#[test]
+fn socket_address_from_args_builds_unix_address() {
+    let usock = "s".repeat(MAX_UNIX_SOCKET_PATH_LEN);
+    assert_eq!(
+        socket_address_from_args(None, None, Some(&usock), false).unwrap(),
+        SocketAddress::new_unix(&usock)
+    );
+}
+
+#[test]
+fn socket_address_from_args_builds_vsock_address() {
+    for vsock_to_host in [false, true] {
+        let addr = socket_address_from_args(Some(3), Some(5005), None, vsock_to_host);
+
+        #[cfg(feature = "vsock")]
+        {
+            let flags = if vsock_to_host {
+                qos_core::io::VMADDR_FLAG_TO_HOST
+            } else {
+                qos_core::io::VMADDR_NO_FLAGS
+            };
+            assert_eq!(addr.unwrap(), SocketAddress::new_vsock(3, 5005, flags));
+        }
+        #[cfg(not(feature = "vsock"))]
+        assert_eq!(
+            addr.unwrap_err(),
+            "vsock addresses require the vsock feature"
+        );
+    }
+}
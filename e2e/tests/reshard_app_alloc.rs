@@ -0,0 +1,62 @@
+//! Allocation accounting for the reshard enclave app. Lives in its own test binary because it
+//! installs a counting global allocator.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use e2e::{reshard_fixture_handles, reshard_fixture_share_set};
+use qos_core::server::RequestProcessor;
+use qos_nsm::mock::MockNsm;
+use reshard_app::service::{ReshardProcessor, ReshardRequest, ReshardResponse};
+use tempdir::TempDir;
+
+/// Counts allocations made by the current thread, so the test harness's other threads don't
+/// skew the numbers.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[test]
+fn retrieve_bundle_does_not_deep_clone() {
+    let tmp_dir = TempDir::new("reshard_app_alloc").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let share_set = reshard_fixture_share_set();
+    let mut processor = ReshardProcessor::new(&handles, &share_set, Box::new(MockNsm)).unwrap();
+
+    let request = borsh::to_vec(&ReshardRequest::RetrieveBundle).unwrap();
+    let first = processor.process(request.clone());
+    let ReshardResponse::Bundle(bundle) = borsh::from_slice(&first).unwrap() else {
+        panic!("expected a bundle");
+    };
+    assert_eq!(bundle.member_outputs.len(), share_set.members.len());
+
+    for _ in 0..10 {
+        let request = request.clone();
+        let before = allocations();
+        let response = processor.process(request);
+        // Only the response buffer itself, no matter how many members the bundle holds
+        assert_eq!(allocations() - before, 1);
+        assert_eq!(response, first);
+    }
+}
@@ -0,0 +1,164 @@
+//! Fuzz test of the reshard enclave app's request decoding: [`ReshardProcessor::process`] must
+//! answer any bytes the host forwards with a valid borsh [`ReshardResponse`], without panicking.
+//!
+//! Inputs are random mutations of the seed corpus in `fixtures/reshard/process-corpus`, one
+//! borsh encoded request per file, along with purely random bytes. The generator is seeded, so a
+//! failure reproduces on every run. Add the failing input to the corpus once fixed.
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use borsh::BorshDeserialize;
+use e2e::{reshard_fixture_handles, reshard_fixture_share_set, RESHARD_FIXTURES};
+use qos_core::server::RequestProcessor;
+use qos_nsm::mock::MockNsm;
+use reshard_app::service::{ReshardProcessor, ReshardRequest, ReshardResponse, MAX_NONCE_LEN};
+use tempdir::TempDir;
+
+const SEED: u64 = 0x5eed_4e5a_4d52_4431;
+const ITERATIONS: usize = 5_000;
+/// Values around the limits of borsh length prefixes and the app's own bounds.
+const INTERESTING_U32: [u32; 7] = [
+    0,
+    1,
+    MAX_NONCE_LEN as u32,
+    MAX_NONCE_LEN as u32 + 1,
+    i32::MAX as u32,
+    u32::MAX - 1,
+    u32::MAX,
+];
+
+/// xorshift64*, so the test needs no randomness crate and replays the same inputs every run.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform-ish in `0..bound`, `bound` must not be 0.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.next() as u8
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.byte()).collect()
+    }
+}
+
+fn corpus() -> Vec<Vec<u8>> {
+    let dir = Path::new(RESHARD_FIXTURES).join("process-corpus");
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "empty corpus in {}", dir.display());
+    files
+        .into_iter()
+        .map(|file| fs::read(file).unwrap())
+        .collect()
+}
+
+/// Apply one random mutation to `input`.
+fn mutate(rng: &mut Rng, input: &mut Vec<u8>, corpus: &[Vec<u8>]) {
+    match rng.below(8) {
+        // Flip a bit
+        0 if !input.is_empty() => {
+            let i = rng.below(input.len());
+            input[i] ^= 1 << rng.below(8);
+        }
+        // Replace a byte
+        1 if !input.is_empty() => {
+            let i = rng.below(input.len());
+            input[i] = [0, 1, 0x7f, 0x80, 0xff, rng.byte()][rng.below(6)];
+        }
+        // Insert a byte
+        2 => {
+            let i = rng.below(input.len() + 1);
+            input.insert(i, rng.byte());
+        }
+        // Remove a byte
+        3 if !input.is_empty() => {
+            input.remove(rng.below(input.len()));
+        }
+        // Truncate
+        4 => input.truncate(rng.below(input.len() + 1)),
+        // Append random bytes
+        5 => {
+            let len = rng.below(64);
+            input.extend(rng.bytes(len));
+        }
+        // Overwrite what may be a length prefix
+        6 if input.len() >= 4 => {
+            let i = rng.below(input.len() - 3);
+            let value = INTERESTING_U32[rng.below(INTERESTING_U32.len())];
+            input[i..i + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        // Splice in the tail of another seed
+        _ => {
+            let other = &corpus[rng.below(corpus.len())];
+            let from = rng.below(other.len() + 1);
+            input.truncate(rng.below(input.len() + 1));
+            input.extend_from_slice(&other[from..]);
+        }
+    }
+}
+
+/// Process `input`, asserting the app answers with valid borsh and without panicking.
+fn check(processor: &mut ReshardProcessor, input: &[u8]) {
+    let response = panic::catch_unwind(AssertUnwindSafe(|| processor.process(input.to_vec())))
+        .unwrap_or_else(|_| panic!("process panicked on {}", qos_hex::encode(input)));
+    let response = ReshardResponse::try_from_slice(&response).unwrap_or_else(|e| {
+        panic!(
+            "process answered {} with invalid borsh: {e}",
+            qos_hex::encode(input)
+        )
+    });
+
+    if ReshardRequest::try_from_slice(input).is_err() {
+        assert_eq!(
+            response,
+            ReshardResponse::Error,
+            "undecodable request {}",
+            qos_hex::encode(input)
+        );
+    }
+}
+
+#[test]
+fn reshard_app_process_never_panics_on_arbitrary_bytes() {
+    let tmp_dir = TempDir::new("reshard_app_fuzz").unwrap();
+    let handles = reshard_fixture_handles(tmp_dir.path());
+    let mut processor =
+        ReshardProcessor::new(&handles, &reshard_fixture_share_set(), Box::new(MockNsm)).unwrap();
+    let corpus = corpus();
+
+    for seed in &corpus {
+        check(&mut processor, seed);
+    }
+
+    let mut rng = Rng(SEED);
+    for _ in 0..ITERATIONS {
+        let input = if rng.below(8) == 0 {
+            let len = rng.below(256);
+            rng.bytes(len)
+        } else {
+            let mut input = corpus[rng.below(corpus.len())].clone();
+            for _ in 0..=rng.below(4) {
+                mutate(&mut rng, &mut input, &corpus);
+            }
+            input
+        };
+        check(&mut processor, &input);
+    }
+}
@@ -0,0 +1,824 @@
+//! Integration tests for reshard host configuration.
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use borsh::BorshDeserialize;
+use e2e::{
+    connect_reshard_client, reshard_fixture_bundle, reshard_fixture_handles,
+    reshard_fixture_share_set, ExecuteConfig, TestArgs, HOST_READY_TIMEOUT, LOCAL_HOST,
+};
+use health_check::{AppHealthCheckable, AppHealthResponse};
+use host_primitives::{
+    enclave_client_timeout, queue_metrics, spawn_queue_consumer, BorshCodec, EnclaveClient,
+    EnclaveConnection, EnclaveQueueMsg, Keepalive, RequestId, GRPC_MAX_RECV_MSG_SIZE,
+};
+use http_body_util::{BodyExt, Full};
+use qos_core::{io::SocketAddress, server::RequestProcessor};
+use qos_nsm::mock::MockNsm;
+use reshard_app::{
+    initializing::InitializingProcessor,
+    service::{ReshardBundle, ReshardProcessor, ReshardRequest, ReshardResponse, MAX_NONCE_LEN},
+};
+use reshard_host::{
+    generated::{
+        reshard::{
+            reshard_service_client::ReshardServiceClient, reshard_service_server::ReshardService,
+            GetBundleIdRequest, GetQueueUtilizationRequest, QueueUtilization, RawRequestRequest,
+            RetrieveAttestationRequest, RetrieveReshardRequest,
+        },
+        verify_descriptor, FILE_DESCRIPTOR_SET,
+    },
+    unexpected_response, Health, Host,
+};
+use tempdir::TempDir;
+use tonic::{
+    codegen::{http, Bytes, Service},
+    transport::Channel,
+};
+use tonic_reflection::pb::v1::{
+    server_reflection_client::ServerReflectionClient, server_reflection_request::MessageRequest,
+    server_reflection_response::MessageResponse, ServerReflectionRequest,
+};
+
+/// List the services advertised by the host's reflection service.
+async fn list_services(host_addr: String) -> Result<Vec<String>, tonic::Status> {
+    let channel = Channel::from_shared(host_addr)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = ServerReflectionClient::new(channel);
+
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut responses = client
+        .server_reflection_info(tokio_stream::iter(vec![request]))
+        .await?
+        .into_inner();
+
+    let response = responses.message().await?.expect("a reflection response");
+    match response.message_response {
+        Some(MessageResponse::ListServicesResponse(list)) => {
+            Ok(list.service.into_iter().map(|s| s.name).collect())
+        }
+        other => panic!("unexpected reflection response: {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn reshard_host_reflection_enabled() {
+    e2e::execute(|args: TestArgs| async move {
+        let services = list_services(args.host_addr).await.unwrap();
+        assert!(services.contains(&"services.reshard.v1.ReshardService".to_string()));
+        assert!(services.contains(&"grpc.health.v1.Health".to_string()));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_host_reflection_disabled() {
+    let config = ExecuteConfig {
+        enable_reflection: false,
+        ..Default::default()
+    };
+    e2e::execute_with_config(config, |args: TestArgs| async move {
+        let status = list_services(args.host_addr).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unimplemented);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_host_keepalive_retains_idle_connection() {
+    let config = ExecuteConfig {
+        keepalive: Keepalive {
+            http2_interval: Some(Duration::from_secs(1)),
+            http2_timeout: Some(Duration::from_secs(1)),
+            tcp: Some(Duration::from_secs(1)),
+        },
+        ..Default::default()
+    };
+    e2e::execute_with_config(config, |args: TestArgs| async move {
+        let mut client = args.reshard_client;
+        client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .unwrap();
+
+        // Idle for several keepalive intervals, pings must keep the connection usable
+        tokio::time::sleep(Duration::from_secs(4)).await;
+
+        client
+            .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .expect("idle connection still serves requests");
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn reshard_host_in_process_retrieve_reshard() {
+    let tmp_dir = TempDir::new("reshard_host_in_process").unwrap();
+    let share_set = reshard_fixture_share_set();
+    let processor = ReshardProcessor::new(
+        &reshard_fixture_handles(tmp_dir.path()),
+        &share_set,
+        Box::new(MockNsm),
+    )
+    .unwrap();
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(processor);
+    let host = Host::new(Arc::new(enclave));
+
+    let resp = host
+        .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+        .await
+        .unwrap()
+        .into_inner();
+    let bundle: ReshardBundle = serde_json::from_str(&resp.reshard_bundle).expect("valid JSON");
+    assert_eq!(bundle.member_outputs.len(), share_set.members.len());
+
+    let attestation_doc = host
+        .retrieve_attestation(tonic::Request::new(RetrieveAttestationRequest::default()))
+        .await
+        .unwrap()
+        .into_inner()
+        .attestation_doc;
+    assert_eq!(attestation_doc, bundle.attestation_doc);
+}
+
+#[tokio::test]
+async fn reshard_host_rewrites_bundle_archive_after_recompute() {
+    let tmp_dir = TempDir::new("reshard_host_bundle_archive").unwrap();
+    let archive_path = tmp_dir.path().join("bundle.json");
+    let processor = ReshardProcessor::new(
+        &reshard_fixture_handles(tmp_dir.path()),
+        &reshard_fixture_share_set(),
+        Box::new(MockNsm),
+    )
+    .unwrap();
+    let enclave = Arc::new(
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(processor),
+    );
+    let host = Host::new(Arc::clone(&enclave)).with_bundle_archive(Some(archive_path.clone()));
+    let retrieve = || async {
+        host.retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .reshard_bundle
+    };
+
+    let before = retrieve().await;
+    assert_eq!(std::fs::read_to_string(&archive_path).unwrap(), before);
+
+    // The same bundle is not written again
+    std::fs::write(&archive_path, "sentinel").unwrap();
+    assert_eq!(retrieve().await, before);
+    assert_eq!(std::fs::read_to_string(&archive_path).unwrap(), "sentinel");
+
+    // A recomputed bundle replaces the archived one
+    let response = enclave.send(ReshardRequest::Recompute).await.unwrap();
+    assert_eq!(response, ReshardResponse::Recomputed);
+    let after = retrieve().await;
+    assert_ne!(after, before);
+    assert_eq!(std::fs::read_to_string(&archive_path).unwrap(), after);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reshard_host_compresses_bundle_responses() {
+    let tmp_dir = TempDir::new("reshard_host_compression").unwrap();
+    let share_set = reshard_fixture_share_set();
+    let processor = ReshardProcessor::new(
+        &reshard_fixture_handles(tmp_dir.path()),
+        &share_set,
+        Box::new(MockNsm),
+    )
+    .unwrap();
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(processor);
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("{LOCAL_HOST}:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(Host::new(Arc::new(enclave)).into_server(GRPC_MAX_RECV_MSG_SIZE, true))
+            .serve(listen_addr),
+    );
+    let host_addr = format!("http://{LOCAL_HOST}:{port}");
+
+    // The e2e client accepts gzip
+    let mut client = connect_reshard_client(&host_addr, HOST_READY_TIMEOUT)
+        .await
+        .unwrap();
+    let response = client
+        .retrieve_reshard(RetrieveReshardRequest::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        response
+            .metadata()
+            .get("grpc-encoding")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        "gzip"
+    );
+    let json = response.into_inner().reshard_bundle;
+    assert!(
+        json.len() > 4096,
+        "bundle JSON is only {} bytes",
+        json.len()
+    );
+    let bundle: ReshardBundle = serde_json::from_str(&json).expect("valid JSON");
+    assert_eq!(bundle.member_outputs.len(), share_set.members.len());
+
+    // Clients that do not accept gzip get the same bundle uncompressed
+    let mut plain = ReshardServiceClient::connect(host_addr).await.unwrap();
+    let response = plain
+        .retrieve_reshard(RetrieveReshardRequest::default())
+        .await
+        .unwrap();
+    assert!(response.metadata().get("grpc-encoding").is_none());
+    assert_eq!(response.into_inner().reshard_bundle, json);
+}
+
+#[tokio::test]
+async fn reshard_host_in_process_attestation_with_nonce() {
+    let tmp_dir = TempDir::new("reshard_host_in_process").unwrap();
+    let processor = ReshardProcessor::new(
+        &reshard_fixture_handles(tmp_dir.path()),
+        &reshard_fixture_share_set(),
+        Box::new(MockNsm),
+    )
+    .unwrap();
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(processor);
+    let host = Host::new(Arc::new(enclave));
+
+    let attestation_doc = host
+        .retrieve_attestation(tonic::Request::new(RetrieveAttestationRequest {
+            nonce: b"fresh".to_vec(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .attestation_doc;
+    assert!(!attestation_doc.is_empty());
+
+    // Rejected by the host without a round trip to the enclave
+    let status = host
+        .retrieve_attestation(tonic::Request::new(RetrieveAttestationRequest {
+            nonce: vec![0; MAX_NONCE_LEN + 1],
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+/// Counts the full bundles the wrapped processor serves.
+struct CountingProcessor {
+    processor: ReshardProcessor,
+    bundles_served: Arc<AtomicUsize>,
+}
+
+impl RequestProcessor for CountingProcessor {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        if matches!(
+            ReshardRequest::try_from_slice(&request),
+            Ok(ReshardRequest::RetrieveBundle)
+        ) {
+            self.bundles_served.fetch_add(1, Ordering::SeqCst);
+        }
+        self.processor.process(request)
+    }
+}
+
+#[tokio::test]
+async fn reshard_host_concurrent_retrievals_share_bundle() {
+    let tmp_dir = TempDir::new("reshard_host_in_process").unwrap();
+    let processor = ReshardProcessor::new(
+        &reshard_fixture_handles(tmp_dir.path()),
+        &reshard_fixture_share_set(),
+        Box::new(MockNsm),
+    )
+    .unwrap();
+    let bundles_served = Arc::new(AtomicUsize::new(0));
+    let enclave = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(
+        CountingProcessor {
+            processor,
+            bundles_served: Arc::clone(&bundles_served),
+        },
+    );
+    let host = Arc::new(Host::new(Arc::new(enclave)));
+
+    let retrievals: Vec<_> = (0..8)
+        .map(|_| {
+            let host = Arc::clone(&host);
+            tokio::spawn(async move { host.bundle(None).await.unwrap() })
+        })
+        .collect();
+    let mut bundles = Vec::new();
+    for retrieval in retrievals {
+        bundles.push(retrieval.await.unwrap());
+    }
+
+    assert_eq!(bundles_served.load(Ordering::SeqCst), 1);
+    assert!(bundles.iter().all(|b| Arc::ptr_eq(b, &bundles[0])));
+
+    // The RPC serves the same bundle without retrieving it again
+    let resp = host
+        .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(resp.bundle_id, bundles[0].id());
+    assert_eq!(bundles_served.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn reshard_host_descriptor_matches_generated_code() {
+    verify_descriptor(FILE_DESCRIPTOR_SET).unwrap();
+
+    // A descriptor from a different (e.g. stale) regen is rejected
+    let mut stale = FILE_DESCRIPTOR_SET.to_vec();
+    stale.truncate(stale.len() - 1);
+    let err = verify_descriptor(&stale).unwrap_err();
+    assert!(err.contains("rerun codegen"), "{err}");
+}
+
+/// App that answers every request with [`ReshardResponse::Error`].
+struct ErrorApp;
+
+impl RequestProcessor for ErrorApp {
+    fn process(&mut self, _request: Vec<u8>) -> Vec<u8> {
+        borsh::to_vec(&ReshardResponse::Error).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn reshard_host_health_unavailable_when_enclave_down() {
+    let tmp_dir = TempDir::new("reshard_host_health").unwrap();
+    // Nothing listens here
+    let enclave_sock = tmp_dir.path().join("enclave.sock");
+    let (queue_tx, queue_rx) = tokio::sync::mpsc::channel(1);
+    spawn_queue_consumer::<BorshCodec, ReshardRequest, ReshardResponse>(
+        Arc::new(EnclaveConnection::new(
+            SocketAddress::new_unix(enclave_sock.to_str().unwrap()),
+            enclave_client_timeout(),
+        )),
+        queue_rx,
+        GRPC_MAX_RECV_MSG_SIZE,
+    );
+    let health = Health::new(Arc::new(EnclaveClient::new(queue_tx)));
+
+    let status = health.app_health_check().await.err().unwrap();
+    assert_eq!(status.code(), tonic::Code::Unavailable, "{status}");
+}
+
+#[tokio::test]
+async fn reshard_host_health_internal_on_unexpected_response() {
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(ErrorApp);
+    let health = Health::new(Arc::new(enclave));
+
+    let status = health.app_health_check().await.err().unwrap();
+    assert_eq!(status.code(), tonic::Code::Internal, "{status}");
+    assert!(status.message().contains("Error"), "{status}");
+}
+
+/// App that is up, but answers everything but health requests with an error.
+struct NoBundleApp;
+
+impl RequestProcessor for NoBundleApp {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        let response = match ReshardRequest::try_from_slice(&request) {
+            Ok(ReshardRequest::HealthRequest) => ReshardResponse::Health,
+            _ => ReshardResponse::Error,
+        };
+        borsh::to_vec(&response).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn reshard_host_health_not_ready_without_bundle_id() {
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(NoBundleApp);
+    let health = Health::new(Arc::new(enclave));
+
+    let response = health.app_health_check().await.unwrap().into_inner();
+    assert_eq!(response, AppHealthResponse::not_ready());
+}
+
+/// App that is up, but answers bundle id requests with invalid borsh.
+struct GarbledBundleIdApp;
+
+impl RequestProcessor for GarbledBundleIdApp {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        match ReshardRequest::try_from_slice(&request) {
+            Ok(ReshardRequest::HealthRequest) => borsh::to_vec(&ReshardResponse::Health).unwrap(),
+            _ => vec![0xff],
+        }
+    }
+}
+
+#[tokio::test]
+async fn reshard_host_health_passes_through_bundle_id_errors() {
+    let enclave = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(
+        GarbledBundleIdApp,
+    );
+    let health = Health::new(Arc::new(enclave));
+
+    // An internal error is not mistaken for an app that is still coming up
+    let status = health.app_health_check().await.err().unwrap();
+    assert_eq!(status.code(), tonic::Code::Internal, "{status}");
+}
+
+/// App that is up and serves a bundle id.
+struct BundleIdApp;
+
+impl RequestProcessor for BundleIdApp {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        let response = match ReshardRequest::try_from_slice(&request) {
+            Ok(ReshardRequest::HealthRequest) => ReshardResponse::Health,
+            Ok(ReshardRequest::RetrieveBundleId) => ReshardResponse::BundleId(vec![1; 32]),
+            _ => ReshardResponse::Error,
+        };
+        borsh::to_vec(&response).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn reshard_host_health_reports_initializing_then_ready() {
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let app = InitializingProcessor::spawn(move || {
+        release_rx.recv().unwrap();
+        Ok(BundleIdApp)
+    });
+    let enclave =
+        Arc::new(EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(app));
+    let health = Health::new(enclave.clone());
+
+    let response = enclave.send(ReshardRequest::HealthRequest).await.unwrap();
+    assert_eq!(response, ReshardResponse::Initializing);
+    let response = health.app_health_check().await.unwrap().into_inner();
+    assert_eq!(response, AppHealthResponse::not_ready());
+
+    release_tx.send(()).unwrap();
+    let start = Instant::now();
+    while health.app_health_check().await.unwrap().into_inner() != AppHealthResponse::ready() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "app still initializing"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let response = enclave.send(ReshardRequest::HealthRequest).await.unwrap();
+    assert_eq!(response, ReshardResponse::Health);
+}
+
+#[tokio::test]
+async fn reshard_host_raw_request_forwards_borsh() {
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(NoBundleApp);
+    let host = Host::new(Arc::new(enclave));
+
+    let response = host
+        .raw_request(tonic::Request::new(RawRequestRequest {
+            request: borsh::to_vec(&ReshardRequest::HealthRequest).unwrap(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .response;
+    assert_eq!(
+        ReshardResponse::try_from_slice(&response).unwrap(),
+        ReshardResponse::Health
+    );
+
+    // Responses are passed through whatever the variant
+    let response = host
+        .raw_request(tonic::Request::new(RawRequestRequest {
+            request: borsh::to_vec(&ReshardRequest::RetrieveBundleId).unwrap(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .response;
+    assert_eq!(
+        ReshardResponse::try_from_slice(&response).unwrap(),
+        ReshardResponse::Error
+    );
+
+    let status = host
+        .raw_request(tonic::Request::new(RawRequestRequest {
+            request: vec![0xff],
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument, "{status}");
+}
+
+/// App that takes 2 seconds to answer anything.
+struct SlowApp;
+
+impl RequestProcessor for SlowApp {
+    fn process(&mut self, _request: Vec<u8>) -> Vec<u8> {
+        std::thread::sleep(Duration::from_secs(2));
+        borsh::to_vec(&ReshardResponse::BundleId(vec![7; 32])).unwrap()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reshard_host_stops_waiting_on_enclave_at_client_deadline() {
+    let enclave = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(SlowApp);
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("{LOCAL_HOST}:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(Host::new(Arc::new(enclave)).into_server(GRPC_MAX_RECV_MSG_SIZE, false))
+            .serve(listen_addr),
+    );
+    let mut client =
+        connect_reshard_client(&format!("http://{LOCAL_HOST}:{port}"), HOST_READY_TIMEOUT)
+            .await
+            .unwrap();
+
+    let mut request = tonic::Request::new(RetrieveReshardRequest::default());
+    request.set_timeout(Duration::from_millis(300));
+    let start = Instant::now();
+    let status = client.retrieve_reshard(request).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded, "{status}");
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "answered after {:?}",
+        start.elapsed()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reshard_host_reports_queue_utilization() {
+    // No consumer, so everything sent stays queued
+    let (queue_tx, _queue_rx) =
+        tokio::sync::mpsc::channel::<Box<EnclaveQueueMsg<ReshardRequest, ReshardResponse>>>(4);
+    queue_metrics().register_queue("grpc-utilization-test", &queue_tx);
+    let mut response_rxs = Vec::new();
+    for _ in 0..3 {
+        let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+        response_rxs.push(response_rx);
+        queue_tx
+            .send(Box::new(EnclaveQueueMsg {
+                response_tx,
+                request: ReshardRequest::HealthRequest,
+                request_id: RequestId::generate(),
+            }))
+            .await
+            .unwrap();
+    }
+
+    let enclave = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(SlowApp);
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("{LOCAL_HOST}:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(Host::new(Arc::new(enclave)).into_server(GRPC_MAX_RECV_MSG_SIZE, false))
+            .serve(listen_addr),
+    );
+    let mut client =
+        connect_reshard_client(&format!("http://{LOCAL_HOST}:{port}"), HOST_READY_TIMEOUT)
+            .await
+            .unwrap();
+
+    let response = client
+        .get_queue_utilization(GetQueueUtilizationRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    // The registry is process wide, so other tests may register queues concurrently
+    let reported = response
+        .queues
+        .into_iter()
+        .find(|queue| queue.name == "grpc-utilization-test");
+    assert_eq!(
+        reported,
+        Some(QueueUtilization {
+            name: "grpc-utilization-test".to_string(),
+            queued: 3,
+            capacity: 4,
+        })
+    );
+}
+
+#[tokio::test]
+async fn reshard_host_enclave_timeout_applies_without_client_deadline() {
+    let enclave = EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(SlowApp);
+    let host = Host::new(Arc::new(enclave)).with_enclave_timeout(Some(Duration::from_millis(100)));
+
+    let start = Instant::now();
+    let status = host
+        .get_bundle_id(tonic::Request::new(GetBundleIdRequest::default()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded, "{status}");
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+/// App that answers bundle id requests with a warning attached.
+struct WarningApp;
+
+impl RequestProcessor for WarningApp {
+    fn process(&mut self, request: Vec<u8>) -> Vec<u8> {
+        let response = match ReshardRequest::try_from_slice(&request) {
+            Ok(ReshardRequest::RetrieveBundleId) => {
+                ReshardResponse::BundleId(vec![7; 32]).with_metadata("warning", "bundle is stale")
+            }
+            _ => ReshardResponse::Error,
+        };
+        borsh::to_vec(&response).unwrap()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reshard_host_forwards_app_metadata() {
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(WarningApp);
+    let port = qos_test_primitives::find_free_port().unwrap();
+    let listen_addr = format!("{LOCAL_HOST}:{port}").parse().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(Host::new(Arc::new(enclave)).into_server(GRPC_MAX_RECV_MSG_SIZE, false))
+            .serve(listen_addr),
+    );
+
+    let mut client =
+        connect_reshard_client(&format!("http://{LOCAL_HOST}:{port}"), HOST_READY_TIMEOUT)
+            .await
+            .unwrap();
+    let response = client
+        .get_bundle_id(GetBundleIdRequest::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        response
+            .metadata()
+            .get("x-reshard-warning")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        "bundle is stale"
+    );
+    assert_eq!(response.into_inner().bundle_id, vec![7; 32]);
+}
+
+#[tokio::test]
+async fn reshard_host_sends_app_metadata_as_trailers() {
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(WarningApp);
+    let mut server = Host::new(Arc::new(enclave)).into_server(GRPC_MAX_RECV_MSG_SIZE, false);
+
+    // An empty, uncompressed GetBundleIdRequest frame
+    let request = http::Request::post("/services.reshard.v1.ReshardService/GetBundleId")
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(tonic::body::Body::new(Full::new(Bytes::from_static(
+            &[0; 5],
+        ))))
+        .unwrap();
+    let response = server.call(request).await.unwrap();
+    assert!(response.headers().get("x-reshard-warning").is_none());
+
+    let body = response.into_body().collect().await.unwrap();
+    let trailers = body.trailers().unwrap();
+    assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+    assert_eq!(
+        trailers.get("x-reshard-warning").unwrap(),
+        "bundle is stale"
+    );
+}
+
+#[test]
+fn reshard_app_response_metadata_merges() {
+    let response = ReshardResponse::Health
+        .with_metadata("warning", "inner")
+        .with_metadata("note", "kept")
+        .with_metadata("warning", "outer");
+    let (response, metadata) = response.into_parts();
+    assert_eq!(response, ReshardResponse::Health);
+    assert_eq!(
+        metadata.into_iter().collect::<Vec<_>>(),
+        [
+            ("note".to_string(), "kept".to_string()),
+            ("warning".to_string(), "outer".to_string())
+        ]
+    );
+
+    let (response, metadata) = ReshardResponse::Error.into_parts();
+    assert_eq!(response, ReshardResponse::Error);
+    assert!(metadata.is_empty());
+}
+
+#[test]
+fn reshard_host_unexpected_response_codes() {
+    let tmp_dir = TempDir::new("reshard_host_status").unwrap();
+    let bundle = Box::new(reshard_fixture_bundle(tmp_dir.path()));
+
+    let cases = [
+        (
+            ReshardResponse::Error,
+            tonic::Code::Unimplemented,
+            "older than this host",
+        ),
+        (
+            ReshardResponse::Health,
+            tonic::Code::Internal,
+            "got a health response",
+        ),
+        (
+            ReshardResponse::Attestation(vec![1]),
+            tonic::Code::Internal,
+            "got an attestation",
+        ),
+        (
+            ReshardResponse::Bundle(bundle),
+            tonic::Code::Internal,
+            "got a bundle",
+        ),
+        (
+            ReshardResponse::Recomputed,
+            tonic::Code::Internal,
+            "got a recompute result",
+        ),
+        (
+            ReshardResponse::RateLimited("over the limit".to_string()),
+            tonic::Code::ResourceExhausted,
+            "rate limited the test request: over the limit",
+        ),
+        (
+            ReshardResponse::RateLimited("over the limit".to_string())
+                .with_metadata("warning", "slow down"),
+            tonic::Code::ResourceExhausted,
+            "rate limited the test request",
+        ),
+        (
+            ReshardResponse::Initializing,
+            tonic::Code::Unavailable,
+            "still initializing",
+        ),
+    ];
+    for (response, code, message) in cases {
+        let status = unexpected_response("test", &response);
+        assert_eq!(status.code(), code, "{response:?}: {status}");
+        assert!(status.message().contains(message), "{status}");
+        assert!(status.message().contains("test"), "{status}");
+    }
+}
+
+#[tokio::test]
+async fn reshard_host_rpcs_report_unimplemented_for_app_errors() {
+    let enclave =
+        EnclaveClient::<BorshCodec, ReshardRequest, ReshardResponse>::in_process(ErrorApp);
+    let host = Host::new(Arc::new(enclave));
+
+    let status = host
+        .retrieve_reshard(tonic::Request::new(RetrieveReshardRequest::default()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unimplemented, "{status}");
+
+    let status = host
+        .retrieve_attestation(tonic::Request::new(RetrieveAttestationRequest::default()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unimplemented, "{status}");
+    assert!(status.message().contains("attestation"), "{status}");
+}
+
+#[test]
+fn host_runtime_uses_configured_worker_threads() {
+    let runtime = reshard_host::cli::runtime(Some(2)).unwrap();
+    assert_eq!(runtime.metrics().num_workers(), 2);
+
+    // Blocking tasks pile up on the workers there are, no others are started
+    let threads = runtime.block_on(async {
+        let tasks: Vec<_> = (0..16)
+            .map(|_| {
+                tokio::spawn(async {
+                    std::thread::sleep(Duration::from_millis(5));
+                    std::thread::current().id()
+                })
+            })
+            .collect();
+        let mut threads = std::collections::HashSet::new();
+        for task in tasks {
+            threads.insert(task.await.unwrap());
+        }
+        threads
+    });
+    assert!(threads.len() <= 2, "{threads:?}");
+}
@@ -0,0 +1,497 @@
+//! Integration tests for the yubikey provisioning ceremony tool.
+use std::{
+    collections::VecDeque,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use reshard_provision::{
+    provisioner::{Prompt, Provisioner, YubikeyPolicy},
+    run_with, secrets, validate_output_dir,
+    verify::{verify, KeyCheck, VerifyConfig},
+    Config, KeyOutcome, OperatorOutcome, OperatorResult,
+};
+use tempdir::TempDir;
+
+/// Records what would have been provisioned onto the yubikeys in each of its readers.
+struct MockProvisioner {
+    readers: usize,
+    provisioned: Mutex<Vec<Provisioned>>,
+    /// Public keys of the yubikeys the operator inserts, in order
+    inserted: Mutex<VecDeque<Vec<u8>>>,
+    /// Attempts to fail before the yubikeys respond, counting the failures
+    failing_attempts: usize,
+    failed_attempts: Mutex<usize>,
+}
+
+#[derive(Debug)]
+struct Provisioned {
+    reader: String,
+    secret: Vec<u8>,
+    policy: YubikeyPolicy,
+}
+
+impl MockProvisioner {
+    fn new(readers: usize) -> Self {
+        Self {
+            readers,
+            provisioned: Mutex::default(),
+            inserted: Mutex::default(),
+            failing_attempts: 0,
+            failed_attempts: Mutex::default(),
+        }
+    }
+
+    fn always_failing(readers: usize) -> Self {
+        Self::failing_first(readers, usize::MAX)
+    }
+
+    fn failing_first(readers: usize, failing_attempts: usize) -> Self {
+        Self {
+            failing_attempts,
+            ..Self::new(readers)
+        }
+    }
+}
+
+impl Provisioner for MockProvisioner {
+    fn generate_key(&self, secret_path: &Path, pub_path: &Path) -> Result<(), String> {
+        let name = pub_path.file_stem().unwrap().to_str().unwrap();
+        fs::write(secret_path, format!("secret {name}")).unwrap();
+        fs::write(pub_path, format!("pub {name}")).unwrap();
+        Ok(())
+    }
+
+    fn readers(&self) -> Result<Vec<String>, String> {
+        Ok((1..=self.readers).map(|r| format!("reader {r}")).collect())
+    }
+
+    fn provision_yubikey(
+        &self,
+        reader: &str,
+        secret_path: &Path,
+        policy: &YubikeyPolicy,
+    ) -> Result<(), String> {
+        {
+            let mut failed_attempts = self.failed_attempts.lock().unwrap();
+            if *failed_attempts < self.failing_attempts {
+                *failed_attempts += 1;
+                return Err("yubikey not responding".to_string());
+            }
+        }
+        self.provisioned.lock().unwrap().push(Provisioned {
+            reader: reader.to_string(),
+            secret: fs::read(secret_path).unwrap(),
+            policy: policy.clone(),
+        });
+        Ok(())
+    }
+
+    fn public_key(&self, _reader: &str) -> Result<Vec<u8>, String> {
+        self.inserted
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| "no yubikey inserted".to_string())
+    }
+}
+
+/// A sober operator who always has the yubikey ready.
+struct SoberOperator;
+
+impl Prompt for SoberOperator {
+    fn confirm(&self, prompt: &str, _default_yes: bool) -> Result<bool, String> {
+        Ok(!prompt.contains("inebriated"))
+    }
+}
+
+/// An operator who says yes to everything, including being inebriated.
+struct InebriatedOperator;
+
+impl Prompt for InebriatedOperator {
+    fn confirm(&self, _prompt: &str, _default_yes: bool) -> Result<bool, String> {
+        Ok(true)
+    }
+}
+
+/// A sober operator who gives up on the ceremony rather than skip a yubikey.
+struct StrictOperator;
+
+impl Prompt for StrictOperator {
+    fn confirm(&self, prompt: &str, _default_yes: bool) -> Result<bool, String> {
+        Ok(!prompt.contains("inebriated") && !prompt.contains("Skip this yubikey"))
+    }
+}
+
+fn config(out: PathBuf, yubikey_policy: YubikeyPolicy) -> Config {
+    Config {
+        num_operators: 2,
+        keys_per_operator: 3,
+        out,
+        include_secrets: false,
+        secrets_passphrase: None,
+        yubikey_policy,
+        skip_safety_prompts: false,
+        max_attempts: 3,
+    }
+}
+
+#[test]
+fn validate_output_dir_accepts_new_and_resumed_ceremonies() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+
+    // Created if missing
+    let out = tmp_dir.path().join("out");
+    validate_output_dir(&out, 3).unwrap();
+    assert!(out.is_dir());
+
+    // Artifacts of an interrupted run are fine
+    fs::write(out.join("1.pub"), "pub").unwrap();
+    fs::write(out.join("1.secret"), "secret").unwrap();
+    fs::write(out.join("3.pub"), "pub").unwrap();
+    validate_output_dir(&out, 3).unwrap();
+
+    // The probe file is cleaned up
+    assert_eq!(fs::read_dir(&out).unwrap().count(), 3);
+}
+
+#[test]
+fn validate_output_dir_rejects_conflicting_files() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let out = tmp_dir.path();
+    fs::write(out.join("1.pub"), "pub").unwrap();
+    fs::write(out.join("notes.txt"), "notes").unwrap();
+    // Operator outside of this ceremony
+    fs::write(out.join("4.pub"), "pub").unwrap();
+    fs::create_dir(out.join("2.secret")).unwrap();
+
+    let err = validate_output_dir(out, 3).unwrap_err();
+    assert!(err.contains("not ceremony artifacts"), "{err}");
+    assert!(err.contains("2.secret, 4.pub, notes.txt"), "{err}");
+    assert!(!err.contains("1.pub"), "{err}");
+
+    // A file where the directory should be
+    let file = out.join("notes.txt");
+    let err = validate_output_dir(&file, 3).unwrap_err();
+    assert!(err.contains("unable to create output dir"), "{err}");
+}
+
+#[test]
+fn validate_output_dir_rejects_read_only_dir() {
+    // Permission bits don't apply to root
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let out = tmp_dir.path().join("out");
+    fs::create_dir(&out).unwrap();
+    fs::set_permissions(&out, fs::Permissions::from_mode(0o555)).unwrap();
+
+    let err = validate_output_dir(&out, 3).unwrap_err();
+    assert!(err.contains("is not writable"), "{err}");
+
+    fs::set_permissions(&out, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn run_forwards_yubikey_policy_to_provisioner() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let pin_file = tmp_dir.path().join("pin");
+    fs::write(&pin_file, "654321").unwrap();
+    let policy = YubikeyPolicy {
+        pin_file: Some(pin_file),
+    };
+    let out = tmp_dir.path().join("out");
+
+    let provisioner = MockProvisioner::new(1);
+    run_with(
+        config(out.clone(), policy.clone()),
+        &provisioner,
+        &SoberOperator,
+    )
+    .unwrap();
+
+    let provisioned = provisioner.provisioned.into_inner().unwrap();
+    assert_eq!(provisioned.len(), 6);
+    for (i, provisioned) in provisioned.iter().enumerate() {
+        let operator = i / 3 + 1;
+        assert_eq!(provisioned.reader, "reader 1");
+        assert_eq!(provisioned.secret, format!("secret {operator}").as_bytes());
+        assert_eq!(provisioned.policy, policy);
+    }
+    assert_eq!(fs::read_to_string(out.join("2.pub")).unwrap(), "pub 2");
+}
+
+#[test]
+fn run_rejects_missing_pin_file_before_provisioning() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let policy = YubikeyPolicy {
+        pin_file: Some(tmp_dir.path().join("missing")),
+    };
+
+    let provisioner = MockProvisioner::new(1);
+    let err = run_with(
+        config(tmp_dir.path().join("out"), policy),
+        &provisioner,
+        &SoberOperator,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("PIN file"), "{err}");
+    assert!(provisioner.provisioned.into_inner().unwrap().is_empty());
+}
+
+#[test]
+fn run_skips_safety_prompts_when_requested() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let cfg = config(tmp_dir.path().join("out"), YubikeyPolicy::default());
+
+    let provisioner = MockProvisioner::new(1);
+    let err = run_with(cfg.clone(), &provisioner, &InebriatedOperator).unwrap_err();
+    assert!(err.to_string().contains("inebriation"), "{err}");
+    assert!(provisioner.provisioned.lock().unwrap().is_empty());
+
+    let cfg = Config {
+        skip_safety_prompts: true,
+        ..cfg
+    };
+    run_with(cfg, &provisioner, &InebriatedOperator).unwrap();
+    assert_eq!(provisioner.provisioned.into_inner().unwrap().len(), 6);
+}
+
+#[test]
+fn run_gives_up_on_yubikey_after_max_attempts() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let cfg = Config {
+        max_attempts: 2,
+        ..config(tmp_dir.path().join("out"), YubikeyPolicy::default())
+    };
+
+    // Skipping a yubikey finishes the ceremony
+    let provisioner = MockProvisioner::failing_first(1, 2);
+    let report = run_with(cfg.clone(), &provisioner, &SoberOperator).unwrap();
+    assert_eq!(*provisioner.failed_attempts.lock().unwrap(), 2);
+    let OperatorOutcome::Provisioned(keys) = &report.operators[0].outcome else {
+        panic!("expected operator 1 to be provisioned");
+    };
+    assert_eq!(
+        *keys,
+        vec![
+            KeyOutcome::Skipped("yubikey not responding".to_string()),
+            KeyOutcome::Provisioned,
+            KeyOutcome::Provisioned,
+        ]
+    );
+    assert_eq!(
+        provisioner.provisioned.into_inner().unwrap().len(),
+        2 * 3 - 1
+    );
+
+    // Aborting stops at the first yubikey
+    let provisioner = MockProvisioner::always_failing(1);
+    let err = run_with(
+        Config {
+            out: tmp_dir.path().join("out2"),
+            ..cfg
+        },
+        &provisioner,
+        &StrictOperator,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("failed 2 times"), "{err}");
+    assert!(err.to_string().contains("yubikey not responding"), "{err}");
+    assert_eq!(*provisioner.failed_attempts.lock().unwrap(), 2);
+}
+
+#[test]
+fn run_fails_operator_with_every_yubikey_skipped() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let out = tmp_dir.path().join("out");
+    let cfg = Config {
+        include_secrets: true,
+        max_attempts: 2,
+        ..config(out.clone(), YubikeyPolicy::default())
+    };
+
+    let provisioner = MockProvisioner::always_failing(1);
+    let err = run_with(cfg, &provisioner, &SoberOperator).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "every yubikey of operator 1 was skipped, removed {}",
+            out.join("1.pub").display()
+        )
+    );
+    assert_eq!(*provisioner.failed_attempts.lock().unwrap(), 3 * 2);
+    // A resumed run provisions the operator again, and operator 2 was never started
+    assert_eq!(fs::read_dir(&out).unwrap().count(), 0);
+}
+
+#[test]
+fn run_reports_skipped_and_provisioned_operators() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let out = tmp_dir.path().join("out");
+    fs::create_dir(&out).unwrap();
+    // Operator 1 was provisioned by an interrupted run
+    fs::write(out.join("1.pub"), "pub 1").unwrap();
+    let cfg = Config {
+        include_secrets: true,
+        ..config(out.clone(), YubikeyPolicy::default())
+    };
+
+    let report = run_with(cfg, &MockProvisioner::new(1), &SoberOperator).unwrap();
+    assert_eq!(
+        report.operators,
+        vec![
+            OperatorResult {
+                operator: 1,
+                outcome: OperatorOutcome::Skipped,
+                pub_path: out.join("1.pub"),
+                secret_path: None,
+            },
+            OperatorResult {
+                operator: 2,
+                outcome: OperatorOutcome::Provisioned(vec![KeyOutcome::Provisioned; 3]),
+                pub_path: out.join("2.pub"),
+                secret_path: Some(out.join("2.secret")),
+            },
+        ]
+    );
+}
+
+#[test]
+fn run_encrypts_kept_secrets_with_passphrase() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let out = tmp_dir.path().join("out");
+    let cfg = Config {
+        include_secrets: true,
+        secrets_passphrase: Some("correct horse battery staple".to_string()),
+        skip_safety_prompts: true,
+        ..config(out.clone(), YubikeyPolicy::default())
+    };
+
+    let report = run_with(cfg, &MockProvisioner::new(1), &SoberOperator).unwrap();
+    for (m, operator) in (1..=2).zip(&report.operators) {
+        let secret_path = out.join(format!("{m}.secret.enc"));
+        assert_eq!(operator.secret_path.as_ref(), Some(&secret_path));
+        assert!(!out.join(format!("{m}.secret")).exists());
+
+        let encrypted = fs::read(&secret_path).unwrap();
+        assert!(encrypted.starts_with(secrets::MAGIC));
+        let secret = format!("secret {m}");
+        assert!(!encrypted
+            .windows(secret.len())
+            .any(|window| window == secret.as_bytes()));
+
+        assert_eq!(
+            *secrets::decrypt(&encrypted, "correct horse battery staple").unwrap(),
+            secret.as_bytes()
+        );
+        let err = secrets::decrypt(&encrypted, "wrong passphrase").unwrap_err();
+        assert!(err.contains("wrong passphrase"), "{err}");
+    }
+
+    // A resumed ceremony accepts the encrypted secrets in the output dir
+    validate_output_dir(&out, 2).unwrap();
+}
+
+#[test]
+fn decrypt_secret_is_readable_by_owner_only() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let secret_path = tmp_dir.path().join("1.secret.enc");
+    let passphrase_path = tmp_dir.path().join("passphrase");
+    let out = tmp_dir.path().join("1.secret");
+    fs::write(
+        &secret_path,
+        secrets::encrypt(b"secret 1", "correct horse battery staple").unwrap(),
+    )
+    .unwrap();
+    fs::write(&passphrase_path, "correct horse battery staple\n").unwrap();
+
+    let status = std::process::Command::new("../target/debug/reshard_provision")
+        .arg("decrypt-secret")
+        .arg("--secret")
+        .arg(&secret_path)
+        .arg("--passphrase-file")
+        .arg(&passphrase_path)
+        .arg("--out")
+        .arg(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read(&out).unwrap(), b"secret 1");
+    assert_eq!(
+        fs::metadata(&out).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+}
+
+#[test]
+fn run_rejects_passphrase_without_included_secrets() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let cfg = Config {
+        secrets_passphrase: Some("passphrase".to_string()),
+        ..config(tmp_dir.path().join("out"), YubikeyPolicy::default())
+    };
+
+    let provisioner = MockProvisioner::new(1);
+    let err = run_with(cfg, &provisioner, &SoberOperator).unwrap_err();
+    assert!(err.to_string().contains("only applies"), "{err}");
+    assert!(provisioner.provisioned.into_inner().unwrap().is_empty());
+}
+
+#[test]
+fn run_requires_a_single_reader() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let out = tmp_dir.path().join("out");
+
+    for (readers, expected) in [(0, "no yubikey reader"), (2, "reader 1, reader 2")] {
+        let provisioner = MockProvisioner::new(readers);
+        let cfg = config(out.clone(), YubikeyPolicy::default());
+        let err = run_with(cfg, &provisioner, &SoberOperator).unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+        assert!(provisioner.provisioned.into_inner().unwrap().is_empty());
+    }
+}
+
+#[test]
+fn verify_reports_mismatched_key() {
+    let tmp_dir = TempDir::new("reshard_provision").unwrap();
+    let out = tmp_dir.path();
+    fs::write(out.join("1.pub"), qos_hex::encode(&[1; 4])).unwrap();
+    fs::write(out.join("2.pub"), format!("{}\n", qos_hex::encode(&[2; 4]))).unwrap();
+
+    let provisioner = MockProvisioner::new(1);
+    *provisioner.inserted.lock().unwrap() =
+        VecDeque::from([vec![1; 4], vec![1; 4], vec![2; 4], vec![9; 4]]);
+    let cfg = VerifyConfig {
+        // Operator 3 never finished provisioning
+        num_operators: 3,
+        keys_per_operator: 2,
+        out: out.to_path_buf(),
+    };
+
+    let report = verify(&cfg, &provisioner, &SoberOperator).unwrap();
+    assert!(!report.passed());
+    assert_eq!(report.operators[0], vec![KeyCheck::Pass, KeyCheck::Pass]);
+    assert_eq!(
+        report.operators[1],
+        vec![KeyCheck::Pass, KeyCheck::Mismatch]
+    );
+    // Not prompted for when the public key is missing
+    let KeyCheck::Error(e) = &report.operators[2][0] else {
+        panic!("expected an error for operator 3");
+    };
+    assert!(e.contains("3.pub"), "{e}");
+    assert!(provisioner.inserted.lock().unwrap().is_empty());
+
+    let matrix = report.to_string();
+    assert!(matrix.contains("1         pass   pass"), "{matrix}");
+    assert!(matrix.contains("2         pass   FAIL"), "{matrix}");
+    assert!(
+        matrix.contains("yubikey 2 of operator 2 holds a different public key"),
+        "{matrix}"
+    );
+}
@@ -0,0 +1,539 @@
+//! Integration tests for the offline reshard bundle verifier.
+use std::path::{Path, PathBuf};
+
+use borsh::BorshDeserialize;
+use e2e::{qos_simulator::MockAttestation, reshard_fixture_bundle, RESHARD_FIXTURES};
+use qos_p256::P256Pair;
+use reshard_app::service::{HashAlgorithm, ReshardBundle};
+use reshard_verify::{
+    attested_pcrs, bundle_pcrs,
+    diff::{self, diff_bundles},
+    load_bundle,
+    reconstruct::{self, Diagnosis},
+    run, share_hash,
+    split::{self, load_member_file, member_file_path, split_bundle},
+    Config,
+};
+use tempdir::TempDir;
+
+fn write_bundle(dir: &Path, bundle: &ReshardBundle) -> PathBuf {
+    let path = dir.join("bundle.json");
+    std::fs::write(&path, serde_json::to_string(bundle).unwrap()).unwrap();
+    path
+}
+
+fn config(bundle: PathBuf) -> Config {
+    Config {
+        bundle,
+        ephemeral_pub: Some(Path::new(RESHARD_FIXTURES).join("ephemeral.pub")),
+        quorum_pub: Some(Path::new(RESHARD_FIXTURES).join("quorum.pub")),
+        attestation_time: None,
+    }
+}
+
+fn json_check<'a>(report: &'a serde_json::Value, name: &str) -> &'a serde_json::Value {
+    report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["name"] == name)
+        .unwrap_or_else(|| panic!("missing check {name}"))
+}
+
+#[test]
+fn verify_json_report_for_valid_bundle() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let report = run(config(path)).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&serde_json::to_string_pretty(&report).unwrap()).unwrap();
+
+    assert_eq!(json["passed"], true);
+    for name in ["signature", "unique_members", "quorum_public_key"] {
+        let check = json_check(&json, name);
+        assert_eq!(check["passed"], true, "{name}");
+        assert_eq!(check["expected"], check["computed"], "{name}");
+    }
+    assert_eq!(
+        json_check(&json, "quorum_public_key")["computed"],
+        qos_hex::encode(&bundle.quorum_public_key)
+    );
+}
+
+#[test]
+fn verify_json_report_for_tampered_bundle() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let mut bundle = reshard_fixture_bundle(tmp_dir.path());
+    bundle.member_outputs[0].encrypted_quorum_key_share[0] ^= 1;
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let report = run(config(path)).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&serde_json::to_string_pretty(&report).unwrap()).unwrap();
+
+    assert_eq!(json["passed"], false);
+    let signature = json_check(&json, "signature");
+    assert_eq!(signature["passed"], false);
+    assert!(signature["computed"]
+        .as_str()
+        .unwrap()
+        .starts_with("invalid signature over "));
+    assert_eq!(json_check(&json, "quorum_public_key")["passed"], true);
+}
+
+#[test]
+fn verify_rejects_key_from_unverified_attestation_doc() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    // The fixture's mock attestation doc's certificates have long expired
+    let report = run(Config {
+        ephemeral_pub: None,
+        ..config(path)
+    })
+    .unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&serde_json::to_string_pretty(&report).unwrap()).unwrap();
+
+    assert_eq!(json["passed"], false);
+    assert_eq!(json_check(&json, "attestation_doc")["passed"], false);
+    let signature = json_check(&json, "signature");
+    assert_eq!(signature["passed"], false);
+    assert!(signature["computed"]
+        .as_str()
+        .unwrap()
+        .starts_with("unattested"));
+}
+
+fn reconstruct_config(bundle: PathBuf, threshold: usize) -> reconstruct::Config {
+    reconstruct::Config {
+        bundle,
+        secrets_dir: Path::new(RESHARD_FIXTURES).join("new-share-set-secrets"),
+        threshold,
+        check_threshold_safety: false,
+        max_safety_combinations: reconstruct::MAX_SAFETY_COMBINATIONS,
+    }
+}
+
+#[test]
+fn reconstruct_matches_bundle_quorum_key() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let report = reconstruct::run(reconstruct_config(path, 3)).unwrap();
+
+    assert!(report.matched);
+    assert_eq!(report.diagnosis, None);
+    assert_eq!(report.members_used, ["reshard-1", "reshard-2", "reshard-3"]);
+}
+
+#[test]
+fn reconstruct_below_threshold_does_not_match() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    // Fewer shares than the real threshold either fail to reconstruct or yield another key
+    if let Ok(report) = reconstruct::run(reconstruct_config(path, 2)) {
+        assert!(!report.matched);
+    }
+}
+
+#[test]
+fn reconstruct_errors_without_enough_secrets() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let err = reconstruct::run(reconstruct_config(path, 5)).unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "found secrets for 4 members but threshold is 5"
+    );
+}
+
+#[test]
+fn reconstruct_diagnoses_wrong_threshold() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let report = reconstruct::run(reconstruct_config(path, 2)).unwrap();
+
+    assert!(!report.matched);
+    assert_eq!(
+        report.diagnosis,
+        Some(Diagnosis::WrongThreshold {
+            threshold: 2,
+            reconstructs_with: 3
+        })
+    );
+}
+
+#[test]
+fn reconstruct_diagnoses_insufficient_shares() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+    let secrets_dir = tmp_dir.path().join("secrets");
+    std::fs::create_dir(&secrets_dir).unwrap();
+    for alias in ["reshard-1", "reshard-3"] {
+        std::fs::copy(
+            Path::new(RESHARD_FIXTURES)
+                .join("new-share-set-secrets")
+                .join(format!("{alias}.secret")),
+            secrets_dir.join(format!("{alias}.secret")),
+        )
+        .unwrap();
+    }
+
+    let cfg = reconstruct::Config {
+        secrets_dir,
+        ..reconstruct_config(path, 3)
+    };
+    let err = reconstruct::run(cfg).unwrap_err();
+
+    assert_eq!(
+        err.downcast_ref::<Diagnosis>(),
+        Some(&Diagnosis::InsufficientShares {
+            found: 2,
+            threshold: 3
+        })
+    );
+}
+
+#[test]
+fn reconstruct_diagnoses_mismatched_share_set() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+    let secrets_dir = tmp_dir.path().join("secrets");
+    std::fs::create_dir(&secrets_dir).unwrap();
+    let secret = Path::new(RESHARD_FIXTURES)
+        .join("new-share-set-secrets")
+        .join("reshard-1.secret");
+    // The secret of another member, and of someone outside the share set
+    std::fs::copy(&secret, secrets_dir.join("reshard-2.secret")).unwrap();
+    std::fs::copy(&secret, secrets_dir.join("stranger.secret")).unwrap();
+
+    let cfg = reconstruct::Config {
+        secrets_dir,
+        ..reconstruct_config(path, 3)
+    };
+    let err = reconstruct::run(cfg).unwrap_err();
+
+    assert_eq!(
+        err.downcast_ref::<Diagnosis>(),
+        Some(&Diagnosis::MismatchedShareSet {
+            aliases: vec!["reshard-2".to_string(), "stranger".to_string()]
+        })
+    );
+}
+
+#[test]
+fn reconstruct_diagnoses_shares_of_another_bundle() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let mut bundle = reshard_fixture_bundle(tmp_dir.path());
+    bundle.quorum_public_key = P256Pair::generate().unwrap().public_key().to_bytes();
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let report = reconstruct::run(reconstruct_config(path, 3)).unwrap();
+
+    assert!(!report.passed());
+    assert_eq!(
+        report.diagnosis,
+        Some(Diagnosis::QuorumKeyMismatch { shares: 4 })
+    );
+}
+
+#[test]
+fn reconstruct_threshold_safety() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let cfg = reconstruct::Config {
+        check_threshold_safety: true,
+        ..reconstruct_config(path, 3)
+    };
+    let report = reconstruct::run(cfg).unwrap();
+
+    assert!(report.passed());
+    assert_eq!(report.skipped_safety_checks(), 0);
+    assert_eq!(report.members_used, ["reshard-1", "reshard-2", "reshard-3"]);
+
+    // All 4 shares are decrypted, so 4 choose 1 and 4 choose 2 combinations are checked
+    let checked: Vec<_> = report
+        .threshold_safety
+        .iter()
+        .map(|s| (s.shares, s.combinations, s.skipped))
+        .collect();
+    assert_eq!(checked, [(1, 4, false), (2, 6, false)]);
+    for safety in &report.threshold_safety {
+        assert_eq!(safety.matches, 0);
+        assert_eq!(
+            safety.reconstruct_errors + safety.mismatches,
+            safety.combinations
+        );
+    }
+}
+
+#[test]
+fn reconstruct_threshold_safety_fails_on_skipped_share_counts() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    // 4 choose 1 is checked, 4 choose 2 is over the limit
+    let cfg = reconstruct::Config {
+        check_threshold_safety: true,
+        max_safety_combinations: 5,
+        ..reconstruct_config(path, 3)
+    };
+    let report = reconstruct::run(cfg).unwrap();
+
+    assert!(report.matched);
+    assert_eq!(report.skipped_safety_checks(), 1);
+    assert!(!report.passed(), "a skipped share count is not a pass");
+    let checked: Vec<_> = report
+        .threshold_safety
+        .iter()
+        .map(|s| (s.shares, s.combinations, s.skipped))
+        .collect();
+    assert_eq!(checked, [(1, 4, false), (2, 6, true)]);
+}
+
+#[test]
+fn share_hash_matches_bundle_share_hash() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let output = &bundle.member_outputs[0];
+    let alias = &output.share_set_member.alias;
+
+    let secret_path = Path::new(RESHARD_FIXTURES)
+        .join("new-share-set-secrets")
+        .join(format!("{alias}.secret"));
+    let share = P256Pair::from_hex_file(secret_path)
+        .unwrap()
+        .decrypt(&output.encrypted_quorum_key_share)
+        .unwrap();
+    let share_path = tmp_dir.path().join("share");
+    std::fs::write(&share_path, share).unwrap();
+
+    assert_eq!(
+        share_hash(&share_path, bundle.hash_algorithm).unwrap(),
+        qos_hex::encode(&output.share_hash)
+    );
+    assert!(share_hash(&tmp_dir.path().join("missing"), HashAlgorithm::Sha512).is_err());
+}
+
+#[test]
+fn pcrs_of_bundle_attestation_doc() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let mut bundle = reshard_fixture_bundle(tmp_dir.path());
+    // Attests to all zero PCRs 0 to 2
+    bundle.attestation_doc = MockAttestation {
+        public_key: vec![4; 65],
+        user_data: vec![7; 32],
+    }
+    .document();
+    let path = write_bundle(tmp_dir.path(), &bundle);
+
+    let pcrs = bundle_pcrs(&path).unwrap();
+    let zero = "00".repeat(48);
+    assert_eq!(
+        pcrs.into_iter().collect::<Vec<_>>(),
+        [(0, zero.clone()), (1, zero.clone()), (2, zero)]
+    );
+
+    let err = attested_pcrs(b"not an attestation doc").unwrap_err();
+    assert!(err.starts_with("unable to parse attestation doc"), "{err}");
+}
+
+#[test]
+fn diff_ignores_differences_between_runs() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let old = reshard_fixture_bundle(tmp_dir.path());
+    let mut new = reshard_fixture_bundle(tmp_dir.path());
+    // A rerun encrypts fresh shares and signs them again
+    assert_ne!(old.member_outputs, new.member_outputs);
+    new.member_outputs.reverse();
+
+    let old_path = tmp_dir.path().join("old.json");
+    std::fs::write(&old_path, serde_json::to_string(&old).unwrap()).unwrap();
+    let new_path = write_bundle(tmp_dir.path(), &new);
+    let report = diff::run(diff::Config {
+        old: old_path,
+        new: new_path,
+    })
+    .unwrap();
+
+    assert!(report.identical, "{report:?}");
+    let names: Vec<&str> = report.fields.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(
+        names[..6],
+        [
+            "quorum_public_key",
+            "manifest_hash",
+            "member reshard-1",
+            "member reshard-2",
+            "member reshard-3",
+            "member reshard-4"
+        ]
+    );
+    assert!(names[6..].iter().all(|name| name.starts_with("pcr ")));
+}
+
+#[test]
+fn diff_reports_changed_fields() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let old = reshard_fixture_bundle(tmp_dir.path());
+    let mut new = old.clone();
+    new.quorum_public_key = P256Pair::generate().unwrap().public_key().to_bytes();
+    new.manifest_envelope.manifest.namespace.nonce += 1;
+    new.member_outputs[0].share_set_member.alias = "alice".to_string();
+    new.member_outputs[1].share_set_member.pub_key =
+        P256Pair::generate().unwrap().public_key().to_bytes();
+
+    let report = diff_bundles(&old, &new).unwrap();
+
+    assert!(!report.identical);
+    let differing: Vec<&str> = report
+        .fields
+        .iter()
+        .filter(|f| f.differs)
+        .map(|f| f.name.as_str())
+        .collect();
+    assert_eq!(
+        differing,
+        [
+            "quorum_public_key",
+            "manifest_hash",
+            "member alice",
+            "member reshard-1",
+            "member reshard-2"
+        ]
+    );
+
+    let field = |name: &str| report.fields.iter().find(|f| f.name == name).unwrap();
+    assert_eq!(field("member alice").old, "absent");
+    assert_eq!(field("member reshard-1").new, "absent");
+    assert_eq!(
+        field("quorum_public_key").new,
+        qos_hex::encode(&new.quorum_public_key)
+    );
+}
+
+#[test]
+fn diff_errors_on_unparsable_attestation_doc() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let old = reshard_fixture_bundle(tmp_dir.path());
+    let mut new = old.clone();
+    new.attestation_doc = b"not an attestation doc".to_vec();
+
+    let err = diff_bundles(&old, &new).unwrap_err();
+    assert!(
+        err.starts_with("new bundle: unable to parse attestation doc"),
+        "{err}"
+    );
+}
+
+#[test]
+fn split_member_files_reassemble_and_verify() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let path = write_bundle(tmp_dir.path(), &bundle);
+    let out_dir = tmp_dir.path().join("members");
+
+    let paths = split::run(split::Config {
+        bundle: path,
+        out_dir: out_dir.clone(),
+    })
+    .unwrap();
+
+    assert_eq!(paths.len(), bundle.member_outputs.len());
+    for (index, output) in bundle.member_outputs.iter().enumerate() {
+        let alias = &output.share_set_member.alias;
+        let member_path = member_file_path(&out_dir, alias);
+        assert_eq!(paths[index], member_path);
+
+        let file = load_member_file(&member_path).unwrap();
+        assert_eq!(file.alias(), alias);
+        assert_eq!(&file.member_output, output);
+        assert_eq!(file.bundle().unwrap(), bundle);
+
+        // Each member file verifies on its own, like the bundle it was split from
+        let report = run(config(member_path)).unwrap();
+        assert!(report.passed, "{report:?}");
+    }
+}
+
+#[test]
+fn split_rejects_aliases_unsafe_as_file_names() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let mut bundle = reshard_fixture_bundle(tmp_dir.path());
+    bundle.member_outputs[1].share_set_member.alias = "../escape".to_string();
+
+    let err = split_bundle(&bundle).unwrap_err();
+    assert_eq!(err, r#"alias "../escape" can not be used as a file name"#);
+
+    let alias = bundle.member_outputs[0].share_set_member.alias.clone();
+    bundle.member_outputs[1].share_set_member.alias = alias.clone();
+    let err = split_bundle(&bundle).unwrap_err();
+    assert_eq!(err, format!("duplicate alias {alias:?}"));
+}
+
+#[test]
+fn split_member_file_rejects_out_of_range_index() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    let mut file = split_bundle(&bundle).unwrap().remove(0);
+    file.member_index = bundle.member_outputs.len();
+
+    assert_eq!(
+        file.bundle().unwrap_err(),
+        "member index 4 is out of range for 4 members"
+    );
+}
+
+#[test]
+fn bundle_hash_algorithm_round_trips_and_rejects_unknown() {
+    let tmp_dir = TempDir::new("reshard_verify").unwrap();
+    let bundle = reshard_fixture_bundle(tmp_dir.path());
+    assert_eq!(bundle.hash_algorithm, HashAlgorithm::Sha512);
+
+    let mut json = serde_json::to_value(&bundle).unwrap();
+    assert_eq!(json["hashAlgorithm"], "sha512");
+    let path = write_bundle(tmp_dir.path(), &bundle);
+    assert_eq!(load_bundle(&path).unwrap(), bundle);
+    let encoded = borsh::to_vec(&bundle).unwrap();
+    assert_eq!(ReshardBundle::try_from_slice(&encoded).unwrap(), bundle);
+
+    // Bundles from before the field existed are sha512
+    json.as_object_mut().unwrap().remove("hashAlgorithm");
+    let path = tmp_dir.path().join("legacy.json");
+    std::fs::write(&path, json.to_string()).unwrap();
+    assert_eq!(load_bundle(&path).unwrap(), bundle);
+
+    json["hashAlgorithm"] = "sha3-512".into();
+    let path = tmp_dir.path().join("unknown.json");
+    std::fs::write(&path, json.to_string()).unwrap();
+    let err = load_bundle(&path).unwrap_err().to_string();
+    assert!(err.contains("unknown variant `sha3-512`"), "{err}");
+
+    // The algorithm is the last field of the borsh encoding
+    let mut encoded = encoded;
+    *encoded.last_mut().unwrap() = 0xff;
+    assert!(ReshardBundle::try_from_slice(&encoded).is_err());
+
+    assert_eq!("sha512".parse(), Ok(HashAlgorithm::Sha512));
+    assert_eq!(
+        "sha3-512".parse::<HashAlgorithm>().unwrap_err(),
+        "unknown hash algorithm 'sha3-512', expected one of: sha512"
+    );
+}